@@ -1,22 +1,28 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use wasmcb::{default_panic_hook, report_error};
-use wrought_wasm_bindings::Wrought;
+use wrought_wasm_bindings::{Wrought, PLUGIN_ERROR, PLUGIN_OK};
 
 #[derive(Serialize, Deserialize)]
 struct DemoStruct {
     call_count: u32,
 }
 
+// Read by the host before running the plugin - see `wrought_wasm_bindings::MANIFEST_SECTION`.
+#[used]
+#[link_section = "wrought_manifest"]
+static MANIFEST: [u8; 70] =
+    *br#"{"name":"example","version":"0.1.0","capabilities":["stdio","bridge"]}"#;
+
 #[no_mangle]
 pub extern "C" fn plugin() -> i32 {
     std::panic::set_hook(Box::new(default_panic_hook));
 
     return match plugin_impl() {
-        Ok(()) => 0,
+        Ok(()) => PLUGIN_OK,
         Err(e) => {
             report_error(&e.to_string());
-            -1
+            PLUGIN_ERROR
         }
     };
 }
@@ -1,5 +1,30 @@
 pub type WroughtResult<T> = Result<T, String>;
 
+/// Name of the wasm custom section a plugin can use to describe itself,
+/// holding a JSON encoded `PluginManifest`.
+pub const MANIFEST_SECTION: &str = "wrought_manifest";
+
+/// Optional description of a plugin, checked by the host before running it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    /// Capabilities the plugin needs, e.g. "stdio" or "bridge".
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+// What a plugin's `plugin` function returns. Any other value is reserved, and
+// reported by the host as an unexpected code.
+/// The plugin succeeded.
+pub const PLUGIN_OK: i32 = 0;
+/// The plugin failed, and has described why with `host_report_error`.
+pub const PLUGIN_ERROR: i32 = -1;
+
+// Levels accepted by `wrought_log`.
+pub const LOG_LEVEL_INFO: i32 = 0;
+pub const LOG_LEVEL_WARN: i32 = 1;
+
 #[cfg(not(feature = "host"))]
 mod client {
     use serde::Serialize;
@@ -32,12 +57,19 @@ mod client {
             content_len: usize,
         );
         fn wrought_ai_query(query_ptr: *const u8, query_len: usize);
+        fn wrought_log(level: i32, message_ptr: *const u8, message_len: usize);
+        fn wrought_fail(exit_code: i32, message_ptr: *const u8, message_len: usize);
+        fn wrought_get_config(key_ptr: *const u8, key_len: usize);
+        fn wrought_content_hash(content_ptr: *const u8, content_len: usize);
+        fn wrought_set_result(result_ptr: *const u8, result_len: usize);
 
         // TODO: Expose these in the Bridge
         fn wrought_init_template();
         fn wrought_drop_template(id: i32);
         fn wrought_add_templates(id: i32, encoded_templates_ptr: *const u8, len: usize);
         fn wrought_render_template(id: i32, key_ptr: *const u8, key_len: usize, content_ptr: *const u8, content_len: usize);
+        fn wrought_use_shared_template(id: i32, key_ptr: *const u8, key_len: usize);
+        fn wrought_enable_render_cache(id: i32);
     }
 
     pub struct Wrought {}
@@ -132,6 +164,82 @@ mod client {
             serde_json::from_slice(&out_buf).unwrap()
         }
 
+        pub fn log(&mut self, message: &str) -> WroughtResult<()> {
+            self.log_with_level(LOG_LEVEL_INFO, message)
+        }
+
+        pub fn warn(&mut self, message: &str) -> WroughtResult<()> {
+            self.log_with_level(LOG_LEVEL_WARN, message)
+        }
+
+        fn log_with_level(&mut self, level: i32, message: &str) -> WroughtResult<()> {
+            let message_buf = message.as_bytes();
+            let len = unsafe {
+                wrought_log(level, message_buf.as_ptr(), message_buf.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
+        /// Fails the run on purpose - `message` is the whole error, and wrought exits with
+        /// `exit_code`. The plugin should return straight after, e.g. with `PLUGIN_ERROR`.
+        pub fn fail(&mut self, message: &str, exit_code: i32) -> WroughtResult<()> {
+            let message_buf = message.as_bytes();
+            let len = unsafe {
+                wrought_fail(exit_code, message_buf.as_ptr(), message_buf.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
+        pub fn get_config(&mut self, key: &str) -> WroughtResult<Option<String>> {
+            let key_buf = key.as_bytes();
+            let len = unsafe {
+                wrought_get_config(key_buf.as_ptr(), key_buf.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
+        /// The same hash wrought uses for file content, e.g. for keying a cache.
+        pub fn content_hash(&mut self, content: &[u8]) -> WroughtResult<String> {
+            let len = unsafe {
+                wrought_content_hash(content.as_ptr(), content.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
+        /// Hands `value` back to the host as the plugin's result, replacing any earlier one.
+        pub fn set_result(&mut self, value: &impl Serialize) -> WroughtResult<()> {
+            let result_json = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+            let len = unsafe {
+                wrought_set_result(result_json.as_ptr(), result_json.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
         pub fn template(&mut self) -> WroughtResult<WroughtTemplate> {
             let len = unsafe {
                 wrought_init_template();
@@ -171,6 +279,35 @@ mod client {
             serde_json::from_slice(&out_buf).unwrap()
         }
 
+        /// Use the host's shared template `key`, replacing any template of that name
+        /// added by the plugin. New templaters already start with all the shared templates.
+        pub fn use_shared_template(&mut self, key: &str) -> WroughtResult<()> {
+            let key_buf = key.as_bytes();
+            let len = unsafe {
+                wrought_use_shared_template(self.id, key_buf.as_ptr(), key_buf.len());
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
+        /// Reuse the output of earlier renders with the same templates and values, in
+        /// this run or an earlier one, rather than rendering again.
+        pub fn enable_cache(&mut self) -> WroughtResult<()> {
+            let len = unsafe {
+                wrought_enable_render_cache(self.id);
+                wasmcb::get_call_buffer_len()
+            };
+            let mut out_buf = vec![0u8; len];
+            unsafe {
+                wasmcb::read_call_buffer(out_buf.as_mut_ptr(), out_buf.len());
+            }
+            serde_json::from_slice(&out_buf).unwrap()
+        }
+
         pub fn render_template(&self, key: &str, values: &impl Serialize) -> WroughtResult<String> {
             let content_json = serde_json::to_vec(values).map_err(|e| e.to_string())?;
             let key_buf = key.as_bytes();
@@ -1,5 +1,6 @@
 use std::{
     collections::BTreeMap,
+    io::{Read, Write},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
@@ -7,10 +8,28 @@ use std::{
 use crate::{
     binary16::ContentHash,
     content_store::ContentStore,
+    fs_utils::replace_file,
     metadata::{MetadataEntry, MetadataKey},
+    project_root::normalize_project_relative_path,
+    text::ensure_single_trailing_newline,
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// The directory, relative to the project root, where wrought keeps its own state
+/// (settings, event log, content store, packages...).
+pub const INTERNAL_DIR: &str = ".wrought";
+
+/// Where the content store lives, relative to the project root.
+pub const CONTENT_DIR: &str = ".wrought/content";
+
+/// Where each run's scratch directory is made, relative to the project root.
+pub const TEMP_DIR: &str = ".wrought/tmp";
+
+/// Where the template render cache's index lives, relative to the project root. Each
+/// entry is a file named by the render's key, holding the hash of the stored output.
+pub const RENDER_CACHE_DIR: &str = ".wrought/render_cache";
 
 /// The backend is purely to access the data,
 /// it does not provide loging of the events, nor
@@ -30,20 +49,219 @@ pub trait Backend {
         key: &MetadataKey,
         value: &Option<MetadataEntry>,
     ) -> anyhow::Result<Option<MetadataEntry>>;
+    /// Every path's metadata at once, in a single read of the store.
+    fn get_all_metadata(
+        &self,
+    ) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<MetadataKey, MetadataEntry>>>;
     fn write_file(
         &self,
         path: &Path,
         value: &[u8],
     ) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
+    fn write_file_with_options(
+        &self,
+        path: &Path,
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
+    /// Like `write_file`, but streams the content from `reader`, so it is never all in
+    /// memory. It is written exactly as read - there is no newline normalization.
+    fn write_file_from_reader(
+        &self,
+        path: &Path,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
     fn read_file(&self, path: &Path) -> anyhow::Result<Option<(ContentHash, Vec<u8>)>>;
+    /// Moves `from` to `to`, replacing anything already at `to`. Returns the hash of
+    /// the moved content. It is an error if `from` doesn't exist.
+    fn rename_file(&self, from: &Path, to: &Path) -> anyhow::Result<ContentHash>;
+    /// Content previously written or seen, from the content store - if it is still there.
+    fn read_stored_content(&self, hash: &ContentHash) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Adds `value` to the content store without writing any file. Previews only hash it.
+    fn store_content(&self, value: &[u8]) -> anyhow::Result<ContentHash>;
+    /// The hash of the stored output the render cache recorded for `key`, if any.
+    fn get_render_cache_entry(&self, key: &ContentHash) -> anyhow::Result<Option<ContentHash>>;
+    /// Records `output`, already in the content store, as the render for `key`.
+    fn set_render_cache_entry(&self, key: &ContentHash, output: &ContentHash)
+        -> anyhow::Result<()>;
+}
+
+/// Per-call tweaks to how content is written. `None` means use the project default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Make text content end in exactly one newline. Binary content is never changed.
+    pub ensure_trailing_newline: Option<bool>,
 }
 
 // -----------------
 
+#[derive(Debug, Clone, Default)]
+pub struct BackendOptions {
+    /// Allow writes inside the `.wrought` directory. Scripts normally must not touch
+    /// wrought's internal state, but trusted scripts (e.g. package init scripts that
+    /// write their own status files) need to.
+    pub allow_internal_writes: bool,
+    /// Default for `WriteOptions::ensure_trailing_newline`, from the project settings.
+    /// Off unless asked for, so existing projects see no change.
+    pub ensure_trailing_newline: bool,
+    /// Refuse anything that would change the project - file writes, metadata changes,
+    /// and even keeping copies of what was read in the content store.
+    pub read_only: bool,
+    /// Write files under this directory instead of the project root, for previewing a
+    /// script's output. Reads still come from the project root, and paths reported back
+    /// are still the project-relative ones. Metadata has nowhere to be redirected to, so
+    /// changing it is refused - as are moves. Nothing is added to the content store
+    /// either, so hashes are still reported but their content isn't kept.
+    pub redirect_root: Option<PathBuf>,
+}
+
 pub struct SimpleBackend {
     pub fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
+    /// Whether `fs` is the real filesystem. Xfs has no way to move files, so files are
+    /// only replaced in one step - by moving a finished temporary file over them - when
+    /// it is. Otherwise they are written in place.
+    pub os_fs: bool,
     pub root: PathBuf,
     pub content_store: Arc<Mutex<dyn ContentStore + Send + 'static>>,
+    pub options: BackendOptions,
+}
+
+/// path -> key -> value.
+type MetadataStore = BTreeMap<String, BTreeMap<MetadataKey, MetadataEntry>>;
+
+/// `MetadataStore` as it is stored in `.wrought/metadata.json`.
+type StoredMetadata = BTreeMap<String, BTreeMap<String, serde_json::Value>>;
+
+/// Bumped whenever the layout of `metadata.json` changes.
+pub const METADATA_STORE_VERSION: u32 = 1;
+
+/// `metadata.json` in any layout wrought has used. Older layouts are upgraded when
+/// read, and the file is always written in the current one - so an old project's
+/// file is migrated the first time its metadata changes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MetadataFile {
+    Versioned {
+        version: u32,
+        entries: StoredMetadata,
+    },
+    /// Version 0, from before values were typed: a bare path -> key -> string map.
+    Unversioned(BTreeMap<String, BTreeMap<String, String>>),
+}
+
+#[derive(Serialize)]
+struct CurrentMetadataFile {
+    version: u32,
+    entries: StoredMetadata,
+}
+
+impl SimpleBackend {
+    fn metadata_path(&self) -> PathBuf {
+        self.root.join(INTERNAL_DIR).join("metadata.json")
+    }
+
+    fn render_cache_entry_path(&self, key: &ContentHash) -> PathBuf {
+        self.root.join(RENDER_CACHE_DIR).join(key.to_hex())
+    }
+
+    fn read_metadata_store(&self) -> anyhow::Result<MetadataStore> {
+        let md_path = self.metadata_path();
+        let Some(reader) = self.fs.lock().unwrap().reader_if_exists(&md_path)? else {
+            return Ok(MetadataStore::new());
+        };
+        let file: MetadataFile = serde_json::from_reader(reader).with_context(|| {
+            format!(
+                "metadata file '{}' is corrupt - it may have been truncated by an interrupted write",
+                md_path.display()
+            )
+        })?;
+        let entries = match file {
+            MetadataFile::Versioned { version, entries } => {
+                if version > METADATA_STORE_VERSION {
+                    bail!(
+                        "metadata file '{}' is version {}, but this wrought only understands up to version {} - upgrade wrought",
+                        md_path.display(),
+                        version,
+                        METADATA_STORE_VERSION
+                    );
+                }
+                entries
+            }
+            MetadataFile::Unversioned(entries) => entries
+                .into_iter()
+                .map(|(path, values)| {
+                    let values = values
+                        .into_iter()
+                        .map(|(k, v)| (k, serde_json::Value::String(v)))
+                        .collect();
+                    (path, values)
+                })
+                .collect(),
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(path, values)| {
+                let values = values
+                    .into_iter()
+                    .map(|(k, v)| (MetadataKey::from(k.as_str()), MetadataEntry::from(v)))
+                    .collect();
+                (path, values)
+            })
+            .collect())
+    }
+
+    fn write_metadata_store(&self, md_store: &MetadataStore) -> anyhow::Result<()> {
+        // Serialize up front, so a failure there can't leave a half written file.
+        let entries = md_store
+            .iter()
+            .map(|(path, values)| {
+                let values = values
+                    .iter()
+                    .map(|(k, v)| (k.as_string(), v.as_json().clone()))
+                    .collect();
+                (path.clone(), values)
+            })
+            .collect();
+        let content = serde_json::to_vec_pretty(&CurrentMetadataFile {
+            version: METADATA_STORE_VERSION,
+            entries,
+        })?;
+        let md_path = self.metadata_path();
+        if self.os_fs {
+            // So an interrupted write can't leave the store truncated.
+            return replace_file(&md_path, &md_path.with_extension("json.tmp"), &mut |w| {
+                Ok(w.write_all(&content)?)
+            });
+        }
+        let mut writer = self.fs.lock().unwrap().writer(&md_path)?;
+        writer.write_all(&content)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn check_not_read_only(&self, path: &Path) -> anyhow::Result<()> {
+        if self.options.read_only {
+            bail!(
+                "refusing to change '{}' - the project was opened read-only",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks `path` stays inside the project and, unless allowed, outside `.wrought`.
+    fn check_writable(&self, path: &Path) -> anyhow::Result<()> {
+        self.check_not_read_only(path)?;
+        let path = normalize_project_relative_path(path)?;
+        if !self.options.allow_internal_writes && path.starts_with(INTERNAL_DIR) {
+            bail!(
+                "refusing to write '{}' - it is inside wrought's internal '{}' directory",
+                path.display(),
+                INTERNAL_DIR
+            );
+        }
+        Ok(())
+    }
 }
 
 impl Backend for SimpleBackend {
@@ -52,17 +270,12 @@ impl Backend for SimpleBackend {
         path: &Path,
         key: &MetadataKey,
     ) -> anyhow::Result<Option<MetadataEntry>> {
-        let md_path = self.root.join(".wrought").join("metadata.json");
-        let md_store: BTreeMap<String, BTreeMap<String, String>> =
-            match self.fs.lock().unwrap().reader_if_exists(&md_path)? {
-                Some(reader) => serde_json::from_reader(reader)?,
-                None => BTreeMap::new(),
-            };
+        let md_store = self.read_metadata_store()?;
         let v = md_store
             .get(&path.display().to_string())
-            .and_then(|c| c.get(&key.as_string()));
+            .and_then(|c| c.get(key));
 
-        Ok(v.map(|s| MetadataEntry::from(s.as_str())))
+        Ok(v.cloned())
     }
 
     fn set_metadata(
@@ -71,24 +284,26 @@ impl Backend for SimpleBackend {
         key: &MetadataKey,
         value: &Option<MetadataEntry>,
     ) -> anyhow::Result<Option<MetadataEntry>> {
-        let md_path = self.root.join(".wrought").join("metadata.json");
-        let mut md_store: BTreeMap<String, BTreeMap<String, String>> =
-            match self.fs.lock().unwrap().reader_if_exists(&md_path)? {
-                Some(reader) => serde_json::from_reader(reader)?,
-                None => BTreeMap::new(),
-            };
+        self.check_not_read_only(path)?;
+        if self.options.redirect_root.is_some() {
+            bail!(
+                "can not set metadata of '{}' while writes are redirected - it would change the project",
+                path.display()
+            );
+        }
+        let mut md_store = self.read_metadata_store()?;
         let original = md_store
             .get(&path.display().to_string())
-            .and_then(|m| m.get(&key.as_string()));
-        let original = original.map(|v| MetadataEntry::from(v.as_str()));
+            .and_then(|m| m.get(key))
+            .cloned();
         if let Some(v) = value {
             md_store
                 .entry(path.display().to_string())
                 .or_default()
-                .insert(key.as_string(), v.as_string());
+                .insert(key.clone(), v.clone());
         } else {
             let clean = if let Some(x) = md_store.get_mut(&path.display().to_string()) {
-                x.remove(&key.as_string());
+                x.remove(key);
                 x.is_empty()
             } else {
                 false
@@ -98,16 +313,45 @@ impl Backend for SimpleBackend {
             }
         }
 
-        let writer = self.fs.lock().unwrap().writer(&md_path)?;
-        serde_json::to_writer_pretty(writer, &md_store)?;
+        self.write_metadata_store(&md_store)?;
         Ok(original)
     }
 
+    fn get_all_metadata(
+        &self,
+    ) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<MetadataKey, MetadataEntry>>> {
+        let md_store = self.read_metadata_store()?;
+        Ok(md_store
+            .into_iter()
+            .map(|(path, entries)| (PathBuf::from(path), entries))
+            .collect())
+    }
+
     fn write_file(
         &self,
         path: &Path,
         value: &[u8],
     ) -> anyhow::Result<(Option<ContentHash>, ContentHash)> {
+        self.write_file_with_options(path, value, &WriteOptions::default())
+    }
+
+    fn write_file_with_options(
+        &self,
+        path: &Path,
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> anyhow::Result<(Option<ContentHash>, ContentHash)> {
+        self.check_writable(path)?;
+        let normalized;
+        let value = if options
+            .ensure_trailing_newline
+            .unwrap_or(self.options.ensure_trailing_newline)
+        {
+            normalized = ensure_single_trailing_newline(value);
+            &normalized[..]
+        } else {
+            value
+        };
         let p = self.root.join(path);
 
         // Check if the file exists
@@ -120,19 +364,82 @@ impl Backend for SimpleBackend {
             None => None,
         };
 
-        // TODO: This should check p and parent are within the root.
+        // The before hash is always the tracked file's - only the write is redirected.
+        let p = match &self.options.redirect_root {
+            Some(redirect_root) => redirect_root.join(path),
+            None => p,
+        };
         let parent = p
             .parent()
             .ok_or_else(|| anyhow!("Unable to find parent for {}", p.display()))?;
         self.fs.lock().unwrap().create_dir_all(parent)?;
         self.fs.lock().unwrap().writer(&p)?.write_all(value)?;
 
-        self.content_store.lock().unwrap().store(value)?;
+        if self.options.redirect_root.is_none() {
+            self.content_store.lock().unwrap().store_if_absent(value)?;
+        }
 
         // TODO: Need to read the previous content if it exists.
         Ok((original_hash, ContentHash::from_content(value)))
     }
 
+    /// The file is written first, then read back into the content store. On the real
+    /// filesystem it is written to a temporary file next to it and moved into place, so
+    /// input that breaks off part way doesn't leave a truncated file behind.
+    fn write_file_from_reader(
+        &self,
+        path: &Path,
+        reader: &mut dyn Read,
+    ) -> anyhow::Result<(Option<ContentHash>, ContentHash)> {
+        self.check_writable(path)?;
+        let p = self.root.join(path);
+        let original_hash = self
+            .fs
+            .lock()
+            .unwrap()
+            .reader_if_exists(&p)?
+            .map(|mut reader| ContentHash::from_reader(&mut reader))
+            .transpose()?;
+
+        let p = match &self.options.redirect_root {
+            Some(redirect_root) => redirect_root.join(path),
+            None => p,
+        };
+        let parent = p
+            .parent()
+            .ok_or_else(|| anyhow!("Unable to find parent for {}", p.display()))?;
+        self.fs.lock().unwrap().create_dir_all(parent)?;
+        if self.os_fs {
+            let name = p
+                .file_name()
+                .ok_or_else(|| anyhow!("Unable to find file name for {}", p.display()))?;
+            let tmp = parent.join(format!(
+                ".{}.{}.tmp",
+                name.to_string_lossy(),
+                std::process::id()
+            ));
+            replace_file(&p, &tmp, &mut |w| {
+                std::io::copy(reader, w)?;
+                Ok(())
+            })?;
+        } else {
+            let mut writer = self.fs.lock().unwrap().writer(&p)?;
+            std::io::copy(reader, &mut writer)?;
+            writer.flush()?;
+        }
+
+        let mut written = self.fs.lock().unwrap().reader(&p)?;
+        let hash = if self.options.redirect_root.is_some() {
+            ContentHash::from_reader(&mut written)?
+        } else {
+            self.content_store
+                .lock()
+                .unwrap()
+                .store_reader(&mut written)?
+        };
+        Ok((original_hash, hash))
+    }
+
     fn read_file(&self, path: &Path) -> anyhow::Result<Option<(ContentHash, Vec<u8>)>> {
         let p = self.root.join(path);
         // Check if the file exists
@@ -145,8 +452,573 @@ impl Backend for SimpleBackend {
             None => None,
         };
 
+        // Keep a copy of what was read, so later runs can see what their inputs used to be.
+        // Previews leave the content store alone too.
+        if !self.options.read_only && self.options.redirect_root.is_none() {
+            if let Some((_, content)) = &original_and_hash {
+                self.content_store
+                    .lock()
+                    .unwrap()
+                    .store_if_absent(content)?;
+            }
+        }
+
         Ok(original_and_hash)
     }
+
+    /// Xfs has no way to move or remove files, so moves are only possible when `fs` is
+    /// the real filesystem.
+    fn rename_file(&self, from: &Path, to: &Path) -> anyhow::Result<ContentHash> {
+        self.check_writable(from)?;
+        self.check_writable(to)?;
+        if self.options.redirect_root.is_some() {
+            bail!(
+                "can not move '{}' while writes are redirected - the move would change the project",
+                from.display()
+            );
+        }
+        if !self.os_fs {
+            bail!(
+                "can not move '{}' - moving files is only supported on the real filesystem",
+                from.display()
+            );
+        }
+        let from_p = self.root.join(from);
+        let to_p = self.root.join(to);
+
+        let hash = match self.fs.lock().unwrap().reader_if_exists(&from_p)? {
+            Some(mut reader) => {
+                let mut content = vec![];
+                reader.read_to_end(&mut content)?;
+                ContentHash::from_content(&content)
+            }
+            None => bail!("can not move '{}' - it does not exist", from.display()),
+        };
+
+        let parent = to_p
+            .parent()
+            .ok_or_else(|| anyhow!("Unable to find parent for {}", to_p.display()))?;
+        self.fs.lock().unwrap().create_dir_all(parent)?;
+        std::fs::rename(&from_p, &to_p).with_context(|| {
+            format!("unable to move {} to {}", from_p.display(), to_p.display())
+        })?;
+        Ok(hash)
+    }
+
+    fn read_stored_content(&self, hash: &ContentHash) -> anyhow::Result<Option<Vec<u8>>> {
+        self.content_store
+            .lock()
+            .unwrap()
+            .retrieve_verified(hash.clone())
+    }
+
+    fn store_content(&self, value: &[u8]) -> anyhow::Result<ContentHash> {
+        if self.options.read_only {
+            bail!("refusing to store content - the project was opened read-only");
+        }
+        if self.options.redirect_root.is_some() {
+            return Ok(ContentHash::from_content(value));
+        }
+        self.content_store.lock().unwrap().store(value)
+    }
+
+    fn get_render_cache_entry(&self, key: &ContentHash) -> anyhow::Result<Option<ContentHash>> {
+        let path = self.render_cache_entry_path(key);
+        let Some(mut reader) = self.fs.lock().unwrap().reader_if_exists(&path)? else {
+            return Ok(None);
+        };
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let hash = ContentHash::from_string(content.trim())
+            .with_context(|| format!("render cache entry '{}' is corrupt", path.display()))?;
+        Ok(Some(hash))
+    }
+
+    /// Nothing is written while the project is read-only or writes are redirected, as
+    /// the index is part of the project's state.
+    fn set_render_cache_entry(
+        &self,
+        key: &ContentHash,
+        output: &ContentHash,
+    ) -> anyhow::Result<()> {
+        let path = self.render_cache_entry_path(key);
+        self.check_not_read_only(&path)?;
+        if self.options.redirect_root.is_some() {
+            bail!("refusing to update the render cache - writes are redirected");
+        }
+        let mut fs = self.fs.lock().unwrap();
+        fs.create_dir_all(&self.root.join(RENDER_CACHE_DIR))?;
+        let mut writer = fs.writer(&path)?;
+        writer.write_all(output.to_string().as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
 }
 
 // ----------------
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use mockall::mock;
+
+    mock! {
+        pub Backend {}
+
+        impl Backend for Backend {
+            fn get_metadata(&self, path: &Path, key: &MetadataKey) -> anyhow::Result<Option<MetadataEntry>>;
+            fn set_metadata(
+                &self,
+                path: &Path,
+                key: &MetadataKey,
+                value: &Option<MetadataEntry>,
+            ) -> anyhow::Result<Option<MetadataEntry>>;
+            fn get_all_metadata(&self) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<MetadataKey, MetadataEntry>>>;
+            fn write_file(&self, path: &Path, value: &[u8]) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
+            fn write_file_with_options(&self, path: &Path, value: &[u8], options: &WriteOptions) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
+            fn write_file_from_reader(&self, path: &Path, reader: &mut dyn Read) -> anyhow::Result<(Option<ContentHash>, ContentHash)>;
+            fn read_file(&self, path: &Path) -> anyhow::Result<Option<(ContentHash, Vec<u8>)>>;
+            fn rename_file(&self, from: &Path, to: &Path) -> anyhow::Result<ContentHash>;
+            fn read_stored_content(&self, hash: &ContentHash) -> anyhow::Result<Option<Vec<u8>>>;
+            fn store_content(&self, value: &[u8]) -> anyhow::Result<ContentHash>;
+            fn get_render_cache_entry(&self, key: &ContentHash) -> anyhow::Result<Option<ContentHash>>;
+            fn set_render_cache_entry(&self, key: &ContentHash, output: &ContentHash) -> anyhow::Result<()>;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        binary16::ContentHash,
+        content_store::FileSystemContentStore,
+        metadata::{MetadataEntry, MetadataKey},
+    };
+
+    use super::{Backend, BackendOptions, SimpleBackend, WriteOptions, METADATA_STORE_VERSION};
+
+    fn simple_test_case(
+        options: BackendOptions,
+    ) -> (Arc<Mutex<xfs::mockfs::MockFS>>, SimpleBackend) {
+        use xfs::Xfs;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = PathBuf::from("project");
+        fs.create_dir_all(&root.join(".wrought").join("content"))
+            .unwrap();
+        fs.add_r(&root.join(".wrought").join("wrought.db"), b"db".to_vec())
+            .unwrap();
+
+        let fs = Arc::new(Mutex::new(fs));
+        let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
+            fs.clone(),
+            root.join(".wrought").join("content"),
+        )));
+        let backend = SimpleBackend {
+            fs: fs.clone(),
+            os_fs: false,
+            root,
+            content_store,
+            options,
+        };
+        (fs, backend)
+    }
+
+    #[test]
+    pub fn broken_input_leaves_the_old_file() {
+        struct BreaksOff(usize);
+        impl std::io::Read for BreaksOff {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+                }
+                let n = self.0.min(buf.len());
+                buf[..n].fill(b'x');
+                self.0 -= n;
+                Ok(n)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::create_dir_all(root.join(".wrought/content")).unwrap();
+        let fs = Arc::new(Mutex::new(xfs::OsFs {}));
+        let backend = SimpleBackend {
+            fs,
+            os_fs: true,
+            root: root.clone(),
+            content_store: Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                root.join(".wrought/content"),
+            ))),
+            options: BackendOptions::default(),
+        };
+        backend
+            .write_file_from_reader(Path::new("out/a.md"), &mut &b"v1"[..])
+            .unwrap();
+        assert!(backend
+            .write_file_from_reader(Path::new("out/a.md"), &mut BreaksOff(100_000))
+            .is_err());
+        assert_eq!(std::fs::read(root.join("out/a.md")).unwrap(), b"v1");
+        // The temporary file is cleaned up too.
+        assert_eq!(std::fs::read_dir(root.join("out")).unwrap().count(), 1);
+    }
+
+    #[test]
+    pub fn moves_need_the_real_filesystem() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        backend.write_file(Path::new("a.md"), b"text").unwrap();
+        let e = backend
+            .rename_file(Path::new("a.md"), Path::new("b.md"))
+            .unwrap_err();
+        assert!(e.to_string().contains("real filesystem"), "{}", e);
+        let fs = fs.lock().unwrap();
+        assert_eq!(fs.get(&PathBuf::from("project/a.md")).unwrap(), b"text");
+        assert!(fs.get(&PathBuf::from("project/b.md")).is_none());
+        drop(fs);
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::create_dir_all(root.join(".wrought/content")).unwrap();
+        let backend = SimpleBackend {
+            fs: Arc::new(Mutex::new(xfs::OsFs {})),
+            os_fs: true,
+            root: root.clone(),
+            content_store: Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                root.join(".wrought/content"),
+            ))),
+            options: BackendOptions::default(),
+        };
+        backend.write_file(Path::new("a.md"), b"text").unwrap();
+        let hash = backend
+            .rename_file(Path::new("a.md"), Path::new("docs/b.md"))
+            .unwrap();
+        assert_eq!(hash, ContentHash::from_content(b"text"));
+        assert!(!root.join("a.md").exists());
+        assert_eq!(std::fs::read(root.join("docs/b.md")).unwrap(), b"text");
+    }
+
+    #[test]
+    pub fn failed_metadata_writes_leave_the_old_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::create_dir_all(root.join(".wrought/content")).unwrap();
+        let fs = Arc::new(Mutex::new(xfs::OsFs {}));
+        let backend = SimpleBackend {
+            fs: fs.clone(),
+            os_fs: true,
+            root: root.clone(),
+            content_store: Arc::new(Mutex::new(FileSystemContentStore::new(
+                fs,
+                root.join(".wrought/content"),
+            ))),
+            options: BackendOptions::default(),
+        };
+        let key = MetadataKey::from("title");
+        let set = |title: &str| {
+            backend.set_metadata(Path::new("a.md"), &key, &Some(MetadataEntry::from(title)))
+        };
+        set("first").unwrap();
+        let md_path = root.join(".wrought/metadata.json");
+        let tmp_path = root.join(".wrought/metadata.json.tmp");
+        assert!(!tmp_path.exists());
+        let before = std::fs::read(&md_path).unwrap();
+
+        // Nothing can be written where the temporary file goes.
+        std::fs::create_dir(&tmp_path).unwrap();
+        assert!(set("second").is_err());
+        assert_eq!(std::fs::read(&md_path).unwrap(), before);
+        assert_eq!(
+            backend.get_metadata(Path::new("a.md"), &key).unwrap(),
+            Some(MetadataEntry::from("first"))
+        );
+    }
+
+    #[test]
+    pub fn truncated_metadata_file_gives_a_clear_error() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        let key = MetadataKey::from("title");
+        backend
+            .set_metadata(
+                Path::new("a.md"),
+                &key,
+                &Some(MetadataEntry::from("A title")),
+            )
+            .unwrap();
+
+        let md_path = PathBuf::from("project/.wrought/metadata.json");
+        let mut content = fs.lock().unwrap().get(&md_path).unwrap();
+        content.truncate(content.len() / 2);
+        fs.lock().unwrap().add_r(&md_path, content).unwrap();
+
+        let e = backend.get_metadata(Path::new("a.md"), &key).unwrap_err();
+        let message = format!("{}", e);
+        assert!(
+            message.contains("project/.wrought/metadata.json"),
+            "{}",
+            message
+        );
+        assert!(message.contains("corrupt"), "{}", message);
+    }
+
+    #[test]
+    pub fn unversioned_metadata_files_are_read_and_upgraded() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        let md_path = PathBuf::from("project/.wrought/metadata.json");
+        fs.lock()
+            .unwrap()
+            .add_r(&md_path, br#"{"a.md": {"title": "A title"}}"#.to_vec())
+            .unwrap();
+
+        let title = backend
+            .get_metadata(Path::new("a.md"), &MetadataKey::from("title"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(title.as_json(), &serde_json::json!("A title"));
+
+        // The first write moves the file to the current layout, keeping what was there.
+        backend
+            .set_metadata(
+                Path::new("a.md"),
+                &MetadataKey::from("words"),
+                &Some(MetadataEntry::from(serde_json::json!(120))),
+            )
+            .unwrap();
+        let content: serde_json::Value =
+            serde_json::from_slice(&fs.lock().unwrap().get(&md_path).unwrap()).unwrap();
+        assert_eq!(
+            content,
+            serde_json::json!({
+                "version": METADATA_STORE_VERSION,
+                "entries": {"a.md": {"title": "A title", "words": 120}},
+            })
+        );
+
+        fs.lock()
+            .unwrap()
+            .add_r(&md_path, br#"{"version": 99, "entries": {}}"#.to_vec())
+            .unwrap();
+        let e = backend.get_all_metadata().unwrap_err();
+        assert!(e.to_string().contains("upgrade wrought"), "{}", e);
+    }
+
+    #[test]
+    pub fn get_all_metadata_returns_the_whole_store() {
+        let (_fs, backend) = simple_test_case(BackendOptions::default());
+        assert!(backend.get_all_metadata().unwrap().is_empty());
+
+        for (path, key, value) in [
+            ("a.md", "title", "A"),
+            ("a.md", "status", "draft"),
+            ("notes/b.md", "title", "B"),
+        ] {
+            backend
+                .set_metadata(
+                    Path::new(path),
+                    &MetadataKey::from(key),
+                    &Some(MetadataEntry::from(value)),
+                )
+                .unwrap();
+        }
+
+        let all = backend.get_all_metadata().unwrap();
+        let expected: BTreeMap<_, BTreeMap<_, _>> = [
+            (
+                PathBuf::from("a.md"),
+                [
+                    (MetadataKey::from("status"), MetadataEntry::from("draft")),
+                    (MetadataKey::from("title"), MetadataEntry::from("A")),
+                ]
+                .into(),
+            ),
+            (
+                PathBuf::from("notes/b.md"),
+                [(MetadataKey::from("title"), MetadataEntry::from("B"))].into(),
+            ),
+        ]
+        .into();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    pub fn render_cache_entries_are_kept_between_backends() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        let key = ContentHash::from_content(b"key");
+        let output = backend.store_content(b"rendered").unwrap();
+        assert_eq!(backend.get_render_cache_entry(&key).unwrap(), None);
+        backend.set_render_cache_entry(&key, &output).unwrap();
+        assert_eq!(
+            backend.get_render_cache_entry(&key).unwrap(),
+            Some(output.clone())
+        );
+
+        // Nothing is recorded while the project can't be changed.
+        let other = ContentHash::from_content(b"other key");
+        let read_only = SimpleBackend {
+            options: BackendOptions {
+                read_only: true,
+                ..BackendOptions::default()
+            },
+            ..backend
+        };
+        assert!(read_only.set_render_cache_entry(&other, &output).is_err());
+        assert_eq!(
+            read_only.get_render_cache_entry(&key).unwrap(),
+            Some(output)
+        );
+        assert!(fs
+            .lock()
+            .unwrap()
+            .get(&PathBuf::from("project/.wrought/render_cache").join(other.to_hex()))
+            .is_none());
+    }
+
+    #[test]
+    pub fn write_into_internal_dir_is_rejected() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+
+        let result = backend.write_file(Path::new(".wrought/wrought.db"), b"oops");
+        assert!(result.is_err());
+
+        let db = fs
+            .lock()
+            .unwrap()
+            .get(&PathBuf::from("project/.wrought/wrought.db"))
+            .unwrap();
+        assert_eq!(db, b"db");
+    }
+
+    #[test]
+    pub fn sneaky_write_into_internal_dir_is_rejected() {
+        let (_fs, backend) = simple_test_case(BackendOptions::default());
+        let result = backend.write_file(Path::new("docs/../.wrought/content/x"), b"oops");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    pub fn write_outside_project_is_rejected() {
+        let (_fs, backend) = simple_test_case(BackendOptions::default());
+        let result = backend.write_file(Path::new("../elsewhere.md"), b"oops");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    pub fn internal_write_allowed_with_flag() {
+        let (fs, backend) = simple_test_case(BackendOptions {
+            allow_internal_writes: true,
+            ..BackendOptions::default()
+        });
+        backend
+            .write_file(Path::new(".wrought/packages/p/status/01.toml"), b"ok")
+            .unwrap();
+        let content = fs
+            .lock()
+            .unwrap()
+            .get(&PathBuf::from("project/.wrought/packages/p/status/01.toml"))
+            .unwrap();
+        assert_eq!(content, b"ok");
+    }
+
+    #[test]
+    pub fn redirected_writes_land_in_the_redirect_root() {
+        let (fs, backend) = simple_test_case(BackendOptions {
+            redirect_root: Some(PathBuf::from("preview")),
+            ..BackendOptions::default()
+        });
+        fs.lock()
+            .unwrap()
+            .add_r(Path::new("project/docs/a.md"), b"old".to_vec())
+            .unwrap();
+        let md_path = PathBuf::from("project/.wrought/metadata.json");
+        let metadata = br#"{"version":1,"entries":{"docs/a.md":{"title":"old"}}}"#.to_vec();
+        fs.lock()
+            .unwrap()
+            .add_r(&md_path, metadata.clone())
+            .unwrap();
+
+        // Metadata has nowhere else to go, so a preview can't change it.
+        assert!(backend
+            .set_metadata(
+                Path::new("docs/a.md"),
+                &MetadataKey::from("title"),
+                &Some(MetadataEntry::from("new"))
+            )
+            .is_err());
+        assert_eq!(fs.lock().unwrap().get(&md_path).unwrap(), metadata);
+
+        let (before, after) = backend.write_file(Path::new("docs/a.md"), b"new").unwrap();
+
+        assert_eq!(before, Some(ContentHash::from_content(b"old")));
+        assert_eq!(after, ContentHash::from_content(b"new"));
+        let fs = fs.lock().unwrap();
+        assert_eq!(fs.get(&PathBuf::from("preview/docs/a.md")).unwrap(), b"new");
+        assert_eq!(fs.get(&PathBuf::from("project/docs/a.md")).unwrap(), b"old");
+        drop(fs);
+        // Reads still see the project.
+        let (_, content) = backend.read_file(Path::new("docs/a.md")).unwrap().unwrap();
+        assert_eq!(content, b"old");
+        assert!(backend
+            .rename_file(Path::new("docs/a.md"), Path::new("docs/b.md"))
+            .is_err());
+
+        // None of it - nor anything else the preview touched - is in the content store.
+        let (_, streamed) = backend
+            .write_file_from_reader(Path::new("docs/c.md"), &mut &b"streamed"[..])
+            .unwrap();
+        assert_eq!(streamed, ContentHash::from_content(b"streamed"));
+        let stored = backend.store_content(b"output").unwrap();
+        assert_eq!(stored, ContentHash::from_content(b"output"));
+        for hash in [before.unwrap(), after, streamed, stored] {
+            assert_eq!(backend.read_stored_content(&hash).unwrap(), None);
+        }
+    }
+
+    #[test]
+    pub fn trailing_newline_normalization_is_off_by_default() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        backend.write_file(Path::new("a.md"), b"text\n\n").unwrap();
+        let content = fs.lock().unwrap().get(&PathBuf::from("project/a.md"));
+        assert_eq!(content.unwrap(), b"text\n\n");
+    }
+
+    #[test]
+    pub fn trailing_newline_normalization_of_text() {
+        let (fs, backend) = simple_test_case(BackendOptions {
+            ensure_trailing_newline: true,
+            ..BackendOptions::default()
+        });
+        let (_, hash) = backend.write_file(Path::new("a.md"), b"text").unwrap();
+        let content = fs.lock().unwrap().get(&PathBuf::from("project/a.md"));
+        assert_eq!(content.unwrap(), b"text\n");
+        assert_eq!(hash, crate::binary16::ContentHash::from_content(b"text\n"));
+
+        // A per-call option overrides the project setting.
+        let options = WriteOptions {
+            ensure_trailing_newline: Some(false),
+        };
+        backend
+            .write_file_with_options(Path::new("b.md"), b"text", &options)
+            .unwrap();
+        let content = fs.lock().unwrap().get(&PathBuf::from("project/b.md"));
+        assert_eq!(content.unwrap(), b"text");
+    }
+
+    #[test]
+    pub fn trailing_newline_normalization_skips_binary() {
+        let (fs, backend) = simple_test_case(BackendOptions::default());
+        let options = WriteOptions {
+            ensure_trailing_newline: Some(true),
+        };
+        let binary = b"\x00\x01\x02".to_vec();
+        backend
+            .write_file_with_options(Path::new("a.bin"), &binary, &options)
+            .unwrap();
+        let content = fs.lock().unwrap().get(&PathBuf::from("project/a.bin"));
+        assert_eq!(content.unwrap(), binary);
+    }
+}
@@ -6,24 +6,55 @@ use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Binary16 {
     pub value: [u8; 16],
 }
 
 impl Binary16 {
+    /// Parses the unpadded URL-safe base64 form. Bad characters, a length that can't be
+    /// whole bytes (usually a truncated copy-paste) and a valid encoding of the wrong
+    /// number of bytes each get their own error, quoting the input.
     pub fn from_string(s: &str) -> anyhow::Result<Binary16> {
         use base64::engine::general_purpose::URL_SAFE_NO_PAD;
         use base64::Engine as _;
 
+        if let Some((i, c)) = s
+            .char_indices()
+            .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            anyhow::bail!(
+                "'{}' is not valid base64 - {:?} at offset {} is not a base64 character",
+                s,
+                c,
+                i
+            );
+        }
+        if s.len() % 4 == 1 {
+            anyhow::bail!(
+                "'{}' has an ambiguous length - {} base64 characters is not a whole number of bytes, so it may be truncated",
+                s,
+                s.len()
+            );
+        }
         let value = URL_SAFE_NO_PAD
             .decode(s)
-            .context("unable to decode binary 16 chunk")?;
-        Ok(Binary16 {
-            value: value
-                .try_into()
-                .map_err(|_e| anyhow::anyhow!("Incorrect key length for binary 16 chunk"))?,
-        })
+            .with_context(|| format!("'{}' is not valid base64", s))?;
+        let len = value.len();
+        let value = value.try_into().map_err(|_| {
+            let hint = if len == 32 {
+                " - 32 byte hashes are not supported yet"
+            } else {
+                ""
+            };
+            anyhow::anyhow!(
+                "'{}' decodes to {} bytes, but a binary 16 chunk is 16 bytes (22 base64 characters){}",
+                s,
+                len,
+                hint
+            )
+        })?;
+        Ok(Binary16 { value })
     }
 
     pub fn from_raw(value: [u8; 16]) -> Binary16 {
@@ -37,6 +68,28 @@ impl Binary16 {
         Binary16 { value }
     }
 
+    /// Lower case hex, as git shows hashes. The canonical form is still base64 (see `Display`).
+    pub fn to_hex(&self) -> String {
+        self.value.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn from_hex(s: &str) -> anyhow::Result<Binary16> {
+        if s.len() != 32 {
+            anyhow::bail!(
+                "Incorrect length for hex binary 16 chunk - expected 32 digits, got {}",
+                s.len()
+            );
+        }
+        if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("invalid hex in binary 16 chunk");
+        }
+        let mut value = [0; 16];
+        for (i, v) in value.iter_mut().enumerate() {
+            *v = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)?;
+        }
+        Ok(Binary16 { value })
+    }
+
     pub fn is_zero(&self) -> bool {
         self.value.iter().all(|c| *c == 0)
     }
@@ -81,7 +134,7 @@ impl Debug for Binary16 {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct ContentHash(Binary16);
 
 impl ContentHash {
@@ -89,6 +142,23 @@ impl ContentHash {
         Binary16::from_string(s).map(ContentHash)
     }
 
+    pub fn from_hex(s: &str) -> anyhow::Result<ContentHash> {
+        Binary16::from_hex(s).map(ContentHash)
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.to_hex()
+    }
+
+    /// Accepts either the base64 or the hex form - they are told apart by length.
+    pub fn parse_any(s: &str) -> anyhow::Result<ContentHash> {
+        if s.len() == 32 {
+            ContentHash::from_hex(s)
+        } else {
+            ContentHash::from_string(s)
+        }
+    }
+
     pub fn from_raw(id: [u8; 16]) -> ContentHash {
         ContentHash(Binary16::from_raw(id))
     }
@@ -102,16 +172,61 @@ impl ContentHash {
     }
 
     /// Get the ContentHash for the given input
+    ///
+    /// This is the hash of file content - the form in the event log, the pins and the
+    /// content store, and what `content_hash` gives scripts. It is untagged so those
+    /// hashes stay valid. Hashes of anything else should use `from_content_tagged`.
     pub fn from_content(content: &[u8]) -> ContentHash {
         use sha2::Digest;
         let digest = Sha256::digest(content);
         ContentHash::from_raw(digest.as_slice()[0..16].try_into().unwrap())
     }
 
+    /// Like `from_content`, but for hashes that aren't of file content (prompts, chunks
+    /// of a file, ...). The `domain` is hashed in ahead of the content, so equal bytes in
+    /// different domains get different hashes and can't be mistaken for one another.
+    ///
+    /// Content that happens to start with a domain's prefix could still collide with
+    /// an untagged file hash - the price of leaving file hashes unchanged.
+    pub fn from_content_tagged(domain: &str, content: &[u8]) -> ContentHash {
+        use sha2::Digest;
+        let mut hasher = Sha256::new();
+        hasher.update(b"wrought-domain:");
+        hasher.update(domain.as_bytes());
+        // Ends the domain, so ("ab", "c...") and ("a", "bc...") differ.
+        hasher.update([0]);
+        hasher.update(content);
+        ContentHash::from_raw(hasher.finalize().as_slice()[0..16].try_into().unwrap())
+    }
+
     pub(crate) fn from_reader(reader: &mut dyn std::io::Read) -> anyhow::Result<ContentHash> {
-        let mut content = vec![];
-        reader.read_to_end(&mut content)?;
-        Ok(ContentHash::from_content(&content))
+        let mut hasher = ContentHasher::default();
+        std::io::copy(reader, &mut hasher)?;
+        Ok(hasher.finish())
+    }
+}
+
+/// Works out a `ContentHash::from_content` hash a piece at a time, for content that
+/// shouldn't be held in memory all at once. Content is added by writing it.
+#[derive(Default)]
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn finish(self) -> ContentHash {
+        use sha2::Digest;
+        ContentHash::from_raw(self.0.finalize().as_slice()[0..16].try_into().unwrap())
+    }
+}
+
+impl std::io::Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
@@ -138,3 +253,106 @@ impl<'de> Deserialize<'de> for ContentHash {
         Binary16::deserialize(deserializer).map(ContentHash)
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::HashSet;
+
+    use super::{Binary16, ContentHash};
+
+    #[test]
+    pub fn content_hash_can_be_a_hash_set_key() {
+        let mut seen = HashSet::new();
+        seen.insert(ContentHash::from_content(b"hello"));
+        seen.insert(ContentHash::from_content(b"hello"));
+        assert_eq!(seen.len(), 1);
+        assert!(seen.contains(&ContentHash::from_content(b"hello")));
+        assert!(!seen.contains(&ContentHash::from_content(b"world")));
+    }
+
+    #[test]
+    pub fn base64_round_trip() {
+        let hash = ContentHash::from_content(b"hello");
+        let s = hash.to_string();
+        assert_eq!(s.len(), 22);
+        assert_eq!(ContentHash::from_string(&s).unwrap(), hash);
+        assert_eq!(ContentHash::parse_any(&s).unwrap(), hash);
+
+        assert!(Binary16::from_string(&s[..20]).is_err());
+    }
+
+    #[test]
+    pub fn bad_base64_errors_say_what_is_wrong() {
+        let hash = ContentHash::from_content(b"hello").to_string();
+        let error = |s: &str| ContentHash::from_string(s).unwrap_err().to_string();
+
+        let e = error(&format!("{}+", &hash[..21]));
+        assert!(e.contains("is not valid base64"), "{}", e);
+        assert!(e.contains("'+' at offset 21"), "{}", e);
+        let e = error(&format!("{}==", hash));
+        assert!(e.contains("'=' at offset 22"), "{}", e);
+
+        // 21 characters can't be whole bytes.
+        let e = error(&hash[..21]);
+        assert!(
+            e.starts_with(&format!("'{}' has an ambiguous length", &hash[..21])),
+            "{}",
+            e
+        );
+
+        // 20 characters decode cleanly, to too few bytes.
+        let e = error(&hash[..20]);
+        assert_eq!(
+            e,
+            format!(
+                "'{}' decodes to 15 bytes, but a binary 16 chunk is 16 bytes (22 base64 characters)",
+                &hash[..20]
+            )
+        );
+        let e = error(&"A".repeat(43));
+        assert!(
+            e.ends_with("decodes to 32 bytes, but a binary 16 chunk is 16 bytes (22 base64 characters) - 32 byte hashes are not supported yet"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    pub fn tagged_hashes_differ_by_domain() {
+        let prompt = ContentHash::from_content_tagged("prompt", b"hello");
+        let chunk = ContentHash::from_content_tagged("chunk", b"hello");
+        assert_ne!(prompt, chunk);
+        assert_ne!(prompt, ContentHash::from_content(b"hello"));
+        assert_eq!(prompt, ContentHash::from_content_tagged("prompt", b"hello"));
+        // The domain's end is marked, so it can't run on into the content.
+        assert_ne!(
+            ContentHash::from_content_tagged("ab", b"c"),
+            ContentHash::from_content_tagged("a", b"bc")
+        );
+    }
+
+    #[test]
+    pub fn hex_round_trip() {
+        let b = Binary16::from_u64s(0x0123456789abcdef, 0xfedcba9876543210);
+        assert_eq!(b.to_hex(), "efcdab89674523011032547698badcfe");
+        assert_eq!(Binary16::from_hex(&b.to_hex()).unwrap(), b);
+
+        let hash = ContentHash::from_content(b"hello");
+        assert_eq!(ContentHash::from_hex(&hash.to_hex()).unwrap(), hash);
+        assert_eq!(ContentHash::parse_any(&hash.to_hex()).unwrap(), hash);
+        assert_eq!(
+            ContentHash::from_hex(&hash.to_hex().to_uppercase()).unwrap(),
+            hash
+        );
+    }
+
+    #[test]
+    pub fn hex_is_validated() {
+        assert!(Binary16::from_hex("abcd").is_err());
+        assert!(Binary16::from_hex(&"0".repeat(34)).is_err());
+        assert!(Binary16::from_hex(&"g".repeat(32)).is_err());
+        assert!(Binary16::from_hex(&format!("+f{}", "0".repeat(30))).is_err());
+        // Multi-byte characters must not cause a panic when slicing.
+        assert!(Binary16::from_hex(&format!("{}\u{e9}", "0".repeat(30))).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Context;
+
+use crate::{binary16::ContentHash, file_history::FileHistoryEntry, text};
+
+/// What last changed a line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineOrigin {
+    /// A run of this command.
+    Run(String),
+    /// A version that didn't follow from the previous one, i.e. a change made outside
+    /// wrought that a later run started from.
+    Outside,
+    /// The local copy, edited since wrought last wrote it.
+    LocalChange,
+}
+
+impl std::fmt::Display for LineOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineOrigin::Run(command) => write!(f, "{}", command),
+            LineOrigin::Outside => write!(f, "(outside wrought)"),
+            LineOrigin::LocalChange => write!(f, "(local changes)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    pub origin: LineOrigin,
+    pub line: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Blame {
+    Lines(Vec<BlameLine>),
+    /// The current version isn't text.
+    Binary,
+    /// There is no current version - the file was deleted, or never existed.
+    Missing,
+}
+
+/// Works out what last changed each line of a file, by diffing each version in its
+/// history (see `file_history::file_history`) against the one before. `content` gives
+/// the content of a version - the local copy's too, for `LocalChanges` - or `None` if
+/// it isn't available.
+///
+/// A version that isn't text, or a change made outside wrought that wasn't stored,
+/// breaks the chain - the lines of the next version are all put down to the change
+/// that made it.
+pub fn blame(
+    entries: &[FileHistoryEntry],
+    content: &dyn Fn(&ContentHash) -> anyhow::Result<Option<Vec<u8>>>,
+) -> anyhow::Result<Blame> {
+    let required = |hash: &ContentHash| {
+        content(hash)?
+            .with_context(|| format!("content {} is missing from the content store", hash))
+    };
+    let mut current: Option<Vec<u8>> = None;
+    let mut lines: Vec<BlameLine> = vec![];
+    for entry in entries {
+        let (new, origin) = match entry {
+            FileHistoryEntry::StoredHash(hash, command) => {
+                (required(hash)?, LineOrigin::Run(command.0.clone()))
+            }
+            FileHistoryEntry::RenamedFrom {
+                hash: Some(hash),
+                command,
+                ..
+            } => (required(hash)?, LineOrigin::Run(command.0.clone())),
+            FileHistoryEntry::LocalChanges(hash) => (required(hash)?, LineOrigin::LocalChange),
+            FileHistoryEntry::UnknownHash(hash) => match content(hash)? {
+                Some(new) => (new, LineOrigin::Outside),
+                None => {
+                    current = None;
+                    continue;
+                }
+            },
+            FileHistoryEntry::RenamedFrom { hash: None, .. }
+            | FileHistoryEntry::RenamedTo { .. }
+            | FileHistoryEntry::Deleted
+            | FileHistoryEntry::DeletedBy(_) => {
+                current = None;
+                continue;
+            }
+            FileHistoryEntry::BrokenChain { .. } => continue,
+        };
+        if !text::looks_like_text(&new) {
+            current = Some(new);
+            lines.clear();
+            continue;
+        }
+        let new_text = String::from_utf8_lossy(&new);
+        lines = match current.as_deref().filter(|c| text::looks_like_text(c)) {
+            Some(old) => {
+                let old_text = String::from_utf8_lossy(old);
+                text::kept_lines(&old_text, &new_text)
+                    .into_iter()
+                    .zip(new_text.lines())
+                    .map(|(kept, line)| BlameLine {
+                        origin: match kept {
+                            Some(i) => lines[i].origin.clone(),
+                            None => origin.clone(),
+                        },
+                        line: line.to_string(),
+                    })
+                    .collect()
+            }
+            None => new_text
+                .lines()
+                .map(|line| BlameLine {
+                    origin: origin.clone(),
+                    line: line.to_string(),
+                })
+                .collect(),
+        };
+        current = Some(new);
+    }
+    Ok(match current {
+        None => Blame::Missing,
+        Some(c) if !text::looks_like_text(&c) => Blame::Binary,
+        Some(_) => Blame::Lines(lines),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{blame, Blame, BlameLine, LineOrigin};
+    use crate::{
+        binary16::ContentHash,
+        file_history::{EventLogCommand, FileHistoryEntry},
+    };
+
+    fn stored(versions: &mut BTreeMap<ContentHash, Vec<u8>>, content: &[u8]) -> ContentHash {
+        let hash = ContentHash::from_content(content);
+        versions.insert(hash.clone(), content.to_vec());
+        hash
+    }
+
+    #[test]
+    pub fn lines_are_put_down_to_the_run_that_last_changed_them() {
+        let mut versions = BTreeMap::new();
+        let v1 = stored(&mut versions, b"# Title\nintro\nbody\n");
+        let v2 = stored(&mut versions, b"# Title\nnew intro\nbody\nfooter\n");
+        let local = stored(
+            &mut versions,
+            b"# Title\nnew intro\nbody\nmy note\nfooter\n",
+        );
+        let content = |hash: &ContentHash| Ok(versions.get(hash).cloned());
+        let run = |hash: &ContentHash, command: &str| {
+            FileHistoryEntry::StoredHash(hash.clone(), EventLogCommand(command.to_string()))
+        };
+
+        let mut entries = vec![
+            run(&v1, "run-script v1.luau"),
+            run(&v2, "run-script v2.luau"),
+        ];
+        let v1_run = LineOrigin::Run("run-script v1.luau".to_string());
+        let v2_run = LineOrigin::Run("run-script v2.luau".to_string());
+        let origins = |blame: Blame| match blame {
+            Blame::Lines(lines) => lines.into_iter().map(|l| l.origin).collect::<Vec<_>>(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            origins(blame(&entries, &content).unwrap()),
+            [
+                v1_run.clone(),
+                v2_run.clone(),
+                v1_run.clone(),
+                v2_run.clone()
+            ]
+        );
+
+        entries.push(FileHistoryEntry::LocalChanges(local));
+        let Blame::Lines(lines) = blame(&entries, &content).unwrap() else {
+            panic!("expected lines");
+        };
+        assert_eq!(
+            lines[3],
+            BlameLine {
+                origin: LineOrigin::LocalChange,
+                line: "my note".to_string()
+            }
+        );
+        assert_eq!(lines[4].origin, v2_run);
+
+        // Deleting the file leaves nothing to blame.
+        entries.push(FileHistoryEntry::Deleted);
+        assert_eq!(blame(&entries, &content).unwrap(), Blame::Missing);
+    }
+
+    #[test]
+    pub fn binary_files_cannot_be_blamed() {
+        let mut versions = BTreeMap::new();
+        let text = stored(&mut versions, b"text\n");
+        let image = stored(&mut versions, b"\x89PNG\r\n\x1a\n\x00\x00");
+        let content = |hash: &ContentHash| Ok(versions.get(hash).cloned());
+        let entries = vec![
+            FileHistoryEntry::StoredHash(text, EventLogCommand("a".into())),
+            FileHistoryEntry::StoredHash(image, EventLogCommand("b".into())),
+        ];
+        assert_eq!(blame(&entries, &content).unwrap(), Blame::Binary);
+
+        // Missing content is an error, as with `diff`.
+        let e = blame(&entries, &|_| Ok(None)).unwrap_err();
+        assert!(
+            e.to_string().contains("missing from the content store"),
+            "{}",
+            e
+        );
+    }
+}
@@ -1,38 +1,299 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use crate::{
-    backend::Backend,
+    backend::{Backend, WriteOptions, CONTENT_DIR, INTERNAL_DIR, TEMP_DIR},
+    binary16::ContentHash,
+    calculate_file_hash,
+    clock::Clock,
+    event_log::EventLog,
     events::{
-        Event, EventGroup, GetMetadataEvent, ReadFileEvent, SetMetadataEvent, WriteFileEvent,
+        DeclareOutputEvent, Event, EventGroup, EventType, GetMetadataEvent, LogEvent, LogLevel,
+        ReadFileEvent, RenameFileEvent, SetMetadataEvent, WriteFileEvent,
     },
+    get_single_file_status,
+    ignore_rules::IgnoreRules,
     llm::LLM,
     metadata::{MetadataEntry, MetadataKey},
+    package_status::{PackageStatusEntryRepr, Severity},
+    progress::{ProgressEvent, ProgressSink},
+    project_root::normalize_project_relative_path,
+    project_status::{list_project_files, FileStatus},
+    run_context::RunContext,
+    run_summary::{FileChange, RunSummary},
+    settings::SCRIPT_VISIBLE_SETTINGS,
 };
 
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use ignore::gitignore::GitignoreBuilder;
+
 pub trait Bridge {
     fn write_file(&mut self, path: &Path, value: &[u8]) -> anyhow::Result<()>;
+    fn write_file_with_options(
+        &mut self,
+        path: &Path,
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> anyhow::Result<()>;
+    /// Writes `path` and sets each entry of `metadata` on it, e.g. `content_type` or
+    /// `generated_by`. The write and its metadata are recorded next to each other in
+    /// the run's event group. If any of it fails, none of it is kept.
+    fn write_file_with_metadata(
+        &mut self,
+        path: &Path,
+        value: &[u8],
+        metadata: &BTreeMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()>;
     fn read_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Moves a file within the project, recorded as a single rename so its history
+    /// follows it to the new path.
+    fn move_file(&mut self, from: &Path, to: &Path) -> anyhow::Result<()>;
+    /// Like `read_file`, but the read isn't recorded, so this run's outputs don't depend
+    /// on `path` - changing it later won't make them stale. Only for peeking at files
+    /// that don't affect what the script produces (e.g. for logging). Anything that
+    /// shapes the output must be read with `read_file`, or status can't tell when the
+    /// output is out of date.
+    fn read_file_untracked(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Reads `path` relative to the running script's package directory, for templates
+    /// and data shipped with the package. Errors if the script isn't part of a package,
+    /// or the path leads out of the package. Like `read_file`, the read is recorded
+    /// (under the file's path in the project), so updating the package makes what the
+    /// run wrote stale.
+    fn read_package_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Like `read_file`, but split into lines (without the line endings).
+    fn read_lines(&mut self, path: &Path) -> anyhow::Result<Option<Vec<String>>>;
+    /// Reads `path` (recording it as an input, like `read_file`), along with the content
+    /// it had when last read by a script. `previous` is `None` if it was never read, was
+    /// missing then, or that content is no longer in the content store.
+    /// Returns `None` if the file doesn't currently exist.
+    fn read_tracked_input(&mut self, path: &Path) -> anyhow::Result<Option<TrackedInput>>;
+    /// The same status `wrought file-status` reports. Doesn't count as reading the file.
+    fn file_status(&mut self, path: &Path) -> anyhow::Result<FileStatus>;
+    /// The hash (in its usual base64 form) recorded by the last tracked write of `path`,
+    /// or `None` if wrought has never written it, or the last write deleted it.
+    /// This is what wrought last wrote, not a hash of what's on disk now - if the
+    /// file has been edited since, they will differ. Doesn't count as reading the file.
+    fn last_hash(&mut self, path: &Path) -> anyhow::Result<Option<String>>;
+    /// The value as a string - non-string values as JSON, e.g. `42` or `["a","b"]`.
     fn get_metadata(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<String>>;
+    /// Like `get_metadata`, but values keep the type they were set with.
+    fn get_metadata_typed(
+        &mut self,
+        path: &Path,
+        key: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>>;
     fn set_metadata(&mut self, path: &Path, key: &str, value: &str) -> anyhow::Result<()>;
+    /// Like `set_metadata`, for values that aren't strings.
+    fn set_metadata_typed(
+        &mut self,
+        path: &Path,
+        key: &str,
+        value: serde_json::Value,
+    ) -> anyhow::Result<()>;
+    /// Sets `key` to `value` on every project file matching `pattern`, a gitignore-style
+    /// glob like those in `.wroughtignore`. Files that `.wroughtignore` excludes are never
+    /// matched. Returns the matching paths - matching nothing does nothing, unless the
+    /// bridge is strict, when it is an error.
+    fn set_metadata_glob(
+        &mut self,
+        pattern: &str,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<Vec<PathBuf>>;
+    /// Every path's metadata, as path -> key -> value. Each entry is recorded as read.
+    fn all_metadata(&mut self) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<String, String>>>;
     fn ai_query(&mut self, query: &str) -> anyhow::Result<String>;
+    /// Reports `message` to the user and records it in the event group, so it can be
+    /// seen later in `wrought log`.
+    fn log(&mut self, level: LogLevel, message: &str) -> anyhow::Result<()>;
+    /// Keeps something the script printed with the run, so `wrought log <group>` can
+    /// show it. Showing it as it is printed is up to the runtime.
+    fn add_output(&mut self, output: &[u8]) -> anyhow::Result<()>;
+    /// Adds (or replaces) the entry `name` in the running package's status, so it shows
+    /// up in `wrought status`. Errors if the script isn't part of a package.
+    fn set_status(&mut self, name: &str, content: &str) -> anyhow::Result<()>;
+    /// Read-only access to the project settings that scripts are allowed to see.
+    /// Errors for keys that are not script visible, `None` for ones that are just unset.
+    fn get_config(&mut self, key: &str) -> anyhow::Result<Option<String>>;
     fn get_event_group(&self) -> Option<EventGroup>;
+    /// Adds this run's events to the event log as a group of their own, once the script
+    /// has finished. Returns the group as recorded, or `None` if the run did nothing.
+    fn record_run(&mut self) -> anyhow::Result<Option<EventGroup>>;
+    /// Claims `path` as an output of this run without writing it, e.g. when it is
+    /// already up to date. Status then treats its current content as what the run
+    /// produced. Errors if the file doesn't exist.
+    fn declare_output(&mut self, path: &Path) -> anyhow::Result<()>;
+    /// A scratch directory for this run, relative to the project root. It is created on
+    /// first use and removed when the run ends. Reads and writes inside it aren't
+    /// recorded, so they don't affect the project's status.
+    fn temp_dir(&mut self) -> anyhow::Result<PathBuf>;
+    /// Earlier recorded runs whose command matches `command_prefix` (see
+    /// `EventLog::groups_for_command`), oldest first. `None` means runs of this same
+    /// script, i.e. an exact match on this run's command. Doesn't count as a read.
+    fn previous_runs(&mut self, command_prefix: Option<&str>) -> anyhow::Result<Vec<PreviousRun>>;
+    /// Labels this run in `wrought log`, e.g. "regenerate all pages". It is added to
+    /// the run's command after a ` # `, so runs of the script still match each other.
+    /// The last call wins, and an empty description removes the label.
+    fn set_run_description(&mut self, description: &str) -> anyhow::Result<()>;
+    /// Keeps `content` in the content store, e.g. a run's output. Nothing is recorded,
+    /// as no file is written.
+    fn store_content(&mut self, content: &[u8]) -> anyhow::Result<ContentHash>;
+    /// A template render kept by `cache_render`, in this run or an earlier one - if its
+    /// output is still stored.
+    fn cached_render(&mut self, key: &ContentHash) -> anyhow::Result<Option<String>>;
+    /// Keeps `rendered` in the content store as the render for `key`.
+    fn cache_render(&mut self, key: &ContentHash, rendered: &str) -> anyhow::Result<()>;
+    /// Fails the run on purpose, e.g. on bad input. The failure is recorded in the event
+    /// group, and the runtime then ends the script with it as the run's error - even if
+    /// the script catches the error this call leads to.
+    fn fail(&mut self, failure: ScriptFailure) -> anyhow::Result<()>;
+    /// The first failure passed to `fail`, if any.
+    fn failure(&self) -> Option<ScriptFailure>;
+}
+
+/// What `read_file` does when a script reads something that isn't a project file,
+/// i.e. a path outside the project root or inside `.wrought`. Such reads make the
+/// script's output depend on things wrought doesn't track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputCheck {
+    #[default]
+    Off,
+    /// Log a warning, but allow the read.
+    Warn,
+    /// Refuse the read.
+    Error,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BridgeOptions {
+    pub input_check: InputCheck,
+    /// The package the script being run belongs to, if any. Status entries from
+    /// `set_status` go in this package's `status` directory.
+    pub package: Option<String>,
+    /// Refuse anything that would change the project. The backend refuses writes
+    /// itself, this covers what the bridge writes directly.
+    pub read_only: bool,
+    /// What the run is recorded as in the event log, e.g. `run-script blog/build.luau`.
+    pub command: Option<String>,
+    /// Set by `--strict`. Calls that are probably mistakes, like a glob that matches
+    /// nothing, fail instead of quietly doing nothing.
+    pub strict: bool,
+    /// A preview run, whose writes go somewhere other than the project and which isn't
+    /// recorded. What the bridge writes directly, like status, is refused.
+    pub preview: bool,
+}
+
+/// A run found by `previous_runs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviousRun {
+    pub id: u64,
+    pub command: String,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Files the run wrote (and didn't then delete) or declared as outputs.
+    pub outputs: Vec<PathBuf>,
+}
+
+impl PreviousRun {
+    pub fn from_event_group(group: &EventGroup) -> PreviousRun {
+        let summary = RunSummary::from_event_group(group);
+        let mut outputs: BTreeSet<PathBuf> = summary
+            .written
+            .into_iter()
+            .filter(|(_, change)| *change != FileChange::Deleted)
+            .map(|(path, _)| path)
+            .collect();
+        for event in &group.events {
+            if let EventType::DeclareOutput(e) = &event.event_type {
+                outputs.insert(e.path.clone());
+            }
+        }
+        PreviousRun {
+            id: group.id,
+            command: group.command.clone(),
+            created_at: group.created_at,
+            outputs: outputs.into_iter().collect(),
+        }
+    }
+}
+
+/// A run a script ended on purpose, with `fail`. Its message is the whole error, unlike
+/// an unexpected script error, which comes with a traceback and context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptFailure {
+    pub message: String,
+    /// What `wrought` exits with. Never 0.
+    pub exit_code: i32,
+}
+
+impl std::fmt::Display for ScriptFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ScriptFailure {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedInput {
+    pub current: Vec<u8>,
+    pub previous: Option<Vec<u8>>,
 }
 
 pub struct SimpleBridge {
     pub backend: Arc<Mutex<dyn Backend + Send + 'static>>,
-    // pub event_log: Arc<Mutex< dyn EventLog >>,
+    /// Read only while the script runs - the events it generates are collected in `run`
+    /// and only added to the log by `record_run`, once it has finished.
+    pub event_log: Arc<Mutex<dyn EventLog + Send + 'static>>,
+    /// Direct access to the project files, for checking their status.
+    pub fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
     pub llm: Arc<Mutex<dyn LLM + Send + 'static>>,
     pub root: PathBuf,
+    /// Only the script visible settings - never secrets.
+    pub config: BTreeMap<String, String>,
+
+    pub run: RunContext,
 
-    pub event_group: EventGroup,
+    pub progress: Option<Arc<dyn ProgressSink + Send + Sync>>,
+    /// How many AI queries this run has made, for progress reporting.
+    pub ai_query_count: usize,
+    pub options: BridgeOptions,
+    /// Times the event group, from when the script first does something.
+    pub clock: Arc<dyn Clock>,
+    /// This run's scratch directory, once a script has asked for it.
+    pub temp_dir: Option<PathBuf>,
+    /// Set by the first call to `fail`.
+    pub failure: Option<ScriptFailure>,
 }
 
 impl Bridge for SimpleBridge {
     fn write_file(&mut self, path: &Path, value: &[u8]) -> anyhow::Result<()> {
-        let (before_hash, hash) = self.backend.lock().unwrap().write_file(path, value)?;
+        self.write_file_with_options(path, value, &WriteOptions::default())
+    }
+
+    fn write_file_with_options(
+        &mut self,
+        path: &Path,
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        if let Some(path) = self.temp_path(path) {
+            let mut fs = self.fs.lock().unwrap();
+            if let Some(parent) = path.parent() {
+                fs.create_dir_all(parent)?;
+            }
+            fs.writer(&path)?.write_all(value)?;
+            return Ok(());
+        }
+        let (before_hash, hash) = self
+            .backend
+            .lock()
+            .unwrap()
+            .write_file_with_options(path, value, options)?;
         let after_hash = Some(hash);
         let event = WriteFileEvent {
             path: path.to_path_buf(),
@@ -43,7 +304,54 @@ impl Bridge for SimpleBridge {
         Ok(())
     }
 
+    fn write_file_with_metadata(
+        &mut self,
+        path: &Path,
+        value: &[u8],
+        metadata: &BTreeMap<String, serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        if self.temp_path(path).is_some() {
+            bail!("files in the temp dir can't have metadata - use write_file");
+        }
+        // Metadata can always be put back as it was, unlike a newly written file, so it
+        // is set first and undone if anything fails - leaving neither half done.
+        let start = self.run.event_group.events.len();
+        let result = metadata
+            .iter()
+            .try_for_each(|(key, value)| self.set_metadata_typed(path, key, value.clone()))
+            .and_then(|()| self.write_file(path, value));
+        if let Err(e) = result {
+            let undone = self.run.event_group.events.split_off(start);
+            for event in undone.iter().rev() {
+                if let EventType::SetMetadata(md) = &event.event_type {
+                    let backend = self.backend.lock().unwrap();
+                    if let Err(undo) = backend.set_metadata(path, &md.key, &md.before_value) {
+                        return Err(undo.context(format!(
+                            "'{}' was not written ({:#}) and restoring its metadata failed",
+                            path.display(),
+                            e
+                        )));
+                    }
+                }
+            }
+            return Err(e);
+        }
+        // Recorded as the write followed by its metadata.
+        let write = self.run.event_group.events.pop().unwrap();
+        self.run.event_group.events.insert(start, write);
+        Ok(())
+    }
+
     fn read_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(path) = self.temp_path(path) {
+            let Some(mut reader) = self.fs.lock().unwrap().reader_if_exists(&path)? else {
+                return Ok(None);
+            };
+            let mut content = vec![];
+            reader.read_to_end(&mut content)?;
+            return Ok(Some(content));
+        }
+        self.check_input(path)?;
         let v = self.backend.lock().unwrap().read_file(path)?;
         let (content_hash, content) = match v {
             Some((content_hash, content)) => (Some(content_hash), Some(content)),
@@ -57,22 +365,147 @@ impl Bridge for SimpleBridge {
         Ok(content)
     }
 
-    fn get_metadata(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<String>> {
-        let key = MetadataKey::from(key);
-        let v = self.backend.lock().unwrap().get_metadata(path, &key)?;
-        let event = GetMetadataEvent {
-            path: path.to_path_buf(),
-            key: key.clone(),
-            value: v.clone(),
+    fn move_file(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        if self.temp_path(from).is_some() || self.temp_path(to).is_some() {
+            bail!("move_file can't move files into or out of the temp dir - use read_file and write_file");
+        }
+        let from = normalize_project_relative_path(from)?;
+        let to = normalize_project_relative_path(to)?;
+        let hash = self.backend.lock().unwrap().rename_file(&from, &to)?;
+        let event = RenameFileEvent {
+            from,
+            to,
+            hash: Some(hash),
+        };
+        self.add_event(event.into());
+        Ok(())
+    }
+
+    /// Read straight from the filesystem - the backend would keep a copy of what was read
+    /// in the content store.
+    fn read_file_untracked(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        let p = match self.temp_path(path) {
+            Some(p) => p,
+            None => {
+                self.check_input(path)?;
+                self.root.join(path)
+            }
+        };
+        let Some(mut reader) = self.fs.lock().unwrap().reader_if_exists(&p)? else {
+            return Ok(None);
+        };
+        let mut content = vec![];
+        reader.read_to_end(&mut content)?;
+        Ok(Some(content))
+    }
+
+    fn read_package_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(package) = &self.options.package else {
+            bail!(
+                "can not read package file '{}' - the script is not part of a package",
+                path.display()
+            );
+        };
+        let relative = normalize_project_relative_path(path).map_err(|_| {
+            anyhow!(
+                "can not read package file '{}' - it is outside package '{}'",
+                path.display(),
+                package
+            )
+        })?;
+        let path = Path::new(INTERNAL_DIR)
+            .join("packages")
+            .join(package)
+            .join(relative);
+        let content = match self
+            .fs
+            .lock()
+            .unwrap()
+            .reader_if_exists(&self.root.join(&path))?
+        {
+            Some(mut reader) => {
+                let mut content = vec![];
+                reader.read_to_end(&mut content)?;
+                Some(content)
+            }
+            None => None,
+        };
+        let event = ReadFileEvent {
+            path,
+            hash: content.as_deref().map(ContentHash::from_content),
         };
         self.add_event(event.into());
-        Ok(v.map(|v| v.as_string()))
+        Ok(content)
+    }
+
+    fn read_lines(&mut self, path: &Path) -> anyhow::Result<Option<Vec<String>>> {
+        // Going via read_file means this records the same ReadFileEvent.
+        let Some(content) = self.read_file(path)? else {
+            return Ok(None);
+        };
+        let content = String::from_utf8(content)
+            .with_context(|| format!("'{}' is not valid UTF-8", path.display()))?;
+        Ok(Some(content.lines().map(|l| l.to_string()).collect()))
+    }
+
+    fn read_tracked_input(&mut self, path: &Path) -> anyhow::Result<Option<TrackedInput>> {
+        let last_read = self.event_log.lock().unwrap().get_last_read_event(path)?;
+        let previous_hash = match last_read.map(|e| e.event_type) {
+            Some(EventType::ReadFile(e)) => e.hash,
+            _ => None,
+        };
+        let Some(current) = self.read_file(path)? else {
+            return Ok(None);
+        };
+        let previous = match previous_hash {
+            Some(hash) => self.backend.lock().unwrap().read_stored_content(&hash)?,
+            None => None,
+        };
+        Ok(Some(TrackedInput { current, previous }))
+    }
+
+    fn file_status(&mut self, path: &Path) -> anyhow::Result<FileStatus> {
+        let status = get_single_file_status(
+            &*self.fs.lock().unwrap(),
+            &self.root,
+            &*self.event_log.lock().unwrap(),
+            path,
+        )?;
+        Ok(status.file_status())
+    }
+
+    fn last_hash(&mut self, path: &Path) -> anyhow::Result<Option<String>> {
+        let last_write = self.event_log.lock().unwrap().get_last_write_event(path)?;
+        Ok(match last_write.map(|e| e.event_type) {
+            Some(EventType::WriteFile(e)) => e.after_hash.map(|h| h.to_string()),
+            _ => None,
+        })
+    }
+
+    fn get_metadata(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.read_metadata(path, key)?.map(|v| v.as_string()))
+    }
+
+    fn get_metadata_typed(
+        &mut self,
+        path: &Path,
+        key: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        Ok(self.read_metadata(path, key)?.map(|v| v.as_json().clone()))
     }
 
     fn set_metadata(&mut self, path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+        self.set_metadata_typed(path, key, serde_json::Value::String(value.to_string()))
+    }
+
+    fn set_metadata_typed(
+        &mut self,
+        path: &Path,
+        key: &str,
+        value: serde_json::Value,
+    ) -> anyhow::Result<()> {
         let key = MetadataKey::from(key);
-        let v = MetadataEntry::from(value);
-        let v = Some(v);
+        let v = Some(MetadataEntry::from(value));
         let before_value = self.backend.lock().unwrap().set_metadata(path, &key, &v)?;
         let event = SetMetadataEvent {
             path: path.to_path_buf(),
@@ -84,20 +517,1374 @@ impl Bridge for SimpleBridge {
         Ok(())
     }
 
+    fn set_metadata_glob(
+        &mut self,
+        pattern: &str,
+        key: &str,
+        value: &str,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut builder = GitignoreBuilder::new("");
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("invalid glob '{}'", pattern))?;
+        let matcher = builder.build()?;
+        let files = {
+            let fs = self.fs.lock().unwrap();
+            let ignore = IgnoreRules::load(&*fs, &self.root)?;
+            list_project_files(&*fs, &self.root, &ignore)?
+        };
+        let matches: Vec<PathBuf> = files
+            .into_iter()
+            .filter(|p| matcher.matched_path_or_any_parents(p, false).is_ignore())
+            .collect();
+        if matches.is_empty() && self.options.strict {
+            bail!("'{}' does not match any files", pattern);
+        }
+        // One event per file, just as if the script had set each of them itself.
+        for path in &matches {
+            self.set_metadata(path, key, value)?;
+        }
+        Ok(matches)
+    }
+
+    fn all_metadata(&mut self) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<String, String>>> {
+        let all = self.backend.lock().unwrap().get_all_metadata()?;
+        let mut result = BTreeMap::new();
+        for (path, entries) in all {
+            let mut values = BTreeMap::new();
+            for (key, value) in entries {
+                values.insert(key.as_string(), value.as_string());
+                let event = GetMetadataEvent {
+                    path: path.clone(),
+                    key,
+                    value: Some(value),
+                };
+                self.add_event(event.into());
+            }
+            result.insert(path, values);
+        }
+        Ok(result)
+    }
+
+    fn previous_runs(&mut self, command_prefix: Option<&str>) -> anyhow::Result<Vec<PreviousRun>> {
+        let groups = match command_prefix {
+            Some(prefix) => self.event_log.lock().unwrap().groups_for_command(prefix)?,
+            None => {
+                let command = self
+                    .options
+                    .command
+                    .as_deref()
+                    .context("this run has no command to look for previous runs of")?;
+                let mut groups = self.event_log.lock().unwrap().groups_for_command(command)?;
+                let described = format!("{} # ", command);
+                groups.retain(|g| g.command == command || g.command.starts_with(&described));
+                groups
+            }
+        };
+        Ok(groups.iter().map(PreviousRun::from_event_group).collect())
+    }
+
+    fn set_run_description(&mut self, description: &str) -> anyhow::Result<()> {
+        let command = self.options.command.clone().unwrap_or_default();
+        self.run.event_group.command = match (command.is_empty(), description.is_empty()) {
+            (_, true) => command,
+            (true, false) => description.to_string(),
+            (false, false) => format!("{} # {}", command, description),
+        };
+        Ok(())
+    }
+
+    fn store_content(&mut self, content: &[u8]) -> anyhow::Result<ContentHash> {
+        self.backend.lock().unwrap().store_content(content)
+    }
+
+    fn cached_render(&mut self, key: &ContentHash) -> anyhow::Result<Option<String>> {
+        let backend = self.backend.lock().unwrap();
+        let Some(hash) = backend.get_render_cache_entry(key)? else {
+            return Ok(None);
+        };
+        match backend.read_stored_content(&hash)? {
+            Some(content) => Ok(Some(String::from_utf8(content)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn cache_render(&mut self, key: &ContentHash, rendered: &str) -> anyhow::Result<()> {
+        let backend = self.backend.lock().unwrap();
+        let hash = backend.store_content(rendered.as_bytes())?;
+        backend.set_render_cache_entry(key, &hash)
+    }
+
+    fn fail(&mut self, failure: ScriptFailure) -> anyhow::Result<()> {
+        if failure.exit_code == 0 {
+            bail!("a failed run needs a non-zero exit code");
+        }
+        if self.failure.is_some() {
+            return Ok(());
+        }
+        self.add_event(
+            LogEvent {
+                level: LogLevel::Error,
+                message: failure.message.clone(),
+            }
+            .into(),
+        );
+        self.failure = Some(failure);
+        Ok(())
+    }
+
+    fn failure(&self) -> Option<ScriptFailure> {
+        self.failure.clone()
+    }
+
     fn get_event_group(&self) -> Option<EventGroup> {
-        if self.event_group.events.is_empty() {
-            return None;
+        self.run.event_group()
+    }
+
+    fn record_run(&mut self) -> anyhow::Result<Option<EventGroup>> {
+        // Output from a run that did nothing isn't kept, as the run isn't recorded.
+        if self.run.event_group().is_some() {
+            if let Some(output) = self.run.take_output() {
+                self.run.event_group.output = Some(self.store_content(&output)?);
+            }
         }
-        Some(self.event_group.clone())
+        self.run.record(&mut *self.event_log.lock().unwrap())
     }
 
     fn ai_query(&mut self, query: &str) -> anyhow::Result<String> {
-        self.llm.lock().unwrap().query(query)
+        self.ai_query_count += 1;
+        let number = self.ai_query_count;
+        self.report_progress(ProgressEvent::LlmQueryStarted { number });
+        let result = self.llm.lock().unwrap().query(query);
+        self.report_progress(ProgressEvent::LlmQueryFinished { number });
+        result
+    }
+
+    fn log(&mut self, level: LogLevel, message: &str) -> anyhow::Result<()> {
+        eprintln!("{}: {}", level, message);
+        let event = LogEvent {
+            level,
+            message: message.to_string(),
+        };
+        self.add_event(event.into());
+        Ok(())
+    }
+
+    fn add_output(&mut self, output: &[u8]) -> anyhow::Result<()> {
+        self.run.add_output(output);
+        Ok(())
+    }
+
+    fn get_config(&mut self, key: &str) -> anyhow::Result<Option<String>> {
+        if !SCRIPT_VISIBLE_SETTINGS.contains(&key) {
+            bail!("setting '{}' is not available to scripts", key);
+        }
+        Ok(self.config.get(key).cloned())
+    }
+
+    fn declare_output(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = normalize_project_relative_path(path)?;
+        let hash = calculate_file_hash(&*self.fs.lock().unwrap(), &self.root.join(&path))?
+            .with_context(|| {
+                format!(
+                    "can not declare '{}' as an output - it does not exist",
+                    path.display()
+                )
+            })?;
+        self.add_event(DeclareOutputEvent { path, hash }.into());
+        Ok(())
+    }
+
+    fn temp_dir(&mut self) -> anyhow::Result<PathBuf> {
+        if let Some(dir) = &self.temp_dir {
+            return Ok(dir.clone());
+        }
+        if self.options.read_only {
+            bail!("can not make a scratch directory - the project was opened read-only");
+        }
+        let run_id = format!(
+            "{}-{}",
+            self.clock.now().format("%Y%m%dT%H%M%S%.3f"),
+            std::process::id()
+        );
+        let dir = PathBuf::from(TEMP_DIR).join(run_id);
+        self.fs
+            .lock()
+            .unwrap()
+            .create_dir_all(&self.root.join(&dir))?;
+        self.temp_dir = Some(dir.clone());
+        Ok(dir)
+    }
+
+    fn set_status(&mut self, name: &str, content: &str) -> anyhow::Result<()> {
+        if self.options.read_only {
+            bail!(
+                "can not set status '{}' - the project was opened read-only",
+                name
+            );
+        }
+        if self.options.preview {
+            bail!(
+                "can not set status '{}' in a preview run - it would change the project",
+                name
+            );
+        }
+        let Some(package) = &self.options.package else {
+            bail!(
+                "can not set status '{}' - the script is not part of a package",
+                name
+            );
+        };
+        // The name becomes a file name, so keep it to something that can't escape the
+        // status directory.
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            bail!(
+                "invalid status name '{}' - use only letters, digits, '_' and '-'",
+                name
+            );
+        }
+        let entry = PackageStatusEntryRepr {
+            title: name.to_string(),
+            status: content.to_string(),
+            next_steps: vec![],
+            severity: Severity::default(),
+        };
+        let status_dir = self
+            .root
+            .join(INTERNAL_DIR)
+            .join("packages")
+            .join(package)
+            .join("status");
+        let mut fs = self.fs.lock().unwrap();
+        fs.create_dir_all(&status_dir)?;
+        fs.writer(&status_dir.join(format!("{}.toml", name)))?
+            .write_all(toml::to_string(&entry)?.as_bytes())?;
+        Ok(())
     }
 }
 
 impl SimpleBridge {
+    /// Removes the run's scratch directory, if it made one. This also happens when the
+    /// bridge is dropped, so a failing script doesn't leave it behind.
+    ///
+    /// Xfs has no way to remove files, so this always acts on the real filesystem.
+    pub fn remove_temp_dir(&mut self) -> anyhow::Result<()> {
+        let Some(dir) = self.temp_dir.take() else {
+            return Ok(());
+        };
+        let dir = self.root.join(dir);
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("unable to remove {}", dir.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Where `path` is on disk, if it is inside this run's scratch directory.
+    fn temp_path(&self, path: &Path) -> Option<PathBuf> {
+        let temp_dir = self.temp_dir.as_ref()?;
+        let path = normalize_project_relative_path(path).ok()?;
+        path.starts_with(temp_dir).then(|| self.root.join(path))
+    }
+
+    /// Reads `key` of `path`, recording the read.
+    fn read_metadata(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<MetadataEntry>> {
+        let key = MetadataKey::from(key);
+        let v = self.backend.lock().unwrap().get_metadata(path, &key)?;
+        let event = GetMetadataEvent {
+            path: path.to_path_buf(),
+            key,
+            value: v.clone(),
+        };
+        self.add_event(event.into());
+        Ok(v)
+    }
+
     pub fn add_event(&mut self, event: Event) {
-        self.event_group.events.push(event);
+        self.run.add_event(event, &*self.clock);
+    }
+
+    /// Applies `options.input_check` to a read of `path`. Reading the content store directly
+    /// is refused whenever checking is on - its files are only meaningful by hash.
+    fn check_input(&mut self, path: &Path) -> anyhow::Result<()> {
+        if self.options.input_check == InputCheck::Off {
+            return Ok(());
+        }
+        let problem = match normalize_project_relative_path(path) {
+            Err(_) => "is outside the project",
+            Ok(p) if p.starts_with(CONTENT_DIR) => {
+                bail!(
+                    "refusing to read '{}' - scripts must not read wrought's content store directly",
+                    path.display()
+                )
+            }
+            Ok(p) if p.starts_with(INTERNAL_DIR) => "is inside wrought's internal directory",
+            Ok(_) => return Ok(()),
+        };
+        let message = format!(
+            "script input '{}' {}, so its changes won't be tracked",
+            path.display(),
+            problem
+        );
+        match self.options.input_check {
+            InputCheck::Error => bail!(message),
+            _ => self.log(LogLevel::Warn, &message),
+        }
+    }
+
+    fn report_progress(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.on_progress(event);
+        }
+    }
+}
+
+impl Drop for SimpleBridge {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove_temp_dir() {
+            eprintln!("warning: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::{DateTime, Utc};
+    use mockall::predicate;
+
+    use crate::{
+        backend::{test_utils::MockBackend, BackendOptions, SimpleBackend},
+        binary16::ContentHash,
+        clock::FixedClock,
+        content_store::{ContentStore, FileSystemContentStore},
+        event_log::{test_utils::MockEventLog, EventLog, SQLiteEventLog},
+        events::{
+            DeclareOutputEvent, Event, EventGroup, EventType, LogLevel, ReadFileEvent,
+            WriteFileEvent,
+        },
+        llm::InvalidLLM,
+        metadata::{MetadataEntry, MetadataKey},
+        package_status::Package,
+        progress::{test_utils::RecordingProgress, ProgressEvent},
+        project_status::FileStatus,
+        run_context::RunContext,
+        scripting_luau,
+    };
+
+    use super::{Bridge, BridgeOptions, InputCheck, SimpleBridge, TrackedInput};
+
+    pub fn test_time() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    pub fn test_bridge(backend: MockBackend, config: BTreeMap<String, String>) -> SimpleBridge {
+        SimpleBridge {
+            backend: Arc::new(Mutex::new(backend)),
+            event_log: Arc::new(Mutex::new(MockEventLog::default())),
+            fs: Arc::new(Mutex::new(xfs::mockfs::MockFS::new())),
+            llm: Arc::new(Mutex::new(InvalidLLM::create_with_error_message(
+                "no LLM in tests",
+            ))),
+            root: PathBuf::from("project"),
+            config,
+            run: RunContext::new(None),
+            progress: None,
+            ai_query_count: 0,
+            options: BridgeOptions::default(),
+            clock: Arc::new(FixedClock(test_time())),
+            temp_dir: None,
+            failure: None,
+        }
+    }
+
+    #[test]
+    pub fn temp_dir_is_untracked_and_removed_when_the_run_ends() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.fs = Arc::new(Mutex::new(xfs::OsFs {}));
+        bridge.root = root.clone();
+
+        let dir = bridge.temp_dir().unwrap();
+        assert!(dir.starts_with(".wrought/tmp"));
+        assert_eq!(bridge.temp_dir().unwrap(), dir);
+        assert!(root.join(&dir).is_dir());
+
+        // The backend would panic if these reached it.
+        let scratch = dir.join("parts/a.txt");
+        bridge.write_file(&scratch, b"scratch").unwrap();
+        assert_eq!(
+            bridge.read_file(&scratch).unwrap(),
+            Some(b"scratch".to_vec())
+        );
+        assert_eq!(bridge.read_file(&dir.join("missing.txt")).unwrap(), None);
+        assert!(bridge.get_event_group().is_none());
+
+        drop(bridge);
+        assert!(!root.join(&dir).exists());
+    }
+
+    #[test]
+    pub fn scripts_can_not_change_a_read_only_project() {
+        use xfs::Xfs;
+
+        let root = PathBuf::from("project");
+        let script = root.join(".wrought/packages/site/build.luau");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.create_dir_all(&root.join(".wrought/content")).unwrap();
+        fs.add_r(&root.join("in.md"), b"input".to_vec()).unwrap();
+        fs.add_r(
+            &script,
+            b"local input = read_file('in.md')\nwrite_file('out.md', input)\n".to_vec(),
+        )
+        .unwrap();
+        let fs = Arc::new(Mutex::new(fs));
+        let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
+            fs.clone(),
+            root.join(".wrought/content"),
+        )));
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.backend = Arc::new(Mutex::new(SimpleBackend {
+            fs: fs.clone(),
+            os_fs: false,
+            root: root.clone(),
+            content_store: content_store.clone(),
+            options: BackendOptions {
+                read_only: true,
+                ..BackendOptions::default()
+            },
+        }));
+        bridge.fs = fs.clone();
+        bridge.options.read_only = true;
+
+        let result = scripting_luau::run_script(Arc::new(Mutex::new(bridge)), fs.clone(), &script);
+        let e = result.unwrap_err();
+        assert!(format!("{:#}", e).contains("read-only"), "{:#}", e);
+        assert!(!fs.lock().unwrap().exists(&root.join("out.md")));
+        assert_eq!(content_store.lock().unwrap().stats().unwrap().objects, 0);
+    }
+
+    #[test]
+    pub fn declare_output_records_the_current_content() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(&PathBuf::from("project/out.md"), b"up to date".to_vec())
+            .unwrap();
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.fs = Arc::new(Mutex::new(fs));
+
+        bridge.declare_output(&PathBuf::from("./out.md")).unwrap();
+        let e = bridge
+            .declare_output(&PathBuf::from("missing.md"))
+            .unwrap_err();
+        assert!(e.to_string().contains("does not exist"), "{}", e);
+
+        let events: Vec<_> = bridge
+            .get_event_group()
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|e| e.event_type)
+            .collect();
+        assert_eq!(
+            events,
+            vec![EventType::DeclareOutput(DeclareOutputEvent {
+                path: PathBuf::from("out.md"),
+                hash: ContentHash::from_content(b"up to date"),
+            })]
+        );
+    }
+
+    #[test]
+    pub fn event_group_is_timed_by_the_clock() {
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        assert!(bridge.get_event_group().is_none());
+        bridge.log(LogLevel::Info, "started").unwrap();
+        let group = bridge.get_event_group().unwrap();
+        assert_eq!(group.created_at, Some(test_time()));
+    }
+
+    #[test]
+    pub fn set_status_shows_up_in_the_package_status() {
+        let fs = Arc::new(Mutex::new(xfs::mockfs::MockFS::new()));
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.fs = fs.clone();
+
+        // Only scripts in a package have somewhere to put their status.
+        assert!(bridge.set_status("summary", "done").is_err());
+
+        bridge.options.package = Some("site".to_string());
+        bridge
+            .set_status("summary", "generated 12 pages, 3 warnings")
+            .unwrap();
+        for bad_name in ["", "../summary", "a/b", ".hidden"] {
+            assert!(bridge.set_status(bad_name, "x").is_err(), "{:?}", bad_name);
+        }
+        bridge.options.preview = true;
+        assert!(bridge.set_status("summary", "previewed").is_err());
+
+        let package = Package {
+            path: PathBuf::from("project/.wrought/packages/site"),
+        };
+        let status = package.status(&*fs.lock().unwrap());
+        assert_eq!(status.entries.len(), 1);
+        let entry = status.entries[0].as_ref().unwrap();
+        assert_eq!(entry.name(), "summary.toml");
+        assert_eq!(entry.title, "summary");
+        assert_eq!(entry.status, "generated 12 pages, 3 warnings");
+    }
+
+    #[test]
+    pub fn get_config_never_exposes_secrets() {
+        // Even if it somehow ended up in the config, the api key must not be readable.
+        let config = BTreeMap::from([
+            ("openai_model".to_string(), "gpt-4o-mini".to_string()),
+            ("openai_api_key".to_string(), "sk-secret".to_string()),
+        ]);
+        let mut bridge = test_bridge(MockBackend::default(), config);
+
+        assert!(bridge.get_config("openai_api_key").is_err());
+        assert_eq!(
+            bridge.get_config("openai_model").unwrap().as_deref(),
+            Some("gpt-4o-mini")
+        );
+        assert_eq!(bridge.get_config("project_name").unwrap(), None);
+    }
+
+    #[test]
+    pub fn untracked_reads_are_not_recorded() {
+        let mut backend = MockBackend::default();
+        // Only the tracked read goes through the backend - and so into the content store.
+        backend
+            .expect_read_file()
+            .with(predicate::eq(PathBuf::from("notes.md")))
+            .times(1)
+            .returning(|_| {
+                Ok(Some((
+                    ContentHash::from_content(b"notes"),
+                    b"notes".to_vec(),
+                )))
+            });
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(&PathBuf::from("project/notes.md"), b"notes".to_vec())
+            .unwrap();
+        bridge.fs = Arc::new(Mutex::new(fs));
+
+        assert_eq!(
+            bridge
+                .read_file_untracked(&PathBuf::from("notes.md"))
+                .unwrap(),
+            Some(b"notes".to_vec())
+        );
+        assert!(bridge.run.event_group.events.is_empty());
+        assert!(bridge.get_event_group().is_none());
+
+        bridge.read_file(&PathBuf::from("notes.md")).unwrap();
+        assert_eq!(bridge.run.event_group.events.len(), 1);
+    }
+
+    #[test]
+    pub fn read_lines_splits_and_records_read() {
+        let content = b"first line\r\nsecond line\n\nlast line\n".to_vec();
+        let hash = ContentHash::from_content(&content);
+
+        let mut backend = MockBackend::default();
+        let h = hash.clone();
+        backend
+            .expect_read_file()
+            .with(predicate::eq(PathBuf::from("notes.md")))
+            .returning(move |_| Ok(Some((h.clone(), content.clone()))));
+        backend
+            .expect_read_file()
+            .with(predicate::eq(PathBuf::from("missing.md")))
+            .returning(|_| Ok(None));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+
+        let lines = bridge.read_lines(&PathBuf::from("notes.md")).unwrap();
+        assert_eq!(
+            lines,
+            Some(vec![
+                "first line".to_string(),
+                "second line".to_string(),
+                "".to_string(),
+                "last line".to_string()
+            ])
+        );
+        assert_eq!(
+            bridge.read_lines(&PathBuf::from("missing.md")).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            bridge.run.event_group.events,
+            vec![
+                Event::from(ReadFileEvent {
+                    path: PathBuf::from("notes.md"),
+                    hash: Some(hash),
+                }),
+                Event::from(ReadFileEvent {
+                    path: PathBuf::from("missing.md"),
+                    hash: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn read_tracked_input_includes_previous_content() {
+        let old = b"old input".to_vec();
+        let new = b"new input".to_vec();
+        let old_hash = ContentHash::from_content(&old);
+        let new_hash = ContentHash::from_content(&new);
+
+        let mut backend = MockBackend::default();
+        backend
+            .expect_read_file()
+            .returning(move |_| Ok(Some((new_hash.clone(), new.clone()))));
+        let h = old_hash.clone();
+        backend
+            .expect_read_stored_content()
+            .with(predicate::eq(old_hash.clone()))
+            .returning(move |_| Ok(Some(old.clone())));
+        // Content that has since been dropped from the store.
+        backend.expect_read_stored_content().returning(|_| Ok(None));
+
+        let mut event_log = MockEventLog::default();
+        event_log
+            .expect_get_last_read_event()
+            .with(predicate::eq(PathBuf::from("input.md")))
+            .returning(move |_| {
+                Ok(Some(Event::from(ReadFileEvent {
+                    path: PathBuf::from("input.md"),
+                    hash: Some(h.clone()),
+                })))
+            });
+        event_log
+            .expect_get_last_read_event()
+            .with(predicate::eq(PathBuf::from("pruned.md")))
+            .returning(|_| {
+                Ok(Some(Event::from(ReadFileEvent {
+                    path: PathBuf::from("pruned.md"),
+                    hash: Some(ContentHash::from_content(b"long gone")),
+                })))
+            });
+        event_log
+            .expect_get_last_read_event()
+            .returning(|_| Ok(None));
+
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+
+        let input = bridge
+            .read_tracked_input(&PathBuf::from("input.md"))
+            .unwrap();
+        assert_eq!(
+            input,
+            Some(TrackedInput {
+                current: b"new input".to_vec(),
+                previous: Some(b"old input".to_vec()),
+            })
+        );
+
+        let input = bridge
+            .read_tracked_input(&PathBuf::from("pruned.md"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(input.previous, None);
+
+        let input = bridge
+            .read_tracked_input(&PathBuf::from("never_read.md"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(input.previous, None);
+
+        // Each call still counts as reading the input.
+        assert_eq!(bridge.run.event_group.events.len(), 3);
+    }
+
+    #[test]
+    pub fn previous_runs_of_this_script() {
+        let run = |id: u64, command: &str, outputs: &[(&str, bool)]| {
+            let mut group = EventGroup::empty();
+            group.id = id;
+            group.command = command.to_string();
+            group.created_at = Some(test_time());
+            for &(path, deleted) in outputs {
+                group.events.push(Event::from(WriteFileEvent {
+                    path: PathBuf::from(path),
+                    before_hash: deleted.then(|| ContentHash::from_content(b"old")),
+                    after_hash: (!deleted).then(|| ContentHash::from_content(b"out")),
+                }));
+            }
+            group
+        };
+        let mut runs = vec![
+            run(3, "run-script blog/build.luau", &[("index.md", false)]),
+            run(5, "run-script blog/build.luau --full", &[]),
+            run(
+                8,
+                "run-script blog/build.luau",
+                &[("index.md", false), ("old.md", true)],
+            ),
+        ];
+        runs[2].events.push(Event::from(DeclareOutputEvent {
+            path: PathBuf::from("feed.xml"),
+            hash: ContentHash::from_content(b"feed"),
+        }));
+
+        let mut event_log = MockEventLog::default();
+        event_log
+            .expect_groups_for_command()
+            .with(predicate::eq("run-script blog/build.luau"))
+            .returning(move |_| Ok(runs.clone()));
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+        assert!(bridge.previous_runs(None).is_err());
+
+        bridge.options.command = Some("run-script blog/build.luau".to_string());
+        let previous = bridge.previous_runs(None).unwrap();
+        // The run with extra arguments only matches when asked for by prefix.
+        let ids: Vec<_> = previous.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![3, 8]);
+        assert_eq!(previous[0].created_at, Some(test_time()));
+        assert_eq!(previous[0].outputs, vec![PathBuf::from("index.md")]);
+        assert_eq!(
+            previous[1].outputs,
+            vec![PathBuf::from("feed.xml"), PathBuf::from("index.md")]
+        );
+
+        let by_prefix = bridge
+            .previous_runs(Some("run-script blog/build.luau"))
+            .unwrap();
+        assert_eq!(by_prefix.len(), 3);
+        assert!(bridge.get_event_group().is_none());
+    }
+
+    #[test]
+    pub fn scripts_can_read_files_shipped_with_their_package() {
+        let package_dir = PathBuf::from("project/.wrought/packages/site");
+        let script = package_dir.join("build.luau");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(
+            &script,
+            b"push_test_value(read_package_file('data/names.txt'))".to_vec(),
+        )
+        .unwrap();
+        fs.add_r(
+            &package_dir.join("data/names.txt"),
+            b"ada\ngrace\n".to_vec(),
+        )
+        .unwrap();
+        fs.add_r(Path::new("project/.wrought/secret.txt"), b"no".to_vec())
+            .unwrap();
+        let fs = Arc::new(Mutex::new(fs));
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.fs = fs.clone();
+        bridge.options.package = Some("site".to_string());
+        let bridge = Arc::new(Mutex::new(bridge));
+
+        let values = Arc::new(Mutex::new(vec![]));
+        let values_copy = values.clone();
+        scripting_luau::run_script_ex(bridge.clone(), fs, &script, |l| {
+            scripting_luau::tests::add_test_helpers(l, values_copy)
+        })
+        .unwrap();
+        assert_eq!(*values.lock().unwrap(), vec!["ada\ngrace\n"]);
+
+        let mut bridge = bridge.lock().unwrap();
+        assert_eq!(
+            bridge.read_package_file(Path::new("missing.txt")).unwrap(),
+            None
+        );
+        let e = bridge
+            .read_package_file(Path::new("../../secret.txt"))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "can not read package file '../../secret.txt' - it is outside package 'site'"
+        );
+        // The reads are inputs of the run, like any other file it reads.
+        let reads: Vec<_> = bridge
+            .get_event_group()
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|e| e.event_type)
+            .collect();
+        assert_eq!(
+            reads,
+            vec![
+                EventType::ReadFile(ReadFileEvent {
+                    path: PathBuf::from(".wrought/packages/site/data/names.txt"),
+                    hash: Some(ContentHash::from_content(b"ada\ngrace\n")),
+                }),
+                EventType::ReadFile(ReadFileEvent {
+                    path: PathBuf::from(".wrought/packages/site/missing.txt"),
+                    hash: None,
+                }),
+            ]
+        );
+
+        bridge.options.package = None;
+        assert!(bridge
+            .read_package_file(Path::new("data/names.txt"))
+            .is_err());
+    }
+
+    #[test]
+    pub fn scripts_can_describe_their_run() {
+        let script = PathBuf::from("project/.wrought/packages/site/build.luau");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(
+            &script,
+            [
+                "set_run_description('first try')",
+                "set_run_description('regenerate all pages')",
+                "log('done')",
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+        let fs = Arc::new(Mutex::new(fs));
+        let event_log = Arc::new(Mutex::new(SQLiteEventLog::open_in_memory().unwrap()));
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.event_log = event_log.clone();
+        bridge.options.command = Some("run-script site/build.luau".to_string());
+        let bridge = Arc::new(Mutex::new(bridge));
+
+        scripting_luau::run_script_ex(bridge.clone(), fs, &script, |_| Ok(())).unwrap();
+        let group = bridge.lock().unwrap().get_event_group().unwrap();
+        let id = event_log
+            .lock()
+            .unwrap()
+            .add_event_group(&group)
+            .unwrap()
+            .id;
+
+        let recorded = event_log
+            .lock()
+            .unwrap()
+            .get_event_group(id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            recorded.command,
+            "run-script site/build.luau # regenerate all pages"
+        );
+        // Still a run of the same script.
+        let previous = bridge.lock().unwrap().previous_runs(None).unwrap();
+        assert_eq!(previous.len(), 1);
+
+        bridge.lock().unwrap().set_run_description("").unwrap();
+        let group = bridge.lock().unwrap().get_event_group().unwrap();
+        assert_eq!(group.command, "run-script site/build.luau");
+    }
+
+    #[test]
+    pub fn file_status_of_a_directory_is_an_error() {
+        use xfs::Xfs;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.create_dir_all(&PathBuf::from("project/docs")).unwrap();
+        let mut event_log = MockEventLog::default();
+        event_log.expect_get_last_write_event().never();
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+        bridge.fs = Arc::new(Mutex::new(fs));
+
+        for root in ["", "."] {
+            let e = bridge.file_status(&PathBuf::from(root)).unwrap_err();
+            assert!(e.to_string().contains("project root"), "{}", e);
+        }
+        let e = bridge.file_status(&PathBuf::from("docs")).unwrap_err();
+        assert!(e.to_string().contains("'docs' is a directory"), "{}", e);
+    }
+
+    #[test]
+    pub fn file_status_uses_the_event_log() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(&PathBuf::from("project/out.md"), b"generated".to_vec())
+            .unwrap();
+        fs.add_r(&PathBuf::from("project/in.md"), b"edited input".to_vec())
+            .unwrap();
+
+        let mut group = EventGroup::empty();
+        group.id = 7;
+        group.command = "generate".to_string();
+        group.events = vec![
+            Event::from(ReadFileEvent {
+                path: PathBuf::from("in.md"),
+                hash: Some(ContentHash::from_content(b"input")),
+            }),
+            Event::from(WriteFileEvent {
+                path: PathBuf::from("out.md"),
+                before_hash: None,
+                after_hash: Some(ContentHash::from_content(b"generated")),
+            }),
+        ];
+        let mut write_event = group.events[1].clone();
+        write_event.group_id = 7;
+
+        let mut event_log = MockEventLog::default();
+        let e = write_event.clone();
+        event_log
+            .expect_get_last_write_event()
+            .with(predicate::eq(PathBuf::from("out.md")))
+            .returning(move |_| Ok(Some(e.clone())));
+        event_log
+            .expect_get_last_write_event()
+            .returning(|_| Ok(None));
+        event_log
+            .expect_get_first_write_event()
+            .returning(move |_| Ok(Some(write_event.clone())));
+        let g = group.clone();
+        event_log
+            .expect_all_event_groups()
+            .returning(move || Ok(vec![g.clone()]));
+        event_log
+            .expect_get_event_group()
+            .with(predicate::eq(7))
+            .returning(move |_| Ok(Some(group.clone())));
+        event_log.expect_get_pin().returning(|_| Ok(None));
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+        bridge.fs = Arc::new(Mutex::new(fs));
+
+        assert!(matches!(
+            bridge.file_status(&PathBuf::from("out.md")).unwrap(),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: true
+            }
+        ));
+        assert!(matches!(
+            bridge.file_status(&PathBuf::from("in.md")).unwrap(),
+            FileStatus::Untracked
+        ));
+
+        // Edit the output too.
+        bridge
+            .fs
+            .lock()
+            .unwrap()
+            .writer(&PathBuf::from("project/out.md"))
+            .unwrap()
+            .write_all(b"hand edited")
+            .unwrap();
+        assert!(matches!(
+            bridge.file_status(&PathBuf::from("out.md")).unwrap(),
+            FileStatus::Present {
+                is_changed: true,
+                is_stale: true
+            }
+        ));
+        // Checking status isn't a read.
+        assert!(bridge.run.event_group.events.is_empty());
+    }
+
+    #[test]
+    pub fn bridge_never_writes_to_the_event_log() {
+        let mut backend = MockBackend::default();
+        backend
+            .expect_write_file_with_options()
+            .returning(|_, value, _| Ok((None, ContentHash::from_content(value))));
+        backend.expect_read_file().returning(|_| Ok(None));
+
+        // The group is added by the caller once the script is done, never by the bridge.
+        let mut event_log = MockEventLog::default();
+        event_log.expect_add_event_group().never();
+        event_log
+            .expect_get_last_read_event()
+            .returning(|_| Ok(None));
+
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+
+        bridge
+            .write_file(&PathBuf::from("out.md"), b"content")
+            .unwrap();
+        bridge.read_file(&PathBuf::from("in.md")).unwrap();
+        bridge.read_tracked_input(&PathBuf::from("in.md")).unwrap();
+
+        assert_eq!(bridge.get_event_group().unwrap().events.len(), 3);
+    }
+
+    #[test]
+    pub fn last_hash_comes_from_the_last_write() {
+        let written = ContentHash::from_content(b"generated");
+        let expected = written.to_string();
+        let mut event_log = MockEventLog::default();
+        event_log
+            .expect_get_last_write_event()
+            .with(predicate::eq(PathBuf::from("out.md")))
+            .returning(move |_| {
+                Ok(Some(Event::from(WriteFileEvent {
+                    path: PathBuf::from("out.md"),
+                    before_hash: None,
+                    after_hash: Some(written.clone()),
+                })))
+            });
+        event_log
+            .expect_get_last_write_event()
+            .with(predicate::eq(PathBuf::from("gone.md")))
+            .returning(|_| {
+                Ok(Some(Event::from(WriteFileEvent {
+                    path: PathBuf::from("gone.md"),
+                    before_hash: Some(ContentHash::from_content(b"old")),
+                    after_hash: None,
+                })))
+            });
+        event_log
+            .expect_get_last_write_event()
+            .returning(|_| Ok(None));
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.event_log = Arc::new(Mutex::new(event_log));
+
+        assert_eq!(
+            bridge.last_hash(&PathBuf::from("out.md")).unwrap(),
+            Some(expected)
+        );
+        assert_eq!(bridge.last_hash(&PathBuf::from("gone.md")).unwrap(), None);
+        assert_eq!(bridge.last_hash(&PathBuf::from("in.md")).unwrap(), None);
+        // Nothing was read.
+        assert!(bridge.run.event_group.events.is_empty());
+    }
+
+    #[test]
+    pub fn last_hash_follows_moves() {
+        let moved = ContentHash::from_content(b"generated");
+        let mut backend = MockBackend::default();
+        let hash = moved.clone();
+        backend
+            .expect_rename_file()
+            .with(
+                predicate::eq(PathBuf::from("a.md")),
+                predicate::eq(PathBuf::from("b.md")),
+            )
+            .returning(move |_, _| Ok(hash.clone()));
+        let event_log = Arc::new(Mutex::new(SQLiteEventLog::open_in_memory().unwrap()));
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("a.md"),
+            before_hash: None,
+            after_hash: Some(moved.clone()),
+        }));
+        event_log.lock().unwrap().add_event_group(&group).unwrap();
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        bridge.event_log = event_log;
+
+        bridge
+            .move_file(&PathBuf::from("a.md"), &PathBuf::from("b.md"))
+            .unwrap();
+        bridge.record_run().unwrap();
+
+        assert_eq!(
+            bridge.last_hash(&PathBuf::from("b.md")).unwrap(),
+            Some(moved.to_string())
+        );
+        assert_eq!(bridge.last_hash(&PathBuf::from("a.md")).unwrap(), None);
+    }
+
+    fn bridge_checking_inputs(input_check: InputCheck) -> SimpleBridge {
+        let mut backend = MockBackend::default();
+        backend
+            .expect_read_file()
+            .returning(|_| Ok(Some((ContentHash::from_content(b"x"), b"x".to_vec()))));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        bridge.options.input_check = input_check;
+        bridge
+    }
+
+    fn warnings(bridge: &SimpleBridge) -> usize {
+        bridge
+            .run
+            .event_group
+            .events
+            .iter()
+            .filter(|e| matches!(&e.event_type, EventType::Log(l) if l.level == LogLevel::Warn))
+            .count()
+    }
+
+    #[test]
+    pub fn reads_inside_the_project_are_fine() {
+        let mut bridge = bridge_checking_inputs(InputCheck::Error);
+        bridge.read_file(&PathBuf::from("notes/in.md")).unwrap();
+        bridge.read_file(&PathBuf::from("notes/../in.md")).unwrap();
+        assert_eq!(warnings(&bridge), 0);
+    }
+
+    #[test]
+    pub fn reads_outside_the_project_warn_or_fail() {
+        let mut bridge = bridge_checking_inputs(InputCheck::Warn);
+        bridge.read_file(&PathBuf::from("../elsewhere.md")).unwrap();
+        bridge
+            .read_file(&PathBuf::from(".wrought/settings.toml"))
+            .unwrap();
+        assert_eq!(warnings(&bridge), 2);
+
+        let mut bridge = bridge_checking_inputs(InputCheck::Error);
+        assert!(bridge.read_file(&PathBuf::from("../elsewhere.md")).is_err());
+        assert!(bridge.read_file(&PathBuf::from("/etc/hosts")).is_err());
+        assert!(bridge
+            .read_file(&PathBuf::from(".wrought/settings.toml"))
+            .is_err());
+
+        // Untracked reads are checked the same way.
+        assert!(bridge
+            .read_file_untracked(&PathBuf::from("../elsewhere.md"))
+            .is_err());
+        assert!(bridge
+            .read_file_untracked(&PathBuf::from(".wrought/content/abc"))
+            .is_err());
+
+        // Without checking, nothing is reported.
+        let mut bridge = bridge_checking_inputs(InputCheck::Off);
+        bridge.read_file(&PathBuf::from("../elsewhere.md")).unwrap();
+        assert_eq!(warnings(&bridge), 0);
+    }
+
+    #[test]
+    pub fn reads_from_the_content_store_fail() {
+        let mut bridge = bridge_checking_inputs(InputCheck::Warn);
+        let e = bridge
+            .read_file(&PathBuf::from(".wrought/content/abc"))
+            .unwrap_err();
+        assert!(e.to_string().contains("content store"), "{}", e);
+    }
+
+    #[test]
+    pub fn ai_queries_report_progress() {
+        let progress = Arc::new(RecordingProgress::default());
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.progress = Some(progress.clone());
+
+        // The test LLM always fails, but the query still counts.
+        assert!(bridge.ai_query("first").is_err());
+        assert!(bridge.ai_query("second").is_err());
+
+        assert_eq!(
+            *progress.events.lock().unwrap(),
+            vec![
+                ProgressEvent::LlmQueryStarted { number: 1 },
+                ProgressEvent::LlmQueryFinished { number: 1 },
+                ProgressEvent::LlmQueryStarted { number: 2 },
+                ProgressEvent::LlmQueryFinished { number: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn set_metadata_glob_sets_every_match() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for p in [
+            "content/a.md",
+            "content/b.md",
+            "content/img.png",
+            "notes.md",
+        ] {
+            fs.add_r(&PathBuf::from("project").join(p), b"x".to_vec())
+                .unwrap();
+        }
+        let mut backend = MockBackend::default();
+        backend
+            .expect_set_metadata()
+            .withf(|_, key, value| {
+                key.as_string() == "status"
+                    && value.as_ref().map(|v| v.as_string()) == Some("draft".to_string())
+            })
+            .times(2)
+            .returning(|_, _, _| Ok(None));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        bridge.fs = Arc::new(Mutex::new(fs));
+
+        let matched = bridge
+            .set_metadata_glob("content/*.md", "status", "draft")
+            .unwrap();
+        assert_eq!(
+            matched,
+            vec![PathBuf::from("content/a.md"), PathBuf::from("content/b.md")]
+        );
+        let set: Vec<_> = bridge
+            .get_event_group()
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|e| match e.event_type {
+                EventType::SetMetadata(e) => e.path,
+                e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(set, matched);
+
+        // Matching nothing does nothing, unless the bridge is strict.
+        assert!(bridge
+            .set_metadata_glob("drafts/*.md", "status", "draft")
+            .unwrap()
+            .is_empty());
+        bridge.options.strict = true;
+        let e = bridge
+            .set_metadata_glob("drafts/*.md", "status", "draft")
+            .unwrap_err();
+        assert_eq!(e.to_string(), "'drafts/*.md' does not match any files");
+    }
+
+    #[test]
+    pub fn typed_metadata_round_trips_through_scripts() {
+        use xfs::Xfs;
+
+        let root = PathBuf::from("project");
+        let script = root.join(".wrought/packages/site/tag.luau");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.create_dir_all(&root.join(".wrought/content")).unwrap();
+        fs.add_r(
+            &script,
+            [
+                "set_metadata('a.md', 'count', 42)",
+                "push_test_value(type(get_metadata_typed('a.md', 'count')))",
+                "push_test_value(get_metadata('a.md', 'count'))",
+                "set_metadata('a.md', 'tags', {'x', 'y'})",
+                "push_test_value(get_metadata_typed('a.md', 'tags')[2])",
+                "push_test_value(get_metadata('a.md', 'tags'))",
+                "set_metadata('a.md', 'title', 'Hi')",
+                "push_test_value(get_metadata_typed('a.md', 'title'))",
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+        let fs = Arc::new(Mutex::new(fs));
+        let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
+            fs.clone(),
+            root.join(".wrought/content"),
+        )));
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.backend = Arc::new(Mutex::new(SimpleBackend {
+            fs: fs.clone(),
+            os_fs: false,
+            root: root.clone(),
+            content_store,
+            options: BackendOptions::default(),
+        }));
+        bridge.fs = fs.clone();
+
+        let values = Arc::new(Mutex::new(vec![]));
+        let values_copy = values.clone();
+        scripting_luau::run_script_ex(Arc::new(Mutex::new(bridge)), fs, &script, |l| {
+            scripting_luau::tests::add_test_helpers(l, values_copy)
+        })
+        .unwrap();
+        assert_eq!(
+            *values.lock().unwrap(),
+            vec!["number", "42", "y", r#"["x","y"]"#, "Hi"]
+        );
+    }
+
+    #[test]
+    pub fn write_file_with_metadata_records_everything_in_one_group() {
+        let mut backend = MockBackend::default();
+        backend
+            .expect_write_file_with_options()
+            .returning(|_, value, _| Ok((None, ContentHash::from_content(value))));
+        backend
+            .expect_set_metadata()
+            .times(2)
+            .returning(|_, _, _| Ok(None));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+
+        let metadata = BTreeMap::from([
+            ("content_type".to_string(), serde_json::json!("text/html")),
+            ("generated_by".to_string(), serde_json::json!("site/build")),
+        ]);
+        bridge
+            .write_file_with_metadata(&PathBuf::from("index.html"), b"<p>hi</p>", &metadata)
+            .unwrap();
+
+        let events: Vec<_> = bridge
+            .get_event_group()
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|e| e.event_type)
+            .collect();
+        assert_eq!(events.len(), 3, "{:?}", events);
+        assert_eq!(
+            events[0],
+            EventType::WriteFile(WriteFileEvent {
+                path: PathBuf::from("index.html"),
+                before_hash: None,
+                after_hash: Some(ContentHash::from_content(b"<p>hi</p>")),
+            })
+        );
+        let keys: Vec<_> = events[1..]
+            .iter()
+            .map(|e| match e {
+                EventType::SetMetadata(e) => {
+                    assert_eq!(e.path, PathBuf::from("index.html"));
+                    e.key.as_string()
+                }
+                e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(keys, vec!["content_type", "generated_by"]);
+    }
+
+    #[test]
+    pub fn write_file_with_metadata_keeps_nothing_if_the_write_fails() {
+        let mut backend = MockBackend::default();
+        let store = Arc::new(Mutex::new(BTreeMap::from([(
+            MetadataKey::from("content_type"),
+            MetadataEntry::from("text/plain"),
+        )])));
+        let md = store.clone();
+        backend
+            .expect_set_metadata()
+            .returning(move |_, key, value| {
+                let mut md = md.lock().unwrap();
+                Ok(match value {
+                    Some(v) => md.insert(key.clone(), v.clone()),
+                    None => md.remove(key),
+                })
+            });
+        backend
+            .expect_write_file_with_options()
+            .returning(|_, _, _| Err(anyhow::anyhow!("disk full")));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+
+        let metadata = BTreeMap::from([
+            ("content_type".to_string(), serde_json::json!("text/html")),
+            ("generated_by".to_string(), serde_json::json!("site/build")),
+        ]);
+        let e = bridge
+            .write_file_with_metadata(&PathBuf::from("index.html"), b"<p>hi</p>", &metadata)
+            .unwrap_err();
+        assert_eq!(e.to_string(), "disk full");
+
+        assert!(bridge.run.event_group.events.is_empty());
+        assert_eq!(
+            *store.lock().unwrap(),
+            BTreeMap::from([(
+                MetadataKey::from("content_type"),
+                MetadataEntry::from("text/plain"),
+            )])
+        );
     }
 }
@@ -1,17 +1,90 @@
 use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use crate::binary16::ContentHash;
+use anyhow::{anyhow, bail, Context};
+
+use crate::binary16::{ContentHash, ContentHasher};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContentStoreStats {
+    pub objects: usize,
+    pub total_bytes: u64,
+    /// The middle object size - or the mean of the middle two. Zero for an empty store.
+    pub median_bytes: u64,
+}
+
+impl ContentStoreStats {
+    pub fn from_sizes(mut sizes: Vec<u64>) -> ContentStoreStats {
+        sizes.sort_unstable();
+        let n = sizes.len();
+        let median_bytes = match n {
+            0 => 0,
+            _ if n % 2 == 1 => sizes[n / 2],
+            _ => (sizes[n / 2 - 1] + sizes[n / 2]) / 2,
+        };
+        ContentStoreStats {
+            objects: n,
+            total_bytes: sizes.iter().sum(),
+            median_bytes,
+        }
+    }
+
+    pub fn mean_bytes(&self) -> u64 {
+        match self.objects {
+            0 => 0,
+            n => self.total_bytes / n as u64,
+        }
+    }
+}
 
 pub trait ContentStore {
     fn store(&mut self, value: &[u8]) -> anyhow::Result<ContentHash>;
+    /// Like `store`, but doesn't rewrite content that is already stored.
+    /// The flag is true if the content was new.
+    fn store_if_absent(&mut self, value: &[u8]) -> anyhow::Result<(ContentHash, bool)>;
+    /// Like `store`, but for content read from `reader` - e.g. piped in - which may be
+    /// too big to hold in memory. This default does read it all in first.
+    fn store_reader(&mut self, reader: &mut dyn Read) -> anyhow::Result<ContentHash> {
+        let mut value = vec![];
+        reader.read_to_end(&mut value)?;
+        self.store(&value)
+    }
     fn retrieve(&self, hash: ContentHash) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Like `retrieve`, but errors if the stored bytes no longer hash to `hash` - i.e.
+    /// the object has been corrupted. Rehashing is cheap next to handing back bad
+    /// content as if it were good.
+    fn retrieve_verified(&self, hash: ContentHash) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(content) = self.retrieve(hash.clone())? else {
+            return Ok(None);
+        };
+        let actual = ContentHash::from_content(&content);
+        if actual != hash {
+            bail!(
+                "content {} is corrupt - the stored bytes hash to {}",
+                hash,
+                actual
+            );
+        }
+        Ok(Some(content))
+    }
+    fn stats(&self) -> anyhow::Result<ContentStoreStats>;
+    /// Every stored object, with its size in bytes.
+    fn objects(&self) -> anyhow::Result<Vec<(ContentHash, u64)>>;
+    /// Deletes an object. The content is gone for good - only `gc` should need this.
+    fn remove(&mut self, hash: &ContentHash) -> anyhow::Result<()>;
 }
 
 pub struct FileSystemContentStore {
     fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
+    /// Whether `fs` is the real filesystem, so objects can be moved into place.
+    os_fs: bool,
     storage_path: PathBuf,
 }
 
@@ -20,7 +93,21 @@ impl FileSystemContentStore {
         fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
         storage_path: std::path::PathBuf,
     ) -> FileSystemContentStore {
-        Self { fs, storage_path }
+        Self {
+            fs,
+            os_fs: false,
+            storage_path,
+        }
+    }
+
+    /// A store on the real filesystem. Unlike one on any other Xfs, it can stream content
+    /// in through a temporary object moved into place once it is hashed.
+    pub fn on_os_fs(storage_path: std::path::PathBuf) -> FileSystemContentStore {
+        Self {
+            fs: Arc::new(Mutex::new(xfs::OsFs {})),
+            os_fs: true,
+            storage_path,
+        }
     }
 }
 
@@ -32,6 +119,54 @@ impl ContentStore for FileSystemContentStore {
         Ok(hash)
     }
 
+    fn store_if_absent(&mut self, value: &[u8]) -> anyhow::Result<(ContentHash, bool)> {
+        let hash = ContentHash::from_content(value);
+        let path = self.storage_path.join(hash.to_string());
+        let mut fs = self.fs.lock().unwrap();
+        if fs.exists(&path) {
+            return Ok((hash, false));
+        }
+        fs.writer(&path)?.write_all(value)?;
+        Ok((hash, true))
+    }
+
+    /// The content is copied into a temporary object while it is hashed, which is then
+    /// renamed. Xfs has no way to rename or remove files, so that is only done on the real
+    /// filesystem - anywhere else the content is read in first, as by default.
+    fn store_reader(&mut self, reader: &mut dyn Read) -> anyhow::Result<ContentHash> {
+        if !self.os_fs {
+            let mut value = vec![];
+            reader.read_to_end(&mut value)?;
+            return self.store(&value);
+        }
+        static INCOMING: AtomicUsize = AtomicUsize::new(0);
+        let incoming = self.storage_path.join(format!(
+            "incoming-{}-{}",
+            std::process::id(),
+            INCOMING.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut copy = || -> anyhow::Result<ContentHash> {
+            let mut tee = Tee {
+                writer: self.fs.lock().unwrap().writer(&incoming)?,
+                hasher: ContentHasher::default(),
+            };
+            std::io::copy(reader, &mut tee)?;
+            tee.writer.flush()?;
+            Ok(tee.hasher.finish())
+        };
+        let hash = match copy() {
+            Ok(hash) => hash,
+            Err(e) => {
+                let _ = std::fs::remove_file(&incoming);
+                return Err(e);
+            }
+        };
+        let path = self.storage_path.join(hash.to_string());
+        std::fs::rename(&incoming, &path)
+            .with_context(|| format!("unable to move stored content to {}", path.display()))?;
+        Ok(hash)
+    }
+
     fn retrieve(&self, hash: ContentHash) -> anyhow::Result<Option<Vec<u8>>> {
         let path = self.storage_path.join(hash.to_string());
         match self.fs.lock().unwrap().reader_if_exists(&path)? {
@@ -43,6 +178,162 @@ impl ContentStore for FileSystemContentStore {
             None => Ok(None),
         }
     }
+
+    fn stats(&self) -> anyhow::Result<ContentStoreStats> {
+        let sizes = self.objects()?.into_iter().map(|(_, size)| size).collect();
+        Ok(ContentStoreStats::from_sizes(sizes))
+    }
+
+    /// Files whose names aren't hashes aren't objects, and are left out.
+    fn objects(&self) -> anyhow::Result<Vec<(ContentHash, u64)>> {
+        let fs = self.fs.lock().unwrap();
+        let mut objects = vec![];
+        if fs.is_dir(&self.storage_path) {
+            // Xfs metadata doesn't give sizes, so we have to read each object.
+            fs.on_each_entry(&self.storage_path, &mut |fs, e| {
+                let path = e.path();
+                let hash = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| ContentHash::from_string(n).ok());
+                if let Some(hash) = hash {
+                    if e.metadata()?.is_file() {
+                        let mut content = vec![];
+                        fs.reader(&path)?.read_to_end(&mut content)?;
+                        objects.push((hash, content.len() as u64));
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(objects)
+    }
+
+    /// Xfs has no way to remove files, so this always acts on the real filesystem.
+    fn remove(&mut self, hash: &ContentHash) -> anyhow::Result<()> {
+        let path = self.storage_path.join(hash.to_string());
+        std::fs::remove_file(&path)
+            .map_err(|e| anyhow::anyhow!("unable to remove {}: {}", path.display(), e))
+    }
+}
+
+/// Writes to `writer`, hashing what is written on the way.
+struct Tee {
+    writer: Box<dyn Write>,
+    hasher: ContentHasher,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.hasher.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// One of the stores in a `LayeredContentStore`.
+pub struct ContentLayer {
+    pub store: Arc<Mutex<dyn ContentStore + Send + 'static>>,
+    /// Read-only layers, such as a shared cache, are only ever read from.
+    pub writable: bool,
+}
+
+/// Several content stores tried in order, nearest first - e.g. the project's own store
+/// in front of a shared remote one. Content found in a later layer is copied into the
+/// earlier writable ones, so the next read doesn't go as far. New content goes to the
+/// first writable layer.
+pub struct LayeredContentStore {
+    layers: Vec<ContentLayer>,
+}
+
+impl LayeredContentStore {
+    pub fn new(layers: Vec<ContentLayer>) -> LayeredContentStore {
+        LayeredContentStore { layers }
+    }
+
+    fn writable_layers(&self) -> impl Iterator<Item = &ContentLayer> {
+        self.layers.iter().filter(|layer| layer.writable)
+    }
+
+    fn first_writable_layer(&self) -> anyhow::Result<&ContentLayer> {
+        self.writable_layers()
+            .next()
+            .ok_or_else(|| anyhow!("none of the content stores can be written to"))
+    }
+}
+
+impl ContentStore for LayeredContentStore {
+    fn store(&mut self, value: &[u8]) -> anyhow::Result<ContentHash> {
+        self.first_writable_layer()?
+            .store
+            .lock()
+            .unwrap()
+            .store(value)
+    }
+
+    /// Only the first writable layer is checked - content that is only in a later layer
+    /// is stored again.
+    fn store_if_absent(&mut self, value: &[u8]) -> anyhow::Result<(ContentHash, bool)> {
+        self.first_writable_layer()?
+            .store
+            .lock()
+            .unwrap()
+            .store_if_absent(value)
+    }
+
+    fn store_reader(&mut self, reader: &mut dyn Read) -> anyhow::Result<ContentHash> {
+        self.first_writable_layer()?
+            .store
+            .lock()
+            .unwrap()
+            .store_reader(reader)
+    }
+
+    fn retrieve(&self, hash: ContentHash) -> anyhow::Result<Option<Vec<u8>>> {
+        for (i, layer) in self.layers.iter().enumerate() {
+            let Some(content) = layer.store.lock().unwrap().retrieve(hash.clone())? else {
+                continue;
+            };
+            // Copying corrupt content forward would hide where it came from.
+            if ContentHash::from_content(&content) == hash {
+                for earlier in self.layers[..i].iter().filter(|layer| layer.writable) {
+                    earlier.store.lock().unwrap().store_if_absent(&content)?;
+                }
+            }
+            return Ok(Some(content));
+        }
+        Ok(None)
+    }
+
+    /// Only the writable layers count - the others aren't ours to manage.
+    fn stats(&self) -> anyhow::Result<ContentStoreStats> {
+        let sizes = self.objects()?.into_iter().map(|(_, size)| size).collect();
+        Ok(ContentStoreStats::from_sizes(sizes))
+    }
+
+    /// The objects in the writable layers, each listed once.
+    fn objects(&self) -> anyhow::Result<Vec<(ContentHash, u64)>> {
+        let mut objects = BTreeMap::new();
+        for layer in self.writable_layers() {
+            objects.extend(layer.store.lock().unwrap().objects()?);
+        }
+        Ok(objects.into_iter().collect())
+    }
+
+    /// Removes the object from every writable layer that has it.
+    fn remove(&mut self, hash: &ContentHash) -> anyhow::Result<()> {
+        for layer in self.writable_layers() {
+            let mut store = layer.store.lock().unwrap();
+            if store.retrieve(hash.clone())?.is_some() {
+                store.remove(hash)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -54,7 +345,9 @@ pub mod tests {
 
     use crate::binary16::ContentHash;
 
-    use super::{ContentStore, FileSystemContentStore};
+    use super::{
+        ContentLayer, ContentStore, ContentStoreStats, FileSystemContentStore, LayeredContentStore,
+    };
 
     fn simple_test_case() -> (Arc<Mutex<xfs::mockfs::MockFS>>, FileSystemContentStore) {
         use xfs::Xfs;
@@ -77,6 +370,68 @@ pub mod tests {
         assert_eq!("This is a test", std::str::from_utf8(&content).unwrap());
     }
 
+    #[test]
+    pub fn store_if_absent_only_writes_new_content() {
+        let (fs, mut store) = simple_test_case();
+        let (hash, is_new) = store.store_if_absent(b"some content").unwrap();
+        assert!(is_new);
+        assert_eq!(hash, ContentHash::from_content(b"some content"));
+
+        // Were it rewritten, this would be replaced.
+        let path = PathBuf::from(format!("some/random/dir/{}", hash));
+        fs.lock().unwrap().add_r(&path, b"marker".to_vec()).unwrap();
+
+        let (second_hash, is_new) = store.store_if_absent(b"some content").unwrap();
+        assert!(!is_new);
+        assert_eq!(second_hash, hash);
+        assert_eq!(fs.lock().unwrap().get(&path).unwrap(), b"marker");
+    }
+
+    #[test]
+    pub fn store_reader_works_on_any_xfs() {
+        use xfs::Xfs;
+
+        let (fs, mut store) = simple_test_case();
+        let hash = store.store_reader(&mut &b"piped in"[..]).unwrap();
+        assert_eq!(hash, ContentHash::from_content(b"piped in"));
+        // Nothing but the object itself - no temporary object left behind.
+        let mut entries = vec![];
+        fs.lock()
+            .unwrap()
+            .on_each_entry(&PathBuf::from("some/random/dir"), &mut |_, e| {
+                entries.push(e.path());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![PathBuf::from(format!("some/random/dir/{}", hash))]
+        );
+    }
+
+    #[test]
+    pub fn stats_count_objects_and_sizes() {
+        let (_fs, mut store) = simple_test_case();
+        assert_eq!(store.stats().unwrap(), ContentStoreStats::default());
+
+        for content in ["a", "bbb", "cccccc", "dddddddddd"] {
+            store.store(content.as_bytes()).unwrap();
+        }
+        // Storing the same content again doesn't add an object.
+        store.store(b"a").unwrap();
+
+        let stats = store.stats().unwrap();
+        assert_eq!(
+            stats,
+            ContentStoreStats {
+                objects: 4,
+                total_bytes: 20,
+                median_bytes: 4,
+            }
+        );
+        assert_eq!(stats.mean_bytes(), 5);
+    }
+
     #[test]
     pub fn store_writes_to_correct_path() {
         let (fs, mut store) = simple_test_case();
@@ -106,4 +461,76 @@ pub mod tests {
 
         assert_eq!(result, content);
     }
+
+    #[test]
+    pub fn retrieve_verified_spots_corrupt_objects() {
+        let (fs, mut store) = simple_test_case();
+        let hash = store.store(b"the real content").unwrap();
+        assert_eq!(
+            store.retrieve_verified(hash.clone()).unwrap(),
+            Some(b"the real content".to_vec())
+        );
+
+        let path = PathBuf::from(format!("some/random/dir/{}", hash));
+        fs.lock()
+            .unwrap()
+            .add_r(&path, b"bit rot".to_vec())
+            .unwrap();
+
+        assert_eq!(
+            store.retrieve(hash.clone()).unwrap(),
+            Some(b"bit rot".to_vec())
+        );
+        let e = store.retrieve_verified(hash.clone()).unwrap_err();
+        assert!(e.to_string().contains("corrupt"), "{}", e);
+
+        let missing = ContentHash::from_content(b"never stored");
+        assert_eq!(store.retrieve_verified(missing).unwrap(), None);
+    }
+
+    #[test]
+    pub fn layered_store_promotes_lower_layer_hits() {
+        let (_local_fs, local) = simple_test_case();
+        let (_remote_fs, mut remote) = simple_test_case();
+        let shared = remote.store(b"only in the remote").unwrap();
+        let local = Arc::new(Mutex::new(local));
+        let remote = Arc::new(Mutex::new(remote));
+        let mut store = LayeredContentStore::new(vec![
+            ContentLayer {
+                store: local.clone(),
+                writable: true,
+            },
+            ContentLayer {
+                store: remote.clone(),
+                writable: false,
+            },
+        ]);
+        assert_eq!(
+            local.lock().unwrap().retrieve(shared.clone()).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            store.retrieve(shared.clone()).unwrap(),
+            Some(b"only in the remote".to_vec())
+        );
+        assert_eq!(
+            local.lock().unwrap().retrieve(shared.clone()).unwrap(),
+            Some(b"only in the remote".to_vec())
+        );
+
+        // New content only goes to the writable layer.
+        let new = store.store(b"new content").unwrap();
+        assert!(local
+            .lock()
+            .unwrap()
+            .retrieve(new.clone())
+            .unwrap()
+            .is_some());
+        assert_eq!(remote.lock().unwrap().retrieve(new.clone()).unwrap(), None);
+        assert_eq!(store.stats().unwrap().objects, 2);
+
+        let missing = ContentHash::from_content(b"nowhere");
+        assert_eq!(store.retrieve(missing).unwrap(), None);
+    }
 }
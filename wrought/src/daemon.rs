@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeSet,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    event_log::EventLog,
+    project_root::normalize_project_relative_path,
+    project_status::{FileStatus, IncrementalStatus},
+};
+
+/// How long the tree must be quiet before `wrought daemon` rehashes what changed. Saving
+/// a file often touches it several times in quick succession.
+pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// What `wrought daemon` reacts to. Queries come from stdin, changes from the
+/// filesystem watcher.
+#[derive(Debug)]
+pub enum DaemonMessage {
+    /// A line of input - a path to report the status of, or `quit`.
+    Query(String),
+    /// Paths that changed on disk, relative to the project root.
+    Changed(Vec<PathBuf>),
+    Shutdown,
+}
+
+/// Answers status queries from `messages`, writing one line of JSON per query to `out`,
+/// until told to stop or every sender has gone.
+///
+/// Changes are collected until there has been none for `debounce`, then rehashed
+/// together. A query rehashes any outstanding changes first, so answers are never stale.
+pub fn run_daemon(
+    status: &mut IncrementalStatus,
+    event_log: &dyn EventLog,
+    fs: &dyn xfs::Xfs,
+    project_root: &Path,
+    messages: &Receiver<DaemonMessage>,
+    debounce: Duration,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut pending = BTreeSet::new();
+    loop {
+        let message = if pending.is_empty() {
+            match messages.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        } else {
+            match messages.recv_timeout(debounce) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    apply_changes(status, event_log, fs, &mut pending);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        };
+        match message {
+            DaemonMessage::Changed(paths) => pending.extend(paths),
+            DaemonMessage::Query(line) => {
+                let line = line.trim();
+                if line == "quit" {
+                    break;
+                }
+                if line.is_empty() {
+                    continue;
+                }
+                apply_changes(status, event_log, fs, &mut pending);
+                writeln!(out, "{}", answer(status, project_root, line))?;
+                out.flush()?;
+            }
+            DaemonMessage::Shutdown => break,
+        }
+    }
+    Ok(())
+}
+
+fn apply_changes(
+    status: &mut IncrementalStatus,
+    event_log: &dyn EventLog,
+    fs: &dyn xfs::Xfs,
+    pending: &mut BTreeSet<PathBuf>,
+) {
+    let mut event_log_changed = false;
+    for path in std::mem::take(pending) {
+        // Covers the database's journal and WAL files too.
+        if path.starts_with(".wrought")
+            && path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with("wrought.db"))
+        {
+            event_log_changed = true;
+            continue;
+        }
+        // Keep going - one unreadable directory shouldn't stop the daemon.
+        if let Err(e) = status.path_changed(fs, &path) {
+            eprintln!("warning: unable to rescan '{}': {:#}", path.display(), e);
+        }
+    }
+    if event_log_changed {
+        if let Err(e) = status.event_log_changed(event_log, fs) {
+            eprintln!("warning: unable to reread the event log: {:#}", e);
+        }
+    }
+}
+
+/// The reply to one query, e.g.
+/// `{"path":"a.md","status":"present","is_changed":false,"is_stale":true}`
+/// or `{"path":"a.md","error":"..."}`.
+#[derive(Serialize)]
+struct Reply<'a> {
+    path: &'a str,
+    #[serde(flatten)]
+    result: ReplyResult,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ReplyResult {
+    Status(FileStatus),
+    Error { error: String },
+}
+
+fn answer(status: &IncrementalStatus, project_root: &Path, query: &str) -> String {
+    let result = Path::new(query)
+        .strip_prefix(project_root)
+        .or_else(|_| Ok(Path::new(query)))
+        .and_then(normalize_project_relative_path)
+        .and_then(|path| status.file_status(&path));
+    let result = match result {
+        Ok(file_status) => ReplyResult::Status(file_status),
+        Err(e) => ReplyResult::Error {
+            error: format!("{:#}", e),
+        },
+    };
+    serde_json::to_string(&Reply {
+        path: query,
+        result,
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        io::Write,
+        path::{Path, PathBuf},
+        sync::mpsc,
+    };
+
+    use xfs::{mockfs::MockFS, Xfs};
+
+    use super::{run_daemon, DaemonMessage, DEBOUNCE};
+    use crate::{
+        binary16::ContentHash,
+        event_log::{EventLog, SQLiteEventLog},
+        events::{Event, EventGroup, WriteFileEvent},
+        ignore_rules::IgnoreRules,
+        project_status::{FileStatus, IncrementalStatus},
+    };
+
+    #[test]
+    pub fn changes_are_picked_up_between_queries() {
+        let mut fs = MockFS::new();
+        let root = PathBuf::from("project");
+        fs.add_r(&root.join("out.md"), b"output".to_vec()).unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("out.md"),
+            before_hash: None,
+            after_hash: Some(ContentHash::from_content(b"output")),
+        }));
+        event_log.add_event_group(&group).unwrap();
+
+        let mut status =
+            IncrementalStatus::new(&event_log, &fs, &root, IgnoreRules::empty(), None).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let query = |q: &str| DaemonMessage::Query(q.to_string());
+        let run = |status: &mut IncrementalStatus, event_log: &SQLiteEventLog, fs: &MockFS| {
+            let mut out = vec![];
+            tx.send(DaemonMessage::Shutdown).unwrap();
+            run_daemon(status, event_log, fs, &root, &rx, DEBOUNCE, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        tx.send(query("out.md")).unwrap();
+        assert_eq!(
+            run(&mut status, &event_log, &fs),
+            "{\"path\":\"out.md\",\"status\":\"present\",\"is_changed\":false,\"is_stale\":false}\n"
+        );
+
+        // Nothing is rehashed until the watcher reports the change.
+        fs.writer(&root.join("out.md"))
+            .unwrap()
+            .write_all(b"edited")
+            .unwrap();
+        fs.add_r(&root.join("new.md"), b"new".to_vec()).unwrap();
+        assert!(matches!(
+            status.file_status(Path::new("out.md")).unwrap(),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+
+        tx.send(DaemonMessage::Changed(vec![
+            PathBuf::from("out.md"),
+            PathBuf::from("new.md"),
+            PathBuf::from(".wrought/content/abc"),
+        ]))
+        .unwrap();
+        tx.send(query("project/out.md")).unwrap();
+        tx.send(query("new.md")).unwrap();
+        tx.send(query("../elsewhere.md")).unwrap();
+        let replies = run(&mut status, &event_log, &fs);
+        let replies: Vec<_> = replies.lines().collect();
+        assert_eq!(
+            replies[..2],
+            [
+                "{\"path\":\"project/out.md\",\"status\":\"present\",\"is_changed\":true,\"is_stale\":false}",
+                "{\"path\":\"new.md\",\"status\":\"untracked\"}",
+            ]
+        );
+        assert!(replies[2].contains("\"error\""), "{}", replies[2]);
+
+        // A run records new.md, which shows once the database is seen to change.
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("new.md"),
+            before_hash: None,
+            after_hash: Some(ContentHash::from_content(b"new")),
+        }));
+        event_log.add_event_group(&group).unwrap();
+        tx.send(DaemonMessage::Changed(vec![PathBuf::from(
+            ".wrought/wrought.db-wal",
+        )]))
+        .unwrap();
+        tx.send(query("new.md")).unwrap();
+        assert_eq!(
+            run(&mut status, &event_log, &fs),
+            "{\"path\":\"new.md\",\"status\":\"present\",\"is_changed\":false,\"is_stale\":false}\n"
+        );
+    }
+}
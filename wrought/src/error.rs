@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use crate::luau_json::ConversionError;
+
+/// The error type of wrought's library API (see `project::Project`), so embedding
+/// applications can match on what went wrong. Internally wrought uses `anyhow`; its
+/// errors are turned into one of these at the boundary, keeping the full message.
+#[derive(Debug)]
+pub enum Error {
+    /// There is no `.wrought` directory in, or above, this directory.
+    ProjectNotFound(PathBuf),
+    Io(std::io::Error),
+    /// An error from the filesystem layer wrought reads and writes projects through.
+    Fs(xfs::XfsError),
+    EventLog(String),
+    ContentStore(String),
+    Llm(String),
+    Script(String),
+    Conversion(ConversionError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    // `{:#}` keeps the context anyhow has gathered, e.g. "opening x: no such file".
+
+    pub fn event_log(e: anyhow::Error) -> Self {
+        Self::EventLog(format!("{:#}", e))
+    }
+
+    pub fn content_store(e: anyhow::Error) -> Self {
+        Self::ContentStore(format!("{:#}", e))
+    }
+
+    pub fn llm(e: anyhow::Error) -> Self {
+        Self::Llm(format!("{:#}", e))
+    }
+
+    pub fn script(e: anyhow::Error) -> Self {
+        Self::Script(format!("{:#}", e))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ProjectNotFound(p) => write!(f, "no wrought project found at {}", p.display()),
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Fs(e) => write!(f, "filesystem error: {}", e),
+            Self::EventLog(e) => write!(f, "event log error: {}", e),
+            Self::ContentStore(e) => write!(f, "content store error: {}", e),
+            Self::Llm(e) => write!(f, "LLM error: {}", e),
+            Self::Script(e) => write!(f, "script error: {}", e),
+            Self::Conversion(e) => write!(f, "conversion error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Fs(e) => Some(e),
+            Self::Conversion(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<xfs::XfsError> for Error {
+    fn from(e: xfs::XfsError) -> Self {
+        Self::Fs(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::EventLog(e.to_string())
+    }
+}
+
+impl From<mlua::Error> for Error {
+    fn from(e: mlua::Error) -> Self {
+        Self::Script(e.to_string())
+    }
+}
+
+impl From<ConversionError> for Error {
+    fn from(e: ConversionError) -> Self {
+        Self::Conversion(e)
+    }
+}
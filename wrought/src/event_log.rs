@@ -1,35 +1,122 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
 
 use crate::{
     binary16::ContentHash,
-    events::{Event, EventGroup, EventType, ReadFileEvent, WriteFileEvent},
+    clock::{Clock, SystemClock},
+    events::{
+        DeclareOutputEvent, Event, EventGroup, EventType, GetMetadataEvent, LogEvent,
+        ReadFileEvent, RenameFileEvent, SetMetadataEvent, WriteFileEvent,
+    },
 };
 
+/// Something wrong with the event log, as found by `wrought verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// An event whose group doesn't exist, so nothing that reads groups ever sees it.
+    OrphanEvent { event_id: u64, group_id: u64 },
+    /// A group with no events. Runs that do nothing aren't recorded, so this is left
+    /// over from something going wrong part way through.
+    EmptyGroup { group_id: u64 },
+    /// Content an event wrote or read that the content store doesn't have.
+    MissingContent {
+        group_id: u64,
+        event_id: u64,
+        hash: ContentHash,
+    },
+}
+
+impl std::fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityIssue::OrphanEvent { event_id, group_id } => write!(
+                f,
+                "event {} belongs to group {}, which doesn't exist",
+                event_id, group_id
+            ),
+            IntegrityIssue::EmptyGroup { group_id } => {
+                write!(f, "group {} has no events", group_id)
+            }
+            IntegrityIssue::MissingContent {
+                group_id,
+                event_id,
+                hash,
+            } => write!(
+                f,
+                "event {} in group {} refers to content {}, which isn't in the content store",
+                event_id, group_id, hash
+            ),
+        }
+    }
+}
+
 pub trait EventLog {
+    /// Renames count as writes: moving a file to `p` as a `WriteFileEvent` of the moved
+    /// content, and moving it away as one deleting `p`.
     fn get_last_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
+    fn get_last_read_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
+    /// The write that created the file, i.e. the earliest write to it - which may be a
+    /// rename to `p`, given as in `get_last_write_event`.
+    fn get_first_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
+    /// Every event about `p`. Renames are included for both their old and new path.
     fn get_file_history(&self, p: &Path) -> anyhow::Result<Vec<Event>>;
     fn get_event_group(&self, group_id: u64) -> anyhow::Result<Option<EventGroup>>;
+    /// Fetches several groups at once, avoiding a query per group.
+    /// Ids that don't correspond to a group are skipped.
+    fn get_groups(&self, ids: &[u64]) -> anyhow::Result<Vec<EventGroup>>;
+    /// The id of the most recently added group, or `None` if there are no groups.
+    /// New groups get larger ids - though deleting the newest group frees its id.
+    fn last_group_id(&self) -> anyhow::Result<Option<u64>>;
+    /// The groups recorded for `command`, oldest first. A group matches if its command
+    /// is `command`, or starts with `command` followed by a space - so `run-script`
+    /// matches every script run, but `run-script a.luau` doesn't match `run-script a.luau2`.
+    fn groups_for_command(&self, command: &str) -> anyhow::Result<Vec<EventGroup>>;
 
     /// Input must have group_id and ids all set to zero.
-    /// Returns the full group with id's correctly set.
+    /// Returns the full group with id's correctly set, and `created_at` set from the
+    /// log's clock if the group didn't already have a time.
     fn add_event_group(&mut self, group: &EventGroup) -> anyhow::Result<EventGroup>;
+    /// Removes a group and all its events. It is an error if there is no such group.
+    fn delete_group(&mut self, group_id: u64) -> anyhow::Result<()>;
+
+    /// Pinned files have had their current content accepted by the user, and are OK
+    /// while they still match the pinned hash, even if they differ from the last write.
+    fn get_pin(&self, p: &Path) -> anyhow::Result<Option<ContentHash>>;
+    fn get_pins(&self) -> anyhow::Result<BTreeMap<PathBuf, ContentHash>>;
+    /// `None` removes any pin.
+    fn set_pin(&mut self, p: &Path, hash: Option<ContentHash>) -> anyhow::Result<()>;
 
     /// Not sure we want to keep this forever - we currently use it as a way to build up
     /// a representation of the current state of the project as far as the event log is
     // / concerned.
     fn all_event_groups(&self) -> anyhow::Result<Vec<EventGroup>>;
+
+    /// Problems with how the log's groups and events fit together - events without a
+    /// group, and groups without events. See `verify::verify_project` for checking the
+    /// content the log refers to as well.
+    fn check_integrity(&self) -> anyhow::Result<Vec<IntegrityIssue>>;
 }
 
 // --------
 
+/// SQL matching renames to or from `?1`. A rename is stored under the path it moved
+/// from, so the path it moved to has to be pulled out of its JSON `data`.
+const RENAME_OF_PATH: &str =
+    "(action_type='rename' AND (file_path=?1 OR json_extract(data, '$.to')=?1))";
+
 /// This is really the start of a SQLite event log
 ///
 /// TODO: Maybe it belongs in it's own file?
 
 pub struct SQLiteEventLog {
     conn: rusqlite::Connection,
+    clock: Arc<dyn Clock>,
 }
 
 impl SQLiteEventLog {
@@ -42,13 +129,111 @@ impl SQLiteEventLog {
                 | OpenFlags::SQLITE_OPEN_URI
                 | OpenFlags::SQLITE_OPEN_NO_MUTEX,
         )?;
-        Ok(SQLiteEventLog { conn })
+        Self::upgrade_schema(&conn)?;
+        Ok(SQLiteEventLog::from_connection(conn))
+    }
+
+    /// Opens the event log so that any attempt to change it fails. The schema can't be
+    /// upgraded like this, so a log from an older wrought may need opening read-write once.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> anyhow::Result<SQLiteEventLog> {
+        use rusqlite::OpenFlags;
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(SQLiteEventLog::from_connection(conn))
+    }
+
+    /// An event log that lives only as long as the returned value - handy for tests.
+    pub fn open_in_memory() -> anyhow::Result<SQLiteEventLog> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        Self::create_tables(&conn)?;
+        Ok(SQLiteEventLog::from_connection(conn))
+    }
+
+    fn from_connection(conn: rusqlite::Connection) -> SQLiteEventLog {
+        SQLiteEventLog {
+            conn,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` to time groups that are added without a time.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> SQLiteEventLog {
+        SQLiteEventLog { clock, ..self }
+    }
+
+    /// Writes of `p` in id `order` (`ASC` or `DESC`), with renames to or from it turned
+    /// into the writes they amount to.
+    fn writes_of(&self, p: &Path, order: &str) -> anyhow::Result<impl Iterator<Item = Event>> {
+        // As in `get_file_history`, a rename's new path is only in `data`.
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT * FROM Events WHERE (action_type='write' AND file_path=?1) OR {} ORDER BY id {}",
+            RENAME_OF_PATH, order
+        ))?;
+        let mut events = stmt.query([format!("{}", p.display())])?;
+        let mut result = vec![];
+        while let Some(event_row) = events.next()? {
+            let mut event = self.event_from_event_row(event_row)?;
+            if let EventType::RenameFile(e) = &event.event_type {
+                let (before_hash, after_hash) = if e.to == p {
+                    (None, e.hash.clone())
+                } else if e.from == p {
+                    (e.hash.clone(), None)
+                } else {
+                    continue;
+                };
+                event.event_type = EventType::WriteFile(WriteFileEvent {
+                    path: p.to_path_buf(),
+                    before_hash,
+                    after_hash,
+                });
+            }
+            result.push(event);
+        }
+        Ok(result.into_iter())
+    }
+
+    /// Brings databases created by older versions of wrought up to date.
+    fn upgrade_schema(conn: &rusqlite::Connection) -> anyhow::Result<()> {
+        // The `data` column was added to hold events that don't fit the file/hash columns.
+        let has_data_column = conn
+            .prepare("SELECT * FROM Events LIMIT 0")?
+            .column_names()
+            .contains(&"data");
+        if !has_data_column {
+            conn.execute("ALTER TABLE Events ADD COLUMN data text", ())?;
+        }
+        // Groups only got times later - older groups have a NULL `created_at`.
+        let has_created_at_column = conn
+            .prepare("SELECT * FROM Groups LIMIT 0")?
+            .column_names()
+            .contains(&"created_at");
+        if !has_created_at_column {
+            conn.execute("ALTER TABLE Groups ADD COLUMN created_at text", ())?;
+        }
+        // As was capturing what runs print.
+        let has_output_column = conn
+            .prepare("SELECT * FROM Groups LIMIT 0")?
+            .column_names()
+            .contains(&"output");
+        if !has_output_column {
+            conn.execute("ALTER TABLE Groups ADD COLUMN output text", ())?;
+        }
+        Self::create_pins_table(conn)?;
+        Ok(())
     }
 }
 
 impl EventLog for SQLiteEventLog {
     fn get_last_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>> {
-        let mut stmt = self.conn.prepare("SELECT * FROM Events WHERE action_type='write' AND file_path=?1 ORDER BY id DESC LIMIT 1")?;
+        Ok(self.writes_of(p, "DESC")?.next())
+    }
+
+    fn get_last_read_event(&self, p: &Path) -> anyhow::Result<Option<Event>> {
+        let mut stmt = self.conn.prepare("SELECT * FROM Events WHERE action_type='read' AND file_path=?1 ORDER BY id DESC LIMIT 1")?;
         let mut events = stmt.query([format!("{}", p.display())])?;
         let Some(event_row) = events.next()? else {
             return Ok(None);
@@ -57,15 +242,24 @@ impl EventLog for SQLiteEventLog {
         Ok(Some(event))
     }
 
+    fn get_first_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>> {
+        // Moving a file away can't be what created it.
+        Ok(self
+            .writes_of(p, "ASC")?
+            .find(|e| matches!(&e.event_type, EventType::WriteFile(w) if w.after_hash.is_some())))
+    }
+
     fn get_file_history(&self, p: &Path) -> anyhow::Result<Vec<Event>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT * FROM Events WHERE file_path=?1 ORDER BY id DESC LIMIT 1")?;
+        // A rename is stored under its old path, so `file_path` matches renames away
+        // from `p`. Renames to it are found through `data`.
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT * FROM Events WHERE file_path=?1 OR {} ORDER BY id",
+            RENAME_OF_PATH
+        ))?;
         let mut events = stmt.query([format!("{}", p.display())])?;
         let mut result = vec![];
         while let Some(event_row) = events.next()? {
-            let event = self.event_from_event_row(event_row)?;
-            result.push(event);
+            result.push(self.event_from_event_row(event_row)?);
         }
         Ok(result)
     }
@@ -92,27 +286,154 @@ impl EventLog for SQLiteEventLog {
         Ok(Some(group))
     }
 
+    fn get_groups(&self, ids: &[u64]) -> anyhow::Result<Vec<EventGroup>> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT * FROM Groups WHERE id IN ({}) ORDER BY id",
+            placeholders
+        ))?;
+        let mut groups = stmt.query(rusqlite::params_from_iter(&ids))?;
+        let mut result = vec![];
+        while let Some(group_row) = groups.next()? {
+            result.push(self.group_from_group_row(group_row)?);
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT * FROM Events WHERE group_id IN ({}) ORDER BY id",
+            placeholders
+        ))?;
+        let mut events = stmt.query(rusqlite::params_from_iter(&ids))?;
+        while let Some(event_row) = events.next()? {
+            let event = self.event_from_event_row(event_row)?;
+            if let Some(group) = result.iter_mut().find(|g| g.id == event.group_id) {
+                group.events.push(event);
+            }
+        }
+        Ok(result)
+    }
+
+    fn last_group_id(&self) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .conn
+            .query_row("SELECT MAX(id) FROM Groups", (), |row| row.get(0))?)
+    }
+
+    fn groups_for_command(&self, command: &str) -> anyhow::Result<Vec<EventGroup>> {
+        // Compared with substr rather than LIKE, so `%` and `_` in commands aren't special.
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM Groups WHERE command=?1 OR substr(command, 1, length(?2))=?2",
+        )?;
+        let ids = stmt
+            .query_map((command, format!("{} ", command)), |row| {
+                row.get::<_, u64>(0)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.get_groups(&ids)
+    }
+
     fn add_event_group(&mut self, group: &EventGroup) -> anyhow::Result<EventGroup> {
         // Create the group.
         let mut group = group.clone();
+        let created_at = *group.created_at.get_or_insert_with(|| self.clock.now());
 
         self.conn.execute(
-            "INSERT INTO Groups (command) VALUES (?1)",
-            [group.command.clone()],
+            "INSERT INTO Groups (command, created_at, output) VALUES (?1, ?2, ?3)",
+            (
+                group.command.clone(),
+                created_at.to_rfc3339(),
+                group.output.as_ref().map(|h| h.to_string()),
+            ),
         )?;
 
         group.id = self.conn.last_insert_rowid() as u64;
-        let mut stmt = self.conn.prepare("INSERT INTO Events (group_id, action_type, file_path, before_hash, after_hash) VALUES(?, ?, ?, ?, ?)")?;
+        let mut stmt = self.conn.prepare("INSERT INTO Events (group_id, action_type, file_path, before_hash, after_hash, data) VALUES(?, ?, ?, ?, ?, ?)")?;
 
         for event in &mut group.events {
             event.group_id = group.id;
-            stmt.execute(self.row_from_event_no_id(event))?;
+            stmt.execute(self.row_from_event_no_id(event)?)?;
             event.id = self.conn.last_insert_rowid() as u64;
         }
 
         Ok(group)
     }
 
+    fn delete_group(&mut self, group_id: u64) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM Events WHERE group_id=?1", [group_id])?;
+        let deleted = tx.execute("DELETE FROM Groups WHERE id=?1", [group_id])?;
+        if deleted == 0 {
+            // Dropping the transaction rolls it back.
+            bail!("there is no group with id {}", group_id);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_pin(&self, p: &Path) -> anyhow::Result<Option<ContentHash>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT hash FROM Pins WHERE file_path=?1")?;
+        let mut rows = stmt.query([format!("{}", p.display())])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let hash: String = row.get(0)?;
+        Ok(Some(ContentHash::from_string(&hash)?))
+    }
+
+    fn get_pins(&self) -> anyhow::Result<BTreeMap<PathBuf, ContentHash>> {
+        let mut stmt = self.conn.prepare("SELECT file_path, hash FROM Pins")?;
+        let mut rows = stmt.query(())?;
+        let mut result = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let hash: String = row.get(1)?;
+            result.insert(PathBuf::from(path), ContentHash::from_string(&hash)?);
+        }
+        Ok(result)
+    }
+
+    fn set_pin(&mut self, p: &Path, hash: Option<ContentHash>) -> anyhow::Result<()> {
+        let path = format!("{}", p.display());
+        match hash {
+            Some(hash) => self.conn.execute(
+                "INSERT OR REPLACE INTO Pins (file_path, hash) VALUES (?1, ?2)",
+                (path, hash.to_string()),
+            )?,
+            None => self
+                .conn
+                .execute("DELETE FROM Pins WHERE file_path=?1", (path,))?,
+        };
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> anyhow::Result<Vec<IntegrityIssue>> {
+        let mut issues = vec![];
+        let mut stmt = self.conn.prepare(
+            "SELECT id, group_id FROM Events WHERE group_id NOT IN (SELECT id FROM Groups) ORDER BY id",
+        )?;
+        for row in stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (event_id, group_id) = row?;
+            issues.push(IntegrityIssue::OrphanEvent { event_id, group_id });
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM Groups WHERE id NOT IN (SELECT group_id FROM Events) ORDER BY id",
+        )?;
+        for group_id in stmt.query_map((), |row| row.get(0))? {
+            issues.push(IntegrityIssue::EmptyGroup {
+                group_id: group_id?,
+            });
+        }
+        Ok(issues)
+    }
+
     fn all_event_groups(&self) -> anyhow::Result<Vec<EventGroup>> {
         // Read the group data
         let mut stmt = self.conn.prepare("SELECT * FROM Groups")?;
@@ -146,7 +467,11 @@ impl SQLiteEventLog {
             bail!("event db file '{}' already exists", path.as_ref().display());
         }
         let conn = rusqlite::Connection::open(path)?;
+        Self::create_tables(&conn)
+    }
 
+    fn create_tables(conn: &rusqlite::Connection) -> anyhow::Result<()> {
+        // `data` holds a JSON encoding of events that don't fit the other columns.
         conn.execute(
             "create table Events (
                  id integer primary key,
@@ -154,14 +479,29 @@ impl SQLiteEventLog {
                  action_type text NOT NULL,
                  file_path text,
                  before_hash text,
-                 after_hash text
+                 after_hash text,
+                 data text
              )",
             (),
         )?;
         conn.execute(
             "create table Groups (
                  id integer primary key,
-                 command text NOT NULL
+                 command text NOT NULL,
+                 created_at text,
+                 output text
+             )",
+            (),
+        )?;
+        Self::create_pins_table(conn)?;
+        Ok(())
+    }
+
+    fn create_pins_table(conn: &rusqlite::Connection) -> anyhow::Result<()> {
+        conn.execute(
+            "create table if not exists Pins (
+                 file_path text primary key,
+                 hash text NOT NULL
              )",
             (),
         )?;
@@ -213,8 +553,20 @@ impl SQLiteEventLog {
                 };
                 EventType::ReadFile(read_file_event)
             }
+            "declare" => {
+                let file_path: String = row.get("file_path")?;
+                let hash: String = row.get("after_hash")?;
+                EventType::DeclareOutput(DeclareOutputEvent {
+                    path: PathBuf::from(file_path),
+                    hash: ContentHash::from_string(&hash)?,
+                })
+            }
+            "get_md" => EventType::GetMetadata(Self::data_from_event_row(row, id)?),
+            "set_md" => EventType::SetMetadata(Self::data_from_event_row(row, id)?),
+            "log" => EventType::Log(Self::data_from_event_row(row, id)?),
+            "rename" => EventType::RenameFile(Self::data_from_event_row(row, id)?),
             _ => {
-                unreachable!("Invalid action_type='{}' encountered", action_type);
+                bail!("Invalid action_type='{}' encountered", action_type);
             }
         };
 
@@ -225,54 +577,115 @@ impl SQLiteEventLog {
         })
     }
 
+    fn data_from_event_row<T: serde::de::DeserializeOwned>(
+        row: &rusqlite::Row,
+        id: u64,
+    ) -> anyhow::Result<T> {
+        let data: Option<String> = row.get("data")?;
+        let Some(data) = data else {
+            bail!(
+                "event {} was recorded by an older version of wrought and has no data",
+                id
+            );
+        };
+        Ok(serde_json::from_str(&data)?)
+    }
+
     fn group_from_group_row(&self, row: &rusqlite::Row) -> anyhow::Result<EventGroup> {
         let command = row.get("command")?;
+        let created_at: Option<String> = row.get("created_at")?;
+        let created_at = created_at
+            .map(|t| {
+                DateTime::parse_from_rfc3339(&t)
+                    .map(|t| t.with_timezone(&Utc))
+                    .with_context(|| format!("invalid group time '{}'", t))
+            })
+            .transpose()?;
+        let output: Option<String> = row.get("output")?;
+        let output = output.map(|h| ContentHash::from_string(&h)).transpose()?;
         // TODO: Fill in is_most_recent_run somehow?
         Ok(EventGroup {
             id: row.get("id")?,
             command,
             events: vec![],
             is_most_recent_run: true,
+            created_at,
+            output,
         })
     }
 
-    // Order is group_id, action_type, file_path, before_hash, after_hash
+    // Order is group_id, action_type, file_path, before_hash, after_hash, data
+    #[allow(clippy::type_complexity)]
     fn row_from_event_no_id(
         &self,
         event: &Event,
-    ) -> (String, String, String, Option<String>, Option<String>) {
-        // TODO: Make this work for more types
-        match &event.event_type {
-            // TODO: Fix the "???" values to use e.before_hash and e.after_hash
+    ) -> anyhow::Result<(
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    )> {
+        let row = match &event.event_type {
             EventType::WriteFile(e) => (
                 event.group_id.to_string(),
                 "write".to_string(),
-                e.path.display().to_string(),
+                Some(e.path.display().to_string()),
                 e.before_hash.as_ref().map(|h| h.to_string()),
                 e.after_hash.as_ref().map(|h| h.to_string()),
+                None,
             ),
             EventType::ReadFile(e) => (
                 event.group_id.to_string(),
                 "read".to_string(),
-                e.path.display().to_string(),
+                Some(e.path.display().to_string()),
                 e.hash.as_ref().map(|h| h.to_string()),
                 None,
+                None,
+            ),
+            EventType::DeclareOutput(e) => (
+                event.group_id.to_string(),
+                "declare".to_string(),
+                Some(e.path.display().to_string()),
+                None,
+                Some(e.hash.to_string()),
+                None,
             ),
             EventType::GetMetadata(e) => (
                 event.group_id.to_string(),
                 "get_md".to_string(),
-                e.path.display().to_string(),
+                Some(e.path.display().to_string()),
                 None,
                 None,
+                Some(serde_json::to_string::<GetMetadataEvent>(e)?),
             ),
             EventType::SetMetadata(e) => (
                 event.group_id.to_string(),
                 "set_md".to_string(),
-                e.path.display().to_string(),
+                Some(e.path.display().to_string()),
                 None,
                 None,
+                Some(serde_json::to_string::<SetMetadataEvent>(e)?),
             ),
-        }
+            EventType::Log(e) => (
+                event.group_id.to_string(),
+                "log".to_string(),
+                None,
+                None,
+                None,
+                Some(serde_json::to_string::<LogEvent>(e)?),
+            ),
+            EventType::RenameFile(e) => (
+                event.group_id.to_string(),
+                "rename".to_string(),
+                Some(e.from.display().to_string()),
+                None,
+                e.hash.as_ref().map(|h| h.to_string()),
+                Some(serde_json::to_string::<RenameFileEvent>(e)?),
+            ),
+        };
+        Ok(row)
     }
 }
 
@@ -286,19 +699,41 @@ pub mod test_utils {
 
         impl EventLog for EventLog {
             fn get_last_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
+            fn get_last_read_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
+            fn get_first_write_event(&self, p: &Path) -> anyhow::Result<Option<Event>>;
             fn get_file_history(&self, p: &Path) -> anyhow::Result<Vec<Event>>;
             fn get_event_group(&self, group_id: u64) -> anyhow::Result<Option<EventGroup>>;
+            fn get_groups(&self, ids: &[u64]) -> anyhow::Result<Vec<EventGroup>>;
+            fn last_group_id(&self) -> anyhow::Result<Option<u64>>;
+            fn groups_for_command(&self, command: &str) -> anyhow::Result<Vec<EventGroup>>;
             fn add_event_group(&mut self, group: &EventGroup) -> anyhow::Result<EventGroup>;
+            fn delete_group(&mut self, group_id: u64) -> anyhow::Result<()>;
+            fn get_pin(&self, p: &Path) -> anyhow::Result<Option<ContentHash>>;
+            fn get_pins(&self) -> anyhow::Result<BTreeMap<PathBuf, ContentHash>>;
+            fn set_pin(&mut self, p: &Path, hash: Option<ContentHash>) -> anyhow::Result<()>;
             fn all_event_groups(&self) -> anyhow::Result<Vec<EventGroup>>;
+            fn check_integrity(&self) -> anyhow::Result<Vec<IntegrityIssue>>;
         }
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use std::path::PathBuf;
-
-    use super::{test_utils::MockEventLog, EventLog};
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use chrono::{DateTime, Utc};
+
+    use super::{test_utils::MockEventLog, EventLog, IntegrityIssue, SQLiteEventLog};
+    use crate::binary16::ContentHash;
+    use crate::clock::FixedClock;
+    use crate::events::{
+        Event, EventGroup, EventType, LogEvent, LogLevel, RenameFileEvent, SetMetadataEvent,
+        WriteFileEvent,
+    };
+    use crate::metadata::{MetadataEntry, MetadataKey};
 
     pub fn check_mocking_works() {
         let mut event_log = MockEventLog::default();
@@ -313,4 +748,253 @@ pub mod test {
             None
         );
     }
+
+    #[test]
+    pub fn log_and_metadata_events_round_trip() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(SetMetadataEvent {
+            path: PathBuf::from("a.md"),
+            key: MetadataKey::from("title"),
+            before_value: None,
+            after_value: Some(MetadataEntry::from("A")),
+        }));
+        group.events.push(Event::from(LogEvent {
+            level: LogLevel::Warn,
+            message: "title was missing".to_string(),
+        }));
+
+        let added = event_log.add_event_group(&group).unwrap();
+        let read_back = event_log.get_event_group(added.id).unwrap().unwrap();
+        assert_eq!(read_back.events, added.events);
+    }
+
+    #[test]
+    pub fn renames_are_in_the_history_of_both_paths() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let hash = ContentHash::from_content(b"a");
+
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("a.md"),
+            before_hash: None,
+            after_hash: Some(hash.clone()),
+        }));
+        group.events.push(Event::from(RenameFileEvent {
+            from: PathBuf::from("a.md"),
+            to: PathBuf::from("b.md"),
+            hash: Some(hash.clone()),
+        }));
+        // A rename that has nothing to do with either.
+        group.events.push(Event::from(RenameFileEvent {
+            from: PathBuf::from("x.md"),
+            to: PathBuf::from("y.md"),
+            hash: None,
+        }));
+        let added = event_log.add_event_group(&group).unwrap();
+        let rename = added.events[1].clone();
+
+        let a_history = event_log.get_file_history(&PathBuf::from("a.md")).unwrap();
+        assert_eq!(a_history.len(), 2);
+        assert_eq!(a_history[1], rename);
+        let b_history = event_log.get_file_history(&PathBuf::from("b.md")).unwrap();
+        assert_eq!(b_history, vec![rename]);
+        assert!(event_log
+            .get_file_history(&PathBuf::from("c.md"))
+            .unwrap()
+            .is_empty());
+
+        // The rename is the last write of both.
+        let last_a = event_log.get_last_write_event(Path::new("a.md")).unwrap();
+        assert!(matches!(
+            last_a.unwrap().event_type,
+            EventType::WriteFile(WriteFileEvent {
+                after_hash: None,
+                ..
+            })
+        ));
+        let last_b = event_log.get_last_write_event(Path::new("b.md")).unwrap();
+        assert!(matches!(
+            last_b.unwrap().event_type,
+            EventType::WriteFile(WriteFileEvent { after_hash: Some(h), .. }) if h == hash
+        ));
+        assert!(event_log
+            .get_last_write_event(Path::new("c.md"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    pub fn old_databases_gain_data_column() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "create table Events (id integer primary key, group_id integer NOT NULL, action_type text NOT NULL, file_path text, before_hash text, after_hash text)",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "create table Groups (id integer primary key, command text NOT NULL)",
+            (),
+        )
+        .unwrap();
+        conn.execute("INSERT INTO Groups (command) VALUES ('old')", ())
+            .unwrap();
+        SQLiteEventLog::upgrade_schema(&conn).unwrap();
+        // Running it again must be harmless.
+        SQLiteEventLog::upgrade_schema(&conn).unwrap();
+
+        let stmt = conn.prepare("SELECT * FROM Events LIMIT 0").unwrap();
+        assert!(stmt.column_names().contains(&"data"));
+        drop(stmt);
+
+        // Groups from before times were recorded have none.
+        let mut event_log = SQLiteEventLog::from_connection(conn);
+        let old = event_log.get_event_group(1).unwrap().unwrap();
+        assert_eq!(old.created_at, None);
+        assert_eq!(old.output, None);
+        let new = event_log.add_event_group(&EventGroup::empty()).unwrap();
+        let new = event_log.get_event_group(new.id).unwrap().unwrap();
+        assert!(new.created_at.is_some());
+    }
+
+    #[test]
+    pub fn created_at_comes_from_the_clock() {
+        let time = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut event_log = SQLiteEventLog::open_in_memory()
+            .unwrap()
+            .with_clock(Arc::new(FixedClock(time)));
+
+        let added = event_log.add_event_group(&EventGroup::empty()).unwrap();
+        assert_eq!(added.created_at, Some(time));
+        let read_back = event_log.get_event_group(added.id).unwrap().unwrap();
+        assert_eq!(read_back.created_at, Some(time));
+
+        // A group that already has a time keeps it.
+        let mut group = EventGroup::empty();
+        let earlier = time - chrono::Duration::days(1);
+        group.created_at = Some(earlier);
+        let added = event_log.add_event_group(&group).unwrap();
+        let read_back = event_log.get_event_group(added.id).unwrap().unwrap();
+        assert_eq!(read_back.created_at, Some(earlier));
+    }
+
+    #[test]
+    pub fn check_integrity_finds_orphans_and_empty_groups() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(LogEvent {
+            level: LogLevel::Info,
+            message: "fine".to_string(),
+        }));
+        event_log.add_event_group(&group).unwrap();
+        assert_eq!(event_log.check_integrity().unwrap(), vec![]);
+
+        let empty = event_log.add_event_group(&EventGroup::empty()).unwrap();
+        // Only possible with foreign keys off, e.g. a database written by another tool.
+        event_log
+            .conn
+            .execute("PRAGMA foreign_keys = OFF", ())
+            .unwrap();
+        event_log
+            .conn
+            .execute(
+                "INSERT INTO Events (group_id, action_type, file_path) VALUES (99, 'read', 'a.md')",
+                (),
+            )
+            .unwrap();
+        let orphan = event_log.conn.last_insert_rowid() as u64;
+
+        assert_eq!(
+            event_log.check_integrity().unwrap(),
+            vec![
+                IntegrityIssue::OrphanEvent {
+                    event_id: orphan,
+                    group_id: 99,
+                },
+                IntegrityIssue::EmptyGroup { group_id: empty.id },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn get_groups_fetches_several_groups() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut ids = vec![];
+        for i in 0..3 {
+            let mut group = EventGroup::empty();
+            group.command = format!("command {}", i);
+            group.events.push(Event::from(LogEvent {
+                level: LogLevel::Info,
+                message: format!("message {}", i),
+            }));
+            ids.push(event_log.add_event_group(&group).unwrap().id);
+        }
+
+        let groups = event_log.get_groups(&[ids[2], ids[0], 1000]).unwrap();
+        let commands: Vec<_> = groups.iter().map(|g| g.command.as_str()).collect();
+        assert_eq!(commands, vec!["command 0", "command 2"]);
+        assert_eq!(groups[1].events.len(), 1);
+    }
+
+    #[test]
+    pub fn groups_for_command_matches_whole_words() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        for command in [
+            "run-script blog/build.luau",
+            "run-script blog/build.luau2",
+            "run-script docs/100%_done.luau",
+            "run-script blog/build.luau",
+        ] {
+            let mut group = EventGroup::empty();
+            group.command = command.to_string();
+            event_log.add_event_group(&group).unwrap();
+        }
+
+        let ids = |command: &str| -> Vec<u64> {
+            event_log
+                .groups_for_command(command)
+                .unwrap()
+                .iter()
+                .map(|g| g.id)
+                .collect()
+        };
+        assert_eq!(ids("run-script blog/build.luau"), vec![1, 4]);
+        assert_eq!(ids("run-script"), vec![1, 2, 3, 4]);
+        assert_eq!(ids("run-script docs/100%_done.luau"), vec![3]);
+        assert!(ids("run-script blog").is_empty());
+    }
+
+    #[test]
+    pub fn first_write_event_is_the_earliest_write() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let path = PathBuf::from("notes.md");
+        let mut hash = None;
+        let mut group_ids = vec![];
+        for i in 0..3u8 {
+            let mut group = EventGroup::empty();
+            group.command = format!("generate {}", i);
+            let after_hash = Some(ContentHash::from_content(&[i]));
+            group.events.push(Event::from(WriteFileEvent {
+                path: path.clone(),
+                before_hash: hash.clone(),
+                after_hash: after_hash.clone(),
+            }));
+            hash = after_hash;
+            group_ids.push(event_log.add_event_group(&group).unwrap().id);
+        }
+
+        let first = event_log.get_first_write_event(&path).unwrap().unwrap();
+        let last = event_log.get_last_write_event(&path).unwrap().unwrap();
+        assert_eq!(first.group_id, group_ids[0]);
+        assert_eq!(last.group_id, group_ids[2]);
+        assert_eq!(
+            event_log
+                .get_first_write_event(&PathBuf::from("other.md"))
+                .unwrap(),
+            None
+        );
+    }
 }
@@ -1,10 +1,14 @@
+use std::fmt::Display;
 use std::path::PathBuf;
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
 use crate::binary16::ContentHash;
 use crate::metadata::MetadataEntry;
 use crate::metadata::MetadataKey;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Event {
     pub id: u64,
     pub group_id: u64,
@@ -18,16 +22,19 @@ impl Event {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum EventType {
     WriteFile(WriteFileEvent),
     ReadFile(ReadFileEvent),
     GetMetadata(GetMetadataEvent),
     SetMetadata(SetMetadataEvent),
+    Log(LogEvent),
+    DeclareOutput(DeclareOutputEvent),
+    RenameFile(RenameFileEvent),
 }
 
 // Can actually represent create/modify/delete
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WriteFileEvent {
     pub path: PathBuf,
     pub before_hash: Option<ContentHash>,
@@ -35,20 +42,37 @@ pub struct WriteFileEvent {
 }
 
 // When called on a missing file, hash=None
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReadFileEvent {
     pub path: PathBuf,
     pub hash: Option<ContentHash>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+// A script claiming a file as its output without writing it, e.g. because it was
+// already up to date. The run takes the content it had then, `hash`, as its output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeclareOutputEvent {
+    pub path: PathBuf,
+    pub hash: ContentHash,
+}
+
+// A file moved from `from` to `to`, keeping its content, `hash`. One event rather
+// than a delete and a write, so the history of both paths stays connected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenameFileEvent {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub hash: Option<ContentHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GetMetadataEvent {
     pub path: PathBuf,
     pub key: MetadataKey,
     pub value: Option<MetadataEntry>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SetMetadataEvent {
     pub path: PathBuf,
     pub key: MetadataKey,
@@ -56,6 +80,90 @@ pub struct SetMetadataEvent {
     pub after_value: Option<MetadataEntry>,
 }
 
+fn display_hash(h: &Option<ContentHash>) -> String {
+    match h {
+        Some(h) => h.to_string(),
+        None => "nothing".to_string(),
+    }
+}
+
+fn display_metadata(v: &Option<MetadataEntry>) -> String {
+    match v {
+        Some(v) => format!("{:?}", v.as_string()),
+        None => "nothing".to_string(),
+    }
+}
+
+// One line summaries, as used by `wrought log`
+impl Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventType::WriteFile(e) => write!(
+                f,
+                "write {} : {} -> {}",
+                e.path.display(),
+                display_hash(&e.before_hash),
+                display_hash(&e.after_hash)
+            ),
+            EventType::ReadFile(e) => {
+                write!(f, "read {} : {}", e.path.display(), display_hash(&e.hash))
+            }
+            EventType::GetMetadata(e) => write!(
+                f,
+                "get_metadata {} {} : {}",
+                e.path.display(),
+                e.key.as_string(),
+                display_metadata(&e.value)
+            ),
+            EventType::SetMetadata(e) => write!(
+                f,
+                "set_metadata {} {} : {} -> {}",
+                e.path.display(),
+                e.key.as_string(),
+                display_metadata(&e.before_value),
+                display_metadata(&e.after_value)
+            ),
+            EventType::Log(e) => write!(f, "{}: {}", e.level, e.message),
+            EventType::DeclareOutput(e) => {
+                write!(f, "declare_output {} : {}", e.path.display(), e.hash)
+            }
+            EventType::RenameFile(e) => write!(
+                f,
+                "rename {} -> {} : {}",
+                e.from.display(),
+                e.to.display(),
+                display_hash(&e.hash)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Info,
+    Warn,
+    /// Only recorded by `Bridge::fail` - a group with one of these is a failed run.
+    Error,
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Warn => write!(f, "warn"),
+            LogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+// A message a script wanted recorded alongside what it did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEvent {
+    pub level: LogLevel,
+    pub message: String,
+}
+
 impl From<WriteFileEvent> for EventType {
     fn from(value: WriteFileEvent) -> Self {
         EventType::WriteFile(value)
@@ -124,12 +232,68 @@ impl From<SetMetadataEvent> for Event {
     }
 }
 
-#[derive(Debug, Clone)]
+impl From<LogEvent> for EventType {
+    fn from(value: LogEvent) -> Self {
+        EventType::Log(value)
+    }
+}
+
+impl From<LogEvent> for Event {
+    fn from(value: LogEvent) -> Self {
+        let event_type = value.into();
+        Event {
+            id: 0,
+            group_id: 0,
+            event_type,
+        }
+    }
+}
+
+impl From<DeclareOutputEvent> for EventType {
+    fn from(value: DeclareOutputEvent) -> Self {
+        EventType::DeclareOutput(value)
+    }
+}
+
+impl From<DeclareOutputEvent> for Event {
+    fn from(value: DeclareOutputEvent) -> Self {
+        let event_type = value.into();
+        Event {
+            id: 0,
+            group_id: 0,
+            event_type,
+        }
+    }
+}
+
+impl From<RenameFileEvent> for EventType {
+    fn from(value: RenameFileEvent) -> Self {
+        EventType::RenameFile(value)
+    }
+}
+
+impl From<RenameFileEvent> for Event {
+    fn from(value: RenameFileEvent) -> Self {
+        let event_type = value.into();
+        Event {
+            id: 0,
+            group_id: 0,
+            event_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventGroup {
     pub id: u64,
     pub command: String,
     pub events: Vec<Event>,
     pub is_most_recent_run: bool,
+    /// When the group was added to the event log. `None` for groups recorded before
+    /// wrought stored times.
+    pub created_at: Option<DateTime<Utc>>,
+    /// What the run printed, kept in the content store. `None` if it printed nothing.
+    pub output: Option<ContentHash>,
 }
 impl EventGroup {
     pub(crate) fn empty() -> EventGroup {
@@ -138,6 +302,8 @@ impl EventGroup {
             command: "unknown".to_string(),
             events: vec![],
             is_most_recent_run: true,
+            created_at: None,
+            output: None,
         }
     }
 }
@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{backend::Backend, binary16::ContentHash, event_log::EventLog, events::EventGroup};
+
+/// Bumped whenever the layout of `EventLogExport` changes.
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Everything wrought knows about a project's history, as written by `wrought export`.
+/// Groups and events are sorted by id and maps by key, so exporting the same state
+/// twice gives byte-identical output that is easy to diff or commit.
+#[derive(Debug, Serialize)]
+pub struct EventLogExport {
+    pub schema_version: u32,
+    pub groups: Vec<EventGroup>,
+    pub pins: BTreeMap<PathBuf, ContentHash>,
+    /// path -> key -> value
+    pub metadata: BTreeMap<PathBuf, BTreeMap<String, String>>,
+}
+
+impl EventLogExport {
+    pub fn collect(event_log: &dyn EventLog, backend: &dyn Backend) -> anyhow::Result<Self> {
+        let mut groups = event_log.all_event_groups()?;
+        groups.sort_by_key(|g| g.id);
+        for group in &mut groups {
+            group.events.sort_by_key(|e| e.id);
+        }
+        let metadata = backend
+            .get_all_metadata()?
+            .into_iter()
+            .map(|(path, entries)| {
+                let entries = entries
+                    .into_iter()
+                    .map(|(k, v)| (k.as_string(), v.as_string()))
+                    .collect();
+                (path, entries)
+            })
+            .collect();
+        Ok(EventLogExport {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            groups,
+            pins: event_log.get_pins()?,
+            metadata,
+        })
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::EventLogExport;
+    use crate::{
+        backend::test_utils::MockBackend,
+        binary16::ContentHash,
+        event_log::{EventLog, SQLiteEventLog},
+        events::{Event, EventGroup, ReadFileEvent, WriteFileEvent},
+        metadata::{MetadataEntry, MetadataKey},
+    };
+
+    #[test]
+    pub fn exports_are_stable() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        for (output, input) in [("b.md", "a.md"), ("a.md", "src.md")] {
+            let mut group = EventGroup::empty();
+            group.command = format!("build {}", output);
+            group.events.push(Event::from(ReadFileEvent {
+                path: PathBuf::from(input),
+                hash: Some(ContentHash::from_content(input.as_bytes())),
+            }));
+            group.events.push(Event::from(WriteFileEvent {
+                path: PathBuf::from(output),
+                before_hash: None,
+                after_hash: Some(ContentHash::from_content(output.as_bytes())),
+            }));
+            event_log.add_event_group(&group).unwrap();
+        }
+        for path in ["z.md", "a.md"] {
+            event_log
+                .set_pin(
+                    &PathBuf::from(path),
+                    Some(ContentHash::from_content(b"pin")),
+                )
+                .unwrap();
+        }
+
+        let mut backend = MockBackend::default();
+        backend.expect_get_all_metadata().returning(|| {
+            let entries: BTreeMap<_, _> = [("z", "1"), ("a", "2")]
+                .into_iter()
+                .map(|(k, v)| (MetadataKey::from(k), MetadataEntry::from(v)))
+                .collect();
+            Ok([(PathBuf::from("a.md"), entries)].into())
+        });
+
+        let export = || {
+            EventLogExport::collect(&event_log, &backend)
+                .unwrap()
+                .to_json()
+                .unwrap()
+        };
+        let first = export();
+        assert_eq!(first, export());
+        assert!(
+            first.starts_with("{\n  \"schema_version\": 1,"),
+            "{}",
+            first
+        );
+        let ids: Vec<_> = EventLogExport::collect(&event_log, &backend)
+            .unwrap()
+            .groups
+            .iter()
+            .map(|g| g.id)
+            .collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "{:?}", ids);
+        assert!(first.find("\"a\": \"2\"").unwrap() < first.find("\"z\": \"1\"").unwrap());
+    }
+}
@@ -1,9 +1,17 @@
 use std::{
-    path::Path,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
-use crate::{binary16::ContentHash, event_log::EventLog, events::EventType};
+use anyhow::Context;
+
+use crate::{
+    binary16::ContentHash,
+    event_log::EventLog,
+    events::{EventGroup, EventType},
+    time_window::TimeWindow,
+};
 
 #[derive(Debug, PartialEq)]
 pub struct EventLogCommand(pub String);
@@ -15,43 +23,142 @@ pub enum FileHistoryEntry {
     UnknownHash(ContentHash),
     StoredHash(ContentHash, EventLogCommand),
     LocalChanges(ContentHash),
+    /// A write didn't start from what the previous write left - `expected` is what that
+    /// was, `found` what the write says it replaced. Either the file was changed outside
+    /// wrought, or the event log is inconsistent. Followed by the entry for `found`.
+    BrokenChain {
+        expected: Option<ContentHash>,
+        found: Option<ContentHash>,
+    },
+    /// The file was moved away to `to`, leaving nothing here.
+    RenamedTo {
+        to: PathBuf,
+        command: EventLogCommand,
+    },
+    /// The file arrived by being moved here from `from`, with its content `hash`.
+    RenamedFrom {
+        from: PathBuf,
+        hash: Option<ContentHash>,
+        command: EventLogCommand,
+    },
 }
 
+/// Reports a change that didn't start from what the previous one left.
+fn check_chain(
+    entries: &mut Vec<FileHistoryEntry>,
+    expected: &Option<ContentHash>,
+    found: &Option<ContentHash>,
+) {
+    if found == expected {
+        return;
+    }
+    entries.push(FileHistoryEntry::BrokenChain {
+        expected: expected.clone(),
+        found: found.clone(),
+    });
+    if let Some(hash) = found {
+        entries.push(FileHistoryEntry::UnknownHash(hash.clone()));
+    } else {
+        entries.push(FileHistoryEntry::Deleted);
+    }
+}
+
+/// Only writes made within `window` are listed. Local changes are listed unless the
+/// window has an end, as they're only known to be "now".
 pub fn file_history(
     fs: Arc<Mutex<dyn xfs::Xfs>>,
     event_log: Arc<Mutex<dyn EventLog>>,
     project_root: &Path,
     file_path: &Path,
+    window: &TimeWindow,
 ) -> anyhow::Result<Vec<FileHistoryEntry>> {
     let mut entries = vec![];
-    let events = event_log.lock().unwrap().get_file_history(file_path)?;
+    let mut events = event_log.lock().unwrap().get_file_history(file_path)?;
+    // Ids only increase, so they give the true order - even if the clock didn't.
+    events.sort_by_key(|e| (e.group_id, e.id));
+
+    // Fetch all the groups we need up front, rather than one query per event.
+    let group_ids: Vec<u64> = events
+        .iter()
+        .filter(|e| {
+            matches!(
+                e.event_type,
+                EventType::WriteFile(_) | EventType::RenameFile(_)
+            )
+        })
+        .map(|e| e.group_id)
+        .collect();
+    let groups: BTreeMap<u64, EventGroup> = event_log
+        .lock()
+        .unwrap()
+        .get_groups(&group_ids)?
+        .into_iter()
+        .map(|g| (g.id, g))
+        .collect();
+
     let mut last_write_hash = None;
     for e in events {
         match e.event_type {
             EventType::WriteFile(write_file_event) => {
-                if write_file_event.before_hash != last_write_hash {
-                    if let Some(hash) = write_file_event.before_hash {
-                        entries.push(FileHistoryEntry::UnknownHash(hash));
-                    } else {
-                        entries.push(FileHistoryEntry::Deleted);
-                    }
+                let group = groups.get(&e.group_id).with_context(|| {
+                    format!("event {} refers to unknown group {}", e.id, e.group_id)
+                })?;
+                // Still need to follow the hashes of writes outside the window,
+                // so the first write inside it isn't reported as following a gap.
+                if !window.contains(group.created_at) {
+                    last_write_hash = write_file_event.after_hash;
+                    continue;
                 }
-                let group = event_log.lock().unwrap().get_event_group(e.group_id)?;
+                check_chain(
+                    &mut entries,
+                    &last_write_hash,
+                    &write_file_event.before_hash,
+                );
+                let command = group.command.clone();
                 if let Some(hash) = &write_file_event.after_hash {
                     entries.push(FileHistoryEntry::StoredHash(
                         hash.clone(),
-                        EventLogCommand(group.unwrap().command),
+                        EventLogCommand(command),
                     ));
                 } else {
-                    entries.push(FileHistoryEntry::DeletedBy(EventLogCommand(
-                        group.unwrap().command,
-                    )));
+                    entries.push(FileHistoryEntry::DeletedBy(EventLogCommand(command)));
                 }
                 last_write_hash = write_file_event.after_hash;
             }
             EventType::ReadFile(_read_file_event) => {}
             EventType::GetMetadata(_get_metadata_event) => {}
-            EventType::SetMetadata(set_metadata_event) => eprint!("{:?}", set_metadata_event),
+            EventType::SetMetadata(_set_metadata_event) => {}
+            EventType::Log(_) => {}
+            // Only writes are history - a declaration doesn't change the file.
+            EventType::DeclareOutput(_) => {}
+            EventType::RenameFile(rename) => {
+                let group = groups.get(&e.group_id).with_context(|| {
+                    format!("event {} refers to unknown group {}", e.id, e.group_id)
+                })?;
+                // Moving onto a path replaces whatever was there, so only the old path
+                // needs its chain checking.
+                let moved_here = rename.to == file_path;
+                if !window.contains(group.created_at) {
+                    last_write_hash = if moved_here { rename.hash } else { None };
+                    continue;
+                }
+                let command = EventLogCommand(group.command.clone());
+                if moved_here {
+                    entries.push(FileHistoryEntry::RenamedFrom {
+                        from: rename.from,
+                        hash: rename.hash.clone(),
+                        command,
+                    });
+                    last_write_hash = rename.hash;
+                } else {
+                    check_chain(&mut entries, &last_write_hash, &rename.hash);
+                    entries.push(FileHistoryEntry::RenamedTo {
+                        to: rename.to,
+                        command,
+                    });
+                    last_write_hash = None;
+                }
+            }
         }
     }
     // Now check the actual file
@@ -66,7 +173,7 @@ pub fn file_history(
     } else {
         None
     };
-    if cur_hash != last_write_hash {
+    if cur_hash != last_write_hash && window.until.is_none() {
         if let Some(hash) = cur_hash {
             entries.push(FileHistoryEntry::LocalChanges(hash));
         } else {
@@ -76,85 +183,46 @@ pub fn file_history(
     Ok(entries)
 }
 
+/// The last two different contents wrought wrote to the file, oldest first. Runs that
+/// rewrote the same content don't count. Has fewer entries if there aren't two versions.
+pub fn last_two_versions(entries: &[FileHistoryEntry]) -> Vec<ContentHash> {
+    let mut versions: Vec<ContentHash> = vec![];
+    for entry in entries.iter().rev() {
+        if let FileHistoryEntry::StoredHash(hash, _) = entry {
+            if versions.last() != Some(hash) {
+                versions.push(hash.clone());
+            }
+            if versions.len() == 2 {
+                break;
+            }
+        }
+    }
+    versions.reverse();
+    versions
+}
+
 #[cfg(test)]
 pub mod test {
     use std::{
-        io::Cursor,
         path::PathBuf,
         sync::{Arc, Mutex},
     };
 
     use anyhow::anyhow;
+    use chrono::{DateTime, Duration, Utc};
 
-    use mockall::{mock, predicate};
+    use mockall::predicate;
 
     use crate::{
         binary16::ContentHash,
         event_log::test_utils::MockEventLog,
-        events::{Event, EventGroup, WriteFileEvent},
+        events::{Event, EventGroup, RenameFileEvent, WriteFileEvent},
         file_history::{EventLogCommand, FileHistoryEntry},
+        fs_utils::test_utils::MockFs,
+        time_window::TimeWindow,
     };
 
-    use super::file_history;
-
-    //TODO: Move this somewhere we can reuse it.
-    mock! {
-
-        pub Fs {}
-
-        impl xfs::Xfs for Fs {
-            fn on_each_entry(
-                &self,
-                p: &std::path::Path,
-                f: &mut dyn FnMut(&dyn xfs::Xfs, &dyn xfs::XfsDirEntry) -> anyhow::Result<()>,
-            ) -> anyhow::Result<()>;
-
-            fn on_each_entry_mut(
-                &mut self,
-                p: &std::path::Path,
-                f: &mut dyn FnMut(&mut dyn xfs::Xfs, &dyn xfs::XfsDirEntry) -> anyhow::Result<()>,
-            ) -> anyhow::Result<()>;
-
-            fn reader(&self, p: &std::path::Path) -> xfs::Result<Box<dyn std::io::Read>>;
-            fn reader_if_exists(&self, p: &std::path::Path) -> xfs::Result<Option<Box<dyn std::io::Read>>>;
-            fn writer(&mut self, p: &std::path::Path) -> xfs::Result<Box<dyn std::io::Write>>;
-            fn create_dir(&mut self, p: &std::path::Path) -> xfs::Result<()>;
-            fn create_dir_all(&mut self, p: &std::path::Path) -> xfs::Result<()>;
-            fn read_all_lines(&self, p: &std::path::Path) -> xfs::Result<Vec<String>>;
-            fn metadata(&self, p: &std::path::Path) -> xfs::Result<Box<dyn xfs::XfsMetadata>>;
-            fn tree(&self) -> String;
-            fn canonicalize(&self, p: &std::path::Path) -> xfs::Result<std::path::PathBuf>;
-            fn copy(&mut self, src_path: &std::path::Path, dst_path: &std::path::Path) -> xfs::Result<()>;
-            fn is_dir(&self, p: &std::path::Path) -> bool;
-            fn is_file(&self, p: &std::path::Path) -> bool;
-            fn exists(&self, p: &std::path::Path) -> bool;
-        }
-
-    }
-
-    impl MockFs {
-        pub fn with_read<P: Into<PathBuf>, B: Into<Vec<u8>>>(&mut self, path: P, content: B) {
-            let content = Box::new(Cursor::new(content.into()));
-            self.expect_reader_if_exists()
-                .with(predicate::eq(path.into()))
-                .returning(move |_| Ok(Some(content.clone())));
-        }
-
-        pub fn with_missing_read<P: Into<PathBuf>>(&mut self, path: P) {
-            self.expect_reader_if_exists()
-                .with(predicate::eq(path.into()))
-                .returning(move |_| Ok(None));
-        }
-
-        pub fn with_read_error<P: Into<PathBuf>, F>(&mut self, path: P, f: F)
-        where
-            F: Fn() -> xfs::XfsError + Send + 'static,
-        {
-            self.expect_reader_if_exists()
-                .with(predicate::eq(path.into()))
-                .returning(move |_| Err(f()));
-        }
-    }
+    use super::{file_history, last_two_versions};
 
     #[test]
     pub fn untracked_nonexistant_file() {
@@ -170,11 +238,18 @@ pub mod test {
             .expect_get_file_history()
             .with(predicate::eq(file_path.clone()))
             .returning(move |_| Ok(vec![]));
+        event_log.expect_get_groups().returning(|_| Ok(vec![]));
 
         let fs = Arc::new(Mutex::new(fs));
         let event_log = Arc::new(Mutex::new(event_log));
-        let history =
-            file_history(fs.clone(), event_log.clone(), &project_root, &file_path).unwrap();
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        )
+        .unwrap();
 
         assert_eq!(history, vec![]);
 
@@ -206,6 +281,8 @@ pub mod test {
             command: "dancing".to_string(),
             events: vec![],
             is_most_recent_run: false,
+            created_at: None,
+            output: None,
         };
 
         event_log
@@ -213,14 +290,20 @@ pub mod test {
             .with(predicate::eq(file_path.clone()))
             .returning(move |_| Ok(mock_events.clone()));
         event_log
-            .expect_get_event_group()
-            .with(predicate::eq(12u64))
-            .returning(move |_| Ok(Some(event_group.clone())));
+            .expect_get_groups()
+            .with(predicate::eq(vec![12u64]))
+            .returning(move |_| Ok(vec![event_group.clone()]));
 
         let fs = Arc::new(Mutex::new(fs));
         let event_log = Arc::new(Mutex::new(event_log));
-        let history =
-            file_history(fs.clone(), event_log.clone(), &project_root, &file_path).unwrap();
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        )
+        .unwrap();
 
         assert_eq!(
             history,
@@ -262,6 +345,8 @@ pub mod test {
             command: "dancing".to_string(),
             events: vec![],
             is_most_recent_run: false,
+            created_at: None,
+            output: None,
         };
 
         event_log
@@ -269,14 +354,20 @@ pub mod test {
             .with(predicate::eq(file_path.clone()))
             .returning(move |_| Ok(mock_events.clone()));
         event_log
-            .expect_get_event_group()
-            .with(predicate::eq(12u64))
-            .returning(move |_| Ok(Some(event_group.clone())));
+            .expect_get_groups()
+            .with(predicate::eq(vec![12u64]))
+            .returning(move |_| Ok(vec![event_group.clone()]));
 
         let fs = Arc::new(Mutex::new(fs));
         let event_log = Arc::new(Mutex::new(event_log));
-        let history =
-            file_history(fs.clone(), event_log.clone(), &project_root, &file_path).unwrap();
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        )
+        .unwrap();
 
         assert_eq!(
             history,
@@ -309,10 +400,17 @@ pub mod test {
             .expect_get_file_history()
             .with(predicate::eq(file_path.clone()))
             .returning(move |_| Ok(vec![]));
+        event_log.expect_get_groups().returning(|_| Ok(vec![]));
 
         let fs = Arc::new(Mutex::new(fs));
         let event_log = Arc::new(Mutex::new(event_log));
-        let history = file_history(fs.clone(), event_log.clone(), &project_root, &file_path);
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        );
 
         let e = history.err().unwrap();
 
@@ -340,7 +438,13 @@ pub mod test {
 
         let fs = Arc::new(Mutex::new(fs));
         let event_log = Arc::new(Mutex::new(event_log));
-        let history = file_history(fs.clone(), event_log.clone(), &project_root, &file_path);
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        );
 
         let e = history.err().unwrap();
 
@@ -349,4 +453,304 @@ pub mod test {
         fs.lock().unwrap().checkpoint();
         event_log.lock().unwrap().checkpoint();
     }
+
+    #[test]
+    pub fn long_history_fetches_groups_once() {
+        let mut fs = MockFs::default();
+        let mut event_log = MockEventLog::default();
+
+        let project_root = PathBuf::from("project_root");
+        let file_path = PathBuf::from("tofu.txt");
+
+        let content = |i: u64| format!("version {}", i).into_bytes();
+        let mock_events: Vec<Event> = (0..100u64)
+            .map(|i| {
+                Event::from(WriteFileEvent {
+                    path: file_path.clone(),
+                    before_hash: (i > 0).then(|| ContentHash::from_content(&content(i - 1))),
+                    after_hash: Some(ContentHash::from_content(&content(i))),
+                })
+                .with_group_id(i + 1)
+            })
+            .collect();
+
+        fs.with_read(project_root.join(&file_path), content(99));
+        event_log
+            .expect_get_file_history()
+            .returning(move |_| Ok(mock_events.clone()));
+        event_log.expect_get_groups().times(1).returning(|ids| {
+            Ok(ids
+                .iter()
+                .map(|id| EventGroup {
+                    id: *id,
+                    command: format!("run {}", id),
+                    events: vec![],
+                    is_most_recent_run: false,
+                    created_at: None,
+                    output: None,
+                })
+                .collect())
+        });
+        event_log.expect_get_event_group().never();
+
+        let fs = Arc::new(Mutex::new(fs));
+        let event_log = Arc::new(Mutex::new(event_log));
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        )
+        .unwrap();
+
+        assert_eq!(history.len(), 100);
+        assert_eq!(
+            history[99],
+            FileHistoryEntry::StoredHash(
+                ContentHash::from_content(&content(99)),
+                EventLogCommand("run 100".to_string())
+            )
+        );
+
+        fs.lock().unwrap().checkpoint();
+        event_log.lock().unwrap().checkpoint();
+    }
+
+    fn write(before: Option<&[u8]>, after: &[u8], group_id: u64) -> Event {
+        let mut event = Event::from(WriteFileEvent {
+            path: PathBuf::from("tofu.txt"),
+            before_hash: before.map(ContentHash::from_content),
+            after_hash: Some(ContentHash::from_content(after)),
+        })
+        .with_group_id(group_id);
+        event.id = group_id;
+        event
+    }
+
+    fn history_of(events: Vec<Event>, current: &[u8]) -> Vec<FileHistoryEntry> {
+        history_of_path("tofu.txt", events, Some(current))
+    }
+
+    fn history_of_path(
+        path: &str,
+        events: Vec<Event>,
+        current: Option<&[u8]>,
+    ) -> Vec<FileHistoryEntry> {
+        let mut fs = MockFs::default();
+        let mut event_log = MockEventLog::default();
+        let project_root = PathBuf::from("project_root");
+        let file_path = PathBuf::from(path);
+
+        match current {
+            Some(current) => fs.with_read(project_root.join(&file_path), current.to_vec()),
+            None => fs.with_missing_read(project_root.join(&file_path)),
+        }
+        event_log
+            .expect_get_file_history()
+            .returning(move |_| Ok(events.clone()));
+        event_log.expect_get_groups().returning(|ids| {
+            Ok(ids
+                .iter()
+                .map(|id| EventGroup {
+                    id: *id,
+                    command: format!("run {}", id),
+                    events: vec![],
+                    is_most_recent_run: false,
+                    created_at: None,
+                    output: None,
+                })
+                .collect())
+        });
+
+        file_history(
+            Arc::new(Mutex::new(fs)),
+            Arc::new(Mutex::new(event_log)),
+            &project_root,
+            &file_path,
+            &TimeWindow::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    pub fn events_are_replayed_in_id_order() {
+        let events = vec![
+            write(Some(b"b"), b"c", 3),
+            write(None, b"a", 1),
+            write(Some(b"a"), b"b", 2),
+        ];
+        let history = history_of(events, b"c");
+        let stored: Vec<_> = history
+            .iter()
+            .map(|e| match e {
+                FileHistoryEntry::StoredHash(_, command) => command.0.as_str(),
+                e => panic!("unexpected entry {:?}", e),
+            })
+            .collect();
+        assert_eq!(stored, vec!["run 1", "run 2", "run 3"]);
+    }
+
+    #[test]
+    pub fn broken_chain_is_reported() {
+        let events = vec![write(None, b"a", 1), write(Some(b"edited"), b"c", 2)];
+        let history = history_of(events, b"c");
+        let hash = |c: &[u8]| ContentHash::from_content(c);
+        assert_eq!(
+            history,
+            vec![
+                FileHistoryEntry::StoredHash(hash(b"a"), EventLogCommand("run 1".to_string())),
+                FileHistoryEntry::BrokenChain {
+                    expected: Some(hash(b"a")),
+                    found: Some(hash(b"edited")),
+                },
+                FileHistoryEntry::UnknownHash(hash(b"edited")),
+                FileHistoryEntry::StoredHash(hash(b"c"), EventLogCommand("run 2".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn renames_show_in_the_history_of_both_paths() {
+        let hash = |c: &[u8]| ContentHash::from_content(c);
+        let mut rename = Event::from(RenameFileEvent {
+            from: PathBuf::from("tofu.txt"),
+            to: PathBuf::from("bean_curd.txt"),
+            hash: Some(hash(b"a")),
+        })
+        .with_group_id(2);
+        rename.id = 2;
+
+        let history = history_of_path("tofu.txt", vec![write(None, b"a", 1), rename.clone()], None);
+        assert_eq!(
+            history,
+            vec![
+                FileHistoryEntry::StoredHash(hash(b"a"), EventLogCommand("run 1".to_string())),
+                FileHistoryEntry::RenamedTo {
+                    to: PathBuf::from("bean_curd.txt"),
+                    command: EventLogCommand("run 2".to_string()),
+                },
+            ]
+        );
+
+        // The moved content is what wrought expects at the new path - no local changes.
+        let history = history_of_path("bean_curd.txt", vec![rename], Some(b"a"));
+        assert_eq!(
+            history,
+            vec![FileHistoryEntry::RenamedFrom {
+                from: PathBuf::from("tofu.txt"),
+                hash: Some(hash(b"a")),
+                command: EventLogCommand("run 2".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn last_two_versions_skips_unchanged_rewrites() {
+        let hash = |c: &[u8]| ContentHash::from_content(c);
+        let events = vec![
+            write(None, b"a", 1),
+            write(Some(b"a"), b"b", 2),
+            write(Some(b"b"), b"b", 3),
+        ];
+        let history = history_of(events, b"edited");
+        assert_eq!(last_two_versions(&history), vec![hash(b"a"), hash(b"b")]);
+
+        let history = history_of(vec![write(None, b"a", 1)], b"a");
+        assert_eq!(last_two_versions(&history), vec![hash(b"a")]);
+    }
+
+    #[test]
+    pub fn history_within_window() {
+        let mut fs = MockFs::default();
+        let mut event_log = MockEventLog::default();
+
+        let project_root = PathBuf::from("project_root");
+        let file_path = PathBuf::from("tofu.txt");
+
+        let day = |d: i64| {
+            DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc)
+                + Duration::days(d)
+        };
+        let content = |i: u64| format!("version {}", i).into_bytes();
+        let mock_events: Vec<Event> = (0..3u64)
+            .map(|i| {
+                Event::from(WriteFileEvent {
+                    path: file_path.clone(),
+                    before_hash: (i > 0).then(|| ContentHash::from_content(&content(i - 1))),
+                    after_hash: Some(ContentHash::from_content(&content(i))),
+                })
+                .with_group_id(i + 1)
+            })
+            .collect();
+
+        fs.with_read(project_root.join(&file_path), b"local edit");
+        event_log
+            .expect_get_file_history()
+            .returning(move |_| Ok(mock_events.clone()));
+        event_log.expect_get_groups().returning(move |ids| {
+            Ok(ids
+                .iter()
+                .map(|id| EventGroup {
+                    id: *id,
+                    command: format!("run {}", id),
+                    events: vec![],
+                    is_most_recent_run: false,
+                    created_at: Some(day(*id as i64)),
+                    output: None,
+                })
+                .collect())
+        });
+
+        let fs = Arc::new(Mutex::new(fs));
+        let event_log = Arc::new(Mutex::new(event_log));
+        let since_day_2 = TimeWindow {
+            since: Some(day(2)),
+            until: None,
+        };
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &since_day_2,
+        )
+        .unwrap();
+        assert_eq!(
+            history,
+            vec![
+                FileHistoryEntry::StoredHash(
+                    ContentHash::from_content(&content(1)),
+                    EventLogCommand("run 2".to_string())
+                ),
+                FileHistoryEntry::StoredHash(
+                    ContentHash::from_content(&content(2)),
+                    EventLogCommand("run 3".to_string())
+                ),
+                FileHistoryEntry::LocalChanges(ContentHash::from_content(b"local edit")),
+            ]
+        );
+
+        let until_day_1 = TimeWindow {
+            since: None,
+            until: Some(day(1)),
+        };
+        let history = file_history(
+            fs.clone(),
+            event_log.clone(),
+            &project_root,
+            &file_path,
+            &until_day_1,
+        )
+        .unwrap();
+        assert_eq!(
+            history,
+            vec![FileHistoryEntry::StoredHash(
+                ContentHash::from_content(&content(0)),
+                EventLogCommand("run 1".to_string())
+            )]
+        );
+    }
 }
@@ -1,4 +1,53 @@
-use std::path::{Path, PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+/// The directories and files `copy_dir_all_with_filters` would create, worked out
+/// without touching the destination.
+#[derive(Debug, Default, PartialEq)]
+pub struct CopyPlan {
+    pub dirs: Vec<PathBuf>,
+    /// (source, destination) pairs.
+    pub files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl CopyPlan {
+    pub fn execute(&self, fs: &mut dyn xfs::Xfs) -> anyhow::Result<()> {
+        for dir in &self.dirs {
+            fs.create_dir_all(dir)?;
+        }
+        for (src, dst) in &self.files {
+            fs.copy(src, dst)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `path` by way of `tmp`, which is moved over it once complete - so a failed
+/// write, or a crash part way through, leaves `path` as it was. Xfs has no way to move or
+/// remove files, so this works on the real filesystem directly.
+pub fn replace_file(
+    path: &Path,
+    tmp: &Path,
+    write: &mut dyn FnMut(&mut dyn Write) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let result = (|| -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(tmp)
+            .with_context(|| format!("unable to create {}", tmp.display()))?;
+        write(&mut file)?;
+        file.flush()?;
+        file.sync_all()?;
+        std::fs::rename(tmp, path)
+            .with_context(|| format!("unable to move {} to {}", tmp.display(), path.display()))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(tmp);
+    }
+    result
+}
 
 pub fn copy_dir_all_with_filters<F, D>(
     fs: &mut dyn xfs::Xfs,
@@ -11,19 +60,35 @@ where
     F: Fn(&PathBuf, usize) -> bool,
     D: Fn(&PathBuf, usize) -> bool,
 {
+    plan_copy_dir_all_with_filters(&*fs, src, dst, file_filter, dir_filter)?.execute(fs)
+}
+
+/// What `copy_dir_all_with_filters` would do. Only reads `fs`.
+pub fn plan_copy_dir_all_with_filters<F, D>(
+    fs: &dyn xfs::Xfs,
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    file_filter: F,
+    dir_filter: D,
+) -> anyhow::Result<CopyPlan>
+where
+    F: Fn(&PathBuf, usize) -> bool,
+    D: Fn(&PathBuf, usize) -> bool,
+{
+    let mut plan = CopyPlan::default();
     // Apply dir_filter before processing the directory
     if !dir_filter(&src.as_ref().to_path_buf(), 0) {
-        return Ok(());
+        return Ok(plan);
     }
 
     let mut stack = Vec::new();
     stack.push((src.as_ref().to_path_buf(), dst.as_ref().to_path_buf(), 0)); // Initialize with depth 0
 
     while let Some((current_src, current_dst, depth)) = stack.pop() {
-        fs.create_dir_all(&current_dst)?;
+        plan.dirs.push(current_dst.clone());
 
-        fs.on_each_entry_mut(&current_src, &mut |fs: &mut dyn xfs::Xfs,
-                                                 entry: &dyn xfs::XfsDirEntry|
+        fs.on_each_entry(&current_src, &mut |_fs: &dyn xfs::Xfs,
+                                             entry: &dyn xfs::XfsDirEntry|
          -> anyhow::Result<()> {
             let src_path = entry.path();
             let dst_path = current_dst.join(src_path.file_name().unwrap());
@@ -34,10 +99,75 @@ where
                 stack.push((src_path, dst_path, depth + 1));
             } else if md.is_file() && file_filter(&src_path, depth) {
                 // Copy the file only if it passes the file filter
-                fs.copy(&src_path, &dst_path)?;
+                plan.files.push((src_path, dst_path));
             }
             Ok(())
         })?;
     }
-    Ok(())
+    Ok(plan)
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::{io::Cursor, path::PathBuf};
+
+    use mockall::{mock, predicate};
+
+    mock! {
+
+        pub Fs {}
+
+        impl xfs::Xfs for Fs {
+            fn on_each_entry(
+                &self,
+                p: &std::path::Path,
+                f: &mut dyn FnMut(&dyn xfs::Xfs, &dyn xfs::XfsDirEntry) -> anyhow::Result<()>,
+            ) -> anyhow::Result<()>;
+
+            fn on_each_entry_mut(
+                &mut self,
+                p: &std::path::Path,
+                f: &mut dyn FnMut(&mut dyn xfs::Xfs, &dyn xfs::XfsDirEntry) -> anyhow::Result<()>,
+            ) -> anyhow::Result<()>;
+
+            fn reader(&self, p: &std::path::Path) -> xfs::Result<Box<dyn std::io::Read>>;
+            fn reader_if_exists(&self, p: &std::path::Path) -> xfs::Result<Option<Box<dyn std::io::Read>>>;
+            fn writer(&mut self, p: &std::path::Path) -> xfs::Result<Box<dyn std::io::Write>>;
+            fn create_dir(&mut self, p: &std::path::Path) -> xfs::Result<()>;
+            fn create_dir_all(&mut self, p: &std::path::Path) -> xfs::Result<()>;
+            fn read_all_lines(&self, p: &std::path::Path) -> xfs::Result<Vec<String>>;
+            fn metadata(&self, p: &std::path::Path) -> xfs::Result<Box<dyn xfs::XfsMetadata>>;
+            fn tree(&self) -> String;
+            fn canonicalize(&self, p: &std::path::Path) -> xfs::Result<std::path::PathBuf>;
+            fn copy(&mut self, src_path: &std::path::Path, dst_path: &std::path::Path) -> xfs::Result<()>;
+            fn is_dir(&self, p: &std::path::Path) -> bool;
+            fn is_file(&self, p: &std::path::Path) -> bool;
+            fn exists(&self, p: &std::path::Path) -> bool;
+        }
+
+    }
+
+    impl MockFs {
+        pub fn with_read<P: Into<PathBuf>, B: Into<Vec<u8>>>(&mut self, path: P, content: B) {
+            let content = Box::new(Cursor::new(content.into()));
+            self.expect_reader_if_exists()
+                .with(predicate::eq(path.into()))
+                .returning(move |_| Ok(Some(content.clone())));
+        }
+
+        pub fn with_missing_read<P: Into<PathBuf>>(&mut self, path: P) {
+            self.expect_reader_if_exists()
+                .with(predicate::eq(path.into()))
+                .returning(move |_| Ok(None));
+        }
+
+        pub fn with_read_error<P: Into<PathBuf>, F>(&mut self, path: P, f: F)
+        where
+            F: Fn() -> xfs::XfsError + Send + 'static,
+        {
+            self.expect_reader_if_exists()
+                .with(predicate::eq(path.into()))
+                .returning(move |_| Err(f()));
+        }
+    }
 }
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    binary16::ContentHash,
+    content_store::{ContentStore, ContentStoreStats},
+    event_log::EventLog,
+    events::EventType,
+};
+
+/// Below this fraction of the store being reachable, `gc` warns before deleting anything.
+const SUSPICIOUS_REACHABLE_FRACTION: f64 = 0.1;
+
+/// Stores smaller than this are never called suspicious - a young project can easily
+/// have more old versions than current ones.
+const SUSPICIOUS_MIN_OBJECTS: usize = 10;
+
+/// Every hash the event log or the pins refer to. Content with any other hash can't be
+/// reached from wrought's history.
+pub fn reachable_hashes(event_log: &dyn EventLog) -> anyhow::Result<BTreeSet<ContentHash>> {
+    let mut hashes = BTreeSet::new();
+    for group in event_log.all_event_groups()? {
+        hashes.extend(group.output);
+        for event in group.events {
+            match event.event_type {
+                EventType::WriteFile(e) => {
+                    hashes.extend(e.before_hash.into_iter().chain(e.after_hash))
+                }
+                EventType::ReadFile(e) => hashes.extend(e.hash),
+                EventType::DeclareOutput(e) => {
+                    hashes.insert(e.hash);
+                }
+                EventType::RenameFile(e) => hashes.extend(e.hash),
+                EventType::GetMetadata(_) | EventType::SetMetadata(_) | EventType::Log(_) => {}
+            }
+        }
+    }
+    hashes.extend(event_log.get_pins()?.into_values());
+    Ok(hashes)
+}
+
+/// What `wrought content-store gc` would delete. Working it out changes nothing.
+#[derive(Debug)]
+pub struct GcPlan {
+    /// Objects nothing refers to, with their sizes.
+    pub unreachable: Vec<(ContentHash, u64)>,
+    /// How many stored objects are still referred to.
+    pub reachable: usize,
+}
+
+impl GcPlan {
+    pub fn new(event_log: &dyn EventLog, content_store: &dyn ContentStore) -> anyhow::Result<Self> {
+        let reachable_hashes = reachable_hashes(event_log)?;
+        let (reachable, mut unreachable): (Vec<_>, Vec<_>) = content_store
+            .objects()?
+            .into_iter()
+            .partition(|(hash, _)| reachable_hashes.contains(hash));
+        unreachable.sort();
+        Ok(GcPlan {
+            unreachable,
+            reachable: reachable.len(),
+        })
+    }
+
+    /// The count and sizes of the objects that would be deleted.
+    pub fn freed(&self) -> ContentStoreStats {
+        ContentStoreStats::from_sizes(self.unreachable.iter().map(|(_, size)| *size).collect())
+    }
+
+    /// True if so little of a sizeable store is reachable that the event log itself may
+    /// be damaged - in which case deleting would throw away content that is still wanted.
+    pub fn is_suspicious(&self) -> bool {
+        let total = self.reachable + self.unreachable.len();
+        total >= SUSPICIOUS_MIN_OBJECTS
+            && (self.reachable as f64) < total as f64 * SUSPICIOUS_REACHABLE_FRACTION
+    }
+
+    /// Deletes the unreachable objects. Returns how many were removed.
+    pub fn execute(&self, content_store: &mut dyn ContentStore) -> anyhow::Result<usize> {
+        for (hash, _) in &self.unreachable {
+            content_store.remove(hash)?;
+        }
+        Ok(self.unreachable.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use super::GcPlan;
+    use crate::{
+        binary16::ContentHash,
+        content_store::{ContentStore, ContentStoreStats, FileSystemContentStore},
+        event_log::{EventLog, SQLiteEventLog},
+        events::{Event, EventGroup, ReadFileEvent, WriteFileEvent},
+    };
+
+    #[test]
+    pub fn dry_run_reports_unreachable_objects_and_deletes_nothing() {
+        let fs = Arc::new(Mutex::new(xfs::mockfs::MockFS::new()));
+        let mut store = FileSystemContentStore::new(fs, PathBuf::from("project/.wrought/content"));
+        for content in ["written", "read", "printed", "pinned", "old", "orphan"] {
+            store.store(content.as_bytes()).unwrap();
+        }
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(ReadFileEvent {
+            path: PathBuf::from("in.md"),
+            hash: Some(ContentHash::from_content(b"read")),
+        }));
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("out.md"),
+            before_hash: None,
+            after_hash: Some(ContentHash::from_content(b"written")),
+        }));
+        group.output = Some(ContentHash::from_content(b"printed"));
+        event_log.add_event_group(&group).unwrap();
+        event_log
+            .set_pin(
+                &PathBuf::from("pinned.md"),
+                Some(ContentHash::from_content(b"pinned")),
+            )
+            .unwrap();
+
+        let plan = GcPlan::new(&event_log, &store).unwrap();
+        let mut expected = vec![
+            (ContentHash::from_content(b"old"), 3),
+            (ContentHash::from_content(b"orphan"), 6),
+        ];
+        expected.sort();
+        assert_eq!(plan.unreachable, expected);
+        assert_eq!(plan.reachable, 4);
+        assert_eq!(
+            plan.freed(),
+            ContentStoreStats {
+                objects: 2,
+                total_bytes: 9,
+                median_bytes: 4,
+            }
+        );
+        assert!(!plan.is_suspicious());
+        assert_eq!(store.stats().unwrap().objects, 6);
+        assert!(store
+            .retrieve(ContentHash::from_content(b"orphan"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    pub fn mostly_unreachable_stores_are_suspicious() {
+        let plan = |reachable: usize, unreachable: usize| GcPlan {
+            unreachable: (0..unreachable)
+                .map(|i| (ContentHash::from_content(&i.to_le_bytes()), 1))
+                .collect(),
+            reachable,
+        };
+        assert!(plan(0, 20).is_suspicious());
+        assert!(plan(1, 19).is_suspicious());
+        assert!(!plan(2, 18).is_suspicious());
+        // Too small to tell.
+        assert!(!plan(0, 5).is_suspicious());
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// gitignore-style patterns, relative to the project root, for files that status
+/// scanning should skip (build artifacts, `node_modules` etc.)
+pub const IGNORE_FILE: &str = ".wroughtignore";
+
+/// Decides which paths (relative to the project root) are left out of status scanning.
+///
+/// On top of the patterns from `.wroughtignore`, the `.wrought` directory is always
+/// ignored, unless internal files are explicitly requested - and even then its content
+/// store is ignored, as it is never interesting.
+pub struct IgnoreRules {
+    matcher: Gitignore,
+    include_internal: bool,
+}
+
+impl IgnoreRules {
+    pub fn empty() -> IgnoreRules {
+        IgnoreRules {
+            matcher: Gitignore::empty(),
+            include_internal: false,
+        }
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<IgnoreRules> {
+        let mut builder = GitignoreBuilder::new("");
+        for line in content.lines() {
+            builder
+                .add_line(Some(PathBuf::from(IGNORE_FILE)), line)
+                .with_context(|| format!("invalid pattern '{}' in {}", line, IGNORE_FILE))?;
+        }
+        Ok(IgnoreRules {
+            matcher: builder.build()?,
+            include_internal: false,
+        })
+    }
+
+    /// Reads `.wroughtignore` from the project root, if there is one.
+    pub fn load(fs: &dyn xfs::Xfs, project_root: &Path) -> anyhow::Result<IgnoreRules> {
+        let Some(mut reader) = fs.reader_if_exists(&project_root.join(IGNORE_FILE))? else {
+            return Ok(IgnoreRules::empty());
+        };
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        IgnoreRules::parse(&content)
+    }
+
+    pub fn with_internal_files(mut self, include_internal: bool) -> IgnoreRules {
+        self.include_internal = include_internal;
+        self
+    }
+
+    /// `path` must be relative to the project root.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.starts_with(".wrought/content") {
+            return true;
+        }
+        if !self.include_internal && path.starts_with(".wrought") {
+            return true;
+        }
+        self.matcher.matched(path, is_dir).is_ignore()
+    }
+
+    /// Like `is_ignored` but also true if any parent directory is ignored.
+    /// Useful for paths that didn't come from walking the directory tree.
+    pub fn is_ignored_or_in_ignored_dir(&self, path: &Path) -> bool {
+        path.ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| self.is_ignored(p, true))
+            || self.is_ignored(path, false)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::Path;
+
+    use super::IgnoreRules;
+
+    #[test]
+    pub fn internal_dirs_are_always_ignored() {
+        let rules = IgnoreRules::empty();
+        assert!(rules.is_ignored(Path::new(".wrought"), true));
+        assert!(rules.is_ignored(Path::new(".wrought/wrought.db"), false));
+        assert!(!rules.is_ignored(Path::new("notes.md"), false));
+
+        let rules = rules.with_internal_files(true);
+        assert!(!rules.is_ignored(Path::new(".wrought/packages/p/status.toml"), false));
+        assert!(rules.is_ignored(Path::new(".wrought/content/abc"), false));
+    }
+
+    #[test]
+    pub fn glob_patterns() {
+        let rules = IgnoreRules::parse("# build output\n*.tmp\n/out.md\n").unwrap();
+        assert!(rules.is_ignored(Path::new("a.tmp"), false));
+        assert!(rules.is_ignored(Path::new("deep/down/a.tmp"), false));
+        assert!(rules.is_ignored(Path::new("out.md"), false));
+        // Anchored patterns only match at the root
+        assert!(!rules.is_ignored(Path::new("sub/out.md"), false));
+        assert!(!rules.is_ignored(Path::new("a.md"), false));
+    }
+
+    #[test]
+    pub fn negated_patterns() {
+        let rules = IgnoreRules::parse("*.log\n!keep.log\n").unwrap();
+        assert!(rules.is_ignored(Path::new("debug.log"), false));
+        assert!(!rules.is_ignored(Path::new("keep.log"), false));
+    }
+
+    #[test]
+    pub fn directory_patterns() {
+        let rules = IgnoreRules::parse("node_modules/\n").unwrap();
+        assert!(rules.is_ignored(Path::new("node_modules"), true));
+        assert!(rules.is_ignored(Path::new("web/node_modules"), true));
+        // A file with the same name isn't a directory, so isn't matched.
+        assert!(!rules.is_ignored(Path::new("node_modules"), false));
+        assert!(rules.is_ignored_or_in_ignored_dir(Path::new("node_modules/x/index.js")));
+        assert!(!rules.is_ignored_or_in_ignored_dir(Path::new("src/index.js")));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    fs_utils::{plan_copy_dir_all_with_filters, CopyPlan},
+    package_status::{Package, PackageDirectory},
+    project_root::{find_first_existing_parent, find_marker_dir},
+    starter,
+};
+
+/// The settings file a new project starts with - every setting, commented out.
+const SETTINGS_TEMPLATE: &str = "\
+# Local settings and secrets - don't commit this file. Settings that are safe
+# to share can go in config.toml instead. Settings here override config.toml,
+# and WROUGHT_<SETTING> environment variables override both.
+
+# General Project Settings
+# project_name = \"My Project\"
+# Make text files written by scripts end in exactly one newline
+# ensure_trailing_newline = true
+
+# LLM Settings
+# Uncomment and set to enable LLM features
+# openai_api_key = \"PUT_YOUR_KEY_HERE\"
+# openai_model = \"gpt-4o-mini\"
+# Answer every query with the prompt itself, for trying scripts without an API key
+# llm_provider = \"echo\"
+# Where LLM responses are cached, relative to the project root
+# llm_cache_dir = \".wrought/llm_cache\"
+";
+
+/// Everything `wrought init` is going to do, worked out up front. Planning only reads
+/// the filesystem, so `init --dry-run` can show the plan without changing anything.
+#[derive(Debug)]
+pub struct InitPlan {
+    pub root: PathBuf,
+    /// Directories init creates itself, before any package is copied.
+    pub dirs: Vec<PathBuf>,
+    /// Files init writes itself - settings, the event log, or the starter package.
+    pub files: Vec<PathBuf>,
+    /// The package being copied into the project, and its name. `None` for `--empty`.
+    pub package: Option<(String, Package)>,
+    pub copies: CopyPlan,
+    /// The copied package's init script, if it has one.
+    pub init_script: Option<PathBuf>,
+}
+
+impl InitPlan {
+    pub fn internal_dir(&self) -> PathBuf {
+        self.root.join(".wrought")
+    }
+
+    pub fn package_dir(&self) -> PathBuf {
+        self.internal_dir().join("packages")
+    }
+}
+
+/// Plans a new project at `root`, using the package `package` from `src_package_dir`,
+/// or the starter package if `package` is `None`. Fails if there is no such package.
+pub fn plan_init(
+    fs: &dyn xfs::Xfs,
+    root: &Path,
+    package: Option<&str>,
+    src_package_dir: &Path,
+) -> anyhow::Result<InitPlan> {
+    let internal_dir = root.join(".wrought");
+    let package_dir = internal_dir.join("packages");
+    let mut plan = InitPlan {
+        root: root.to_path_buf(),
+        dirs: vec![
+            root.to_path_buf(),
+            internal_dir.clone(),
+            internal_dir.join("content"),
+            package_dir.clone(),
+        ],
+        files: vec![
+            internal_dir.join("settings.toml"),
+            internal_dir.join("wrought.db"),
+        ],
+        package: None,
+        copies: CopyPlan::default(),
+        init_script: None,
+    };
+
+    let Some(package) = package else {
+        let starter_package = package_dir.join(starter::STARTER_PACKAGE);
+        plan.dirs.push(starter_package.clone());
+        plan.files.push(starter_package.join("init.luau"));
+        plan.files.push(starter_package.join("README.md"));
+        return Ok(plan);
+    };
+
+    let source = PackageDirectory {
+        path: src_package_dir.to_path_buf(),
+    }
+    .package(fs, package)?;
+    let project_package = package_dir.join(package);
+    plan.copies = plan_copy_dir_all_with_filters(
+        fs,
+        &source.path,
+        &project_package,
+        |_, _| true,
+        |_, _| true,
+    )?;
+    let init_script = project_package.join("init.luau");
+    if plan.copies.files.iter().any(|(_, dst)| *dst == init_script) {
+        plan.init_script = Some(init_script);
+    }
+    plan.package = Some((package.to_string(), source));
+    Ok(plan)
+}
+
+/// What `init_project` should create.
+pub struct InitParams<'a> {
+    pub path: PathBuf,
+    /// The package to copy in. `None` for an empty project, with the starter package.
+    pub package: Option<String>,
+    pub src_package_dir: PathBuf,
+    /// Only work out the plan - create nothing.
+    pub dry_run: bool,
+    /// Creates the event log database at the given path. Passed in, as SQLite works
+    /// on the real filesystem rather than through `fs`.
+    pub init_event_log: &'a dyn Fn(&Path) -> anyhow::Result<()>,
+}
+
+/// Creates a new project, as `wrought init` does, and returns the plan it carried
+/// out. Running the package's init script, if it has one, is left to the caller.
+/// Fails without creating anything if the target is already inside a project, or
+/// the package doesn't exist.
+pub fn init_project(fs: Arc<Mutex<dyn xfs::Xfs>>, params: &InitParams) -> anyhow::Result<InitPlan> {
+    let mut fs = fs.lock().unwrap();
+    let path = &params.path;
+
+    let existing_parent =
+        find_first_existing_parent(&*fs, path).context("in find_first_existing_parent")?;
+    if let Some(existing_parent) = existing_parent {
+        if let Some(root) =
+            find_marker_dir(&*fs, &existing_parent, ".wrought").context("in find_marker_dir")?
+        {
+            bail!(
+                "Path '{}' is part of project with root '{}'",
+                path.display(),
+                root.display()
+            );
+        }
+    }
+
+    // Work out everything up front, so a missing package is reported before anything
+    // is created.
+    let plan = plan_init(
+        &*fs,
+        path,
+        params.package.as_deref(),
+        &params.src_package_dir,
+    )?;
+    if params.dry_run {
+        return Ok(plan);
+    }
+
+    for dir in &plan.dirs {
+        fs.create_dir_all(dir)?;
+    }
+    fs.writer(&plan.internal_dir().join("settings.toml"))?
+        .write_all(SETTINGS_TEMPLATE.as_bytes())?;
+    (params.init_event_log)(&plan.internal_dir().join("wrought.db"))
+        .context("creating the event log")?;
+
+    match &plan.package {
+        Some(_) => plan.copies.execute(&mut *fs)?,
+        None => starter::write_starter_package(
+            &mut *fs,
+            &plan.package_dir().join(starter::STARTER_PACKAGE),
+        )?,
+    }
+    Ok(plan)
+}
+
+// As printed by `wrought init --dry-run`
+impl Display for InitPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for dir in self.dirs.iter().chain(&self.copies.dirs) {
+            writeln!(f, "create dir {}", dir.display())?;
+        }
+        for file in &self.files {
+            writeln!(f, "write {}", file.display())?;
+        }
+        for (src, dst) in &self.copies.files {
+            writeln!(f, "copy {} -> {}", src.display(), dst.display())?;
+        }
+        match (&self.package, &self.init_script) {
+            (_, Some(script)) => writeln!(f, "run {}", script.display()),
+            (Some((name, _)), None) => writeln!(f, "no init script in package '{}'", name),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::{Path, PathBuf};
+
+    use std::{
+        cell::RefCell,
+        sync::{Arc, Mutex},
+    };
+
+    use xfs::Xfs;
+
+    use super::{init_project, plan_init, InitParams};
+
+    fn package_fs() -> xfs::mockfs::MockFS {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for p in [
+            "blog/init.luau",
+            "blog/templates/post.md",
+            "notes/README.md",
+        ] {
+            fs.add_r(&Path::new("packages").join(p), b"x".to_vec())
+                .unwrap();
+        }
+        fs
+    }
+
+    #[test]
+    pub fn planning_writes_nothing() {
+        let fs = package_fs();
+        let before = fs.tree();
+
+        let plan = plan_init(&fs, Path::new("site"), Some("blog"), Path::new("packages")).unwrap();
+
+        assert_eq!(fs.tree(), before);
+        assert!(!fs.exists(Path::new("site")));
+        let mut copied: Vec<_> = plan.copies.files.iter().map(|(_, dst)| dst).collect();
+        copied.sort();
+        assert_eq!(
+            copied,
+            vec![
+                &PathBuf::from("site/.wrought/packages/blog/init.luau"),
+                &PathBuf::from("site/.wrought/packages/blog/templates/post.md"),
+            ]
+        );
+        assert_eq!(
+            plan.init_script,
+            Some(PathBuf::from("site/.wrought/packages/blog/init.luau"))
+        );
+        let shown = plan.to_string();
+        assert!(
+            shown.contains("create dir site/.wrought/content\n"),
+            "{}",
+            shown
+        );
+        assert!(
+            shown.contains("run site/.wrought/packages/blog/init.luau\n"),
+            "{}",
+            shown
+        );
+    }
+
+    #[test]
+    pub fn packages_without_an_init_script_are_noted() {
+        let fs = package_fs();
+        let plan = plan_init(&fs, Path::new("site"), Some("notes"), Path::new("packages")).unwrap();
+        assert_eq!(plan.init_script, None);
+        assert!(plan
+            .to_string()
+            .ends_with("no init script in package 'notes'\n"));
+
+        assert!(plan_init(&fs, Path::new("site"), Some("bogus"), Path::new("packages")).is_err());
+    }
+
+    #[test]
+    pub fn init_creates_the_project_layout() {
+        let fs = Arc::new(Mutex::new(package_fs()));
+        let created_dbs = RefCell::new(vec![]);
+        let params = InitParams {
+            path: PathBuf::from("site"),
+            package: Some("blog".to_string()),
+            src_package_dir: PathBuf::from("packages"),
+            dry_run: false,
+            init_event_log: &|p| {
+                created_dbs.borrow_mut().push(p.to_path_buf());
+                Ok(())
+            },
+        };
+
+        let plan = init_project(fs.clone(), &params).unwrap();
+
+        assert_eq!(
+            *created_dbs.borrow(),
+            vec![PathBuf::from("site/.wrought/wrought.db")]
+        );
+        let fs = fs.lock().unwrap();
+        for dir in [".wrought/content", ".wrought/packages/blog/templates"] {
+            assert!(fs.is_dir(&Path::new("site").join(dir)), "{}", dir);
+        }
+        let settings = fs.get(Path::new("site/.wrought/settings.toml")).unwrap();
+        assert!(String::from_utf8(settings)
+            .unwrap()
+            .contains("# openai_api_key"));
+        assert!(fs.is_file(Path::new("site/.wrought/packages/blog/init.luau")));
+        assert_eq!(
+            plan.init_script,
+            Some(PathBuf::from("site/.wrought/packages/blog/init.luau"))
+        );
+    }
+
+    #[test]
+    pub fn init_refuses_to_nest_projects() {
+        let fs = Arc::new(Mutex::new(package_fs()));
+        let params = |path: &str| InitParams {
+            path: PathBuf::from(path),
+            package: None,
+            src_package_dir: PathBuf::from("packages"),
+            dry_run: false,
+            init_event_log: &|_| Ok(()),
+        };
+        init_project(fs.clone(), &params("site")).unwrap();
+        assert!(fs
+            .lock()
+            .unwrap()
+            .is_file(Path::new("site/.wrought/packages/starter/init.luau")));
+
+        let before = fs.lock().unwrap().tree();
+        let e = init_project(fs.clone(), &params("site/inner")).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Path 'site/inner' is part of project with root 'site'"
+        );
+        assert_eq!(fs.lock().unwrap().tree(), before);
+    }
+}
@@ -1,16 +1,19 @@
 // Wrappers for the rust_openai stuff
 
 use std::{
+    future::Future,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
     thread::JoinHandle,
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use async_trait::async_trait;
 use rust_openai::types::{ChatRequest, SystemMessage};
 use xfs::Xfs;
 
+use crate::cancel::CancellationToken;
+
 type AsyncMutex<T> = tokio::sync::Mutex<T>;
 
 // Our big problem is that the AI library we use uses async, but we dont want that in
@@ -18,6 +21,10 @@ type AsyncMutex<T> = tokio::sync::Mutex<T>;
 // So what we do is create a worker thread for handling AI requests,
 // give it its own tokio threadpool for its workers and our synchronous code just posts stuff to that
 // main worker via channels.
+//
+// The thread and its runtime are made once per `OpenAILLM`, when the first query is made,
+// and every later query reuses them - a query costs two channel messages, not a new
+// runtime. Runs that never query the LLM never start the worker at all.
 
 enum AiWorkRequest {
     Query(AiWorkQueryRequest),
@@ -26,6 +33,8 @@ enum AiWorkRequest {
 struct AiWorkQueryRequest {
     query: String,
     response_channel: tokio::sync::oneshot::Sender<AiQueryResponse>,
+    /// Abandons the request, even part way through, when cancelled.
+    cancel: CancellationToken,
 }
 
 struct AiQueryResponse {
@@ -40,7 +49,20 @@ pub struct AiSettings {
 
 pub struct AiWorker {
     llm: rust_openai::request::OpenAILLM,
-    rx: tokio::sync::mpsc::Receiver<AiWorkRequest>,
+}
+
+/// What the worker thread uses to answer queries - split out so tests can stand in
+/// for the real LLM.
+#[async_trait]
+trait AiQueryHandler: Send {
+    async fn query(&mut self, query: &str) -> anyhow::Result<String>;
+}
+
+#[async_trait]
+impl AiQueryHandler for AiWorker {
+    async fn query(&mut self, query: &str) -> anyhow::Result<String> {
+        run_as_worker_query_internal(self, query).await
+    }
 }
 
 pub async fn run_as_worker_query_internal(
@@ -50,24 +72,93 @@ pub async fn run_as_worker_query_internal(
     let messages = vec![SystemMessage::new(query).into()];
     let request = ChatRequest::new(rust_openai::types::ModelId::Gpt4oMini, messages);
     let (response, _) = worker.llm.make_request(&request).await?;
-    let result = response.choices[0]
-        .message
-        .as_assistant_message()
-        .as_ref()
-        .unwrap()
-        .content
-        .as_ref()
-        .unwrap()
-        .clone();
-    Ok(result)
+    first_choice_content(&response.choices, |choice| {
+        let message = choice.message.as_assistant_message();
+        message.as_ref().and_then(|m| m.content.clone())
+    })
+}
+
+/// The model queries go to - the same one `run_as_worker_query_internal` asks for.
+const OPENAI_MODEL: &str = "gpt-4o-mini";
+
+/// Sends queries to an OpenAI compatible endpoint other than the public one - e.g. a
+/// proxy. rust_openai's requester only talks to the public endpoint, so this makes the
+/// chat completion request itself. Its responses aren't cached.
+struct CompatibleEndpoint {
+    client: reqwest::Client,
+    /// e.g. `https://proxy.example.com/openai/v1`, without a trailing slash.
+    base_url: String,
+    openai_api_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatMessage {
+    content: Option<String>,
+}
+
+#[async_trait]
+impl AiQueryHandler for CompatibleEndpoint {
+    async fn query(&mut self, query: &str) -> anyhow::Result<String> {
+        let url = format!("{}/chat/completions", self.base_url);
+        let body = serde_json::json!({
+            "model": OPENAI_MODEL,
+            "messages": [{"role": "system", "content": query}],
+        });
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.openai_api_key)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .with_context(|| format!("LLM request to {} failed", url))?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            bail!("LLM request to {} failed with {}: {}", url, status, text);
+        }
+        let response: ChatResponse = serde_json::from_str(&text)
+            .with_context(|| format!("unexpected LLM response from {}", url))?;
+        first_choice_content(&response.choices, |choice| choice.message.content.clone())
+    }
+}
+
+/// Gets the text of the first choice in a response, erroring rather than panicking
+/// when there are no choices or the choice has no (or only whitespace) content -
+/// e.g. a function-call-only response.
+fn first_choice_content<C>(
+    choices: &[C],
+    content: impl Fn(&C) -> Option<String>,
+) -> anyhow::Result<String> {
+    let Some(choice) = choices.first() else {
+        bail!("LLM returned no choices");
+    };
+    match content(choice) {
+        Some(content) if !content.trim().is_empty() => Ok(content),
+        _ => bail!("LLM returned no content"),
+    }
 }
 
 async fn run_ai_worker_query(
-    worker: &mut AiWorker,
+    handler: &mut dyn AiQueryHandler,
     query: AiWorkQueryRequest,
 ) -> anyhow::Result<()> {
     // Note we dont ues ? here as we want to forward failures down the channel.
-    let result = run_as_worker_query_internal(worker, &query.query).await;
+    let result = tokio::select! {
+        result = handler.query(&query.query) => result,
+        _ = query.cancel.cancelled() => Err(anyhow::anyhow!("LLM query was cancelled")),
+    };
     query
         .response_channel
         .send(AiQueryResponse { result })
@@ -92,24 +183,38 @@ async fn run_ai_worker(
     let cache = Arc::new(AsyncMutex::new(cache));
 
     let llm = rust_openai::request::OpenAILLM::new(requester, cache);
-    let mut worker = AiWorker { rx, llm };
+    serve_ai_queries(AiWorker { llm }, rx).await
+}
 
-    while let Some(request) = worker.rx.recv().await {
+async fn serve_ai_queries(
+    mut handler: impl AiQueryHandler,
+    mut rx: tokio::sync::mpsc::Receiver<AiWorkRequest>,
+) -> anyhow::Result<()> {
+    while let Some(request) = rx.recv().await {
         match request {
             AiWorkRequest::Query(query) => {
-                run_ai_worker_query(&mut worker, query).await?;
+                run_ai_worker_query(&mut handler, query).await?;
             }
         };
     }
     Ok(())
 }
 
-fn start_ai_workers(
-    settings: AiSettings,
-) -> (
+type AiWorkerHandles = (
     tokio::sync::mpsc::Sender<AiWorkRequest>,
     JoinHandle<anyhow::Result<()>>,
-) {
+);
+
+fn start_ai_workers(settings: AiSettings) -> AiWorkerHandles {
+    spawn_ai_worker(|rx| run_ai_worker(settings, rx))
+}
+
+/// Runs `worker` on its own thread, with its own tokio runtime.
+fn spawn_ai_worker<F, Fut>(worker: F) -> AiWorkerHandles
+where
+    F: FnOnce(tokio::sync::mpsc::Receiver<AiWorkRequest>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
     // Create a channel and get the sync receiver
     let (tx, rx) = tokio::sync::mpsc::channel::<AiWorkRequest>(32);
 
@@ -118,14 +223,19 @@ fn start_ai_workers(
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(run_ai_worker(settings, rx))
+        rt.block_on(worker(rx))
     });
     (tx, jh)
 }
 
+type StartAiWorker = Box<dyn FnOnce() -> AiWorkerHandles + Send>;
+
 pub struct OpenAILLM {
-    channel: tokio::sync::mpsc::Sender<AiWorkRequest>,
-    join_handle: std::thread::JoinHandle<anyhow::Result<()>>,
+    /// Set by the first query.
+    worker: Option<AiWorkerHandles>,
+    /// Starts the worker - taken by the first query.
+    start_worker: Option<StartAiWorker>,
+    cancel: CancellationToken,
 }
 
 impl OpenAILLM {
@@ -141,26 +251,68 @@ impl OpenAILLM {
             fs,
         };
 
-        let (channel, join_handle) = start_ai_workers(settings);
+        Ok(OpenAILLM::with_worker(move || start_ai_workers(settings)))
+    }
 
-        Ok(OpenAILLM {
-            channel,
-            join_handle,
+    /// Like `create_with_key`, but queries go to `base_url` (the `openai_base_url`
+    /// setting) rather than the public endpoint.
+    pub fn create_with_base_url(openai_api_key: String, base_url: String) -> OpenAILLM {
+        OpenAILLM::with_worker(move || {
+            spawn_ai_worker(move |rx| {
+                let endpoint = CompatibleEndpoint {
+                    client: reqwest::Client::new(),
+                    base_url,
+                    openai_api_key,
+                };
+                serve_ai_queries(endpoint, rx)
+            })
         })
     }
+
+    /// `start_worker` is called once, by the first query.
+    fn with_worker(start_worker: impl FnOnce() -> AiWorkerHandles + Send + 'static) -> OpenAILLM {
+        OpenAILLM {
+            worker: None,
+            start_worker: Some(Box::new(start_worker)),
+            cancel: CancellationToken::default(),
+        }
+    }
+
+    fn channel(&mut self) -> &tokio::sync::mpsc::Sender<AiWorkRequest> {
+        let (channel, _) = self.worker.get_or_insert_with(|| {
+            let start_worker = self
+                .start_worker
+                .take()
+                .expect("the LLM worker is only started once");
+            start_worker()
+        });
+        channel
+    }
+
+    /// Cancelling this makes any query in flight, and all later ones, return an error
+    /// straight away rather than waiting for the LLM.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
 }
 
 impl LLM for OpenAILLM {
     fn query(&mut self, query: &str) -> anyhow::Result<String> {
+        if self.cancel.is_cancelled() {
+            bail!("LLM query was cancelled");
+        }
         let (response_tx, response_rx) = tokio::sync::oneshot::channel();
         let request = AiWorkRequest::Query(AiWorkQueryRequest {
             query: query.to_string(),
             response_channel: response_tx,
+            cancel: self.cancel.clone(),
         });
-        self.channel.blocking_send(request)?;
+        self.channel().blocking_send(request)?;
 
         // Wait for response synchronously
-        let response = response_rx.blocking_recv().unwrap();
+        let response = response_rx
+            .blocking_recv()
+            .map_err(|_| anyhow::anyhow!("LLM worker stopped without responding"))?;
         response.result
     }
 }
@@ -232,3 +384,204 @@ impl LLM for InvalidLLM {
         bail!("Unable to access LLM: {}", self.error_message)
     }
 }
+
+/// Which `LLM` queries go to, from the `llm_provider` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmProvider {
+    OpenAI,
+    Echo,
+}
+
+/// Answers every query with the query itself, marked as a dry run, so scripts can be
+/// developed without spending tokens. Unlike a mock, it needs no responses set up.
+#[derive(Debug, Default)]
+pub struct EchoLLM;
+
+impl LLM for EchoLLM {
+    fn query(&mut self, query: &str) -> anyhow::Result<String> {
+        Ok(format!("[dry-run] {}", query))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+
+    use super::{
+        first_choice_content, serve_ai_queries, spawn_ai_worker, AiQueryHandler, EchoLLM,
+        OpenAILLM, LLM,
+    };
+    use crate::{
+        backend::test_utils::MockBackend,
+        bridge::{tests::test_bridge, Bridge},
+    };
+
+    // Stands in for an LLM that takes far longer than any test should.
+    struct SlowHandler;
+
+    #[async_trait]
+    impl AiQueryHandler for SlowHandler {
+        async fn query(&mut self, _query: &str) -> anyhow::Result<String> {
+            tokio::time::sleep(Duration::from_secs(600)).await;
+            Ok("too late".to_string())
+        }
+    }
+
+    // Answers with how many queries it has seen.
+    #[derive(Default)]
+    struct CountingHandler {
+        queries: usize,
+    }
+
+    #[async_trait]
+    impl AiQueryHandler for CountingHandler {
+        async fn query(&mut self, _query: &str) -> anyhow::Result<String> {
+            self.queries += 1;
+            Ok(self.queries.to_string())
+        }
+    }
+
+    #[test]
+    pub fn queries_share_one_lazily_started_worker() {
+        let workers_started = Arc::new(AtomicUsize::new(0));
+        let started = workers_started.clone();
+        let mut llm = OpenAILLM::with_worker(move || {
+            started.fetch_add(1, Ordering::SeqCst);
+            spawn_ai_worker(|rx| serve_ai_queries(CountingHandler::default(), rx))
+        });
+        assert_eq!(workers_started.load(Ordering::SeqCst), 0);
+
+        for i in 1..=100 {
+            assert_eq!(llm.query("hello").unwrap(), i.to_string());
+        }
+        // One worker thread and runtime answered them all.
+        assert_eq!(workers_started.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    pub fn echo_llm_answers_any_query_through_the_bridge() {
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.llm = Arc::new(Mutex::new(EchoLLM));
+        assert_eq!(
+            bridge.ai_query("Summarise this").unwrap(),
+            "[dry-run] Summarise this"
+        );
+        assert_eq!(bridge.ai_query("").unwrap(), "[dry-run] ");
+    }
+
+    #[test]
+    pub fn cancelling_interrupts_a_query_in_flight() {
+        let mut llm =
+            OpenAILLM::with_worker(|| spawn_ai_worker(|rx| serve_ai_queries(SlowHandler, rx)));
+        let cancel = llm.cancellation_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+
+        let start = Instant::now();
+        let e = llm.query("hello").unwrap_err();
+        assert_eq!(e.to_string(), "LLM query was cancelled");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        canceller.join().unwrap();
+
+        // Later queries don't even reach the worker.
+        assert!(llm.query("again").is_err());
+    }
+
+    /// Answers one HTTP request with `body`, handing back the request it got.
+    fn serve_one_request(body: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/openai/v1", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            // Headers, then as much body as they say there is.
+            let body_len = loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_lowercase();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .map(|l| l.trim().parse::<usize>().unwrap())
+                        .unwrap_or(0);
+                    break end + 4 + length;
+                }
+            };
+            while request.len() < body_len {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, server)
+    }
+
+    #[test]
+    pub fn queries_go_to_the_configured_base_url() {
+        let (url, server) = serve_one_request(
+            r#"{"choices":[{"message":{"role":"assistant","content":"from the proxy"}}]}"#,
+        );
+        let mut llm = OpenAILLM::create_with_base_url("test-key".to_string(), url);
+        assert_eq!(llm.query("hello").unwrap(), "from the proxy");
+
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /openai/v1/chat/completions HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(
+            request
+                .to_lowercase()
+                .contains("authorization: bearer test-key"),
+            "{}",
+            request
+        );
+        let body: serde_json::Value =
+            serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["messages"][0]["content"], "hello");
+    }
+
+    #[test]
+    pub fn empty_choices_is_an_error() {
+        let e = first_choice_content::<()>(&[], |_| None).unwrap_err();
+        assert_eq!(e.to_string(), "LLM returned no choices");
+    }
+
+    #[test]
+    pub fn missing_or_blank_content_is_an_error() {
+        let e = first_choice_content(&[()], |_| None).unwrap_err();
+        assert_eq!(e.to_string(), "LLM returned no content");
+        let e = first_choice_content(&[()], |_| Some(" \n".to_string())).unwrap_err();
+        assert_eq!(e.to_string(), "LLM returned no content");
+    }
+
+    #[test]
+    pub fn uses_first_choice() {
+        let choices = ["first", "second"];
+        let content = first_choice_content(&choices, |c| Some(c.to_string())).unwrap();
+        assert_eq!(content, "first");
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+/// Where LLM responses are cached unless `llm_cache_dir` is set, relative to the project root.
+pub const DEFAULT_LLM_CACHE_DIR: &str = ".wrought/llm_cache";
+
+/// The on-disk cache of LLM responses. Entries are `<32 hex digit hash>.json` files
+/// directly inside the directory - anything else in there isn't ours to touch.
+pub struct LlmCache {
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LlmCacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+}
+
+fn is_cache_entry_name(name: &str) -> bool {
+    let Some(stem) = name.strip_suffix(".json") else {
+        return false;
+    };
+    stem.len() == 32 && stem.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl LlmCache {
+    pub fn new(dir: PathBuf) -> LlmCache {
+        LlmCache { dir }
+    }
+
+    /// The files in the cache directory that are cache entries.
+    pub fn entries(&self, fs: &dyn xfs::Xfs) -> anyhow::Result<Vec<PathBuf>> {
+        if !fs.is_dir(&self.dir) {
+            return Ok(vec![]);
+        }
+        let mut result = vec![];
+        fs.on_each_entry(&self.dir, &mut |_fs, e| {
+            let path = e.path();
+            let is_entry = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(is_cache_entry_name);
+            if is_entry && e.metadata()?.is_file() {
+                result.push(path);
+            }
+            Ok(())
+        })?;
+        result.sort();
+        Ok(result)
+    }
+
+    pub fn stats(&self, fs: &dyn xfs::Xfs) -> anyhow::Result<LlmCacheStats> {
+        let mut stats = LlmCacheStats::default();
+        for entry in self.entries(fs)? {
+            let mut content = vec![];
+            fs.reader(&entry)?.read_to_end(&mut content)?;
+            stats.entries += 1;
+            stats.total_bytes += content.len() as u64;
+        }
+        Ok(stats)
+    }
+
+    /// Deletes every cache entry, leaving anything else in the directory alone.
+    /// Returns the number of entries removed.
+    ///
+    /// Xfs has no way to remove files, so this always acts on the real filesystem.
+    pub fn clear(&self, fs: &dyn xfs::Xfs) -> anyhow::Result<usize> {
+        let entries = self.entries(fs)?;
+        for entry in &entries {
+            remove_file(entry)?;
+        }
+        Ok(entries.len())
+    }
+}
+
+fn remove_file(p: &Path) -> anyhow::Result<()> {
+    std::fs::remove_file(p).map_err(|e| anyhow::anyhow!("unable to remove {}: {}", p.display(), e))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use super::{LlmCache, LlmCacheStats};
+
+    const ENTRY: &str = "17270385db0388b592d48dcbed42e8c6.json";
+
+    #[test]
+    pub fn only_hash_named_json_files_are_entries() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let dir = PathBuf::from("project/.wrought/llm_cache");
+        fs.add_r(&dir.join(ENTRY), b"{}".to_vec()).unwrap();
+        fs.add_r(&dir.join("notes.json"), b"keep me".to_vec())
+            .unwrap();
+        fs.add_r(&dir.join("README"), b"keep me".to_vec()).unwrap();
+        fs.add_r(
+            &dir.join("nested")
+                .join("0123456789abcdef0123456789abcdef.json"),
+            b"{}".to_vec(),
+        )
+        .unwrap();
+
+        let cache = LlmCache::new(dir.clone());
+        assert_eq!(cache.entries(&fs).unwrap(), vec![dir.join(ENTRY)]);
+        assert_eq!(
+            cache.stats(&fs).unwrap(),
+            LlmCacheStats {
+                entries: 1,
+                total_bytes: 2
+            }
+        );
+    }
+
+    #[test]
+    pub fn missing_cache_dir_is_empty() {
+        let fs = xfs::mockfs::MockFS::new();
+        let cache = LlmCache::new(PathBuf::from("nowhere"));
+        assert_eq!(cache.stats(&fs).unwrap(), LlmCacheStats::default());
+    }
+
+    #[test]
+    pub fn clear_leaves_other_files_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::write(dir.join(ENTRY), b"{}").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"keep me").unwrap();
+
+        let cache = LlmCache::new(dir.clone());
+        let removed = cache.clear(&xfs::OsFs {}).unwrap();
+        let left_over: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+
+        assert_eq!(removed, 1);
+        assert_eq!(left_over, vec!["notes.txt"]);
+    }
+}
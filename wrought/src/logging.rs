@@ -0,0 +1,67 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Writes wrought's own log records to stderr, e.g. `debug: using project root '/x'`.
+/// Records from dependencies (wasmtime, tera, ...) are dropped - at debug level they
+/// would drown out everything else.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with("wrought")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}: {}",
+                record.level().as_str().to_lowercase(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// What `-v` given `verbose` times shows: warnings only by default, then info, debug
+/// and trace.
+pub fn level_filter(verbose: u8) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Call once, at startup.
+pub fn init(verbose: u8) {
+    // Only fails if a logger is already set, which can only happen in tests.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level_filter(verbose));
+}
+
+#[cfg(test)]
+pub mod tests {
+    use log::{Level, Log, MetadataBuilder};
+
+    use super::{level_filter, StderrLogger};
+
+    #[test]
+    pub fn debug_output_needs_verbose() {
+        let enabled = |verbose: u8, level: Level, target: &str| {
+            log::set_max_level(level_filter(verbose));
+            StderrLogger.enabled(&MetadataBuilder::new().level(level).target(target).build())
+        };
+        assert!(!enabled(0, Level::Info, "wrought::scripting_wasm"));
+        assert!(!enabled(0, Level::Debug, "wrought::main"));
+        assert!(enabled(0, Level::Warn, "wrought::main"));
+        assert!(enabled(1, Level::Info, "wrought::scripting_wasm"));
+        assert!(!enabled(1, Level::Debug, "wrought::main"));
+        assert!(enabled(2, Level::Debug, "wrought::main"));
+        assert!(!enabled(3, Level::Debug, "cranelift_codegen::isa"));
+        log::set_max_level(level_filter(0));
+    }
+}
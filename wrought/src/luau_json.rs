@@ -89,8 +89,9 @@ pub fn json_value_to_lua_value<'a>(
         JsonValue::Null => Ok(LuaValue::Nil),
         JsonValue::Bool(b) => Ok(LuaValue::Boolean(*b)),
         JsonValue::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                Ok(LuaValue::Integer(i as i32))
+            // Luau integers are only 32 bit - anything bigger has to be a (double) number.
+            if let Some(i) = n.as_i64().and_then(|i| i32::try_from(i).ok()) {
+                Ok(LuaValue::Integer(i))
             } else if let Some(f) = n.as_f64() {
                 if f.is_finite() {
                     Ok(LuaValue::Number(f))
@@ -107,6 +108,10 @@ pub fn json_value_to_lua_value<'a>(
             for (i, value) in arr.iter().enumerate() {
                 table.set(i + 1, json_value_to_lua_value(lua, value)?)?;
             }
+            // Lua would lose trailing nulls, so record the length (see `lua_table_to_json`)
+            if arr.last() == Some(&JsonValue::Null) {
+                table.set("n", arr.len())?;
+            }
             Ok(LuaValue::Table(table))
         }
         JsonValue::Object(obj) => {
@@ -127,70 +132,99 @@ fn to_array_index(key: &LuaValue) -> Option<usize> {
     }
 }
 
+/// Set in the metatable of tables made by `empty_array`, to mark them as arrays.
+pub const ARRAY_MARKER: &str = "__wrought_json_array";
+
+/// An empty table that `lua_table_to_json` always turns into `[]`, whatever
+/// `empty_table_is_array` says.
+pub fn empty_array(lua: &Lua) -> mlua::Result<mlua::Table<'_>> {
+    let table = lua.create_table()?;
+    let metatable = lua.create_table()?;
+    metatable.set(ARRAY_MARKER, true)?;
+    table.set_metatable(Some(metatable));
+    Ok(table)
+}
+
+fn is_marked_array(table: &mlua::Table) -> bool {
+    table
+        .get_metatable()
+        .and_then(|mt| mt.raw_get::<_, bool>(ARRAY_MARKER).ok())
+        .unwrap_or(false)
+}
+
+/// Largest array we'll build from a lua table. Stops a table like `{[1e9] = 1}`
+/// from turning into a billion element array of nulls.
+pub const MAX_ARRAY_LEN: usize = 1 << 20;
+
+/// Converts a lua table to a JSON array or object.
+///
+/// A table with only positive integer keys is an array, with any internal holes
+/// becoming `null`. Lua can't store a trailing `nil`, so `{1, 2, nil}` is just
+/// `[1, 2]`. To keep trailing nulls give the table an explicit length in an `n`
+/// field, as `table.pack` does: `{1, 2, nil, n = 3}` is `[1, 2, null]`.
+/// (A table with *only* an `n` field is still an object.)
+///
+/// A table with only string keys is an object. Anything else is an error.
+///
+/// An empty table is ambiguous - Lua has no way to say whether `{}` is meant as an
+/// empty array or an empty object - so it is whichever `empty_table_is_array` says,
+/// unless it was made by `empty_array`, which is always an array.
 pub fn lua_table_to_json(
     table: mlua::Table,
     empty_table_is_array: bool,
 ) -> Result<JsonValue, ConversionError> {
-    let len = table.len()? as usize;
-
-    eprintln!("lua_table_to_json: table.len={}", len);
-
-    let mut is_array = true;
-    let mut is_object = true;
-
+    let marked_array = is_marked_array(&table);
     let mut array = vec![];
+    let mut has_index = false;
     let mut object = BTreeMap::new();
 
-    let mut is_empty = true;
     for pair in table.pairs::<LuaValue, LuaValue>() {
-        is_empty = false;
         let (key, value) = pair?;
 
         if let Some(index) = to_array_index(&key) {
-            is_object = false;
-            if !is_array {
-                break;
+            if index > MAX_ARRAY_LEN {
+                return Err(ConversionError::invalid_table_key(format!(
+                    "array index {} is larger than the maximum of {}",
+                    index, MAX_ARRAY_LEN
+                )));
             }
-            // Ensure the vector is long enough
-            let i = index - 1;
-            if i + 1 > array.len() {
-                let n = i + 1 - array.len();
-                for _ in 0..n {
-                    array.push(LuaValue::Nil);
-                }
+            has_index = true;
+            if index > array.len() {
+                array.resize(index, LuaValue::Nil);
             }
-            // Now we can update the value
-            array[i] = value;
-            // TODO: Should we have a maximum gap size?
+            array[index - 1] = value;
+        } else if let LuaValue::String(s) = key {
+            object.insert(s.to_str()?.to_string(), value);
         } else {
-            is_array = false;
-            if !is_object {
-                break;
-            }
-            if let LuaValue::String(s) = key {
-                let ss = s.to_str()?;
-                object.insert(ss.to_string(), value);
-            } else {
-                is_object = false;
-                break;
-            }
+            return Err(ConversionError::invalid_table_key(format!("{:?}", key)));
         }
     }
 
-    if is_empty {
-        if empty_table_is_array {
+    if !has_index && object.is_empty() {
+        if empty_table_is_array || marked_array {
             return Ok(serde_json::json!([]));
         } else {
             return Ok(serde_json::json!({}));
         }
     }
 
-    if !is_array && !is_object {
-        //TODO: Add more details
-        return Err(ConversionError::invalid_table_key("invalid table key"));
-    }
-
-    if is_array {
+    if has_index {
+        if let Some(n) = object.remove("n") {
+            let n = match to_array_index(&n) {
+                Some(n) if n >= array.len() && n <= MAX_ARRAY_LEN => n,
+                _ => {
+                    return Err(ConversionError::invalid_table_key(format!(
+                        "array length n={:?} does not cover its {} entries",
+                        n,
+                        array.len()
+                    )))
+                }
+            };
+            array.resize(n, LuaValue::Nil);
+        }
+        if !object.is_empty() {
+            return Err(ConversionError::MixedArrayKeys);
+        }
         let mut json_array = Vec::with_capacity(array.len());
         for value in array {
             json_array.push(lua_value_to_json_value(value, empty_table_is_array)?);
@@ -233,8 +267,8 @@ mod json_tests {
         let test_cases = vec![
             json!([]),
             json!([1, 2, 3]),
-            // We can't handle a null as the last entry in an array, but we shoulud be able to handle an internal null,
             json!([1, "two", null, true,]),
+            json!([1, null, null]),
             json!([[1, 2], ["a", "b"]]),
         ];
 
@@ -290,4 +324,64 @@ mod json_tests {
 
         Ok(())
     }
+
+    fn eval_to_json(lua: &Lua, code: &str) -> Result<JsonValue, ConversionError> {
+        let value: LuaValue = lua.load(code).eval()?;
+        lua_value_to_json_value(value, true)
+    }
+
+    #[test]
+    fn test_array_holes() -> Result<(), Box<dyn std::error::Error>> {
+        let lua = Lua::new();
+
+        assert_eq!(
+            eval_to_json(&lua, "return {1, nil, 3}")?,
+            json!([1, null, 3])
+        );
+        assert_eq!(
+            eval_to_json(&lua, "return {nil, nil, 3}")?,
+            json!([null, null, 3])
+        );
+        // Trailing nils don't exist as far as lua is concerned...
+        assert_eq!(eval_to_json(&lua, "return {1, 2, nil}")?, json!([1, 2]));
+        // ... unless there's an explicit length.
+        assert_eq!(
+            eval_to_json(&lua, "return {1, 2, nil, n = 3}")?,
+            json!([1, 2, null])
+        );
+        assert_eq!(
+            eval_to_json(&lua, "return table.pack(1, nil, nil)")?,
+            json!([1, null, null])
+        );
+        // Only an n, so it's an object
+        assert_eq!(eval_to_json(&lua, "return {n = 3}")?, json!({"n": 3}));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_arrays() {
+        let lua = Lua::new();
+
+        assert!(matches!(
+            eval_to_json(&lua, "return {1, 2, n = 1}"),
+            Err(ConversionError::InvalidTableKey(_))
+        ));
+        assert!(matches!(
+            eval_to_json(&lua, "return {1, 2, x = 1}"),
+            Err(ConversionError::MixedArrayKeys)
+        ));
+        assert!(matches!(
+            eval_to_json(&lua, "return {[1e9] = 1}"),
+            Err(ConversionError::InvalidTableKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_huge_integers() -> Result<(), Box<dyn std::error::Error>> {
+        let lua = Lua::new();
+        let value = json_value_to_lua_value(&lua, &json!(10_000_000_000i64))?;
+        assert_eq!(value, LuaValue::Number(10_000_000_000.0));
+        Ok(())
+    }
 }
@@ -3,40 +3,84 @@ use std::{
     iter::repeat,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail, Context};
-use backend::{Backend, SimpleBackend};
-use bridge::{Bridge, SimpleBridge};
+use backend::{Backend, BackendOptions, SimpleBackend};
+use bridge::{Bridge, BridgeOptions, InputCheck, ScriptFailure, SimpleBridge};
 use clap::{Parser, Subcommand};
+use clock::SystemClock;
 
 pub mod backend;
 pub mod binary16;
+pub mod blame;
 pub mod bridge;
+pub mod cancel;
+pub mod clock;
 pub mod content_store;
+pub mod daemon;
+pub mod error;
 pub mod event_log;
 pub mod events;
+pub mod export;
 pub mod file_history;
 pub mod fs_utils;
+pub mod gc;
+pub mod ignore_rules;
+pub mod init_plan;
 pub mod llm;
+pub mod llm_cache;
+pub mod logging;
 pub mod luau_json;
 pub mod metadata;
+pub mod package_status;
+pub mod progress;
+pub mod project;
+pub mod project_root;
 pub mod project_status;
+pub mod render_cache;
+pub mod run_context;
+pub mod run_summary;
 pub mod scripting_luau;
 pub mod scripting_wasm;
+pub mod settings;
+pub mod starter;
+pub mod text;
+pub mod time_window;
+pub mod undo;
+pub mod verify;
 
 use binary16::ContentHash;
+use blame::Blame;
+use cancel::CancellationToken;
 use content_store::{ContentStore, FileSystemContentStore};
+use daemon::DaemonMessage;
 use event_log::{EventLog, SQLiteEventLog};
 use events::{Event, EventGroup};
 use events::{EventType, GetMetadataEvent, SetMetadataEvent, WriteFileEvent};
 
 use file_history::FileHistoryEntry;
-use llm::{InvalidLLM, OpenAILLM, LLM};
+use ignore_rules::IgnoreRules;
+use init_plan::{init_project, InitParams};
+use llm::{EchoLLM, InvalidLLM, LlmProvider, OpenAILLM, LLM};
+use llm_cache::LlmCache;
 use metadata::MetadataEntry;
 use metadata::MetadataKey;
-use project_status::get_project_status;
-use serde::{Deserialize, Serialize};
+use package_status::{
+    script_at_path, PackageDirectory, PackageHealth, Script, ScriptKind, Severity,
+};
+use progress::{JsonLinesProgress, ProgressSink};
+use project_root::{
+    check_for_nested_projects, requested_project_root, resolve_project_and_path,
+    resolve_project_root,
+};
+use project_status::{get_project_status, tracked_files, IncrementalStatus};
+use run_context::RunContext;
+use run_summary::RunSummary;
+use settings::{Settings, DEFAULT_OPENAI_BASE_URL};
+use text::format_byte_size;
+use time_window::TimeWindow;
 use xfs::Xfs;
 
 pub struct Wrought {
@@ -44,15 +88,18 @@ pub struct Wrought {
 }
 
 impl Wrought {
-    pub fn begin_script<N, F>(&mut self, name: N, f: F)
+    /// Runs `f`. An error from `f` is returned, with the script's name as context.
+    pub fn begin_script<N, F>(&mut self, name: N, f: F) -> anyhow::Result<()>
     where
         N: Into<String>,
         F: FnOnce(&mut MicroService) -> anyhow::Result<()>,
     {
+        let name = name.into();
         let mut m = MicroService::new(self.backend.clone());
-        println!("Wrought::begin_script - runnning {}", name.into());
-        f(&mut m).unwrap();
-        eprintln!("Wrough::begin_script - logged events =\n{:#?}", m.events);
+        log::debug!("running script '{}'", name);
+        f(&mut m).with_context(|| format!("script '{}' failed", name))?;
+        log::debug!("script '{}' logged events:\n{:#?}", name, m.events);
+        Ok(())
     }
 
     pub fn new(backend: Arc<Mutex<dyn Backend>>) -> Wrought {
@@ -144,7 +191,7 @@ impl MicroService {
     }
 }
 
-pub fn hello_world(wrought: &mut Wrought) {
+pub fn hello_world(wrought: &mut Wrought) -> anyhow::Result<()> {
     wrought.begin_script("hello world", |m: &mut MicroService| {
         if let Some(md) = m.get_metadata("index.md", "name")? {
             m.write_file(
@@ -156,16 +203,35 @@ pub fn hello_world(wrought: &mut Wrought) {
             m.write_file("hello.txt", "greetings!".as_bytes())?;
         }
         Ok(())
-    });
+    })
 }
 
 /// Search for a pattern in a file and display the lines that contain it.
 #[derive(Parser)]
 struct Cli {
-    /// pick a different project root
+    /// pick a different project root. If not given, `WROUGHT_PROJECT_ROOT` is used,
+    /// and failing that we search upwards from the current directory for `.wrought`.
     #[arg(long)]
     project_root: Option<PathBuf>,
 
+    /// Treat ambiguous project layouts (e.g. a project nested inside another) as errors
+    /// rather than warnings.
+    #[arg(long, default_value = "false")]
+    strict: bool,
+
+    /// Report progress of long running commands as JSON lines on stderr
+    #[arg(long, default_value = "false")]
+    progress: bool,
+
+    /// Open the project without allowing any changes to it - scripts can't write files
+    /// or metadata and the event log is opened read-only.
+    #[arg(long, global = true, default_value = "false")]
+    read_only: bool,
+
+    /// Say more about what wrought is doing. Repeat for debugging output, e.g. `-vv`
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
     /// Command to run
     #[command(subcommand)]
     command: Command,
@@ -177,16 +243,55 @@ enum Command {
     Init(InitCmd),
     RunScript(RunScriptCmd),
     Status(StatusCmd),
+    /// Keep the project's status in memory, updated as files change. Each line read
+    /// from stdin is a path, answered with a line of JSON giving its status
+    Daemon,
     History(HistoryCmd),
+    /// Show what the last run that changed a file changed, i.e. the difference between
+    /// the last two versions wrought wrote
+    Diff(DiffCmd),
+    /// Show which run last changed each line of a file
+    Blame(BlameCmd),
+    /// Print a file's content, as wrought sees it
+    Show(ShowCmd),
+    /// Write content piped in to a file, recording the write like a script's
+    Write(WriteCmd),
+    /// Accept a file's current content, so it is reported as OK even if it differs
+    /// from what was generated.
+    Pin(PinCmd),
+    /// Remove a pin added by `pin`.
+    Unpin(PinCmd),
+    Log(LogCmd),
+    /// Remove a run from the history.
+    Undo(UndoCmd),
+    ListTracked,
+    /// Print the event log, pins and metadata as JSON, in a stable order for diffing
+    Export,
+    /// Manage the LLM integration
+    Llm(LlmCmd),
+    /// Inspect file metadata
+    Metadata(MetadataCmd),
     ContentStoreShow(ContentStoreShowCmd),
+    /// Inspect the content store
+    ContentStore(ContentStoreCmd),
+    /// Check the event log for damage, and that the content it refers to is stored
+    Verify,
     HelloWorld,
 }
 
 #[derive(Debug, Parser)]
 struct InitCmd {
     path: PathBuf,
-    #[arg(long)]
-    package: String,
+    #[arg(long, required_unless_present = "empty")]
+    package: Option<String>,
+
+    /// Don't use a package - create a minimal project with a runnable starter script
+    #[arg(long, default_value = "false", conflicts_with = "package")]
+    empty: bool,
+
+    /// Print what init would create, copy and run, without changing anything
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -196,6 +301,14 @@ struct StatusCmd {
 
     #[arg(long, default_value = "false")]
     include_aux: bool,
+
+    /// Only show package status entries at or above this severity
+    #[arg(long, value_enum, default_value_t = Severity::Info)]
+    min_severity: Severity,
+
+    /// Exit with an error if any package reports an error or can't be read, e.g. in CI
+    #[arg(long, default_value = "false")]
+    fail_on_package_error: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -205,284 +318,312 @@ struct FileStatusCmd {
 
 #[derive(Debug, Parser)]
 struct RunScriptCmd {
-    script_name: String,
+    /// The script to run, as `<package>/<script>`. Just `<script>` will do if only one
+    /// package has a script by that name.
+    #[arg(required_unless_present = "path", conflicts_with = "path")]
+    script_name: Option<String>,
+
+    /// Run the script at this path instead, which must be inside the project
+    #[arg(long)]
+    path: Option<PathBuf>,
+
+    /// Don't print a summary of what the script did
+    #[arg(long, default_value = "false")]
+    quiet: bool,
+
+    /// Print the summary as JSON
+    #[arg(long, default_value = "false", conflicts_with = "quiet")]
+    json: bool,
+
+    /// Warn when the script reads files outside the project or inside `.wrought`,
+    /// as changes to them aren't tracked. With --strict these reads are errors.
+    #[arg(long, default_value = "false")]
+    strict_inputs: bool,
+
+    /// Print the event group the run records, as JSON, after the summary. Combine
+    /// with --read-only to see it without recording anything.
+    #[arg(long, default_value = "false")]
+    dump_events: bool,
+
+    /// Write the script's output files under this directory instead of the project,
+    /// to preview them. Reads still come from the project. The summary shows project
+    /// paths, but nothing is recorded, so `status` is unchanged by a preview run.
+    /// Scripts that set metadata or status, or move files, fail in a preview.
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Parser)]
 struct HistoryCmd {
     path: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = HashFormat::Base64)]
+    format: HashFormat,
+
+    #[command(flatten)]
+    window: TimeWindowArgs,
 }
 
-//TODO: Make this a sub-command on a ContentStore function
 #[derive(Debug, Parser)]
-struct ContentStoreShowCmd {
-    hash: String,
+struct DiffCmd {
+    path: PathBuf,
+
+    /// Compare the last two versions wrought wrote, i.e. what the last run that
+    /// changed the file changed. The only comparison there is so far, so it is on
+    /// whether given or not.
+    #[arg(long, default_value = "true")]
+    last: bool,
 }
 
-fn find_first_existing_parent(
-    fs: &dyn xfs::Xfs,
-    starting_dir: &Path,
-) -> anyhow::Result<Option<PathBuf>> {
-    let mut current_dir = starting_dir;
+#[derive(Debug, Parser)]
+struct BlameCmd {
+    path: PathBuf,
+}
 
-    loop {
-        if fs.exists(current_dir) {
-            return Ok(Some(current_dir.to_path_buf()));
-        }
+/// Limits output to runs in a time range. Runs recorded before wrought stored times
+/// are left out whenever either limit is given.
+#[derive(Debug, clap::Args)]
+struct TimeWindowArgs {
+    /// Only runs at or after this time - RFC3339, or relative like "2d" or "1h"
+    #[arg(long)]
+    since: Option<String>,
 
-        let parent_dir = current_dir.parent();
-        match parent_dir {
-            Some(parent) => current_dir = parent,
-            None => return Ok(None),
-        }
+    /// Only runs at or before this time - RFC3339, or relative like "2d" or "1h"
+    #[arg(long)]
+    until: Option<String>,
+}
+
+impl TimeWindowArgs {
+    fn window(&self) -> anyhow::Result<TimeWindow> {
+        TimeWindow::parse(
+            self.since.as_deref(),
+            self.until.as_deref(),
+            chrono::Utc::now(),
+        )
     }
 }
 
-fn find_marker_dir(
-    fs: &dyn xfs::Xfs,
-    starting_dir: &Path,
-    marker: &str,
-) -> anyhow::Result<Option<PathBuf>> {
-    let starting_dir = fs.canonicalize(starting_dir)?;
-    let mut current_dir: &Path = &starting_dir;
-
-    loop {
-        let marker_path = current_dir.join(marker);
-        if fs.is_dir(&marker_path) {
-            return Ok(Some(current_dir.to_path_buf()));
-        }
+/// How hashes are shown to the user. Storage always uses base64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum HashFormat {
+    Base64,
+    Hex,
+}
 
-        let parent_dir = current_dir.parent();
-        match parent_dir {
-            Some(parent) => current_dir = parent,
-            None => return Ok(None),
+impl HashFormat {
+    fn show(&self, hash: &ContentHash) -> String {
+        match self {
+            HashFormat::Base64 => hash.to_string(),
+            HashFormat::Hex => hash.to_hex(),
         }
     }
 }
 
-fn cmd_init(cmd: &InitCmd) -> anyhow::Result<()> {
-    let fs = Arc::new(Mutex::new(xfs::OsFs {}));
-    let path = &cmd.path;
+#[derive(Debug, Parser)]
+struct MetadataCmd {
+    #[command(subcommand)]
+    command: MetadataSubcommand,
+}
 
-    // Check the target is not already in a project.
-    let check = || -> anyhow::Result<Option<PathBuf>> {
-        let existing_parent = find_first_existing_parent(&*fs.lock().unwrap(), path)
-            .context("in find_first_existing_parent")?;
-        let Some(existing_parent) = existing_parent else {
-            return Ok(None);
-        };
-        find_marker_dir(&*fs.lock().unwrap(), &existing_parent, ".wrought")
-            .context("in find_marker_dir")
-    };
+#[derive(Debug, Subcommand)]
+enum MetadataSubcommand {
+    /// Show the metadata of every file that has some
+    List,
+}
 
-    if let Some(parent_path) = check().unwrap() {
-        panic!(
-            "Path '{}' is part of project with root '{}'",
-            path.display(),
-            parent_path.display()
-        );
-    }
+#[derive(Debug, Parser)]
+struct LlmCmd {
+    #[command(subcommand)]
+    command: LlmSubcommand,
+}
 
-    fs.lock().unwrap().create_dir_all(path).unwrap();
-    fs.lock()
-        .unwrap()
-        .create_dir_all(&path.join(".wrought"))
-        .unwrap();
+#[derive(Debug, Subcommand)]
+enum LlmSubcommand {
+    /// Manage the cache of LLM responses
+    #[command(subcommand)]
+    Cache(LlmCacheCmd),
+}
 
-    let mut writer = fs
-        .lock()
-        .unwrap()
-        .writer(&path.join(".wrought").join("settings.toml"))?;
-    writer.write_all(
-        [
-            "# General Project Settings",
-            "",
-            "# LLM Settings",
-            "# Uncomment and set to enable LLM features",
-            "# openai_api_key = \"PUT_YOUR_KEY_HERE\"",
-            "",
-        ]
-        .join("\n")
-        .as_bytes(),
-    )?;
+#[derive(Debug, Subcommand)]
+enum LlmCacheCmd {
+    /// Delete all cached responses
+    Clear(LlmCacheClearCmd),
+    /// Show the number and total size of cached responses
+    Stats,
+}
 
-    let content_dir = path.join(".wrought").join("content");
-    fs.lock().unwrap().create_dir_all(&content_dir).unwrap();
+#[derive(Debug, Parser)]
+struct LlmCacheClearCmd {
+    /// Don't ask for confirmation
+    #[arg(long, short = 'y', default_value = "false")]
+    yes: bool,
+}
 
-    // TODO: Make this configurable.
-    let src_package_dir = PathBuf::from("./resources/packages/");
-    let project_package_dir = path.join(".wrought").join("packages");
+#[derive(Debug, Parser)]
+struct PinCmd {
+    path: PathBuf,
+}
 
-    fs.lock()
-        .unwrap()
-        .create_dir_all(&path.join(".wrought"))
-        .unwrap();
-    SQLiteEventLog::init(path.join(".wrought").join("wrought.db")).unwrap();
-    fs.lock()
-        .unwrap()
-        .create_dir_all(&project_package_dir)
-        .unwrap();
+#[derive(Debug, Parser)]
+struct LogCmd {
+    /// Show just this run, as shown by `log`, along with what it printed
+    #[arg(conflicts_with = "follow")]
+    group_id: Option<u64>,
 
-    let project_package = project_package_dir.join(&cmd.package);
-    fs.lock().unwrap().create_dir_all(&project_package).unwrap();
+    #[command(flatten)]
+    window: TimeWindowArgs,
 
-    fs_utils::copy_dir_all_with_filters(
-        &mut *fs.lock().unwrap(),
-        src_package_dir.join(&cmd.package),
-        &project_package,
-        |_, _| true,
-        |_, _| true,
-    )?;
+    /// Keep running, printing new runs as they are recorded
+    #[arg(long, default_value = "false")]
+    follow: bool,
 
-    // Now if there is an init script we should run it.
-    println!("Running init scripts");
+    /// With --follow, print the existing history before following
+    #[arg(long, default_value = "false", requires = "follow")]
+    all: bool,
+}
 
-    let bridge = create_bridge(path)?;
+#[derive(Debug, Parser)]
+struct UndoCmd {
+    /// The id of the run, as shown by `log`
+    group_id: u64,
 
-    if project_package.join("init.luau").is_file() {
-        scripting_luau::run_script(bridge.clone(), fs, &project_package.join("init.luau"))?;
-        // TODO: Does this belong in the bridge?
-        let event_log = create_event_log(path).unwrap();
-        if let Some(event_group) = bridge.lock().unwrap().get_event_group() {
-            event_log
-                .lock()
-                .unwrap()
-                .add_event_group(&event_group)
-                .unwrap();
-        };
-    } else {
-        println!(
-            "No init script at '{}'",
-            project_package.join("init.luau").display()
-        );
-    }
-    Ok(())
-}
+    /// Undo the run even if later runs read files it wrote
+    #[arg(long, default_value = "false")]
+    force: bool,
 
-#[derive(Debug)]
-struct PackageStatusEntry {
-    path: PathBuf,
-    title: String,
-    status: String,
-    next_steps: Vec<String>,
+    /// Then delete stored content nothing refers to any more, as `content-store gc -y`
+    /// would
+    #[arg(long, default_value = "false")]
+    gc: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct PackageStatusEntryRepr {
-    title: String,
-    status: String,
-    next_steps: Vec<String>,
+#[derive(Debug, Parser)]
+struct ContentStoreCmd {
+    #[command(subcommand)]
+    command: ContentStoreSubcommand,
 }
 
-impl PackageStatusEntry {
-    pub fn read_from(fs: &dyn xfs::Xfs, p: &Path) -> anyhow::Result<PackageStatusEntry> {
-        let mut content = String::new();
-        fs.reader(p)?.read_to_string(&mut content)?;
-        let content: PackageStatusEntryRepr = toml::from_str(&content)?;
+#[derive(Debug, Subcommand)]
+enum ContentStoreSubcommand {
+    /// Show the number and sizes of stored objects
+    Stats,
+    /// Store content piped in, and print its hash
+    Add(ContentStoreAddCmd),
+    /// Delete stored objects that nothing in the event log or pins refers to
+    Gc(ContentStoreGcCmd),
+}
 
-        Ok(PackageStatusEntry {
-            path: p.to_path_buf(),
-            title: content.title,
-            status: content.status,
-            next_steps: content.next_steps,
-        })
-    }
+#[derive(Debug, Parser)]
+struct ContentStoreGcCmd {
+    /// Only report what would be deleted
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
 
-    pub fn name(&self) -> String {
-        self.path.file_name().unwrap().to_string_lossy().to_string()
-    }
+    /// Don't ask for confirmation
+    #[arg(long, short = 'y', default_value = "false")]
+    yes: bool,
 }
 
-#[derive(Debug)]
-pub struct PackageStatus {
-    package: Package,
-    entries: Vec<anyhow::Result<PackageStatusEntry>>,
+#[derive(Debug, Parser)]
+struct ContentStoreAddCmd {
+    /// Where to read the content from - only `-`, for stdin
+    #[arg(value_parser = ["-"])]
+    source: String,
 }
 
-#[derive(Clone, Debug)]
-struct Package {
+#[derive(Debug, Parser)]
+struct WriteCmd {
     path: PathBuf,
+
+    /// Where to read the content from - only `-`, for stdin
+    #[arg(value_parser = ["-"])]
+    source: String,
 }
 
-impl Package {
-    fn status(&self, fs: &dyn xfs::Xfs) -> PackageStatus {
-        let status_dir = self.path.join("status");
-        let mut entries = vec![];
+#[derive(Debug, Parser)]
+struct ShowCmd {
+    path: PathBuf,
 
-        let mut f = |fs: &dyn Xfs, entry: &dyn xfs::XfsDirEntry| -> anyhow::Result<()> {
-            let md = match entry.metadata() {
-                Ok(md) => md,
-                Err(e) => {
-                    entries.push(
-                        Err(e).with_context(|| format!("getting metadata for {:?}", entry.path())),
-                    );
-                    return Ok(());
-                }
-            };
-            if !md.is_file() {
-                entries.push(Err(anyhow!(
-                    "status entry {:?} is not a file",
-                    entry.path()
-                )));
-                return Ok(());
-            }
+    /// Print the version with this hash (base64 or hex) from the content store,
+    /// instead of the current content
+    #[arg(long)]
+    at: Option<String>,
+}
 
-            entries.push(PackageStatusEntry::read_from(fs, &entry.path()));
-            Ok(())
-        };
+//TODO: Make this a sub-command on a ContentStore function
+#[derive(Debug, Parser)]
+struct ContentStoreShowCmd {
+    /// The hash, in either base64 or hex form
+    hash: String,
+}
 
-        if let Err(e) = fs.on_each_entry(&status_dir, &mut f) {
-            entries.push(
-                Err(e).with_context(|| format!("while reading statuses from {:?}", status_dir)),
-            );
-        }
-        PackageStatus {
-            package: self.clone(),
-            entries,
-        }
+fn cmd_init(cmd: &InitCmd, read_only: bool) -> anyhow::Result<()> {
+    if read_only {
+        bail!("`init` can not be used with --read-only");
     }
+    let fs = Arc::new(Mutex::new(xfs::OsFs {}));
+    let path = &cmd.path;
 
-    fn name(&self) -> String {
-        self.path.file_name().unwrap().to_str().unwrap().to_string()
+    let package = if cmd.empty {
+        None
+    } else {
+        Some(
+            cmd.package
+                .clone()
+                .context("either --package or --empty is required")?,
+        )
+    };
+    let plan = init_project(
+        fs.clone(),
+        &InitParams {
+            path: path.clone(),
+            package,
+            // TODO: Make this configurable.
+            src_package_dir: PathBuf::from("./resources/packages/"),
+            dry_run: cmd.dry_run,
+            init_event_log: &|p| SQLiteEventLog::init(p),
+        },
+    )?;
+    if cmd.dry_run {
+        print!("{}", plan);
+        return Ok(());
     }
-}
 
-struct PackageDirectory {
-    path: PathBuf,
-}
+    let Some((package, _)) = &plan.package else {
+        println!(
+            "Created an empty project - try `wrought run-script {}/init.luau`",
+            starter::STARTER_PACKAGE
+        );
+        return Ok(());
+    };
 
-impl PackageDirectory {
-    fn packages(&self, fs: &dyn xfs::Xfs) -> Vec<anyhow::Result<Package>> {
-        let mut result = vec![];
+    // Now if there is an init script we should run it.
+    println!("Running init scripts");
 
-        let mut f = |_fs: &dyn Xfs, entry: &dyn xfs::XfsDirEntry| -> anyhow::Result<()> {
-            let md = match entry.metadata() {
-                Err(e) => {
-                    result.push(
-                        Err(e).with_context(|| format!("getting metadata for {:?}", entry.path())),
-                    );
-                    return Ok(());
-                }
-                Ok(md) => md,
-            };
-            if !md.is_dir() {
-                result.push(Err(anyhow!(
-                    "package directory entry {:?} is not a directory",
-                    entry.path()
-                )));
-                return Ok(());
-            }
-            result.push(Ok(Package { path: entry.path() }));
-            Ok(())
-        };
+    // Package init scripts are trusted to write their own status into `.wrought/packages`.
+    let bridge = create_bridge(
+        path,
+        BackendOptions {
+            allow_internal_writes: true,
+            ..BackendOptions::default()
+        },
+        None,
+        BridgeOptions {
+            package: Some(package.to_string()),
+            ..BridgeOptions::default()
+        },
+    )?;
 
-        if let Err(e) = fs.on_each_entry(&self.path, &mut f) {
-            result.push(
-                Err(e).with_context(|| format!("while reading packages from {:?}", self.path)),
-            );
-        }
-        result
+    if let Some(init_script) = &plan.init_script {
+        scripting_luau::run_script(bridge.clone(), fs, init_script)?;
+        bridge.lock().unwrap().record_run()?;
+    } else {
+        println!(
+            "No init script at '{}'",
+            plan.package_dir().join(package).join("init.luau").display()
+        );
     }
+    Ok(())
 }
 
 struct StatusFormatter {
@@ -497,6 +638,7 @@ const TERM_COLOR_RESET: &str = "\x1b[0m";
 const TERM_COLOR_CYAN: &str = "\x1b[36m";
 const TERM_COLOR_MAGENTA: &str = "\x1b[35m";
 const TERM_COLOR_BLUE: &str = "\x1b[34m";
+const TERM_COLOR_YELLOW: &str = "\x1b[33;1m";
 
 impl StatusFormatter {
     fn maybe_colorise<'a>(&self, message: &'a str, color: &str) -> Cow<'a, str> {
@@ -557,19 +699,110 @@ impl StatusFormatter {
         println!(" {}{} {}", changed_str, stale_str, path.display());
     }
 
+    fn severity(&self, severity: Severity) -> Cow<'static, str> {
+        match severity {
+            Severity::Info => "*".into(),
+            Severity::Warning => self.maybe_colorise("!", TERM_COLOR_YELLOW),
+            Severity::Error => self.maybe_colorise("!!", TERM_COLOR_RED),
+        }
+    }
+
     fn note(&self, message: &str) {
         self.maybe_colorise(message, TERM_COLOR_BLUE);
     }
 }
 
-fn cmd_status(project_root: &Path, cmd: StatusCmd) -> anyhow::Result<()> {
-    let fs = Arc::new(Mutex::new(xfs::OsFs {}));
-    let event_log = create_event_log(project_root)?;
+fn cmd_daemon(project_root: &Path) -> anyhow::Result<()> {
+    let fs = xfs::OsFs {};
+    // The watcher reports canonical paths.
+    let project_root = fs.canonicalize(project_root)?;
+    // The daemon only reads the event log, so it never gets in the way of a run.
+    let event_log = create_event_log(&project_root, true)?;
+    let ignore = IgnoreRules::load(&fs, &project_root)?;
+    let settings = Settings::load(&fs, &project_root)?;
+    for warning in &settings.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let mut status = IncrementalStatus::new(
+        &*event_log.lock().unwrap(),
+        &fs,
+        &project_root,
+        ignore,
+        settings.max_track_size()?,
+    )?;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let watch_root = project_root.clone();
+    let watch_tx = tx.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // A failed watch only means a missed change, which the next one will fix.
+        let Ok(event) = event else {
+            return;
+        };
+        let paths = event
+            .paths
+            .iter()
+            .filter_map(|p| p.strip_prefix(&watch_root).ok())
+            .map(Path::to_path_buf)
+            .collect();
+        let _ = watch_tx.send(DaemonMessage::Changed(paths));
+    })?;
+    notify::Watcher::watch(
+        &mut watcher,
+        &project_root,
+        notify::RecursiveMode::Recursive,
+    )?;
+
+    // Closing stdin shuts the daemon down, just like `quit`.
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send(DaemonMessage::Query(line)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(DaemonMessage::Shutdown);
+    });
+
+    let event_log = event_log.lock().unwrap();
+    daemon::run_daemon(
+        &mut status,
+        &*event_log,
+        &fs,
+        &project_root,
+        &rx,
+        daemon::DEBOUNCE,
+        &mut std::io::stdout().lock(),
+    )
+}
 
+fn cmd_status(
+    project_root: &Path,
+    cmd: StatusCmd,
+    progress: Option<&dyn ProgressSink>,
+    read_only: bool,
+) -> anyhow::Result<()> {
+    let fs = Arc::new(Mutex::new(xfs::OsFs {}));
+    let event_log = create_event_log(project_root, read_only)?;
+
+    // Skip anything in the .wrought directory unless requested,
+    // the content directory is always skipped.
+    let ignore =
+        IgnoreRules::load(&*fs.lock().unwrap(), project_root)?.with_internal_files(cmd.include_aux);
+    let settings = Settings::load(&*fs.lock().unwrap(), project_root)?;
+    for warning in &settings.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let max_track_size = settings.max_track_size()?;
     let project_status = get_project_status(
         &*event_log.lock().unwrap(),
         &*fs.lock().unwrap(),
         project_root,
+        &ignore,
+        max_track_size,
+        progress,
     )?;
 
     let fmt = StatusFormatter {
@@ -587,16 +820,7 @@ fn cmd_status(project_root: &Path, cmd: StatusCmd) -> anyhow::Result<()> {
     ];
 
     for f in &project_status.file_statuses {
-        // Skip anything in the .wrought directory unless requested,
-        // but always skip the content directory.
-        //
-        // TODO: There may be others we want to skip too...
-        let skip = match cmd.include_aux {
-            true => f.path.starts_with(".wrought/content"),
-            false => f.path.starts_with(".wrought"),
-        };
-
-        if skip || skipped_system_files.contains(&f.path) {
+        if skipped_system_files.contains(&f.path) {
             continue;
         }
 
@@ -624,16 +848,46 @@ fn cmd_status(project_root: &Path, cmd: StatusCmd) -> anyhow::Result<()> {
     if !printed_amnything {
         fmt.note("(nothing to report)")
     }
+    if !project_status.skipped.is_empty() {
+        println!(
+            "({} untracked files larger than max_track_size were not hashed)",
+            project_status.skipped.len()
+        );
+    }
+    if !project_status.problems.is_empty() {
+        println!();
+        fmt.heading("Warnings", 2);
+        for (path, e) in &project_status.problems {
+            println!(" !! {} could not be read: {:#}", path.display(), e);
+        }
+    }
     println!();
     fmt.heading("Package Statuses", 2);
 
+    let health = PackageHealth::of(
+        &project_status.package_statuses,
+        project_status.package_problems.len(),
+    );
+    println!("{}", health);
+    for e in &project_status.package_problems {
+        println!("{} {:#}", fmt.severity(Severity::Error), e);
+    }
+
     // Now print the statuses of the packages
     for package_status in &project_status.package_statuses {
         fmt.heading(&package_status.package.name(), 3);
         for status in &package_status.entries {
             match status {
                 Ok(status) => {
-                    println!("* {} [{}]", status.title.trim(), status.name());
+                    if status.severity < cmd.min_severity {
+                        continue;
+                    }
+                    println!(
+                        "{} {} [{}]",
+                        fmt.severity(status.severity),
+                        status.title.trim(),
+                        status.name()
+                    );
                     println!("  {}", status.status.trim().replace("\n", "\n  "));
                 }
                 Err(e) => println!("* error: {}", e),
@@ -648,7 +902,7 @@ fn cmd_status(project_root: &Path, cmd: StatusCmd) -> anyhow::Result<()> {
         for status in &package_status.entries {
             match status {
                 Ok(status) => {
-                    if status.next_steps.is_empty() {
+                    if status.next_steps.is_empty() || status.severity < cmd.min_severity {
                         continue;
                     }
                     println!("* {} [{}]", status.title.trim(), status.name());
@@ -661,92 +915,151 @@ fn cmd_status(project_root: &Path, cmd: StatusCmd) -> anyhow::Result<()> {
         }
     }
 
+    if cmd.fail_on_package_error && health.error > 0 {
+        bail!("{} package(s) have errors", health.error);
+    }
     Ok(())
 }
 
+/// Runs `script` in the runtime its kind needs, returning any result it set. A script
+/// that called `fail` gives just its `ScriptFailure` as the error, even if it caught the
+/// error that led to.
+fn run_script_file(
+    bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
+    fs: Arc<Mutex<dyn xfs::Xfs>>,
+    script: &Script,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    let script_path = &script.path;
+    // Worked out first, so the fs is unlocked before the runtime takes it.
+    let kind = script.kind(&*fs.lock().unwrap())?;
+    let result = match kind {
+        ScriptKind::Luau => scripting_luau::run_script(bridge.clone(), fs, script_path)
+            .map(|()| None)
+            .with_context(|| format!("error running lua script {}", script.name)),
+        ScriptKind::Wasm => scripting_wasm::run_script(bridge.clone(), fs, script_path)
+            .with_context(|| format!("error running WASM script {}", script.name)),
+    };
+    let failure = bridge.lock().unwrap().failure();
+    match failure {
+        Some(failure) => Err(failure.into()),
+        None => result,
+    }
+}
+
 fn cmd_run_script(
     bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
-    project_root: &Path,
+    script: &Script,
     cmd: RunScriptCmd,
 ) -> anyhow::Result<()> {
     let fs = Arc::new(Mutex::new(xfs::OsFs {}));
-    let script_path = project_root
-        .join(".wrought")
-        .join("packages")
-        .join(&cmd.script_name);
-    // TODO: Get rid of unwrap here...
-    let extension = script_path.extension().unwrap();
-    if extension == "luau" || extension == "lua" {
-        scripting_luau::run_script(bridge, fs, &script_path)
-            .with_context(|| format!("error running lua script {}", cmd.script_name))?;
-    } else if extension == "wasm" {
-        scripting_wasm::run_script(bridge, fs, &script_path)
-            .with_context(|| format!("error running WASM script {}", cmd.script_name))?;
-    } else {
-        bail!(
-            "Unsupported script extension '{:?}' for {}",
-            extension,
-            script_path.display()
-        );
+    let result = run_script_file(bridge.clone(), fs, script)?;
+
+    if !cmd.quiet {
+        let event_group = bridge
+            .lock()
+            .unwrap()
+            .get_event_group()
+            .unwrap_or_else(EventGroup::empty);
+        let summary = RunSummary {
+            result,
+            ..RunSummary::from_event_group(&event_group)
+        };
+        if cmd.json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else {
+            print!("{}", summary);
+        }
+    } else if let Some(result) = result {
+        // Even a quiet run hands back what the plugin computed.
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
+    if cmd.dump_events {
+        let event_group = bridge
+            .lock()
+            .unwrap()
+            .get_event_group()
+            .unwrap_or_else(EventGroup::empty);
+        println!("{}", serde_json::to_string_pretty(&event_group)?);
     }
     Ok(())
 }
 
-pub fn create_backend(path: &Path) -> anyhow::Result<Arc<Mutex<dyn Backend + Send + 'static>>> {
+pub fn create_backend(
+    path: &Path,
+    options: BackendOptions,
+) -> anyhow::Result<Arc<Mutex<dyn Backend + Send + 'static>>> {
     let fs = Arc::new(Mutex::new(xfs::OsFs {}));
     let path = fs.lock().unwrap().canonicalize(path)?;
     let content_storage_path = path.join(".wrought").join("content");
-    let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
-        fs.clone(),
+    let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
         content_storage_path,
     )));
+    let settings = Settings::load(&*fs.lock().unwrap(), &path)?;
+    let options = BackendOptions {
+        ensure_trailing_newline: settings.ensure_trailing_newline()?,
+        ..options
+    };
     Ok(Arc::new(Mutex::new(SimpleBackend {
         fs,
+        os_fs: true,
         root: path,
         content_store,
+        options,
     })))
 }
 
-pub fn create_event_log(path: &Path) -> anyhow::Result<Arc<Mutex<dyn EventLog>>> {
-    Ok(Arc::new(Mutex::new(
-        SQLiteEventLog::open(path.join(".wrought").join("wrought.db")).unwrap(),
-    )))
+pub fn create_event_log(path: &Path, read_only: bool) -> anyhow::Result<Arc<Mutex<dyn EventLog>>> {
+    let db_path = path.join(".wrought").join("wrought.db");
+    let event_log = if read_only {
+        SQLiteEventLog::open_read_only(db_path)?
+    } else {
+        SQLiteEventLog::open(db_path).unwrap()
+    };
+    Ok(Arc::new(Mutex::new(event_log)))
 }
 
-pub fn create_bridge(path: &Path) -> anyhow::Result<Arc<Mutex<dyn Bridge + Send + 'static>>> {
+pub fn create_bridge(
+    path: &Path,
+    options: BackendOptions,
+    progress: Option<Arc<dyn ProgressSink + Send + Sync>>,
+    bridge_options: BridgeOptions,
+) -> anyhow::Result<Arc<Mutex<dyn Bridge + Send + 'static>>> {
     let fs = Arc::new(Mutex::new(xfs::OsFs {}));
     // Load up an settings in the project settings file - needed
     // to initialise the openAI LLM.
     let root = fs.lock().unwrap().canonicalize(path)?;
-    let reader = fs
-        .lock()
-        .unwrap()
-        .reader_if_exists(&root.join(".wrought").join("settings.toml"))?;
-    let settings = match reader {
-        Some(mut reader) => {
-            let mut settings = String::new();
-            reader.read_to_string(&mut settings)?;
-            settings.parse::<toml::Table>()?
-        }
-        None => toml::Table::new(),
-    };
-    let backend = create_backend(path)?;
-    let llm_cache_dir = root.join(".wrought").join("llm_cache");
-    fs.lock().unwrap().create_dir_all(&llm_cache_dir)?;
-    // TODO: Get this from somewhere...
-
-    let openai_api_key = match settings.get("openai_api_key") {
-        Some(openai_api_key) => Some(
-            openai_api_key
-                .as_str()
-                .context("invalid setting: openai_api_key is not a string")?
-                .to_string(),
-        ),
-        None => None,
-    };
+    let settings = Settings::load(&*fs.lock().unwrap(), &root)?;
+    for warning in &settings.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let read_only = options.read_only;
+    let backend = create_backend(path, options)?;
+    let db_path = root.join(".wrought").join("wrought.db");
+    let event_log = Arc::new(Mutex::new(if read_only {
+        SQLiteEventLog::open_read_only(db_path)?
+    } else {
+        SQLiteEventLog::open(db_path)?
+    }));
+    let llm_cache_dir = settings.llm_cache_dir(&root)?;
+    if !read_only {
+        fs.lock().unwrap().create_dir_all(&llm_cache_dir)?;
+    }
+
+    let openai_api_key = settings.openai_api_key()?;
+    let openai_base_url = settings.openai_base_url()?;
+    let llm_provider = settings.llm_provider()?;
     let llm: Arc<Mutex<dyn LLM + Send + 'static>> = match openai_api_key {
+        // Nothing is cached, so it works in read-only projects too.
+        _ if llm_provider == LlmProvider::Echo => Arc::new(Mutex::new(EchoLLM)),
+        // Responses are written to the cache, so the LLM is off in read-only projects.
+        _ if read_only => Arc::new(Mutex::new(InvalidLLM::create_with_error_message(
+            "LLM queries are disabled in read-only projects",
+        ))),
+        Some(openai_api_key) if openai_base_url != DEFAULT_OPENAI_BASE_URL => Arc::new(Mutex::new(
+            OpenAILLM::create_with_base_url(openai_api_key, openai_base_url),
+        )),
         Some(openai_api_key) => {
-            let llm = OpenAILLM::create_with_key(openai_api_key, fs, llm_cache_dir)?;
+            let llm = OpenAILLM::create_with_key(openai_api_key, fs.clone(), llm_cache_dir)?;
             Arc::new(Mutex::new(llm))
         }
         None => {
@@ -759,105 +1072,556 @@ pub fn create_bridge(path: &Path) -> anyhow::Result<Arc<Mutex<dyn Bridge + Send
     Ok(Arc::new(Mutex::new(SimpleBridge {
         root,
         backend,
-        event_group: EventGroup::empty(),
+        event_log,
+        fs,
+        run: RunContext::new(bridge_options.command.as_deref()),
         llm,
+        config: settings.script_config(),
+        progress,
+        ai_query_count: 0,
+        options: bridge_options,
+        clock: Arc::new(SystemClock),
+        temp_dir: None,
+        failure: None,
     })))
 }
 
-fn get_absolute_project_and_relative_file(
-    fs: &dyn xfs::Xfs,
-    working_dir: &Path,
+fn cmd_pin(
+    fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
+    event_log: Arc<Mutex<dyn EventLog>>,
+    project_root: &Path,
     file_path: &Path,
-    project_root: Option<&Path>,
-) -> anyhow::Result<(PathBuf, PathBuf)> {
-    eprintln!(
-        "get_absolute_project_and_relative_file: working_dir={:?} file_path={:?} project_root={:?}",
-        working_dir, file_path, project_root
-    );
-
-    assert!(working_dir.is_absolute());
-
-    let file_path = if file_path.is_absolute() {
-        file_path.to_path_buf()
-    } else {
-        working_dir.join(file_path)
-    };
-
-    //NOTE: We can't immediately cannonicalise file_path as it may not exits.
+) -> anyhow::Result<()> {
+    let hash = calculate_file_hash(&*fs.lock().unwrap(), &project_root.join(file_path))?
+        .with_context(|| format!("can not pin {} - it does not exist", file_path.display()))?;
+    event_log.lock().unwrap().set_pin(file_path, Some(hash))?;
+    Ok(())
+}
 
-    // Now if we've explicitly specified a project_root, use that
-    // and check the file is inside the project root, otherwise search for the project root.
+fn cmd_unpin(event_log: Arc<Mutex<dyn EventLog>>, file_path: &Path) -> anyhow::Result<()> {
+    event_log.lock().unwrap().set_pin(file_path, None)
+}
 
-    let project_root = match project_root {
-        Some(p) => {
-            let p = if p.is_absolute() {
-                p.to_path_buf()
-            } else {
-                working_dir.join(p)
-            };
-            if !fs.is_dir(&p.join(".wrought")) {
-                bail!("specified project root {} has no .wrought subdirectory - it is not a valid root", p.display());
-            }
-            fs.canonicalize(&p)?
-        }
-        None => {
-            let parent = find_first_existing_parent(fs, &file_path)?;
-            let parent = parent.with_context(|| {
-                format!(
-                    "Unable to find existing parent directory for {:?}",
-                    file_path
-                )
-            })?;
-            let parent = fs.canonicalize(&parent)?;
-            let project_root = find_marker_dir(fs, &parent, ".wrought")?;
-            project_root.with_context(|| {
-                format!("Unable to find wrought root containing {:?}", file_path)
-            })?
+fn cmd_llm(
+    cmd: LlmCmd,
+    fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
+    project_root: &Path,
+    read_only: bool,
+) -> anyhow::Result<()> {
+    let fs = fs.lock().unwrap();
+    let settings = Settings::load(&*fs, project_root)?;
+    let cache = LlmCache::new(settings.llm_cache_dir(project_root)?);
+    match cmd.command {
+        LlmSubcommand::Cache(LlmCacheCmd::Stats) => {
+            let stats = cache.stats(&*fs)?;
+            println!("cache directory: {}", cache.dir.display());
+            println!("entries: {}", stats.entries);
+            println!("total size: {} bytes", stats.total_bytes);
         }
-    };
-    eprintln!("using project_root = {:?}", project_root);
-
-    let relative_file_path = file_path
-        .strip_prefix(&project_root)
-        .with_context(|| {
-            format!(
-                "file '{:?}' not inside project root '{:?}'",
-                file_path, project_root
-            )
-        })?
-        .to_path_buf();
-    Ok((project_root, relative_file_path))
+        LlmSubcommand::Cache(LlmCacheCmd::Clear(clear_cmd)) => {
+            if read_only {
+                bail!("can not clear the LLM cache - the project was opened read-only");
+            }
+            let stats = cache.stats(&*fs)?;
+            if stats.entries == 0 {
+                println!("LLM cache is already empty");
+                return Ok(());
+            }
+            if !clear_cmd.yes {
+                print!(
+                    "Delete {} cached LLM responses ({} bytes) from {}? [y/N] ",
+                    stats.entries,
+                    stats.total_bytes,
+                    cache.dir.display()
+                );
+                std::io::Write::flush(&mut std::io::stdout())?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    println!("Not clearing the cache");
+                    return Ok(());
+                }
+            }
+            let removed = cache.clear(&*fs)?;
+            println!("Removed {} cached responses", removed);
+        }
+    }
+    Ok(())
 }
 
 fn cmd_history(
-    _cmd: HistoryCmd,
+    cmd: HistoryCmd,
     fs: Arc<Mutex<dyn xfs::Xfs>>,
     event_log: Arc<Mutex<dyn EventLog>>,
     project_root: &Path,
     file_path: &Path,
 ) -> anyhow::Result<()> {
-    let entries = file_history::file_history(fs, event_log, project_root, file_path)?;
+    let window = cmd.window.window()?;
+    let entries = file_history::file_history(fs, event_log, project_root, file_path, &window)?;
     for e in entries {
         match e {
             FileHistoryEntry::Deleted => eprintln!("- nothing"),
             FileHistoryEntry::DeletedBy(cmd) => eprintln!("+ nothing : {}", cmd.0),
-            FileHistoryEntry::UnknownHash(hash) => eprintln!("- {} : ???", hash),
-            FileHistoryEntry::StoredHash(hash, cmd) => {
-                eprintln!("+ {} : {}", hash, cmd.0)
+            FileHistoryEntry::UnknownHash(hash) => {
+                eprintln!("- {} : ???", cmd.format.show(&hash))
+            }
+            FileHistoryEntry::StoredHash(hash, command) => {
+                eprintln!("+ {} : {}", cmd.format.show(&hash), command.0)
             }
             FileHistoryEntry::LocalChanges(hash) => {
-                eprintln!("- {} : local changes", hash)
+                eprintln!("- {} : local changes", cmd.format.show(&hash))
+            }
+            FileHistoryEntry::RenamedTo { to, command } => {
+                eprintln!("+ moved to {} : {}", to.display(), command.0)
+            }
+            FileHistoryEntry::RenamedFrom {
+                from,
+                hash,
+                command,
+            } => {
+                let hash = match &hash {
+                    Some(h) => cmd.format.show(h),
+                    None => "nothing".to_string(),
+                };
+                eprintln!("+ {} (moved from {}) : {}", hash, from.display(), command.0)
+            }
+            FileHistoryEntry::BrokenChain { expected, found } => {
+                let show = |h: &Option<ContentHash>| match h {
+                    Some(h) => cmd.format.show(h),
+                    None => "nothing".to_string(),
+                };
+                eprintln!(
+                    "! expected {} but the next write replaced {} - changed outside wrought?",
+                    show(&expected),
+                    show(&found)
+                )
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cmd_diff(
+    fs: Arc<Mutex<dyn xfs::Xfs>>,
+    event_log: Arc<Mutex<dyn EventLog>>,
+    content_store: Arc<Mutex<dyn ContentStore>>,
+    project_root: &Path,
+    file_path: &Path,
+) -> anyhow::Result<()> {
+    let entries = file_history::file_history(
+        fs,
+        event_log,
+        project_root,
+        file_path,
+        &TimeWindow::default(),
+    )?;
+    let versions = file_history::last_two_versions(&entries);
+    let [old_hash, new_hash] = &versions[..] else {
+        println!("no previous version");
+        return Ok(());
+    };
+    let retrieve = |hash: &ContentHash| -> anyhow::Result<Vec<u8>> {
+        content_store
+            .lock()
+            .unwrap()
+            .retrieve_verified(hash.clone())?
+            .with_context(|| format!("content {} is missing from the content store", hash))
+    };
+    let old = retrieve(old_hash)?;
+    let new = retrieve(new_hash)?;
+    if !text::looks_like_text(&old) || !text::looks_like_text(&new) {
+        println!("binary");
+        return Ok(());
+    }
+    print!(
+        "{}",
+        text::unified_diff(
+            &String::from_utf8_lossy(&old),
+            &String::from_utf8_lossy(&new),
+            &format!("{} ({})", file_path.display(), old_hash),
+            &format!("{} ({})", file_path.display(), new_hash),
+            3,
+        )
+    );
+    Ok(())
+}
+
+fn cmd_blame(
+    fs: Arc<Mutex<dyn xfs::Xfs>>,
+    event_log: Arc<Mutex<dyn EventLog>>,
+    content_store: Arc<Mutex<dyn ContentStore>>,
+    project_root: &Path,
+    file_path: &Path,
+) -> anyhow::Result<()> {
+    let entries = file_history::file_history(
+        fs.clone(),
+        event_log,
+        project_root,
+        file_path,
+        &TimeWindow::default(),
+    )?;
+    let content = |hash: &ContentHash| -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(content) = content_store
+            .lock()
+            .unwrap()
+            .retrieve_verified(hash.clone())?
+        {
+            return Ok(Some(content));
+        }
+        // Local changes aren't stored.
+        let local = fs
+            .lock()
+            .unwrap()
+            .reader_if_exists(&project_root.join(file_path))?
+            .map(|mut reader| -> anyhow::Result<Vec<u8>> {
+                let mut content = vec![];
+                reader.read_to_end(&mut content)?;
+                Ok(content)
+            })
+            .transpose()?;
+        Ok(local.filter(|c| ContentHash::from_content(c) == *hash))
+    };
+    match blame::blame(&entries, &content)? {
+        Blame::Missing => bail!("'{}' does not exist", file_path.display()),
+        Blame::Binary => println!("binary; cannot blame"),
+        Blame::Lines(lines) => {
+            let width = lines
+                .iter()
+                .map(|l| l.origin.to_string().len())
+                .max()
+                .unwrap_or(0);
+            let number_width = lines.len().to_string().len();
+            for (i, line) in lines.iter().enumerate() {
+                println!(
+                    "{:<width$} {:>number_width$}) {}",
+                    line.origin.to_string(),
+                    i + 1,
+                    line.line,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The content `wrought show` prints - the file as it is now, or the version `at`.
+fn show_content(
+    backend: &dyn Backend,
+    file_path: &Path,
+    at: Option<&str>,
+) -> anyhow::Result<Vec<u8>> {
+    match at {
+        Some(at) => {
+            let hash = ContentHash::parse_any(at)?;
+            backend
+                .read_stored_content(&hash)?
+                .with_context(|| format!("content {} is not in the content store", hash))
+        }
+        None => Ok(backend
+            .read_file(file_path)?
+            .with_context(|| format!("'{}' does not exist", file_path.display()))?
+            .1),
+    }
+}
+
+fn cmd_show(
+    cmd: ShowCmd,
+    backend: Arc<Mutex<dyn Backend + Send + 'static>>,
+    file_path: &Path,
+) -> anyhow::Result<()> {
+    use std::io::{IsTerminal, Write};
+
+    let content = show_content(&*backend.lock().unwrap(), file_path, cmd.at.as_deref())?;
+    // Binary content is still written when redirected, so `show x > y` copies it.
+    let mut stdout = std::io::stdout();
+    if !text::looks_like_text(&content) && stdout.is_terminal() {
+        println!(
+            "binary content ({}) - redirect the output to save it",
+            format_byte_size(content.len() as u64)
+        );
+        return Ok(());
+    }
+    stdout.write_all(&content)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn cmd_metadata(
+    cmd: MetadataCmd,
+    backend: Arc<Mutex<dyn Backend + Send + 'static>>,
+) -> anyhow::Result<()> {
+    match cmd.command {
+        MetadataSubcommand::List => {
+            for (path, entries) in backend.lock().unwrap().get_all_metadata()? {
+                println!("{}", path.display());
+                for (key, value) in entries {
+                    println!("    {} = {}", key.as_string(), value.as_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How often `log --follow` checks for new groups.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn cmd_log(
+    cmd: LogCmd,
+    event_log: Arc<Mutex<dyn EventLog>>,
+    content_store: Arc<Mutex<dyn ContentStore>>,
+) -> anyhow::Result<()> {
+    if let Some(group_id) = cmd.group_id {
+        let group = event_log.lock().unwrap().get_event_group(group_id)?;
+        let Some(group) = group else {
+            bail!("there is no group with id {}", group_id);
+        };
+        print_event_group(&group);
+        return print_group_output(&group, &*content_store.lock().unwrap());
+    }
+    let window = cmd.window.window()?;
+    let mut print = |group: &EventGroup| {
+        if window.contains(group.created_at) {
+            print_event_group(group);
+        }
+    };
+    if !cmd.follow {
+        let groups = event_log.lock().unwrap().all_event_groups()?;
+        groups.iter().for_each(print);
+        return Ok(());
+    }
+
+    let tail = event_log.lock().unwrap().last_group_id()?.unwrap_or(0);
+    if cmd.all {
+        let groups = event_log.lock().unwrap().all_event_groups()?;
+        groups.iter().filter(|g| g.id <= tail).for_each(&mut print);
+    }
+    follow_log(
+        &event_log,
+        tail,
+        FOLLOW_POLL_INTERVAL,
+        &CancellationToken::default(),
+        &mut print,
+    )
+}
+
+/// Calls `emit` for each group added after group `after`, oldest first, checking
+/// every `interval` until `cancel` is cancelled.
+fn follow_log(
+    event_log: &Mutex<dyn EventLog>,
+    mut after: u64,
+    interval: Duration,
+    cancel: &CancellationToken,
+    emit: &mut dyn FnMut(&EventGroup),
+) -> anyhow::Result<()> {
+    while !cancel.is_cancelled() {
+        let (last, groups) = {
+            let event_log = event_log.lock().unwrap();
+            // Less than `after` if the newest groups were undone - their ids can be reused.
+            let last = event_log.last_group_id()?.unwrap_or(0);
+            let ids: Vec<u64> = (after.min(last) + 1..=last).collect();
+            (last, event_log.get_groups(&ids)?)
+        };
+        groups.iter().for_each(&mut *emit);
+        after = last;
+        std::thread::sleep(interval);
+    }
+    Ok(())
+}
+
+fn print_event_group(group: &EventGroup) {
+    match group.created_at {
+        Some(t) => println!(
+            "[{}] {} ({})",
+            group.id,
+            group.command,
+            t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ),
+        None => println!("[{}] {}", group.id, group.command),
+    }
+    for e in &group.events {
+        println!("    {}", e.event_type);
+    }
+}
+
+/// What the run printed, if anything.
+fn print_group_output(group: &EventGroup, content_store: &dyn ContentStore) -> anyhow::Result<()> {
+    let Some(hash) = &group.output else {
+        return Ok(());
+    };
+    match content_store.retrieve_verified(hash.clone())? {
+        Some(output) => {
+            println!("output:");
+            print!("{}", String::from_utf8_lossy(&output));
+        }
+        None => println!("output: {} is missing from the content store", hash),
+    }
+    Ok(())
+}
+
+fn cmd_content_store(
+    cmd: ContentStoreCmd,
+    content_store: Arc<Mutex<dyn ContentStore>>,
+    event_log: Arc<Mutex<dyn EventLog>>,
+    read_only: bool,
+) -> anyhow::Result<()> {
+    match cmd.command {
+        ContentStoreSubcommand::Stats => {
+            let stats = content_store.lock().unwrap().stats()?;
+            println!("objects: {}", stats.objects);
+            println!("total size: {}", format_byte_size(stats.total_bytes));
+            println!("mean size: {}", format_byte_size(stats.mean_bytes()));
+            println!("median size: {}", format_byte_size(stats.median_bytes));
+        }
+        ContentStoreSubcommand::Add(_) => {
+            if read_only {
+                bail!("can not add to the content store - the project was opened read-only");
+            }
+            cmd_content_store_add(
+                &mut piped_stdin()?,
+                &mut *content_store.lock().unwrap(),
+                &mut std::io::stdout(),
+            )?;
+        }
+        ContentStoreSubcommand::Gc(gc_cmd) => {
+            let plan =
+                gc::GcPlan::new(&*event_log.lock().unwrap(), &*content_store.lock().unwrap())?;
+            let freed = plan.freed();
+            println!(
+                "{} of {} objects are unreachable ({})",
+                freed.objects,
+                freed.objects + plan.reachable,
+                format_byte_size(freed.total_bytes)
+            );
+            if plan.is_suspicious() {
+                eprintln!(
+                    "warning: only {} stored objects are reachable - if the event log is damaged, deleting the rest would lose content that is still wanted",
+                    plan.reachable
+                );
+            }
+            if gc_cmd.dry_run || freed.objects == 0 {
+                return Ok(());
+            }
+            if read_only {
+                bail!("can not delete from the content store - the project was opened read-only");
             }
+            if !gc_cmd.yes {
+                print!(
+                    "Permanently delete {} objects ({})? [y/N] ",
+                    freed.objects,
+                    format_byte_size(freed.total_bytes)
+                );
+                std::io::Write::flush(&mut std::io::stdout())?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    println!("Not deleting anything");
+                    return Ok(());
+                }
+            }
+            let removed = plan.execute(&mut *content_store.lock().unwrap())?;
+            println!("Removed {} objects", removed);
         }
     }
     Ok(())
 }
 
+fn cmd_verify(
+    event_log: Arc<Mutex<dyn EventLog>>,
+    content_store: Arc<Mutex<dyn ContentStore>>,
+) -> anyhow::Result<()> {
+    let issues =
+        verify::verify_project(&*event_log.lock().unwrap(), &*content_store.lock().unwrap())?;
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    if !issues.is_empty() {
+        bail!("found {} problem(s)", issues.len());
+    }
+    println!("no problems found");
+    Ok(())
+}
+
+/// Stdin, for content piped in. Errors if it is a terminal - nothing was piped in, and
+/// waiting for the user to type a file's content is never what was meant.
+fn piped_stdin() -> anyhow::Result<std::io::Stdin> {
+    use std::io::IsTerminal;
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        bail!("no input - pipe the content in, e.g. `generate | wrought write out.md -`");
+    }
+    Ok(stdin)
+}
+
+fn cmd_content_store_add(
+    input: &mut dyn std::io::Read,
+    content_store: &mut dyn ContentStore,
+    out: &mut dyn std::io::Write,
+) -> anyhow::Result<()> {
+    let hash = content_store.store_reader(input)?;
+    writeln!(out, "{}", hash)?;
+    Ok(())
+}
+
+fn cmd_undo(
+    cmd: UndoCmd,
+    event_log: &mut dyn EventLog,
+    content_store: &mut dyn ContentStore,
+    read_only: bool,
+    out: &mut dyn std::io::Write,
+) -> anyhow::Result<()> {
+    if read_only {
+        bail!("can not undo a run - the project was opened read-only");
+    }
+    undo::undo_group(event_log, cmd.group_id, cmd.force)?;
+    if !cmd.gc {
+        return Ok(());
+    }
+    let plan = gc::GcPlan::new(event_log, content_store)?;
+    if plan.is_suspicious() {
+        // Unlike `content-store gc`, there is no one to ask.
+        bail!(
+            "not deleting anything - only {} stored objects are reachable, so the event log may be damaged (see `content-store gc --dry-run`)",
+            plan.reachable
+        );
+    }
+    let freed = plan.freed();
+    let removed = plan.execute(content_store)?;
+    writeln!(
+        out,
+        "Removed {} objects ({})",
+        removed,
+        format_byte_size(freed.total_bytes)
+    )?;
+    Ok(())
+}
+
+/// Records the write as a group of its own, so it shows in `log` and can be undone.
+fn cmd_write(
+    input: &mut dyn std::io::Read,
+    backend: &dyn Backend,
+    event_log: &mut dyn EventLog,
+    file_path: &Path,
+) -> anyhow::Result<EventGroup> {
+    let (before_hash, after_hash) = backend.write_file_from_reader(file_path, input)?;
+    let mut group = EventGroup::empty();
+    group.command = format!("write {}", file_path.display());
+    group.events.push(
+        WriteFileEvent {
+            path: file_path.to_path_buf(),
+            before_hash,
+            after_hash: Some(after_hash),
+        }
+        .into(),
+    );
+    event_log.add_event_group(&group)
+}
+
 fn cmd_content_store_show(
     cmd: ContentStoreShowCmd,
     content_store: Arc<Mutex<dyn ContentStore>>,
 ) -> anyhow::Result<()> {
-    let hash = ContentHash::from_string(&cmd.hash)?;
+    let hash = ContentHash::parse_any(&cmd.hash)?;
     let content = content_store.lock().unwrap().retrieve(hash)?;
     let Some(content) = content else {
         return Err(anyhow!("Hash does not correspond to known content"));
@@ -875,136 +1639,429 @@ fn main() {
         .canonicalize(&PathBuf::from("."))
         .unwrap();
     let args = Cli::parse();
+    logging::init(args.verbose);
 
     // Have to handle Init differntly as it doesn't care about the project_root already
     // existing etc.
     if let Command::Init(cmd) = &args.command {
-        cmd_init(cmd).unwrap();
+        cmd_init(cmd, args.read_only).unwrap();
         return;
     }
 
+    let requested_root = requested_project_root(args.project_root.as_deref());
+    let progress: Option<Arc<dyn ProgressSink + Send + Sync>> = if args.progress {
+        Some(Arc::new(JsonLinesProgress))
+    } else {
+        None
+    };
+
     match args.command {
         Command::FileStatus(cmd) => {
             // resolve the path relative to the project root.
-            let (project_root, file_path) = get_absolute_project_and_relative_file(
+            let (project_root, file_path) = resolve_project_and_path(
                 &*fs.lock().unwrap(),
                 &working_dir,
                 &cmd.path,
-                args.project_root.as_deref(),
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            // The project root itself has no parent within the project.
+            let file_dir = project_root.join(file_path.parent().unwrap_or(Path::new("")));
+            check_for_nested_projects(&*fs.lock().unwrap(), &project_root, &file_dir, args.strict)
+                .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let status = get_single_file_status(
+                &*fs.lock().unwrap(),
+                &project_root,
+                &*event_log.lock().unwrap(),
+                &file_path,
             )
             .unwrap();
-            let event_log = create_event_log(&project_root).unwrap();
-            let status =
-                get_single_file_status(&fs, &project_root, &event_log, &file_path).unwrap();
             print_single_file_status(&status);
         }
         Command::HelloWorld => {
             // Check the project_root exists
-            let project_root = match &args.project_root {
-                Some(p) => {
-                    if !fs.lock().unwrap().is_dir(&p.join(".wrought")) {
-                        panic!("specified project root {} has no .wrought subdirectory - it is not a valid root", p.display());
-                    }
-                    p.clone()
-                }
-                None => {
-                    match find_marker_dir(&*fs.lock().unwrap(), &PathBuf::from("."), ".wrought") {
-                        Ok(Some(p)) => p,
-                        Ok(None) => panic!("Unable to find project root for current directory"),
-                        Err(e) => panic!("Error looking for project root: {}", e),
-                    }
-                }
-            };
-            // eprintln!("Using project root: '{}'", project_root.display());
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
 
-            let backend = create_backend(&project_root).unwrap();
+            let backend = create_backend(
+                &project_root,
+                BackendOptions {
+                    read_only: args.read_only,
+                    ..BackendOptions::default()
+                },
+            )
+            .unwrap();
             let mut w = Wrought::new(backend);
-            hello_world(&mut w);
+            hello_world(&mut w).unwrap();
         }
         Command::Status(cmd) => {
             // Check the project_root exists
-            let project_root = match &args.project_root {
-                Some(p) => {
-                    if !fs.lock().unwrap().is_dir(&p.join(".wrought")) {
-                        panic!("specified project root {} has no .wrought subdirectory - it is not a valid root", p.display());
-                    }
-                    p.clone()
-                }
-                None => {
-                    match find_marker_dir(&*fs.lock().unwrap(), &PathBuf::from("."), ".wrought") {
-                        Ok(Some(p)) => p,
-                        Ok(None) => panic!("Unable to find project root for current directory"),
-                        Err(e) => panic!("Error looking for project root: {}", e),
-                    }
-                }
-            };
-            // eprintln!("Using project root: '{}'", project_root.display());
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            check_for_nested_projects(
+                &*fs.lock().unwrap(),
+                &project_root,
+                &working_dir,
+                args.strict,
+            )
+            .unwrap();
 
-            cmd_status(&project_root, cmd).unwrap();
+            cmd_status(
+                &project_root,
+                cmd,
+                progress.as_deref().map(|p| p as &dyn ProgressSink),
+                args.read_only,
+            )
+            .unwrap();
+        }
+        Command::Daemon => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            cmd_daemon(&project_root).unwrap();
         }
         Command::History(cmd) => {
             // resolve the path relative to the project root.
-            let (project_root, file_path) = get_absolute_project_and_relative_file(
+            let (project_root, file_path) = resolve_project_and_path(
                 &*fs.lock().unwrap(),
                 &working_dir,
                 &cmd.path,
-                args.project_root.as_deref(),
+                requested_root.as_deref(),
             )
             .unwrap();
-            let event_log = create_event_log(&project_root).unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
             cmd_history(cmd, fs, event_log, &project_root, &file_path).unwrap();
         }
+        Command::Diff(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                project_root.join(".wrought").join("content"),
+            )));
+            cmd_diff(fs, event_log, content_store, &project_root, &file_path).unwrap();
+        }
+        Command::Blame(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                project_root.join(".wrought").join("content"),
+            )));
+            cmd_blame(fs, event_log, content_store, &project_root, &file_path).unwrap();
+        }
+        Command::Show(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            // Showing a file must not change anything - not even the content store.
+            let backend = create_backend(
+                &project_root,
+                BackendOptions {
+                    read_only: true,
+                    ..BackendOptions::default()
+                },
+            )
+            .unwrap();
+            cmd_show(cmd, backend, &file_path).unwrap();
+        }
+        Command::Write(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let backend = create_backend(
+                &project_root,
+                BackendOptions {
+                    read_only: args.read_only,
+                    ..BackendOptions::default()
+                },
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let group = cmd_write(
+                &mut piped_stdin().unwrap(),
+                &*backend.lock().unwrap(),
+                &mut *event_log.lock().unwrap(),
+                &file_path,
+            )
+            .unwrap();
+            println!("[{}] {}", group.id, group.command);
+        }
+        Command::Pin(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            cmd_pin(fs, event_log, &project_root, &file_path).unwrap();
+        }
+        Command::Unpin(cmd) => {
+            let (project_root, file_path) = resolve_project_and_path(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                &cmd.path,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            cmd_unpin(event_log, &file_path).unwrap();
+        }
+        Command::Log(cmd) => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                project_root.join(".wrought").join("content"),
+            )));
+            cmd_log(cmd, event_log, content_store).unwrap();
+        }
+        Command::Undo(cmd) => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let mut content_store =
+                FileSystemContentStore::on_os_fs(project_root.join(".wrought").join("content"));
+            cmd_undo(
+                cmd,
+                &mut *event_log.lock().unwrap(),
+                &mut content_store,
+                args.read_only,
+                &mut std::io::stdout(),
+            )
+            .unwrap();
+        }
+        Command::Llm(cmd) => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            cmd_llm(cmd, fs, &project_root, args.read_only).unwrap();
+        }
+        Command::Metadata(cmd) => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let backend = create_backend(
+                &project_root,
+                BackendOptions {
+                    read_only: args.read_only,
+                    ..BackendOptions::default()
+                },
+            )
+            .unwrap();
+            cmd_metadata(cmd, backend).unwrap();
+        }
+        Command::Export => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            let backend = create_backend(
+                &project_root,
+                BackendOptions {
+                    read_only: args.read_only,
+                    ..BackendOptions::default()
+                },
+            )
+            .unwrap();
+            let export = export::EventLogExport::collect(
+                &*event_log.lock().unwrap(),
+                &*backend.lock().unwrap(),
+            )
+            .unwrap();
+            println!("{}", export.to_json().unwrap());
+        }
+        Command::ListTracked => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            for p in tracked_files(&*event_log.lock().unwrap()).unwrap() {
+                println!("{}", p.display());
+            }
+        }
         Command::ContentStoreShow(cmd) => {
             // resolve the path relative to the project root.
             // Has the user specified a path?
-            let project_root = match args.project_root {
-                Some(project_root) => fs
-                    .lock()
-                    .unwrap()
-                    .canonicalize(&working_dir.join(project_root))
-                    .unwrap(),
-                None => find_marker_dir(&*fs.lock().unwrap(), &working_dir, ".wrought")
-                    .unwrap()
-                    .unwrap(),
-            };
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
 
             let content_storage_path = project_root.join(".wrought").join("content");
-            let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
-                fs.clone(),
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
                 content_storage_path,
             )));
 
             cmd_content_store_show(cmd, content_store).unwrap();
         }
+        Command::ContentStore(cmd) => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                project_root.join(".wrought").join("content"),
+            )));
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            cmd_content_store(cmd, content_store, event_log, args.read_only).unwrap();
+        }
+        Command::Verify => {
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+                project_root.join(".wrought").join("content"),
+            )));
+            let event_log = create_event_log(&project_root, args.read_only).unwrap();
+            cmd_verify(event_log, content_store).unwrap();
+        }
         Command::RunScript(cmd) => {
             // Check the project_root exists
-            let project_root = match &args.project_root {
-                Some(p) => {
-                    if !fs.lock().unwrap().is_dir(&p.join(".wrought")) {
-                        panic!("specified project root {} has no .wrought subdirectory - it is not a valid root", p.display());
-                    }
-                    p.clone()
-                }
-                None => {
-                    match find_marker_dir(&*fs.lock().unwrap(), &PathBuf::from("."), ".wrought") {
-                        Ok(Some(p)) => p,
-                        Ok(None) => panic!("Unable to find project root for current directory"),
-                        Err(e) => panic!("Error looking for project root: {}", e),
-                    }
-                }
+            let project_root = resolve_project_root(
+                &*fs.lock().unwrap(),
+                &working_dir,
+                requested_root.as_deref(),
+            )
+            .unwrap();
+            check_for_nested_projects(
+                &*fs.lock().unwrap(),
+                &project_root,
+                &working_dir,
+                args.strict,
+            )
+            .unwrap();
+
+            let input_check = match (cmd.strict_inputs, args.strict) {
+                (false, _) => InputCheck::Off,
+                (true, false) => InputCheck::Warn,
+                (true, true) => InputCheck::Error,
             };
-            // eprintln!("Using project root: '{}'", project_root.display());
-
-            let bridge = create_bridge(&project_root).unwrap();
-            cmd_run_script(bridge.clone(), &project_root, cmd).unwrap();
-            let event_log = create_event_log(&project_root).unwrap();
-            if let Some(event_group) = bridge.lock().unwrap().get_event_group() {
-                event_log
-                    .lock()
-                    .unwrap()
-                    .add_event_group(&event_group)
+            let script = match (&cmd.script_name, &cmd.path) {
+                (Some(name), _) => PackageDirectory {
+                    path: project_root.join(".wrought").join("packages"),
+                }
+                .script(&*fs.lock().unwrap(), name),
+                (None, Some(path)) => {
+                    let (root, path) = resolve_project_and_path(
+                        &*fs.lock().unwrap(),
+                        &working_dir,
+                        path,
+                        Some(&project_root),
+                    )
                     .unwrap();
+                    script_at_path(&*fs.lock().unwrap(), &root, &path)
+                }
+                (None, None) => unreachable!("clap requires a script name or --path"),
+            }
+            .unwrap();
+            let redirect_root = cmd.output_dir.as_ref().map(|d| working_dir.join(d));
+            let preview = redirect_root.is_some();
+            let bridge = create_bridge(
+                &project_root,
+                BackendOptions {
+                    read_only: args.read_only,
+                    redirect_root,
+                    ..BackendOptions::default()
+                },
+                progress.clone(),
+                BridgeOptions {
+                    input_check,
+                    package: script.package.clone(),
+                    read_only: args.read_only,
+                    command: Some(format!("run-script {}", script.name)),
+                    strict: args.strict,
+                    preview,
+                },
+            )
+            .unwrap();
+            // A script that failed still has its run recorded, marked failed.
+            let failure = match cmd_run_script(bridge.clone(), &script, cmd) {
+                Ok(()) => None,
+                Err(e) => match e.downcast::<ScriptFailure>() {
+                    Ok(failure) => Some(failure),
+                    // An error in the script, or in wrought, rather than a call to `fail`.
+                    Err(e) => {
+                        let failure = ScriptFailure {
+                            message: format!("{:#}", e),
+                            exit_code: 1,
+                        };
+                        bridge.lock().unwrap().fail(failure.clone()).unwrap();
+                        Some(failure)
+                    }
+                },
             };
+            if args.read_only {
+                // Nothing was written, and there is nowhere to record what was read.
+            } else if preview {
+                // The recorded writes would describe files the project doesn't have.
+                eprintln!("Preview run - nothing recorded in the event log");
+            } else {
+                bridge.lock().unwrap().record_run().unwrap();
+            }
+            if let Some(failure) = failure {
+                eprintln!("error: {}", failure);
+                std::process::exit(failure.exit_code);
+            }
         }
         Command::Init(_) => unreachable!("`init` should already have been handled"),
     }
@@ -1036,132 +2093,122 @@ pub fn calculate_file_hash(fs: &dyn xfs::Xfs, p: &Path) -> anyhow::Result<Option
 #[derive(Debug)]
 pub struct SingleFileStatusResult {
     path: PathBuf,
-    status: SingleFileStatus,
+    status: project_status::FileStatus,
+    // The user has pinned the file's current content, so it is OK regardless.
+    is_pinned: bool,
+    // `None` for files no script has written - untracked, or only renamed or declared.
+    history: Option<WriteHistory>,
 }
 
-#[derive(Debug)]
-enum SingleFileStatus {
-    Untracked,
-    TrackedFileStatus(TrackedFileStatus),
-}
-
-#[derive(Debug)]
-struct TrackedFileInput {
-    path: PathBuf,
-    tracked_hash: Option<ContentHash>,
-    current_hash: Option<ContentHash>,
+impl SingleFileStatusResult {
+    /// The summary used by `status` and scripts.
+    pub fn file_status(&self) -> project_status::FileStatus {
+        self.status.clone()
+    }
 }
 
 #[derive(Debug)]
-struct TrackedFileStatus {
-    current_hash: Option<ContentHash>,
-    tracked_hash: Option<ContentHash>,
-    inputs: Vec<TrackedFileInput>,
+struct WriteHistory {
+    // The command of the change set that last wrote the file.
     command: String,
+    // The command of the change set that first wrote the file.
+    created_by: String,
     // Was the change set that produced this file, the most recent run of command?
     is_most_recent_run: bool,
 }
 
-impl TrackedFileStatus {
-    pub fn changed(&self) -> bool {
-        self.current_hash != self.tracked_hash
-    }
-
-    pub fn stale(&self) -> bool {
-        for input in &self.inputs {
-            if input.current_hash != input.tracked_hash {
-                return true;
-            }
-        }
-        false
-    }
-}
-
+/// The status of one file, as `wrought status` would report it, along with which
+/// commands wrote it.
 pub fn get_single_file_status(
-    fs: &Arc<Mutex<dyn xfs::Xfs + Send + 'static>>,
+    fs: &dyn xfs::Xfs,
     project_root: &Path,
-    event_log: &Arc<Mutex<dyn EventLog>>,
+    event_log: &dyn EventLog,
     p: &Path,
 ) -> anyhow::Result<SingleFileStatusResult> {
-    let event_log = event_log.lock().unwrap();
-
-    // To get the file status we need to know the last write to it - which should return a hash
-    // and the change-set-id that it was last changed in.
-    // We can then compare the hash of the file with that in the change-set to determine if it has changed,
-    // and compare the hash of all the inputs to determine if it is stale.
-
-    let Some(event) = event_log.get_last_write_event(p)? else {
-        // TODO: Do we want to differentiate between Untracked and doesn't exist locally,
-        //       and untracked and does exist locally?
-        return Ok(SingleFileStatusResult {
-            path: p.to_owned(),
-            status: SingleFileStatus::Untracked,
-        });
-    };
-
-    let EventType::WriteFile(write_event) = event.event_type else {
-        unreachable!("get_last_write_event returned a non WriteFile event!");
-    };
-
-    let current_hash = calculate_file_hash(&*fs.lock().unwrap(), &project_root.join(p))?;
-    eprintln!("Getting file hash for {:?} = {:?}", p, current_hash);
+    // Directories have no status of their own - `status` covers them.
+    if p.components().all(|c| c == std::path::Component::CurDir) {
+        bail!("can not get the file status of the project root - use `wrought status`");
+    }
+    if fs.is_dir(&project_root.join(p)) {
+        bail!(
+            "'{}' is a directory - file status is only for files, use `wrought status`",
+            p.display()
+        );
+    }
 
-    let Some(event_group) = event_log.get_event_group(event.group_id)? else {
-        unreachable!("get_last_write_event returned an event with invalid group_id");
+    // Shared with `status`, along with its ignore rules and size limit, so the two
+    // can't disagree.
+    let ignore = IgnoreRules::load(fs, project_root)?;
+    let max_track_size = Settings::load(fs, project_root)?.max_track_size()?;
+    let status =
+        project_status::get_file_status(event_log, fs, project_root, p, &ignore, max_track_size)?;
+    let is_pinned = match event_log.get_pin(p)? {
+        Some(pin) => calculate_file_hash(fs, &project_root.join(p))? == Some(pin),
+        None => false,
     };
 
-    let mut inputs = vec![];
-    for e in &event_group.events {
-        match &e.event_type {
-            EventType::ReadFile(read_file_event) => {
-                let path = read_file_event.path.clone();
-                let current_hash =
-                    calculate_file_hash(&*fs.lock().unwrap(), &project_root.join(&path))?;
-                inputs.push(TrackedFileInput {
-                    path,
-                    tracked_hash: read_file_event.hash.clone(),
-                    current_hash,
-                });
-            }
-            _ => {}
+    let history = match event_log.get_last_write_event(p)? {
+        Some(event) if status != project_status::FileStatus::Untracked => {
+            let event_group = event_log
+                .get_event_group(event.group_id)?
+                .context("get_last_write_event returned an event with invalid group_id")?;
+            let created_by = match event_log.get_first_write_event(p)? {
+                Some(first) if first.group_id != event.group_id => {
+                    event_log
+                        .get_event_group(first.group_id)?
+                        .context("get_first_write_event returned an event with invalid group_id")?
+                        .command
+                }
+                _ => event_group.command.clone(),
+            };
+            Some(WriteHistory {
+                command: event_group.command,
+                created_by,
+                is_most_recent_run: event_group.is_most_recent_run,
+            })
         }
-    }
-
-    let t = TrackedFileStatus {
-        current_hash,
-        tracked_hash: write_event.after_hash,
-        inputs,
-        command: event_group.command,
-        is_most_recent_run: event_group.is_most_recent_run,
+        _ => None,
     };
 
     Ok(SingleFileStatusResult {
         path: p.to_owned(),
-        status: SingleFileStatus::TrackedFileStatus(t),
+        status,
+        is_pinned,
+        history,
     })
 }
 
 pub fn print_single_file_status(result: &SingleFileStatusResult) {
-    dbg!(&result);
+    log::debug!("{:#?}", result);
     match &result.status {
-        SingleFileStatus::Untracked => {
+        project_status::FileStatus::Untracked => {
             println!("Untracked");
+            return;
         }
-        SingleFileStatus::TrackedFileStatus(t) => {
-            let mut something_printed = false;
-            if t.changed() {
+        project_status::FileStatus::Deleted => println!("Deleted"),
+        project_status::FileStatus::Present {
+            is_changed,
+            is_stale,
+        } => {
+            if *is_changed {
                 println!("Changed");
-                something_printed = true;
             }
-            if t.stale() {
+            if *is_stale {
                 println!("Stale");
-                something_printed = true;
             }
-            if !something_printed {
-                println!("OK")
+            if !is_changed && !is_stale {
+                if result.is_pinned {
+                    println!("OK (pinned)")
+                } else {
+                    println!("OK")
+                }
             }
         }
     }
+    if let Some(history) = &result.history {
+        println!("created by: {}", history.created_by);
+        println!("last modified by: {}", history.command);
+    }
 }
 
 pub mod api {
@@ -1280,3 +2327,448 @@ pub mod api {
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use anyhow::anyhow;
+    use xfs::mockfs::MockFS;
+
+    use super::{
+        cmd_content_store_add, cmd_init, cmd_status, cmd_undo, cmd_write, follow_log,
+        get_single_file_status, run_script_file, show_content, Cli, Command, DiffCmd, MicroService,
+        StatusCmd, UndoCmd, Wrought,
+    };
+    use crate::{
+        backend::{test_utils::MockBackend, BackendOptions, SimpleBackend},
+        binary16::ContentHash,
+        bridge::{tests::test_bridge, Bridge, ScriptFailure},
+        cancel::CancellationToken,
+        content_store::{ContentStore, FileSystemContentStore},
+        event_log::{EventLog, SQLiteEventLog},
+        events::{Event, EventGroup, EventType, LogLevel, ReadFileEvent, WriteFileEvent},
+        ignore_rules::IgnoreRules,
+        package_status::{Script, Severity},
+        project_status::{get_project_status, FileStatus},
+    };
+
+    #[test]
+    pub fn status_can_fail_on_package_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let packages = dir.join(".wrought/packages");
+        std::fs::create_dir_all(packages.join("good/status")).unwrap();
+        std::fs::write(packages.join("good/status/build.txt"), "All good.\n").unwrap();
+        // No status directory.
+        std::fs::create_dir_all(packages.join("broken")).unwrap();
+        SQLiteEventLog::init(dir.join(".wrought/wrought.db")).unwrap();
+
+        let status = |fail_on_package_error: bool| {
+            cmd_status(
+                &dir,
+                StatusCmd {
+                    color: false,
+                    include_aux: false,
+                    min_severity: Severity::Info,
+                    fail_on_package_error,
+                },
+                None,
+                true,
+            )
+        };
+        assert!(status(false).is_ok());
+        let e = status(true).unwrap_err();
+        assert_eq!(e.to_string(), "1 package(s) have errors");
+
+        std::fs::create_dir_all(packages.join("broken/status")).unwrap();
+        assert!(status(true).is_ok());
+    }
+
+    #[test]
+    pub fn script_errors_are_returned_not_panics() {
+        let mut wrought = Wrought::new(Arc::new(Mutex::new(MockBackend::default())));
+        let e = wrought
+            .begin_script("broken", |_: &mut MicroService| Err(anyhow!("boom")))
+            .unwrap_err();
+        assert_eq!(format!("{:#}", e), "script 'broken' failed: boom");
+
+        assert!(wrought
+            .begin_script("fine", |_: &mut MicroService| Ok(()))
+            .is_ok());
+    }
+
+    #[test]
+    pub fn follow_emits_groups_added_after_it_starts() {
+        let event_log = Arc::new(Mutex::new(SQLiteEventLog::open_in_memory().unwrap()));
+        let group = |command: &str| {
+            let mut group = EventGroup::empty();
+            group.command = command.to_string();
+            group
+        };
+        event_log
+            .lock()
+            .unwrap()
+            .add_event_group(&group("before"))
+            .unwrap();
+        let tail = event_log.lock().unwrap().last_group_id().unwrap().unwrap();
+
+        let cancel = CancellationToken::default();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let follower = {
+            let (event_log, cancel, seen) = (event_log.clone(), cancel.clone(), seen.clone());
+            std::thread::spawn(move || {
+                follow_log(
+                    &*event_log,
+                    tail,
+                    Duration::from_millis(5),
+                    &cancel,
+                    &mut |g| seen.lock().unwrap().push(g.command.clone()),
+                )
+            })
+        };
+        event_log
+            .lock()
+            .unwrap()
+            .add_event_group(&group("after"))
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while seen.lock().unwrap().is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        cancel.cancel();
+        follower.join().unwrap().unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec!["after"]);
+    }
+
+    #[test]
+    pub fn show_prints_current_and_historical_content() {
+        let old = ContentHash::from_content(b"old");
+        let mut backend = MockBackend::default();
+        backend
+            .expect_read_file()
+            .withf(|p| p == Path::new("a.md"))
+            .returning(|_| Ok(Some((ContentHash::from_content(b"new"), b"new".to_vec()))));
+        backend.expect_read_file().returning(|_| Ok(None));
+        let stored = old.clone();
+        backend
+            .expect_read_stored_content()
+            .returning(move |h| Ok((*h == stored).then(|| b"old".to_vec())));
+
+        assert_eq!(
+            show_content(&backend, Path::new("a.md"), None).unwrap(),
+            b"new"
+        );
+        assert_eq!(
+            show_content(&backend, Path::new("a.md"), Some(&old.to_string())).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            show_content(&backend, Path::new("a.md"), Some(&old.to_hex())).unwrap(),
+            b"old"
+        );
+
+        let e = show_content(&backend, Path::new("gone.md"), None).unwrap_err();
+        assert_eq!(e.to_string(), "'gone.md' does not exist");
+        let unknown = ContentHash::from_content(b"unknown");
+        let e = show_content(&backend, Path::new("a.md"), Some(&unknown.to_string())).unwrap_err();
+        assert!(
+            e.to_string().contains("is not in the content store"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    pub fn diff_takes_last_but_does_not_need_it() {
+        use clap::Parser;
+
+        for args in [
+            &["wrought", "diff", "a.md", "--last"][..],
+            &["wrought", "diff", "a.md"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(matches!(
+                cli.command,
+                Command::Diff(DiffCmd { last: true, .. })
+            ));
+        }
+    }
+
+    #[test]
+    pub fn init_refuses_read_only() {
+        use clap::Parser;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("project");
+        let cli = Cli::try_parse_from([
+            "wrought".as_ref(),
+            "--read-only".as_ref(),
+            "init".as_ref(),
+            "--empty".as_ref(),
+            path.as_os_str(),
+        ])
+        .unwrap();
+        let Command::Init(cmd) = &cli.command else {
+            panic!("expected an init command");
+        };
+        let e = cmd_init(cmd, cli.read_only).unwrap_err();
+        assert_eq!(e.to_string(), "`init` can not be used with --read-only");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    pub fn undo_can_remove_what_only_the_run_refers_to() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut content_store = FileSystemContentStore::on_os_fs(tmp.path().to_path_buf());
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut write = |before: Option<&[u8]>, after: &[u8]| {
+            content_store.store(after).unwrap();
+            let mut group = EventGroup::empty();
+            group.events.push(Event::from(WriteFileEvent {
+                path: PathBuf::from("a.md"),
+                before_hash: before.map(ContentHash::from_content),
+                after_hash: Some(ContentHash::from_content(after)),
+            }));
+            event_log.add_event_group(&group).unwrap().id
+        };
+        write(None, b"v1");
+        let second = write(Some(b"v1"), b"v2");
+
+        let undo = |group_id: u64, gc: bool| UndoCmd {
+            group_id,
+            force: false,
+            gc,
+        };
+        let mut out = vec![];
+        assert!(cmd_undo(
+            undo(second, true),
+            &mut event_log,
+            &mut content_store,
+            true,
+            &mut out
+        )
+        .is_err());
+        assert_eq!(event_log.all_event_groups().unwrap().len(), 2);
+
+        cmd_undo(
+            undo(second, true),
+            &mut event_log,
+            &mut content_store,
+            false,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Removed 1 objects (2 B)\n");
+        assert_eq!(
+            content_store
+                .retrieve(ContentHash::from_content(b"v2"))
+                .unwrap(),
+            None
+        );
+        // The earlier run still refers to v1.
+        assert!(content_store
+            .retrieve(ContentHash::from_content(b"v1"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    pub fn piped_content_is_stored_and_written() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let content_dir = dir.join(".wrought/content");
+        std::fs::create_dir_all(&content_dir).unwrap();
+        let fs = Arc::new(Mutex::new(xfs::OsFs {}));
+        let content_store = Arc::new(Mutex::new(FileSystemContentStore::on_os_fs(
+            content_dir.clone(),
+        )));
+        let content = b"generated elsewhere\n".repeat(10_000);
+
+        let mut out = vec![];
+        cmd_content_store_add(
+            &mut std::io::Cursor::new(&content),
+            &mut *content_store.lock().unwrap(),
+            &mut out,
+        )
+        .unwrap();
+        let hash = ContentHash::from_content(&content);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n", hash));
+        let stored = content_store
+            .lock()
+            .unwrap()
+            .retrieve(hash.clone())
+            .unwrap();
+        assert_eq!(stored.unwrap(), content);
+        // Nothing is left behind but the object itself.
+        assert_eq!(std::fs::read_dir(&content_dir).unwrap().count(), 1);
+
+        let backend = SimpleBackend {
+            fs,
+            os_fs: true,
+            root: dir.clone(),
+            content_store,
+            options: BackendOptions::default(),
+        };
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let group = cmd_write(
+            &mut std::io::Cursor::new(b"v2"),
+            &backend,
+            &mut event_log,
+            Path::new("out/a.md"),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(dir.join("out/a.md")).unwrap(), b"v2");
+        assert_eq!(group.command, "write out/a.md");
+        let EventType::WriteFile(write) = &group.events[0].event_type else {
+            panic!("unexpected event {:?}", group.events[0]);
+        };
+        assert_eq!(write.before_hash, None);
+        assert_eq!(write.after_hash, Some(ContentHash::from_content(b"v2")));
+        assert_eq!(event_log.all_event_groups().unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn single_file_status_agrees_with_project_status() {
+        let write = |path: &str, content: &[u8]| {
+            Event::from(WriteFileEvent {
+                path: PathBuf::from(path),
+                before_hash: None,
+                after_hash: Some(ContentHash::from_content(content)),
+            })
+        };
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.command = "generate".to_string();
+        group.events = vec![
+            write("out.md", b"generated"),
+            // Read after the write, but still an input of the run.
+            Event::from(ReadFileEvent {
+                path: PathBuf::from("in.md"),
+                hash: Some(ContentHash::from_content(b"input")),
+            }),
+            write("notes.md", b"notes"),
+        ];
+        event_log.add_event_group(&group).unwrap();
+        let mut group = EventGroup::empty();
+        group.command = "other".to_string();
+        group.events = vec![write("kept.md", b"kept"), write("gone.md", b"gone")];
+        event_log.add_event_group(&group).unwrap();
+        // An ignored input counts as missing, and an ignored output isn't reported.
+        let mut group = EventGroup::empty();
+        group.command = "summarise".to_string();
+        group.events = vec![
+            Event::from(ReadFileEvent {
+                path: PathBuf::from("run.log"),
+                hash: Some(ContentHash::from_content(b"log")),
+            }),
+            write("summary.md", b"summary"),
+            write("debug.log", b"debug"),
+        ];
+        event_log.add_event_group(&group).unwrap();
+
+        let root = PathBuf::from("project");
+        let mut fs = MockFS::new();
+        for (path, content) in [
+            (".wroughtignore", &b"*.log\n"[..]),
+            ("run.log", b"log"),
+            ("summary.md", b"summary"),
+            ("debug.log", b"debug"),
+            ("in.md", b"edited input"),
+            ("out.md", b"generated"),
+            ("notes.md", b"edited notes"),
+            ("kept.md", b"kept"),
+            ("new.md", b"new"),
+        ] {
+            fs.add_r(&root.join(path), content.to_vec()).unwrap();
+        }
+
+        let ignore = IgnoreRules::load(&fs, &root).unwrap();
+        let status = get_project_status(&event_log, &fs, &root, &ignore, None, None).unwrap();
+        assert_eq!(status.file_statuses.len(), 8);
+        for entry in &status.file_statuses {
+            let single = get_single_file_status(&fs, &root, &event_log, &entry.path).unwrap();
+            assert_eq!(
+                single.file_status(),
+                entry.status,
+                "{}",
+                entry.path.display()
+            );
+        }
+        let single = |path: &str| {
+            get_single_file_status(&fs, &root, &event_log, Path::new(path))
+                .unwrap()
+                .file_status()
+        };
+        assert_eq!(
+            single("out.md"),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: true
+            }
+        );
+        assert_eq!(
+            single("notes.md"),
+            FileStatus::Present {
+                is_changed: true,
+                is_stale: true
+            }
+        );
+        assert_eq!(single("gone.md"), FileStatus::Deleted);
+        assert_eq!(
+            single("summary.md"),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: true
+            }
+        );
+        assert_eq!(single("debug.log"), FileStatus::Untracked);
+    }
+
+    #[test]
+    pub fn failing_on_purpose_gives_just_the_message() {
+        let run = |source: &str| {
+            let mut fs = MockFS::new();
+            fs.add_r(Path::new("project/check.luau"), source.as_bytes().to_vec())
+                .unwrap();
+            let bridge = Arc::new(Mutex::new(test_bridge(
+                MockBackend::default(),
+                Default::default(),
+            )));
+            let script = Script {
+                path: PathBuf::from("project/check.luau"),
+                name: "check.luau".to_string(),
+                package: None,
+            };
+            let e = run_script_file(bridge.clone(), Arc::new(Mutex::new(fs)), &script).unwrap_err();
+            let group = bridge.lock().unwrap().get_event_group().unwrap();
+            (e, group)
+        };
+
+        let (e, group) = run(r#"fail("bad input")"#);
+        assert_eq!(e.to_string(), "bad input");
+        assert_eq!(format!("{:#}", e), "bad input");
+        assert_eq!(e.downcast_ref::<ScriptFailure>().unwrap().exit_code, 1);
+        // The run is marked failed.
+        assert!(matches!(
+            &group.events[..],
+            [Event { event_type: EventType::Log(l), .. }]
+                if l.level == LogLevel::Error && l.message == "bad input"
+        ));
+
+        // Catching the error doesn't undo the failure.
+        let (e, _) = run(r#"pcall(fail, "no pages", 3) log("carrying on")"#);
+        assert_eq!(
+            e.downcast_ref::<ScriptFailure>().unwrap(),
+            &ScriptFailure {
+                message: "no pages".to_string(),
+                exit_code: 3
+            }
+        );
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Debug, Clone, PartialEq)]
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum MetadataKey {
     StringKey(String),
 }
@@ -17,21 +21,162 @@ impl From<&str> for MetadataKey {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A metadata value. Usually a string, but scripts can store numbers, booleans and
+/// tables too, which keep their type. Entries stored before values were typed are
+/// plain JSON strings, so still read back as strings.
+///
+/// Entries are ordered by type - null, booleans, numbers, strings, arrays then tables -
+/// and then by value, so listings come out the same every time.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MetadataEntry {
-    value: String,
+    value: serde_json::Value,
 }
 
 impl MetadataEntry {
+    /// Strings as they are, anything else as JSON.
     pub fn as_string(&self) -> String {
-        self.value.clone()
+        match &self.value {
+            serde_json::Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }
+    }
+
+    pub fn as_json(&self) -> &serde_json::Value {
+        &self.value
     }
 }
 
 impl From<&str> for MetadataEntry {
     fn from(value: &str) -> Self {
         MetadataEntry {
-            value: value.to_string(),
+            value: serde_json::Value::String(value.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Value> for MetadataEntry {
+    fn from(value: serde_json::Value) -> Self {
+        MetadataEntry { value }
+    }
+}
+
+impl PartialOrd for MetadataEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MetadataEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_json(&self.value, &other.value)
+    }
+}
+
+/// A total order on JSON values that agrees with their `==`.
+fn cmp_json(a: &serde_json::Value, b: &serde_json::Value) -> Ordering {
+    use serde_json::Value;
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
         }
     }
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => cmp_numbers(a, b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| cmp_json(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b)
+            .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| cmp_json(va, vb)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Numerically, exactly for integers. An integer and a float of the same value aren't
+/// equal in JSON, so the integer comes first.
+fn cmp_numbers(a: &serde_json::Number, b: &serde_json::Number) -> Ordering {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        return a.cmp(&b);
+    }
+    if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+        return a.cmp(&b);
+    }
+    // -0.0 and 0.0 are equal in JSON.
+    let as_f64 = |n: &serde_json::Number| n.as_f64().unwrap_or(0.0) + 0.0;
+    as_f64(a)
+        .total_cmp(&as_f64(b))
+        .then_with(|| a.is_f64().cmp(&b.is_f64()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::{MetadataEntry, MetadataKey};
+
+    #[test]
+    pub fn keys_and_entries_can_key_maps() {
+        let mut by_key = BTreeMap::new();
+        by_key.insert(MetadataKey::from("title"), MetadataEntry::from("Home"));
+        by_key.insert(MetadataKey::from("author"), MetadataEntry::from(json!(3)));
+        by_key.insert(MetadataKey::from("title"), MetadataEntry::from("About"));
+        assert_eq!(
+            by_key.into_iter().collect::<Vec<_>>(),
+            [
+                (MetadataKey::from("author"), MetadataEntry::from(json!(3))),
+                (MetadataKey::from("title"), MetadataEntry::from("About")),
+            ]
+        );
+
+        let mut entries: Vec<MetadataEntry> = [
+            json!("b"),
+            json!({"x": 1}),
+            json!(2.5),
+            json!(null),
+            json!([1, 2]),
+            json!(-3),
+            json!(2),
+            json!(true),
+            json!("a"),
+            json!(2.0),
+        ]
+        .into_iter()
+        .map(MetadataEntry::from)
+        .collect();
+        entries.sort();
+        assert_eq!(
+            entries.iter().map(|e| e.as_string()).collect::<Vec<_>>(),
+            [
+                "null",
+                "true",
+                "-3",
+                "2",
+                "2.0",
+                "2.5",
+                "a",
+                "b",
+                "[1,2]",
+                "{\"x\":1}"
+            ]
+        );
+        assert_eq!(
+            MetadataEntry::from(json!(0.0)).cmp(&MetadataEntry::from(json!(-0.0))),
+            std::cmp::Ordering::Equal
+        );
+    }
 }
@@ -0,0 +1,648 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use xfs::Xfs;
+
+use crate::project_root::normalize_project_relative_path;
+
+/// How much a package status entry needs the user's attention. Ordered, so
+/// `status --min-severity` can hide everything below a level.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PackageStatusEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub status: String,
+    pub next_steps: Vec<String>,
+    pub severity: Severity,
+}
+
+/// The on-disk form of a package status entry, `.wrought/packages/<package>/status/*.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackageStatusEntryRepr {
+    pub title: String,
+    pub status: String,
+    pub next_steps: Vec<String>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+impl PackageStatusEntry {
+    pub fn read_from(fs: &dyn xfs::Xfs, p: &Path) -> anyhow::Result<PackageStatusEntry> {
+        let mut content = String::new();
+        fs.reader(p)?.read_to_string(&mut content)?;
+        Self::parse(p, &content)
+    }
+
+    /// `.toml` entries are structured (see `PackageStatusEntryRepr`). Anything else is
+    /// plain text - the whole file is the status, titled by the file name, at `Info`.
+    pub fn parse(p: &Path, content: &str) -> anyhow::Result<PackageStatusEntry> {
+        if p.extension().is_some_and(|e| e == "toml") {
+            let content: PackageStatusEntryRepr =
+                toml::from_str(content).with_context(|| format!("in {}", p.display()))?;
+            return Ok(PackageStatusEntry {
+                path: p.to_path_buf(),
+                title: content.title,
+                status: content.status,
+                next_steps: content.next_steps,
+                severity: content.severity,
+            });
+        }
+        Ok(PackageStatusEntry {
+            path: p.to_path_buf(),
+            title: p
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            status: content.to_string(),
+            next_steps: vec![],
+            severity: Severity::Info,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.path.file_name().unwrap().to_string_lossy().to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct PackageStatus {
+    pub package: Package,
+    pub entries: Vec<anyhow::Result<PackageStatusEntry>>,
+}
+
+impl PackageStatus {
+    /// The most severe of its entries. An entry that couldn't be read - or a package
+    /// whose status directory couldn't be read at all - counts as an error.
+    pub fn health(&self) -> Severity {
+        self.entries
+            .iter()
+            .map(|e| match e {
+                Ok(e) => e.severity,
+                Err(_) => Severity::Error,
+            })
+            .max()
+            .unwrap_or(Severity::Info)
+    }
+}
+
+/// How many packages are in each state, for the summary `status` prints.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PackageHealth {
+    pub ok: usize,
+    pub warning: usize,
+    pub error: usize,
+}
+
+impl PackageHealth {
+    /// `unreadable` is how many entries of the packages directory aren't packages at
+    /// all. Each counts as a package with an error.
+    pub fn of(statuses: &[PackageStatus], unreadable: usize) -> PackageHealth {
+        let mut health = PackageHealth {
+            error: unreadable,
+            ..PackageHealth::default()
+        };
+        for status in statuses {
+            match status.health() {
+                Severity::Info => health.ok += 1,
+                Severity::Warning => health.warning += 1,
+                Severity::Error => health.error += 1,
+            }
+        }
+        health
+    }
+}
+
+impl std::fmt::Display for PackageHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ok, {} with warnings, {} with errors",
+            self.ok, self.warning, self.error
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub path: PathBuf,
+}
+
+impl Package {
+    pub fn status(&self, fs: &dyn xfs::Xfs) -> PackageStatus {
+        let status_dir = self.path.join("status");
+        let mut entries = vec![];
+
+        let mut f = |fs: &dyn Xfs, entry: &dyn xfs::XfsDirEntry| -> anyhow::Result<()> {
+            let md = match entry.metadata() {
+                Ok(md) => md,
+                Err(e) => {
+                    entries.push(
+                        Err(e).with_context(|| format!("getting metadata for {:?}", entry.path())),
+                    );
+                    return Ok(());
+                }
+            };
+            if !md.is_file() {
+                entries.push(Err(anyhow!(
+                    "status entry {:?} is not a file",
+                    entry.path()
+                )));
+                return Ok(());
+            }
+
+            entries.push(PackageStatusEntry::read_from(fs, &entry.path()));
+            Ok(())
+        };
+
+        if let Err(e) = fs.on_each_entry(&status_dir, &mut f) {
+            entries.push(
+                Err(e).with_context(|| format!("while reading statuses from {:?}", status_dir)),
+            );
+        }
+        PackageStatus {
+            package: self.clone(),
+            entries,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.path.file_name().unwrap().to_str().unwrap().to_string()
+    }
+}
+
+pub struct PackageDirectory {
+    pub path: PathBuf,
+}
+
+/// Files with these extensions can be run by `run-script`.
+pub const SCRIPT_EXTENSIONS: [&str; 3] = ["luau", "lua", "wasm"];
+
+/// A script for `run-script`, found by name or by path.
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    pub path: PathBuf,
+    /// How runs of it are recorded - `<package>/<script>` for a package's script,
+    /// otherwise its path relative to the project root.
+    pub name: String,
+    pub package: Option<String>,
+}
+
+/// Which runtime runs a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    Luau,
+    Wasm,
+}
+
+/// Every WASM module starts with these bytes.
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+impl Script {
+    /// Decided by the extension, or for a script run by path without a known one, by
+    /// whether it starts like a WASM module.
+    pub fn kind(&self, fs: &dyn xfs::Xfs) -> anyhow::Result<ScriptKind> {
+        match self.path.extension().and_then(|e| e.to_str()) {
+            Some("luau" | "lua") => return Ok(ScriptKind::Luau),
+            Some("wasm") => return Ok(ScriptKind::Wasm),
+            _ => {}
+        }
+        let mut start = vec![];
+        fs.reader(&self.path)?
+            .take(WASM_MAGIC.len() as u64)
+            .read_to_end(&mut start)
+            .with_context(|| format!("reading {}", self.path.display()))?;
+        if start == WASM_MAGIC {
+            return Ok(ScriptKind::Wasm);
+        }
+        bail!(
+            "can not tell what kind of script {} is - name it .luau or .wasm",
+            self.name
+        )
+    }
+}
+
+impl PackageDirectory {
+    pub fn packages(&self, fs: &dyn xfs::Xfs) -> Vec<anyhow::Result<Package>> {
+        let mut result = vec![];
+
+        let mut f = |_fs: &dyn Xfs, entry: &dyn xfs::XfsDirEntry| -> anyhow::Result<()> {
+            let md = match entry.metadata() {
+                Err(e) => {
+                    result.push(
+                        Err(e).with_context(|| format!("getting metadata for {:?}", entry.path())),
+                    );
+                    return Ok(());
+                }
+                Ok(md) => md,
+            };
+            if !md.is_dir() {
+                result.push(Err(anyhow!(
+                    "package directory entry {:?} is not a directory",
+                    entry.path()
+                )));
+                return Ok(());
+            }
+            result.push(Ok(Package { path: entry.path() }));
+            Ok(())
+        };
+
+        if let Err(e) = fs.on_each_entry(&self.path, &mut f) {
+            result.push(
+                Err(e).with_context(|| format!("while reading packages from {:?}", self.path)),
+            );
+        }
+        result
+    }
+
+    /// The package called `name`. If there isn't one, the error lists the packages
+    /// that do exist.
+    pub fn package(&self, fs: &dyn xfs::Xfs, name: &str) -> anyhow::Result<Package> {
+        let path = self.path.join(name);
+        if fs.is_dir(&path) {
+            return Ok(Package { path });
+        }
+        let mut available: Vec<_> = self
+            .packages(fs)
+            .into_iter()
+            .filter_map(|p| p.ok())
+            .map(|p| p.name())
+            .collect();
+        available.sort();
+        let available = if available.is_empty() {
+            "none".to_string()
+        } else {
+            available.join(", ")
+        };
+        bail!(
+            "there is no package '{}' in {} (available packages: {})",
+            name,
+            self.path.display(),
+            available
+        )
+    }
+
+    /// Every script in every package, as `<package>/<script>`, sorted.
+    pub fn scripts(&self, fs: &dyn xfs::Xfs) -> anyhow::Result<Vec<String>> {
+        let mut scripts = vec![];
+        for package in self.packages(fs).into_iter().filter_map(|p| p.ok()) {
+            collect_scripts(fs, &package.path, &mut scripts)?;
+        }
+        let mut names: Vec<_> = scripts
+            .iter()
+            .map(|p| p.strip_prefix(&self.path).unwrap().display().to_string())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// The script `name` names. That's either `<package>/<script>`, or just `<script>`
+    /// if only one package has a script by that name. If it names no script, or more
+    /// than one, the error lists the candidates.
+    pub fn script(&self, fs: &dyn xfs::Xfs, name: &str) -> anyhow::Result<Script> {
+        let relative = normalize_project_relative_path(Path::new(name))
+            .with_context(|| format!("invalid script name '{}'", name))?;
+        let candidates: Vec<PathBuf> = if relative.components().count() > 1 {
+            // Qualified with its package.
+            vec![relative.clone()]
+        } else {
+            self.packages(fs)
+                .into_iter()
+                .filter_map(|p| p.ok())
+                .map(|p| PathBuf::from(p.name()).join(&relative))
+                .collect()
+        };
+        let mut found: Vec<_> = candidates
+            .into_iter()
+            .filter(|p| fs.is_file(&self.path.join(p)))
+            .collect();
+        found.sort();
+
+        match found.as_slice() {
+            [qualified] => Ok(Script {
+                path: self.path.join(qualified),
+                name: qualified.display().to_string(),
+                package: qualified
+                    .components()
+                    .next()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string()),
+            }),
+            [] => {
+                let available = self.scripts(fs)?;
+                let available = if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                };
+                bail!(
+                    "there is no script '{}' in {} (available scripts: {})",
+                    name,
+                    self.path.display(),
+                    available
+                )
+            }
+            _ => {
+                let found: Vec<_> = found.iter().map(|p| p.display().to_string()).collect();
+                bail!(
+                    "more than one package has a script '{}' - use one of {}",
+                    name,
+                    found.join(", ")
+                )
+            }
+        }
+    }
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| SCRIPT_EXTENSIONS.iter().any(|s| e == *s))
+}
+
+fn collect_scripts(
+    fs: &dyn xfs::Xfs,
+    dir: &Path,
+    scripts: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    fs.on_each_entry(dir, &mut |fs, e| {
+        let md = e.metadata()?;
+        if md.is_dir() {
+            collect_scripts(fs, &e.path(), scripts)?;
+        } else if md.is_file() && is_script(&e.path()) {
+            scripts.push(e.path());
+        }
+        Ok(())
+    })
+}
+
+/// The script at `path`, relative to `project_root`, as given to `run-script --path`.
+/// It belongs to a package if it is inside one.
+pub fn script_at_path(
+    fs: &dyn xfs::Xfs,
+    project_root: &Path,
+    path: &Path,
+) -> anyhow::Result<Script> {
+    let full_path = project_root.join(path);
+    if !fs.is_file(&full_path) {
+        bail!("there is no script at '{}'", path.display());
+    }
+    // Named just as if it had been run by name, so both are recorded alike.
+    let in_package = path
+        .strip_prefix(Path::new(".wrought").join("packages"))
+        .ok()
+        .filter(|p| p.components().count() > 1);
+    Ok(Script {
+        path: full_path,
+        name: in_package.unwrap_or(path).display().to_string(),
+        package: in_package
+            .and_then(|p| p.components().next())
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::Path;
+
+    use super::{
+        script_at_path, PackageDirectory, PackageHealth, PackageStatusEntry, Script, ScriptKind,
+        Severity,
+    };
+
+    #[test]
+    pub fn structured_entries_have_a_severity() {
+        let entry = PackageStatusEntry::parse(
+            Path::new("status/build.toml"),
+            "title = \"Build\"\nstatus = \"3 pages failed\"\nnext_steps = [\"fix them\"]\nseverity = \"warning\"\n",
+        )
+        .unwrap();
+        assert_eq!(entry.title, "Build");
+        assert_eq!(entry.status, "3 pages failed");
+        assert_eq!(entry.next_steps, vec!["fix them"]);
+        assert_eq!(entry.severity, Severity::Warning);
+    }
+
+    #[test]
+    pub fn severity_defaults_to_info() {
+        let entry = PackageStatusEntry::parse(
+            Path::new("status/build.toml"),
+            "title = \"Build\"\nstatus = \"ok\"\nnext_steps = []\n",
+        )
+        .unwrap();
+        assert_eq!(entry.severity, Severity::Info);
+
+        let e = PackageStatusEntry::parse(
+            Path::new("status/build.toml"),
+            "title = \"Build\"\nstatus = \"ok\"\nnext_steps = []\nseverity = \"dire\"\n",
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", e).contains("status/build.toml"), "{:#}", e);
+    }
+
+    #[test]
+    pub fn missing_packages_list_the_available_ones() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for p in ["blog/init.luau", "docs/init.luau", "README.md"] {
+            fs.add_r(&Path::new("packages").join(p), b"x".to_vec())
+                .unwrap();
+        }
+        let dir = PackageDirectory {
+            path: "packages".into(),
+        };
+
+        assert_eq!(
+            dir.package(&fs, "docs").unwrap().path,
+            Path::new("packages/docs")
+        );
+        let e = dir.package(&fs, "bogus").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "there is no package 'bogus' in packages (available packages: blog, docs)"
+        );
+        // Files are not packages.
+        assert!(dir.package(&fs, "README.md").is_err());
+    }
+
+    #[test]
+    pub fn package_health_counts_unreadable_packages_as_errors() {
+        let (fs, dir) = packages_fs(&[
+            "good/status/build.txt",
+            "invalid/status/build.toml",
+            "broken/init.luau",
+            "stray.md",
+        ]);
+        let statuses: Vec<_> = dir
+            .packages(&fs)
+            .into_iter()
+            .filter_map(|p| p.ok())
+            .map(|p| p.status(&fs))
+            .collect();
+        assert_eq!(statuses.len(), 3);
+        // The mock's files all hold "x" - fine as text, but not valid toml.
+        let health = PackageHealth::of(&statuses, 1);
+        assert_eq!(
+            health,
+            PackageHealth {
+                ok: 1,
+                warning: 0,
+                error: 3
+            }
+        );
+        assert_eq!(health.to_string(), "1 ok, 0 with warnings, 3 with errors");
+    }
+
+    #[test]
+    pub fn plain_text_entries_are_info() {
+        let entry =
+            PackageStatusEntry::parse(Path::new("status/notes.txt"), "All good.\n").unwrap();
+        assert_eq!(entry.title, "notes");
+        assert_eq!(entry.status, "All good.\n");
+        assert!(entry.next_steps.is_empty());
+        assert_eq!(entry.severity, Severity::Info);
+        assert!(Severity::Info < Severity::Warning && Severity::Warning < Severity::Error);
+    }
+
+    fn packages_fs(paths: &[&str]) -> (xfs::mockfs::MockFS, PackageDirectory) {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for p in paths {
+            fs.add_r(&Path::new("packages").join(p), b"x".to_vec())
+                .unwrap();
+        }
+        let dir = PackageDirectory {
+            path: "packages".into(),
+        };
+        (fs, dir)
+    }
+
+    #[test]
+    pub fn scripts_are_found_by_qualified_or_bare_name() {
+        let (fs, dir) = packages_fs(&["blog/init.luau", "blog/tools/build.wasm", "blog/notes.md"]);
+        let expected = |name: &str| Script {
+            path: Path::new("packages").join(name),
+            name: name.to_string(),
+            package: Some("blog".to_string()),
+        };
+
+        assert_eq!(
+            dir.script(&fs, "blog/tools/build.wasm").unwrap(),
+            expected("blog/tools/build.wasm")
+        );
+        // Only one package, so its name can be left off.
+        assert_eq!(
+            dir.script(&fs, "init.luau").unwrap(),
+            expected("blog/init.luau")
+        );
+
+        let e = dir.script(&fs, "blog/notes.luau").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "there is no script 'blog/notes.luau' in packages (available scripts: blog/init.luau, blog/tools/build.wasm)"
+        );
+        assert!(dir.script(&fs, "../blog/init.luau").is_err());
+    }
+
+    #[test]
+    pub fn bare_script_names_must_be_unambiguous() {
+        let (fs, dir) = packages_fs(&["blog/init.luau", "docs/init.luau", "docs/build.luau"]);
+
+        let e = dir.script(&fs, "init.luau").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "more than one package has a script 'init.luau' - use one of blog/init.luau, docs/init.luau"
+        );
+        assert_eq!(
+            dir.script(&fs, "docs/init.luau").unwrap().package,
+            Some("docs".to_string())
+        );
+        assert_eq!(
+            dir.script(&fs, "build.luau").unwrap().name,
+            "docs/build.luau"
+        );
+    }
+
+    #[test]
+    pub fn scripts_can_be_run_by_path() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for p in ["tools/gen.luau", ".wrought/packages/blog/init.luau"] {
+            fs.add_r(&Path::new("project").join(p), b"x".to_vec())
+                .unwrap();
+        }
+        let root = Path::new("project");
+
+        let script = script_at_path(&fs, root, Path::new("tools/gen.luau")).unwrap();
+        assert_eq!(script.name, "tools/gen.luau");
+        assert_eq!(script.package, None);
+        // Named just as if it had been run by name.
+        let script =
+            script_at_path(&fs, root, Path::new(".wrought/packages/blog/init.luau")).unwrap();
+        assert_eq!(script.name, "blog/init.luau");
+        assert_eq!(script.package, Some("blog".to_string()));
+        assert!(script_at_path(&fs, root, Path::new("tools/missing.luau")).is_err());
+    }
+
+    #[test]
+    pub fn scripts_run_in_the_runtime_their_kind_needs() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        for (p, content) in [
+            ("build.luau", b"print('hi')".as_slice()),
+            ("old.lua", b"print('hi')"),
+            ("plugin.wasm", b"not checked"),
+            ("plugin", b"\0asm\x01\0\0\0"),
+            ("notes", b"print('hi')"),
+        ] {
+            fs.add_r(Path::new(p), content.to_vec()).unwrap();
+        }
+        let kind = |name: &str| {
+            Script {
+                path: name.into(),
+                name: name.to_string(),
+                package: None,
+            }
+            .kind(&fs)
+        };
+
+        assert_eq!(kind("build.luau").unwrap(), ScriptKind::Luau);
+        assert_eq!(kind("old.lua").unwrap(), ScriptKind::Luau);
+        assert_eq!(kind("plugin.wasm").unwrap(), ScriptKind::Wasm);
+        // No extension, so the content decides.
+        assert_eq!(kind("plugin").unwrap(), ScriptKind::Wasm);
+        assert_eq!(
+            kind("notes").unwrap_err().to_string(),
+            "can not tell what kind of script notes is - name it .luau or .wasm"
+        );
+    }
+}
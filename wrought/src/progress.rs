@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+/// Progress through a long running operation, for tools wrapping wrought.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// `done` of `total` files have been hashed while scanning the project.
+    HashingFiles {
+        done: usize,
+        total: usize,
+    },
+    /// The `number`th LLM query of this run has been sent.
+    LlmQueryStarted {
+        number: usize,
+    },
+    LlmQueryFinished {
+        number: usize,
+    },
+}
+
+pub trait ProgressSink {
+    fn on_progress(&self, event: ProgressEvent);
+}
+
+/// Sends each event to an optional sink, so emitters don't need to care if anyone is listening.
+pub fn report(sink: Option<&dyn ProgressSink>, event: ProgressEvent) {
+    if let Some(sink) = sink {
+        sink.on_progress(event);
+    }
+}
+
+/// Writes each event as a line of JSON on stderr (enabled by `--progress`).
+pub struct JsonLinesProgress;
+
+impl ProgressSink for JsonLinesProgress {
+    fn on_progress(&self, event: ProgressEvent) {
+        // Progress is best effort - it must never stop the real work.
+        if let Ok(line) = serde_json::to_string(&event) {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::sync::Mutex;
+
+    use super::{ProgressEvent, ProgressSink};
+
+    /// Records every event, for checking what was reported.
+    #[derive(Default)]
+    pub struct RecordingProgress {
+        pub events: Mutex<Vec<ProgressEvent>>,
+    }
+
+    impl ProgressSink for RecordingProgress {
+        fn on_progress(&self, event: ProgressEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ProgressEvent;
+
+    #[test]
+    pub fn events_serialize_as_tagged_json() {
+        let line = serde_json::to_string(&ProgressEvent::HashingFiles {
+            done: 120,
+            total: 500,
+        })
+        .unwrap();
+        assert_eq!(line, r#"{"event":"hashing_files","done":120,"total":500}"#);
+    }
+}
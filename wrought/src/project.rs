@@ -0,0 +1,136 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    binary16::ContentHash,
+    content_store::{ContentStore, FileSystemContentStore},
+    error::{Error, Result},
+    event_log::{EventLog, IntegrityIssue, SQLiteEventLog},
+    events::EventGroup,
+    project_root::find_marker_dir,
+    verify::verify_project,
+};
+
+/// A wrought project, for applications that embed wrought rather than running the
+/// CLI. Every method returns `error::Error`, so callers never see `anyhow`.
+pub struct Project {
+    root: PathBuf,
+    event_log: SQLiteEventLog,
+    content_store: FileSystemContentStore,
+}
+
+impl Project {
+    /// Opens the project containing `dir`, found the same way the CLI finds the
+    /// project for the current directory.
+    pub fn open(dir: &Path) -> Result<Project> {
+        let fs: Arc<Mutex<dyn xfs::Xfs + Send + 'static>> = Arc::new(Mutex::new(xfs::OsFs {}));
+        // `dir` not existing also means there's no project, but other failures to
+        // search (e.g. permissions) are reported as they are.
+        let root = match find_marker_dir(&*fs.lock().unwrap(), dir, ".wrought") {
+            Ok(Some(root)) => root,
+            Ok(None) => return Err(Error::ProjectNotFound(dir.to_path_buf())),
+            Err(e) if is_not_found(&e) => return Err(Error::ProjectNotFound(dir.to_path_buf())),
+            Err(e) => {
+                return Err(Error::Io(std::io::Error::other(format!(
+                    "searching for a wrought project from {}: {:#}",
+                    dir.display(),
+                    e
+                ))))
+            }
+        };
+        let internal_dir = root.join(".wrought");
+        let event_log =
+            SQLiteEventLog::open(internal_dir.join("wrought.db")).map_err(Error::event_log)?;
+        let content_store = FileSystemContentStore::on_os_fs(internal_dir.join("content"));
+        Ok(Project {
+            root,
+            event_log,
+            content_store,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Every recorded run, oldest first.
+    pub fn event_groups(&self) -> Result<Vec<EventGroup>> {
+        let mut groups = self
+            .event_log
+            .all_event_groups()
+            .map_err(Error::event_log)?;
+        groups.sort_by_key(|g| g.id);
+        Ok(groups)
+    }
+
+    /// Stored content, checked against its hash. `None` if it isn't stored.
+    pub fn content(&self, hash: &ContentHash) -> Result<Option<Vec<u8>>> {
+        self.content_store
+            .retrieve_verified(hash.clone())
+            .map_err(Error::content_store)
+    }
+
+    /// The same checks as `wrought verify`.
+    pub fn verify(&self) -> Result<Vec<IntegrityIssue>> {
+        verify_project(&self.event_log, &self.content_store).map_err(Error::event_log)
+    }
+}
+
+/// Whether `e` comes from a missing file or directory.
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<xfs::XfsError>(),
+            Some(xfs::XfsError::FileNotFound(..))
+        ) || cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::error::Error as _;
+
+    use super::Project;
+    use crate::{
+        binary16::ContentHash, error::Error, event_log::SQLiteEventLog, luau_json::ConversionError,
+    };
+
+    #[test]
+    pub fn failures_can_be_matched_on() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        assert!(matches!(
+            Project::open(&dir),
+            Err(Error::ProjectNotFound(p)) if p == dir
+        ));
+        let missing = dir.join("missing");
+        assert!(matches!(
+            Project::open(&missing),
+            Err(Error::ProjectNotFound(p)) if p == missing
+        ));
+        // Anything else going wrong isn't mistaken for there being no project.
+        std::fs::write(dir.join("file"), b"").unwrap();
+        assert!(matches!(
+            Project::open(&dir.join("file/sub")),
+            Err(Error::Io(_))
+        ));
+        std::fs::create_dir_all(dir.join(".wrought/content")).unwrap();
+        assert!(matches!(Project::open(&dir), Err(Error::EventLog(_))));
+
+        SQLiteEventLog::init(dir.join(".wrought/wrought.db")).unwrap();
+        let project = Project::open(&dir).unwrap();
+        assert!(project.event_groups().unwrap().is_empty());
+        assert!(project.verify().unwrap().is_empty());
+        let missing = ContentHash::from_content(b"never stored");
+        assert_eq!(project.content(&missing).unwrap(), None);
+
+        let e = Error::from(ConversionError::MixedArrayKeys);
+        assert_eq!(e.to_string(), "conversion error: Mixed array keys");
+        assert!(e.source().is_some());
+    }
+}
@@ -0,0 +1,670 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+
+/// How many parent directories we're willing to walk through before giving up.
+/// Real projects are nowhere near this deep, so hitting it usually means something
+/// odd is going on (e.g. a very deep network mount).
+pub const MAX_SEARCH_DEPTH: usize = 256;
+
+/// Environment variable holding a list of directories (separated like `PATH`) that
+/// the upward search for a project root will not go above. Similar to git's
+/// `GIT_CEILING_DIRECTORIES`, and handy for stopping at a mount point.
+pub const CEILING_DIRECTORIES_ENV: &str = "WROUGHT_CEILING_DIRECTORIES";
+
+/// Environment variable naming the project root directly, skipping the upward search.
+/// Useful for tools that invoke wrought many times from the same project.
+pub const PROJECT_ROOT_ENV: &str = "WROUGHT_PROJECT_ROOT";
+
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub max_depth: usize,
+    /// The search checks a ceiling directory itself, but never its parents.
+    pub ceiling_dirs: Vec<PathBuf>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            max_depth: MAX_SEARCH_DEPTH,
+            ceiling_dirs: vec![],
+        }
+    }
+}
+
+impl SearchLimits {
+    pub fn from_env() -> SearchLimits {
+        let ceiling_dirs = match std::env::var_os(CEILING_DIRECTORIES_ENV) {
+            Some(v) => std::env::split_paths(&v)
+                .filter(|p| !p.as_os_str().is_empty())
+                .collect(),
+            None => vec![],
+        };
+        SearchLimits {
+            ceiling_dirs,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn is_ceiling(&self, fs: &dyn xfs::Xfs, p: &Path) -> bool {
+        self.ceiling_dirs.iter().any(|c| {
+            // Compare canonically where we can, so a ceiling given via a symlink still works.
+            let c = fs.canonicalize(c).unwrap_or_else(|_| c.clone());
+            c == p
+        })
+    }
+}
+
+/// Returns the canonical form of the first directory (or file) that exists
+/// while walking up from `starting_dir`.
+pub fn find_first_existing_parent(
+    fs: &dyn xfs::Xfs,
+    starting_dir: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    find_first_existing_parent_with_limits(fs, starting_dir, &SearchLimits::default())
+}
+
+pub fn find_first_existing_parent_with_limits(
+    fs: &dyn xfs::Xfs,
+    starting_dir: &Path,
+    limits: &SearchLimits,
+) -> anyhow::Result<Option<PathBuf>> {
+    let mut current_dir = starting_dir;
+    let mut depth = 0;
+
+    loop {
+        if fs.exists(current_dir) {
+            let canonical = fs
+                .canonicalize(current_dir)
+                .with_context(|| format!("canonicalizing {:?}", current_dir))?;
+            return Ok(Some(canonical));
+        }
+
+        let Some(parent) = current_dir.parent() else {
+            return Ok(None);
+        };
+        depth += 1;
+        if depth > limits.max_depth {
+            bail!(
+                "no existing parent of '{}' found within {} levels - giving up",
+                starting_dir.display(),
+                limits.max_depth
+            );
+        }
+        current_dir = parent;
+    }
+}
+
+/// Canonicalizes the longest existing prefix of `p` and re-appends the rest.
+///
+/// Unlike `canonicalize` this works for paths that don't exist yet, and the result
+/// shares a prefix with other canonical paths, so `strip_prefix` against a
+/// canonical project root behaves.
+pub fn canonicalize_partial(fs: &dyn xfs::Xfs, p: &Path) -> anyhow::Result<PathBuf> {
+    let mut existing = p;
+    while !fs.exists(existing) {
+        existing = existing
+            .parent()
+            .with_context(|| format!("Unable to find existing parent directory for {:?}", p))?;
+    }
+    let remainder = p.strip_prefix(existing)?;
+    let canonical = fs
+        .canonicalize(existing)
+        .with_context(|| format!("canonicalizing {:?}", existing))?;
+    Ok(canonical.join(remainder))
+}
+
+/// Normalizes a path that is meant to be relative to the project root,
+/// resolving `.` and `..` components. Errors if the path is absolute or
+/// would escape the project root.
+pub fn normalize_project_relative_path(p: &Path) -> anyhow::Result<PathBuf> {
+    use std::path::Component;
+
+    let mut result = PathBuf::new();
+    for c in p.components() {
+        match c {
+            Component::Normal(n) => result.push(n),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    bail!("path '{}' is outside the project root", p.display());
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                bail!(
+                    "path '{}' must be relative to the project root",
+                    p.display()
+                );
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Walks up from `starting_dir` looking for a directory containing `marker`.
+///
+/// The starting directory is canonicalized first, so symlinks are resolved and
+/// the walk happens over the real path. Ceiling directories are taken from
+/// `WROUGHT_CEILING_DIRECTORIES`.
+pub fn find_marker_dir(
+    fs: &dyn xfs::Xfs,
+    starting_dir: &Path,
+    marker: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    find_marker_dir_with_limits(fs, starting_dir, marker, &SearchLimits::from_env())
+}
+
+pub fn find_marker_dir_with_limits(
+    fs: &dyn xfs::Xfs,
+    starting_dir: &Path,
+    marker: &str,
+    limits: &SearchLimits,
+) -> anyhow::Result<Option<PathBuf>> {
+    let starting_dir = fs.canonicalize(starting_dir)?;
+    let mut current_dir: &Path = &starting_dir;
+    let mut depth = 0;
+
+    loop {
+        let marker_path = current_dir.join(marker);
+        if fs.is_dir(&marker_path) {
+            return Ok(Some(current_dir.to_path_buf()));
+        }
+
+        if limits.is_ceiling(fs, current_dir) {
+            return Ok(None);
+        }
+        let Some(parent) = current_dir.parent() else {
+            return Ok(None);
+        };
+        depth += 1;
+        if depth > limits.max_depth {
+            bail!(
+                "searched {} levels above '{}' for '{}' without reaching the filesystem root - giving up",
+                limits.max_depth,
+                starting_dir.display(),
+                marker
+            );
+        }
+        current_dir = parent;
+    }
+}
+
+/// The project root the user explicitly asked for, if any.
+///
+/// Resolution order is: the `--project-root` flag, then `WROUGHT_PROJECT_ROOT`,
+/// and only if neither is set do we search upwards for a `.wrought` directory.
+pub fn requested_project_root(flag: Option<&Path>) -> Option<PathBuf> {
+    requested_project_root_from(flag, std::env::var_os(PROJECT_ROOT_ENV))
+}
+
+fn requested_project_root_from(
+    flag: Option<&Path>,
+    env_value: Option<std::ffi::OsString>,
+) -> Option<PathBuf> {
+    match flag {
+        Some(p) => Some(p.to_path_buf()),
+        None => env_value.filter(|v| !v.is_empty()).map(PathBuf::from),
+    }
+}
+
+/// Checks that `p` (relative paths are taken relative to `working_dir`) is a valid
+/// project root, i.e. it has a `.wrought` subdirectory, and returns its canonical form.
+pub fn validate_project_root(
+    fs: &dyn xfs::Xfs,
+    working_dir: &Path,
+    p: &Path,
+) -> anyhow::Result<PathBuf> {
+    let p = if p.is_absolute() {
+        p.to_path_buf()
+    } else {
+        working_dir.join(p)
+    };
+    if !fs.is_dir(&p.join(".wrought")) {
+        bail!(
+            "specified project root {} has no .wrought subdirectory - it is not a valid root",
+            p.display()
+        );
+    }
+    Ok(fs.canonicalize(&p)?)
+}
+
+/// Uses the `requested` root if there is one (see `requested_project_root`),
+/// otherwise searches upwards from `working_dir`.
+pub fn resolve_project_root(
+    fs: &dyn xfs::Xfs,
+    working_dir: &Path,
+    requested: Option<&Path>,
+) -> anyhow::Result<PathBuf> {
+    let root = match requested {
+        Some(p) => validate_project_root(fs, working_dir, p)?,
+        None => find_marker_dir(fs, working_dir, ".wrought")?
+            .context("Unable to find project root for current directory")?,
+    };
+    log::debug!("using project root '{}'", root.display());
+    Ok(root)
+}
+
+/// Resolves a path argument given on the command line, for every command that takes
+/// one. Relative paths are taken relative to `working_dir`. The project is the
+/// `requested` root if there is one, otherwise the project containing the path.
+/// Returns the canonical project root and the path relative to it, and errors if
+/// the path is outside the project.
+pub fn resolve_project_and_path(
+    fs: &dyn xfs::Xfs,
+    working_dir: &Path,
+    arg: &Path,
+    requested: Option<&Path>,
+) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let path = working_dir.join(arg);
+
+    //NOTE: We can't immediately canonicalize the path as it may not exist.
+    //      So we canonicalize the part that does exist, so that it shares a prefix
+    //      with the (canonical) project root.
+    let path = canonicalize_partial(fs, &path)?;
+
+    let root = match requested {
+        Some(p) => validate_project_root(fs, working_dir, p)?,
+        None => {
+            let parent = find_first_existing_parent(fs, &path)?.with_context(|| {
+                format!("Unable to find existing parent directory for {:?}", path)
+            })?;
+            find_marker_dir(fs, &parent, ".wrought")?
+                .with_context(|| format!("Unable to find wrought root containing {:?}", path))?
+        }
+    };
+
+    let relative = path.strip_prefix(&root).map_err(|_| {
+        anyhow!(
+            "'{}' is not inside the project at '{}'",
+            arg.display(),
+            root.display()
+        )
+    })?;
+    // The part that didn't exist yet may still contain `..`.
+    let relative = normalize_project_relative_path(relative)?;
+    log::debug!(
+        "using project root '{}' for '{}'",
+        root.display(),
+        relative.display()
+    );
+    Ok((root, relative))
+}
+
+/// Finds other projects that make it ambiguous which project `working_dir` belongs
+/// to: any `.wrought` marker in a directory between `root` and `working_dir`, and the
+/// nearest project enclosing `root`. Normally there are none.
+///
+/// `root` must be canonical, as returned by `resolve_project_root`.
+pub fn find_nested_project_roots(
+    fs: &dyn xfs::Xfs,
+    root: &Path,
+    working_dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut result = vec![];
+    let working_dir = canonicalize_partial(fs, working_dir)?;
+    if working_dir.starts_with(root) {
+        let mut between: Vec<PathBuf> = working_dir
+            .ancestors()
+            .take_while(|p| *p != root)
+            .filter(|p| fs.is_dir(&p.join(".wrought")))
+            .map(|p| p.to_path_buf())
+            .collect();
+        between.reverse();
+        result.extend(between);
+    }
+    if let Some(parent) = root.parent() {
+        if let Some(enclosing) = find_marker_dir(fs, parent, ".wrought")? {
+            result.insert(0, enclosing);
+        }
+    }
+    Ok(result)
+}
+
+/// Warns about (or with `strict`, refuses to use) a project root that has other
+/// projects nested around it - see `find_nested_project_roots`.
+pub fn check_for_nested_projects(
+    fs: &dyn xfs::Xfs,
+    root: &Path,
+    working_dir: &Path,
+    strict: bool,
+) -> anyhow::Result<()> {
+    let others = find_nested_project_roots(fs, root, working_dir)?;
+    if others.is_empty() {
+        return Ok(());
+    }
+    let others: Vec<_> = others.iter().map(|p| p.display().to_string()).collect();
+    let message = format!(
+        "using project root {} but found other wrought projects at {} - use --project-root to pick one",
+        root.display(),
+        others.join(", ")
+    );
+    if strict {
+        bail!(message);
+    }
+    eprintln!("warning: {}", message);
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::fs_utils::test_utils::MockFs;
+
+    use super::*;
+
+    fn deep_path(depth: usize) -> PathBuf {
+        let mut p = PathBuf::from("/");
+        for i in 0..depth {
+            p.push(format!("d{}", i));
+        }
+        p
+    }
+
+    #[test]
+    pub fn finds_marker_in_ancestor() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/project/.wrought"));
+
+        let root = find_marker_dir_with_limits(
+            &fs,
+            Path::new("/project/a/b"),
+            ".wrought",
+            &SearchLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(root, Some(PathBuf::from("/project")));
+    }
+
+    #[test]
+    pub fn deeply_nested_search_hits_limit() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir().returning(|_| false);
+
+        let start = deep_path(MAX_SEARCH_DEPTH + 10);
+        let e = find_marker_dir_with_limits(&fs, &start, ".wrought", &SearchLimits::default())
+            .unwrap_err();
+        assert!(e.to_string().contains("giving up"), "{}", e);
+    }
+
+    #[test]
+    pub fn deeply_nested_search_within_limit_finds_root_marker() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/.wrought"));
+
+        let start = deep_path(MAX_SEARCH_DEPTH);
+        let root =
+            find_marker_dir_with_limits(&fs, &start, ".wrought", &SearchLimits::default()).unwrap();
+        assert_eq!(root, Some(PathBuf::from("/")));
+    }
+
+    #[test]
+    pub fn search_stops_at_ceiling() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/mnt/.wrought"));
+
+        let limits = SearchLimits {
+            ceiling_dirs: vec![PathBuf::from("/mnt/share")],
+            ..SearchLimits::default()
+        };
+        let root = find_marker_dir_with_limits(&fs, Path::new("/mnt/share/a"), ".wrought", &limits)
+            .unwrap();
+        assert_eq!(root, None);
+    }
+
+    #[test]
+    pub fn ceiling_directory_itself_is_checked() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/mnt/share/.wrought"));
+
+        let limits = SearchLimits {
+            ceiling_dirs: vec![PathBuf::from("/mnt/share")],
+            ..SearchLimits::default()
+        };
+        let root = find_marker_dir_with_limits(&fs, Path::new("/mnt/share/a"), ".wrought", &limits)
+            .unwrap();
+        assert_eq!(root, Some(PathBuf::from("/mnt/share")));
+    }
+
+    #[test]
+    pub fn search_walks_the_canonical_path() {
+        // /link/a is a symlink to /real/a - the marker lives under /real.
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize()
+            .returning(|p| Ok(PathBuf::from("/real").join(p.strip_prefix("/link").unwrap())));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/real/.wrought"));
+
+        let root = find_marker_dir_with_limits(
+            &fs,
+            Path::new("/link/a"),
+            ".wrought",
+            &SearchLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(root, Some(PathBuf::from("/real")));
+    }
+
+    #[test]
+    pub fn first_existing_parent_is_canonical() {
+        let mut fs = MockFs::default();
+        fs.expect_exists().returning(|p| p == Path::new("/link/a"));
+        fs.expect_canonicalize()
+            .returning(|_| Ok(PathBuf::from("/real/a")));
+
+        let parent =
+            find_first_existing_parent(&fs, Path::new("/link/a/missing/file.txt")).unwrap();
+        assert_eq!(parent, Some(PathBuf::from("/real/a")));
+
+        let p = canonicalize_partial(&fs, Path::new("/link/a/missing/file.txt")).unwrap();
+        assert_eq!(p, PathBuf::from("/real/a/missing/file.txt"));
+    }
+
+    #[test]
+    pub fn first_existing_parent_hits_limit() {
+        let mut fs = MockFs::default();
+        fs.expect_exists().returning(|_| false);
+
+        let limits = SearchLimits {
+            max_depth: 5,
+            ..SearchLimits::default()
+        };
+        let e = find_first_existing_parent_with_limits(&fs, &deep_path(10), &limits).unwrap_err();
+        assert!(e.to_string().contains("giving up"), "{}", e);
+    }
+
+    #[test]
+    pub fn normalize_resolves_dots() {
+        assert_eq!(
+            normalize_project_relative_path(Path::new("./a/b/../c.md")).unwrap(),
+            PathBuf::from("a/c.md")
+        );
+    }
+
+    #[test]
+    pub fn normalize_rejects_escaping_paths() {
+        assert!(normalize_project_relative_path(Path::new("a/../../c.md")).is_err());
+        assert!(normalize_project_relative_path(Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    pub fn flag_takes_precedence_over_env() {
+        assert_eq!(
+            requested_project_root_from(Some(Path::new("/flag")), Some("/env".into())),
+            Some(PathBuf::from("/flag"))
+        );
+        assert_eq!(
+            requested_project_root_from(None, Some("/env".into())),
+            Some(PathBuf::from("/env"))
+        );
+        assert_eq!(requested_project_root_from(None, Some("".into())), None);
+        assert_eq!(requested_project_root_from(None, None), None);
+    }
+
+    #[test]
+    pub fn requested_root_skips_search() {
+        let mut fs = MockFs::default();
+        fs.expect_is_dir()
+            .times(1)
+            .returning(|p| p == Path::new("/project/.wrought"));
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+
+        let root = resolve_project_root(&fs, Path::new("/elsewhere"), Some(Path::new("/project")))
+            .unwrap();
+        assert_eq!(root, PathBuf::from("/project"));
+    }
+
+    #[test]
+    pub fn requested_root_without_marker_is_rejected() {
+        let mut fs = MockFs::default();
+        fs.expect_is_dir().returning(|_| false);
+
+        let e = resolve_project_root(&fs, Path::new("/"), Some(Path::new("/not-a-project")))
+            .unwrap_err();
+        assert!(e.to_string().contains("no .wrought subdirectory"), "{}", e);
+    }
+
+    /// A filesystem where `/project` (containing `docs/`) and `/outside` exist.
+    fn project_fs() -> MockFs {
+        let mut fs = MockFs::default();
+        fs.expect_exists().returning(|p| {
+            ["/", "/project", "/project/docs", "/outside"]
+                .iter()
+                .any(|e| p == Path::new(e))
+        });
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/project/.wrought"));
+        fs
+    }
+
+    #[test]
+    pub fn path_arguments_can_be_absolute() {
+        let fs = project_fs();
+        let (root, path) = resolve_project_and_path(
+            &fs,
+            Path::new("/outside"),
+            Path::new("/project/docs/a.md"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(root, PathBuf::from("/project"));
+        assert_eq!(path, PathBuf::from("docs/a.md"));
+    }
+
+    #[test]
+    pub fn path_arguments_are_relative_to_the_working_dir() {
+        let fs = project_fs();
+        let (root, path) =
+            resolve_project_and_path(&fs, Path::new("/project/docs"), Path::new("a.md"), None)
+                .unwrap();
+        assert_eq!(root, PathBuf::from("/project"));
+        assert_eq!(path, PathBuf::from("docs/a.md"));
+
+        let (_, path) = resolve_project_and_path(
+            &fs,
+            Path::new("/project/docs"),
+            Path::new("new/../../b.md"),
+            Some(Path::new("/project")),
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("b.md"));
+    }
+
+    #[test]
+    pub fn path_arguments_outside_the_project_are_rejected() {
+        let fs = project_fs();
+        let e = resolve_project_and_path(
+            &fs,
+            Path::new("/project"),
+            Path::new("/outside/a.md"),
+            Some(Path::new("/project")),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("is not inside the project"), "{}", e);
+
+        let e = resolve_project_and_path(
+            &fs,
+            Path::new("/project/docs"),
+            Path::new("missing/../../../c.md"),
+            Some(Path::new("/project")),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("outside the project root"), "{}", e);
+
+        let e = resolve_project_and_path(&fs, Path::new("/outside"), Path::new("a.md"), None)
+            .unwrap_err();
+        assert!(
+            e.to_string().contains("Unable to find wrought root"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    pub fn nested_project_markers_are_found() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_exists().returning(|_| true);
+        fs.expect_is_dir().returning(|p| {
+            p == Path::new("/outer/.wrought")
+                || p == Path::new("/outer/inner/.wrought")
+                || p == Path::new("/outer/inner/sub/deeper/.wrought")
+        });
+
+        // Explicitly using the middle project, from inside the deepest one.
+        let found = find_nested_project_roots(
+            &fs,
+            Path::new("/outer/inner"),
+            Path::new("/outer/inner/sub/deeper/src"),
+        )
+        .unwrap();
+        assert_eq!(
+            found,
+            vec![
+                PathBuf::from("/outer"),
+                PathBuf::from("/outer/inner/sub/deeper")
+            ]
+        );
+
+        let e = check_for_nested_projects(
+            &fs,
+            Path::new("/outer/inner"),
+            Path::new("/outer/inner/sub/deeper/src"),
+            true,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("/outer/inner/sub/deeper"), "{}", e);
+        // Without --strict it is just a warning.
+        check_for_nested_projects(
+            &fs,
+            Path::new("/outer/inner"),
+            Path::new("/outer/inner/sub/deeper/src"),
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    pub fn lone_project_has_no_nested_markers() {
+        let mut fs = MockFs::default();
+        fs.expect_canonicalize().returning(|p| Ok(p.to_path_buf()));
+        fs.expect_exists().returning(|_| true);
+        fs.expect_is_dir()
+            .returning(|p| p == Path::new("/project/.wrought"));
+
+        let found =
+            find_nested_project_roots(&fs, Path::new("/project"), Path::new("/project/a/b"))
+                .unwrap();
+        assert!(found.is_empty());
+    }
+}
@@ -1,24 +1,96 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
+    io::Read,
     path::{Path, PathBuf},
 };
 
-use crate::{binary16::ContentHash, event_log::EventLog, PackageDirectory, PackageStatus};
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::{
+    backend::INTERNAL_DIR,
+    binary16::ContentHash,
+    event_log::EventLog,
+    ignore_rules::IgnoreRules,
+    metadata::MetadataEntry,
+    package_status::{PackageDirectory, PackageStatus},
+    progress::{report, ProgressEvent, ProgressSink},
+};
+
+/// Metadata key marking a file that several scripts write, each owning part of it. Set
+/// to `true` (or `"true"`), the file is stale if the inputs of any script that wrote it
+/// have changed - not just those of the last run. Each script counts once, by its
+/// latest run that wrote the file. Whether the file has changed is still judged
+/// against the last write, as that is what left the file as it should be.
+///
+/// The flag is read from the event log's `SetMetadata` events, not from
+/// `.wrought/metadata.json`. Status is worked out from the event log alone - which is
+/// what `wrought daemon` watches - and wrought records an event for every metadata
+/// change it makes, so the two normally agree. Where they don't, e.g. after the run
+/// that set the flag is undone, the event log wins.
+pub const MULTI_WRITER_KEY: &str = "multi_writer";
+
+/// Inputs, with the hash each had when it was read. A multi-writer file can have the
+/// same input more than once, if its scripts read different versions.
+type Dependencies = BTreeSet<(PathBuf, Option<ContentHash>)>;
+
+/// Whether `path`, relative to the project root, is a file shipped with a package. These
+/// are in `.wrought`, which status never scans, but runs that read them depend on them
+/// like any other input - so they are hashed when something depends on them.
+fn is_package_file(path: &Path) -> bool {
+    path.starts_with(Path::new(INTERNAL_DIR).join("packages"))
+}
 
 pub struct FileRepresentationFromEvents {
     hash: ContentHash,
-    dependencies_and_hashes: BTreeMap<PathBuf, Option<ContentHash>>,
+    dependencies_and_hashes: Dependencies,
 }
 
 pub struct ProjectRepresentationFromEvents {
     entries: BTreeMap<PathBuf, FileRepresentationFromEvents>,
 }
 
+/// Files that were found but couldn't be read, with the error reading them gave.
+pub type FileProblems = Vec<(PathBuf, anyhow::Error)>;
+
 pub struct ProjectRepresentationFromFilesystem {
     entries: BTreeMap<PathBuf, ContentHash>,
+    problems: FileProblems,
+    skipped: Vec<PathBuf>,
 }
 
-#[derive(Debug)]
+/// What hashing the files under a directory found.
+#[derive(Debug, Default)]
+pub struct FileHashes {
+    pub hashes: BTreeMap<PathBuf, ContentHash>,
+    pub problems: FileProblems,
+    /// Files over the size limit, which weren't hashed.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Files bigger than `max_size` bytes aren't hashed when scanning a project, so a
+/// few huge files (datasets, videos) don't slow down every `status`. Files in
+/// `always_track` - the ones a script wrote - are hashed whatever their size.
+#[derive(Debug, Clone, Default)]
+pub struct TrackSizeLimit {
+    pub max_size: Option<u64>,
+    /// Relative to the project root.
+    pub always_track: BTreeSet<PathBuf>,
+}
+
+impl TrackSizeLimit {
+    /// The limit for one file, relative to the project root.
+    fn max_size_for(&self, relative_path: &Path) -> Option<u64> {
+        if self.always_track.contains(relative_path) {
+            None
+        } else {
+            self.max_size
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum FileStatus {
     Untracked,
     Deleted,
@@ -35,43 +107,158 @@ pub struct FileStatusEntry {
 pub struct ProjectStatus {
     pub file_statuses: Vec<FileStatusEntry>,
     pub package_statuses: Vec<PackageStatus>,
+    /// Entries in the packages directory that aren't packages, or the error reading it.
+    pub package_problems: Vec<anyhow::Error>,
+    /// Files that couldn't be read. They are left out of `file_statuses`, as we can't
+    /// tell what state they are in.
+    pub problems: FileProblems,
+    /// Untracked files over `max_track_size`. They show as untracked without being hashed.
+    pub skipped: Vec<PathBuf>,
 }
 
+/// Hashes every file under `path`, skipping anything `ignore` rules out.
+/// Ignored directories are not descended into.
+/// Files that can't be read don't stop the scan - they are returned alongside the
+/// hashes with the error that reading them gave. Files over `limit` are skipped.
 pub fn get_all_file_hashes_in_directory<P: Into<PathBuf>>(
     fs: &dyn xfs::Xfs,
+    project_root: &Path,
     path: P,
-) -> anyhow::Result<BTreeMap<PathBuf, ContentHash>> {
-    let mut result = BTreeMap::new();
+    ignore: &IgnoreRules,
+    limit: &TrackSizeLimit,
+    progress: Option<&dyn ProgressSink>,
+) -> anyhow::Result<FileHashes> {
+    // Find everything first, so progress can be reported against a total.
+    let mut files = vec![];
+    collect_files(fs, project_root, &path.into(), ignore, limit, &mut files)?;
+    Ok(hash_files(fs, files, progress))
+}
+
+/// Every file in the project that `ignore` doesn't rule out, relative to the root.
+pub fn list_project_files(
+    fs: &dyn xfs::Xfs,
+    project_root: &Path,
+    ignore: &IgnoreRules,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    collect_files(
+        fs,
+        project_root,
+        project_root,
+        ignore,
+        &TrackSizeLimit::default(),
+        &mut files,
+    )?;
+    let mut files: Vec<_> = files
+        .into_iter()
+        .map(|(p, _)| p.strip_prefix(project_root).unwrap().to_path_buf())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads all of `reader`, or `None` if it is longer than `max_size`.
+fn read_within_limit(
+    mut reader: impl Read,
+    max_size: Option<u64>,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    // xfs metadata doesn't give a file's length, so a limited file is read up to
+    // one byte past the limit - never the whole of a huge file.
+    let mut content = vec![];
+    match max_size {
+        Some(max_size) => {
+            reader.take(max_size + 1).read_to_end(&mut content)?;
+            if content.len() as u64 > max_size {
+                return Ok(None);
+            }
+        }
+        None => {
+            reader.read_to_end(&mut content)?;
+        }
+    }
+    Ok(Some(content))
+}
+
+/// Hashes each file, skipping those longer than their size limit.
+fn hash_files(
+    fs: &dyn xfs::Xfs,
+    files: Vec<(PathBuf, Option<u64>)>,
+    progress: Option<&dyn ProgressSink>,
+) -> FileHashes {
+    let total = files.len();
+    let mut result = FileHashes::default();
+    for (i, (p, max_size)) in files.into_iter().enumerate() {
+        let content = (|| read_within_limit(fs.reader(&p)?, max_size))();
+        match content {
+            Ok(Some(content)) => {
+                result.hashes.insert(p, ContentHash::from_content(&content));
+            }
+            Ok(None) => result.skipped.push(p),
+            Err(e) => result.problems.push((p, e)),
+        }
+        report(progress, ProgressEvent::HashingFiles { done: i + 1, total });
+    }
+    result
+}
+
+fn collect_files(
+    fs: &dyn xfs::Xfs,
+    project_root: &Path,
+    path: &Path,
+    ignore: &IgnoreRules,
+    limit: &TrackSizeLimit,
+    files: &mut Vec<(PathBuf, Option<u64>)>,
+) -> anyhow::Result<()> {
     // I hate recursion - this should use a stack instead. But for now it's nice and easy.
-    fs.on_each_entry(&path.into(), &mut |fs, e| {
+    fs.on_each_entry(path, &mut |fs, e| {
         let md = e.metadata()?;
+        let relative_path = e.path();
+        let relative_path = relative_path.strip_prefix(project_root)?;
+        if ignore.is_ignored(relative_path, md.is_dir()) {
+            return Ok(());
+        }
         if md.is_dir() {
-            let mut child_hashes = get_all_file_hashes_in_directory(fs, e.path())?;
-            result.append(&mut child_hashes);
+            collect_files(fs, project_root, &e.path(), ignore, limit, files)?;
         } else if md.is_file() {
-            let mut reader = fs.reader(&e.path())?;
-            let mut content = vec![];
-            reader.read_to_end(&mut content)?;
-            result.insert(e.path(), ContentHash::from_content(&content));
+            files.push((e.path(), limit.max_size_for(relative_path)));
         }
         Ok(())
-    })?;
-    Ok(result)
+    })
 }
 
 pub fn build_rep_from_fs<P: Into<PathBuf>>(
     fs: &dyn xfs::Xfs,
     project_root: P,
+    ignore: &IgnoreRules,
+    limit: &TrackSizeLimit,
+    progress: Option<&dyn ProgressSink>,
 ) -> anyhow::Result<ProjectRepresentationFromFilesystem> {
     let project_root = project_root.into();
-    let file_hashes = get_all_file_hashes_in_directory(fs, &project_root)?;
+    let file_hashes = get_all_file_hashes_in_directory(
+        fs,
+        &project_root,
+        &project_root,
+        ignore,
+        limit,
+        progress,
+    )?;
     // Remove the project_root prefix from them all.
-    let file_hashes = file_hashes
+    let relative = |p: PathBuf| p.strip_prefix(&project_root).unwrap().to_path_buf();
+    let entries = file_hashes
+        .hashes
         .into_iter()
-        .map(|(k, v)| (k.strip_prefix(&project_root).unwrap().to_path_buf(), v))
+        .map(|(k, v)| (relative(k), v))
         .collect();
+    let problems = file_hashes
+        .problems
+        .into_iter()
+        .map(|(k, e)| (relative(k), e))
+        .collect();
+    let skipped = file_hashes.skipped.into_iter().map(relative).collect();
     Ok(ProjectRepresentationFromFilesystem {
-        entries: file_hashes,
+        entries,
+        problems,
+        skipped,
     })
 }
 
@@ -84,15 +271,37 @@ pub fn build_rep_from_event_log(
     let mut result = ProjectRepresentationFromEvents {
         entries: BTreeMap::new(),
     };
+    // For each file, the inputs of each script that wrote it - for multi-writer files.
+    let mut contributions: BTreeMap<PathBuf, BTreeMap<String, Dependencies>> = BTreeMap::new();
+    let mut multi_writer = BTreeSet::new();
 
     for group in all_event_groups {
+        // Runs of a script are told apart from other scripts' by their command, without
+        // any description the run gave itself.
+        let script = match group.command.split_once(" # ") {
+            Some((command, _)) => command.to_string(),
+            None => group.command.clone(),
+        };
+        // Whatever a run read, it may have used for any file it wrote - before or after
+        // the read.
+        // TODO: For now we only track dependencies on files - not metadata.
+        let dependencies: Dependencies = group
+            .events
+            .iter()
+            .filter_map(|event| match &event.event_type {
+                crate::events::EventType::ReadFile(e) => Some((e.path.clone(), e.hash.clone())),
+                _ => None,
+            })
+            .collect();
         for event in group.events {
-            // TODO: For now we only track dependencies on files - not metadata.
-            let mut dependencies = BTreeMap::new();
             match event.event_type {
                 crate::events::EventType::WriteFile(write_file_event) => {
                     match write_file_event.after_hash {
                         Some(hash) => {
+                            contributions
+                                .entry(write_file_event.path.clone())
+                                .or_default()
+                                .insert(script.clone(), dependencies.clone());
                             result.entries.insert(
                                 write_file_event.path,
                                 FileRepresentationFromEvents {
@@ -103,66 +312,223 @@ pub fn build_rep_from_event_log(
                         }
                         None => {
                             // Represents removal of the file
+                            contributions.remove(&write_file_event.path);
                             result.entries.remove(&write_file_event.path);
                         }
                     }
                 }
-                crate::events::EventType::ReadFile(read_file_event) => {
-                    dependencies.insert(read_file_event.path, read_file_event.hash);
+                // The declaring run owns the file as it was then, just as if it had
+                // written it - so later edits show as changes, not as untracked.
+                crate::events::EventType::DeclareOutput(declare_output_event) => {
+                    contributions
+                        .entry(declare_output_event.path.clone())
+                        .or_default()
+                        .insert(script.clone(), dependencies.clone());
+                    result.entries.insert(
+                        declare_output_event.path,
+                        FileRepresentationFromEvents {
+                            hash: declare_output_event.hash,
+                            dependencies_and_hashes: dependencies.clone(),
+                        },
+                    );
                 }
+                // The content moves with the file, so the new path is tracked just as
+                // the old one was.
+                crate::events::EventType::RenameFile(rename_file_event) => {
+                    let carried = result.entries.remove(&rename_file_event.from);
+                    let carried_contributions = contributions.remove(&rename_file_event.from);
+                    if let Some(hash) = rename_file_event.hash {
+                        contributions.insert(
+                            rename_file_event.to.clone(),
+                            carried_contributions.unwrap_or_else(|| {
+                                BTreeMap::from([(script.clone(), dependencies.clone())])
+                            }),
+                        );
+                        let dependencies_and_hashes = carried
+                            .map(|e| e.dependencies_and_hashes)
+                            .unwrap_or_else(|| dependencies.clone());
+                        result.entries.insert(
+                            rename_file_event.to,
+                            FileRepresentationFromEvents {
+                                hash,
+                                dependencies_and_hashes,
+                            },
+                        );
+                    }
+                }
+                crate::events::EventType::ReadFile(_) => {}
                 crate::events::EventType::GetMetadata(_) => {}
-                crate::events::EventType::SetMetadata(_) => {}
+                crate::events::EventType::SetMetadata(set_metadata_event) => {
+                    if set_metadata_event.key.as_string() == MULTI_WRITER_KEY {
+                        if is_set(&set_metadata_event.after_value) {
+                            multi_writer.insert(set_metadata_event.path);
+                        } else {
+                            multi_writer.remove(&set_metadata_event.path);
+                        }
+                    }
+                }
+                crate::events::EventType::Log(_) => {}
             }
         }
     }
+
+    for path in &multi_writer {
+        if let (Some(entry), Some(by_script)) =
+            (result.entries.get_mut(path), contributions.get(path))
+        {
+            entry.dependencies_and_hashes = by_script.values().flatten().cloned().collect();
+        }
+    }
     Ok(result)
 }
 
+/// True for a flag set to `true`, as a boolean or a string.
+fn is_set(value: &Option<MetadataEntry>) -> bool {
+    value.as_ref().is_some_and(|v| v.as_string() == "true")
+}
+
+/// The files the event log currently considers tracked - i.e. written and not since deleted.
+/// Doesn't touch the filesystem, so it's cheap even for big projects.
+pub fn tracked_files(event_log: &dyn EventLog) -> anyhow::Result<Vec<PathBuf>> {
+    let rep = build_rep_from_event_log(event_log)?;
+    Ok(rep.entries.into_keys().collect())
+}
+
+/// The status of one file, from what the event log says about it (`tracked`) and the
+/// hash of its local copy (`current`). `all_current` holds the local hashes of every
+/// file, for checking its inputs.
+fn compare_file(
+    tracked: Option<&FileRepresentationFromEvents>,
+    current: Option<&ContentHash>,
+    pin: Option<&ContentHash>,
+    all_current: &BTreeMap<PathBuf, ContentHash>,
+) -> FileStatus {
+    match (tracked, current) {
+        // Either a local copy with no entry in the event log, or a file too big to
+        // hash that no script ever wrote.
+        (None, _) => FileStatus::Untracked,
+        (Some(_), None) => {
+            // We have an entry in the event log, but no local copy.
+            // Lets just mark that as deleted
+            FileStatus::Deleted
+        }
+        (Some(_), Some(current)) if pin == Some(current) => {
+            // The user has accepted the current content, whatever the event log says.
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false,
+            }
+        }
+        (Some(tracked), Some(current)) => {
+            // We have both a local copy and a tracked version.
+            // We have to check if it has changes, and if its inputs have changed.
+            let is_changed = tracked.hash != *current;
+            let is_stale = tracked
+                .dependencies_and_hashes
+                .iter()
+                .any(|(dep_path, dep_hash)| all_current.get(dep_path) != dep_hash.as_ref());
+            FileStatus::Present {
+                is_changed,
+                is_stale,
+            }
+        }
+    }
+}
+
+/// The status of the file at `path`, relative to the project root, worked out just as
+/// `get_project_status` would - but only the file and its inputs are hashed.
+///
+/// Ignored files and untracked files over `max_track_size` count as absent, as they
+/// do when scanning the whole project. An ignored file is left out of the project's
+/// status altogether, so on its own it is reported as untracked.
+pub fn get_file_status(
+    event_log: &dyn EventLog,
+    fs: &dyn xfs::Xfs,
+    project_root: &Path,
+    path: &Path,
+    ignore: &IgnoreRules,
+    max_track_size: Option<u64>,
+) -> anyhow::Result<FileStatus> {
+    let mut rep = build_rep_from_event_log(event_log)?;
+    rep.entries
+        .retain(|p, _| !ignore.is_ignored_or_in_ignored_dir(p));
+    let Some(tracked) = rep.entries.get(path) else {
+        return Ok(FileStatus::Untracked);
+    };
+    let limit = TrackSizeLimit {
+        max_size: max_track_size,
+        always_track: rep.entries.keys().cloned().collect(),
+    };
+    let mut current = BTreeMap::new();
+    for p in std::iter::once(path).chain(tracked.dependencies_and_hashes.iter().map(|(p, _)| &**p))
+    {
+        if ignore.is_ignored_or_in_ignored_dir(p) && !is_package_file(p) {
+            continue;
+        }
+        let Some(reader) = fs.reader_if_exists(&project_root.join(p))? else {
+            continue;
+        };
+        if let Some(content) = read_within_limit(reader, limit.max_size_for(p))? {
+            current.insert(p.to_path_buf(), ContentHash::from_content(&content));
+        }
+    }
+    let pin = event_log.get_pin(path)?;
+    Ok(compare_file(
+        Some(tracked),
+        current.get(path),
+        pin.as_ref(),
+        &current,
+    ))
+}
+
 pub fn get_project_status(
     event_log: &dyn EventLog,
     fs: &dyn xfs::Xfs,
     project_root: &Path,
+    ignore: &IgnoreRules,
+    max_track_size: Option<u64>,
+    progress: Option<&dyn ProgressSink>,
 ) -> anyhow::Result<ProjectStatus> {
     let mut file_statuses = vec![];
-    let rep1 = build_rep_from_event_log(event_log)?;
-    let rep2 = build_rep_from_fs(fs, project_root)?;
+    let mut rep1 = build_rep_from_event_log(event_log)?;
+    // Ignored files are left out entirely, rather than showing up as deleted.
+    rep1.entries
+        .retain(|p, _| !ignore.is_ignored_or_in_ignored_dir(p));
+    let limit = TrackSizeLimit {
+        max_size: max_track_size,
+        always_track: rep1.entries.keys().cloned().collect(),
+    };
+    let rep2 = build_rep_from_fs(fs, project_root, ignore, &limit, progress)?;
+    let pins = event_log.get_pins()?;
+    let mut all_current = rep2.entries.clone();
+    let package_files: BTreeSet<&PathBuf> = rep1
+        .entries
+        .values()
+        .flat_map(|e| e.dependencies_and_hashes.iter().map(|(p, _)| p))
+        .filter(|p| is_package_file(p))
+        .collect();
+    for p in package_files {
+        if let Some(mut reader) = fs.reader_if_exists(&project_root.join(p))? {
+            all_current.insert(p.clone(), ContentHash::from_reader(&mut reader)?);
+        }
+    }
 
     let mut all_paths: BTreeSet<&PathBuf> = rep1.entries.keys().collect();
-    for p in rep2.entries.keys() {
+    for p in rep2.entries.keys().chain(&rep2.skipped) {
         all_paths.insert(p);
     }
+    // An unreadable file would otherwise look deleted.
+    for (p, _) in &rep2.problems {
+        all_paths.remove(p);
+    }
 
     for p in all_paths {
-        let e1 = rep1.entries.get(p);
-        let e2 = rep2.entries.get(p);
-        let status = match (e1, e2) {
-            (None, None) => unreachable!(),
-            (Some(_), None) => {
-                // We have an entry in the event log, but no local copy.
-                // Lets just mark that as deleted
-                FileStatus::Deleted
-            }
-            (None, Some(_)) => {
-                // We have a local copy, but it has no entry in the event log.
-                FileStatus::Untracked
-            }
-            (Some(e1), Some(e2)) => {
-                // We have both a local copy and a tracked version.
-                // We have to check if it has changes, and if its inputs have changed.
-                let is_changed = e1.hash != *e2;
-                let mut is_stale = false;
-                for (dep_path, dep_hash) in &e1.dependencies_and_hashes {
-                    if rep2.entries.get(dep_path) != dep_hash.as_ref() {
-                        is_stale = true;
-                        break;
-                    }
-                }
-                FileStatus::Present {
-                    is_changed,
-                    is_stale,
-                }
-            }
-        };
+        let status = compare_file(
+            rep1.entries.get(p),
+            rep2.entries.get(p),
+            pins.get(p),
+            &all_current,
+        );
         file_statuses.push(FileStatusEntry {
             path: p.clone(),
             status,
@@ -175,14 +541,638 @@ pub fn get_project_status(
     };
 
     let mut package_statuses = vec![];
+    let mut package_problems = vec![];
     let packages = package_dir.packages(fs);
     for package in packages {
-        let package = package?;
-        package_statuses.push(package.status(fs));
+        match package {
+            Ok(package) => package_statuses.push(package.status(fs)),
+            Err(e) => package_problems.push(e),
+        }
     }
 
     Ok(ProjectStatus {
         file_statuses,
         package_statuses,
+        package_problems,
+        problems: rep2.problems,
+        skipped: rep2.skipped,
     })
 }
+
+/// A project's file statuses, kept in memory and brought up to date one path at a
+/// time. `get_project_status` rehashes the whole project on every call, which is too
+/// slow for `wrought daemon` to do on every query.
+pub struct IncrementalStatus {
+    project_root: PathBuf,
+    ignore: IgnoreRules,
+    limit: TrackSizeLimit,
+    tracked: ProjectRepresentationFromEvents,
+    pins: BTreeMap<PathBuf, ContentHash>,
+    current: BTreeMap<PathBuf, ContentHash>,
+    skipped: BTreeSet<PathBuf>,
+    /// Files that couldn't be read, with the error reading them gave.
+    problems: BTreeMap<PathBuf, String>,
+}
+
+impl IncrementalStatus {
+    /// Scans the whole project, as `get_project_status` does.
+    pub fn new(
+        event_log: &dyn EventLog,
+        fs: &dyn xfs::Xfs,
+        project_root: &Path,
+        ignore: IgnoreRules,
+        max_track_size: Option<u64>,
+    ) -> anyhow::Result<IncrementalStatus> {
+        let mut result = IncrementalStatus {
+            project_root: project_root.to_path_buf(),
+            ignore,
+            limit: TrackSizeLimit {
+                max_size: max_track_size,
+                always_track: BTreeSet::new(),
+            },
+            tracked: ProjectRepresentationFromEvents {
+                entries: BTreeMap::new(),
+            },
+            pins: BTreeMap::new(),
+            current: BTreeMap::new(),
+            skipped: BTreeSet::new(),
+            problems: BTreeMap::new(),
+        };
+        result.load_event_log(event_log)?;
+        result.path_changed(fs, Path::new(""))?;
+        Ok(result)
+    }
+
+    /// Rereads the event log and pins, after a run or a `pin` changed them.
+    pub fn event_log_changed(
+        &mut self,
+        event_log: &dyn EventLog,
+        fs: &dyn xfs::Xfs,
+    ) -> anyhow::Result<()> {
+        self.load_event_log(event_log)?;
+        // Files a script has now written are hashed whatever their size.
+        let newly_tracked: Vec<_> = self
+            .skipped
+            .iter()
+            .filter(|p| self.limit.always_track.contains(*p))
+            .cloned()
+            .collect();
+        for p in newly_tracked {
+            self.path_changed(fs, &p)?;
+        }
+        Ok(())
+    }
+
+    fn load_event_log(&mut self, event_log: &dyn EventLog) -> anyhow::Result<()> {
+        let mut tracked = build_rep_from_event_log(event_log)?;
+        tracked
+            .entries
+            .retain(|p, _| !self.ignore.is_ignored_or_in_ignored_dir(p));
+        self.limit.always_track = tracked.entries.keys().cloned().collect();
+        self.tracked = tracked;
+        self.pins = event_log.get_pins()?;
+        Ok(())
+    }
+
+    /// Rehashes whatever is now at `path`, relative to the project root. `path` may be
+    /// a file or a directory, and may no longer exist.
+    pub fn path_changed(&mut self, fs: &dyn xfs::Xfs, path: &Path) -> anyhow::Result<()> {
+        if !path.as_os_str().is_empty() && self.ignore.is_ignored_or_in_ignored_dir(path) {
+            return Ok(());
+        }
+        // A directory may have been created, removed or renamed, so forget everything
+        // under it before looking again.
+        self.current.retain(|p, _| !p.starts_with(path));
+        self.skipped.retain(|p| !p.starts_with(path));
+        self.problems.retain(|p, _| !p.starts_with(path));
+
+        let full_path = self.project_root.join(path);
+        let mut files = vec![];
+        if fs.is_dir(&full_path) {
+            collect_files(
+                fs,
+                &self.project_root,
+                &full_path,
+                &self.ignore,
+                &self.limit,
+                &mut files,
+            )?;
+        } else if fs.is_file(&full_path) {
+            files.push((full_path, self.limit.max_size_for(path)));
+        }
+
+        let hashes = hash_files(fs, files, None);
+        let relative = |p: PathBuf| p.strip_prefix(&self.project_root).unwrap().to_path_buf();
+        for (p, hash) in hashes.hashes {
+            self.current.insert(relative(p), hash);
+        }
+        for p in hashes.skipped {
+            self.skipped.insert(relative(p));
+        }
+        for (p, e) in hashes.problems {
+            self.problems.insert(relative(p), format!("{:#}", e));
+        }
+        Ok(())
+    }
+
+    /// The status of the file at `path`, relative to the project root, as of the last
+    /// change we were told about.
+    pub fn file_status(&self, path: &Path) -> anyhow::Result<FileStatus> {
+        if let Some(e) = self.problems.get(path) {
+            bail!("can not read '{}' - {}", path.display(), e);
+        }
+        Ok(compare_file(
+            self.tracked.entries.get(path),
+            self.current.get(path),
+            self.pins.get(path),
+            &self.current,
+        ))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        io::Cursor,
+        path::{Path, PathBuf},
+    };
+
+    use mockall::predicate;
+
+    use crate::{
+        binary16::ContentHash,
+        event_log::test_utils::MockEventLog,
+        event_log::{EventLog, SQLiteEventLog},
+        events::{
+            DeclareOutputEvent, Event, EventGroup, ReadFileEvent, RenameFileEvent,
+            SetMetadataEvent, WriteFileEvent,
+        },
+        metadata::{MetadataEntry, MetadataKey},
+    };
+
+    use super::{
+        build_rep_from_fs, get_file_status, get_project_status, hash_files, tracked_files,
+        FileHashes, FileStatus, TrackSizeLimit, MULTI_WRITER_KEY,
+    };
+    use crate::fs_utils::test_utils::MockFs;
+    use crate::ignore_rules::IgnoreRules;
+    use crate::progress::{test_utils::RecordingProgress, ProgressEvent};
+
+    fn write_event(path: &str, after: Option<&[u8]>) -> Event {
+        Event::from(WriteFileEvent {
+            path: PathBuf::from(path),
+            before_hash: None,
+            after_hash: after.map(ContentHash::from_content),
+        })
+    }
+
+    #[test]
+    pub fn tracked_files_accounts_for_deletions() {
+        let mut event_log = MockEventLog::default();
+        event_log.expect_all_event_groups().returning(|| {
+            let mut g1 = EventGroup::empty();
+            g1.id = 1;
+            g1.events = vec![
+                write_event("b.md", Some(b"b")),
+                write_event("a.md", Some(b"a")),
+                write_event("gone.md", Some(b"gone")),
+            ];
+            let mut g2 = EventGroup::empty();
+            g2.id = 2;
+            g2.events = vec![write_event("gone.md", None)];
+            // Out of order, to check we replay them by id.
+            Ok(vec![g2, g1])
+        });
+
+        let files = tracked_files(&event_log).unwrap();
+        assert_eq!(files, vec![PathBuf::from("a.md"), PathBuf::from("b.md")]);
+    }
+
+    #[test]
+    pub fn renamed_files_are_tracked_at_their_new_path() {
+        let mut event_log = MockEventLog::default();
+        event_log.expect_all_event_groups().returning(|| {
+            let mut g1 = EventGroup::empty();
+            g1.id = 1;
+            g1.events = vec![write_event("a.md", Some(b"a"))];
+            let mut g2 = EventGroup::empty();
+            g2.id = 2;
+            g2.events = vec![Event::from(RenameFileEvent {
+                from: PathBuf::from("a.md"),
+                to: PathBuf::from("b.md"),
+                hash: Some(ContentHash::from_content(b"a")),
+            })];
+            Ok(vec![g1, g2])
+        });
+
+        let files = tracked_files(&event_log).unwrap();
+        assert_eq!(files, vec![PathBuf::from("b.md")]);
+    }
+
+    #[test]
+    pub fn scan_skips_ignored_files() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = PathBuf::from("project");
+        for p in [
+            "a.md",
+            "debug.log",
+            "node_modules/lib/index.js",
+            ".wrought/wrought.db",
+            ".wrought/content/abc",
+        ] {
+            fs.add_r(&root.join(p), b"x".to_vec()).unwrap();
+        }
+
+        let ignore = IgnoreRules::parse("*.log\nnode_modules/\n").unwrap();
+        let progress = RecordingProgress::default();
+        let rep = build_rep_from_fs(
+            &fs,
+            &root,
+            &ignore,
+            &TrackSizeLimit::default(),
+            Some(&progress),
+        )
+        .unwrap();
+        let paths: Vec<_> = rep.entries.keys().cloned().collect();
+        assert_eq!(paths, vec![PathBuf::from("a.md")]);
+        // Ignored files aren't counted.
+        assert_eq!(
+            *progress.events.lock().unwrap(),
+            vec![ProgressEvent::HashingFiles { done: 1, total: 1 }]
+        );
+    }
+
+    #[test]
+    pub fn unreadable_files_dont_stop_hashing() {
+        let mut fs = MockFs::default();
+        for name in ["a.md", "c.md"] {
+            fs.expect_reader()
+                .with(predicate::eq(PathBuf::from(name)))
+                .returning(move |_| Ok(Box::new(Cursor::new(name.as_bytes().to_vec()))));
+        }
+        fs.expect_reader()
+            .with(predicate::eq(PathBuf::from("b.md")))
+            .returning(|_| {
+                Err(xfs::XfsError::UnspecifiedError(
+                    "permission denied".to_string(),
+                ))
+            });
+
+        let files = ["a.md", "b.md", "c.md"]
+            .map(|p| (PathBuf::from(p), None))
+            .to_vec();
+        let FileHashes {
+            hashes, problems, ..
+        } = hash_files(&fs, files, None);
+        assert_eq!(
+            hashes,
+            [
+                (PathBuf::from("a.md"), ContentHash::from_content(b"a.md")),
+                (PathBuf::from("c.md"), ContentHash::from_content(b"c.md")),
+            ]
+            .into()
+        );
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, PathBuf::from("b.md"));
+        assert!(
+            problems[0].1.to_string().contains("permission denied"),
+            "{}",
+            problems[0].1
+        );
+    }
+
+    #[test]
+    pub fn large_untracked_files_are_not_hashed() {
+        use xfs::Xfs;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = PathBuf::from("project");
+        fs.create_dir_all(&root.join(".wrought").join("packages"))
+            .unwrap();
+        fs.add_r(&root.join("small.md"), b"small".to_vec()).unwrap();
+        fs.add_r(&root.join("video.mp4"), vec![0; 100]).unwrap();
+        fs.add_r(&root.join("generated.csv"), vec![1; 100]).unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group
+            .events
+            .push(write_event("generated.csv", Some(&[1; 100])));
+        event_log.add_event_group(&group).unwrap();
+
+        let status = get_project_status(
+            &event_log,
+            &fs,
+            &root,
+            &IgnoreRules::empty(),
+            Some(10),
+            None,
+        )
+        .unwrap();
+        assert_eq!(status.skipped, vec![PathBuf::from("video.mp4")]);
+        let status_of = |name: &str| {
+            &status
+                .file_statuses
+                .iter()
+                .find(|e| e.path == Path::new(name))
+                .unwrap()
+                .status
+        };
+        assert!(matches!(status_of("video.mp4"), FileStatus::Untracked));
+        assert!(matches!(status_of("small.md"), FileStatus::Untracked));
+        // Written by a script, so hashed despite its size.
+        assert!(matches!(
+            status_of("generated.csv"),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+
+        let hashes = hash_files(
+            &fs,
+            vec![
+                (root.join("small.md"), Some(10)),
+                (root.join("video.mp4"), Some(10)),
+            ],
+            None,
+        );
+        assert_eq!(hashes.hashes.len(), 1);
+        assert!(hashes.hashes.contains_key(&root.join("small.md")));
+        assert_eq!(hashes.skipped, vec![root.join("video.mp4")]);
+    }
+
+    #[test]
+    pub fn declared_outputs_are_tracked_without_a_write() {
+        use xfs::Xfs;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = PathBuf::from("project");
+        fs.create_dir_all(&root.join(".wrought").join("packages"))
+            .unwrap();
+        fs.add_r(&root.join("a.md"), b"up to date".to_vec())
+            .unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(DeclareOutputEvent {
+            path: PathBuf::from("a.md"),
+            hash: ContentHash::from_content(b"up to date"),
+        }));
+        event_log.add_event_group(&group).unwrap();
+
+        let status_of_a = |fs: &xfs::mockfs::MockFS| {
+            get_project_status(&event_log, fs, &root, &IgnoreRules::empty(), None, None)
+                .unwrap()
+                .file_statuses
+                .into_iter()
+                .find(|e| e.path == Path::new("a.md"))
+                .unwrap()
+                .status
+        };
+        assert!(matches!(
+            status_of_a(&fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+
+        fs.writer(&root.join("a.md"))
+            .unwrap()
+            .write_all(b"hand edited")
+            .unwrap();
+        assert!(matches!(
+            status_of_a(&fs),
+            FileStatus::Present {
+                is_changed: true,
+                is_stale: false
+            }
+        ));
+    }
+
+    #[test]
+    pub fn outputs_depend_on_the_package_files_they_read() {
+        use std::io::Write;
+        use xfs::Xfs;
+
+        let root = PathBuf::from("project");
+        let template = ".wrought/packages/site/page.html";
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(&root.join(template), b"v1".to_vec()).unwrap();
+        fs.add_r(&root.join("out.html"), b"out".to_vec()).unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events = vec![
+            Event::from(ReadFileEvent {
+                path: PathBuf::from(template),
+                hash: Some(ContentHash::from_content(b"v1")),
+            }),
+            write_event("out.html", Some(b"out")),
+        ];
+        event_log.add_event_group(&group).unwrap();
+
+        let statuses = |fs: &xfs::mockfs::MockFS| {
+            let ignore = IgnoreRules::empty();
+            let project = get_project_status(&event_log, fs, &root, &ignore, None, None).unwrap();
+            let in_project = project
+                .file_statuses
+                .into_iter()
+                .find(|e| e.path == Path::new("out.html"))
+                .unwrap()
+                .status;
+            let single =
+                get_file_status(&event_log, fs, &root, Path::new("out.html"), &ignore, None)
+                    .unwrap();
+            (in_project, single)
+        };
+        let fresh = FileStatus::Present {
+            is_changed: false,
+            is_stale: false,
+        };
+        assert_eq!(statuses(&fs), (fresh.clone(), fresh));
+
+        fs.writer(&root.join(template))
+            .unwrap()
+            .write_all(b"v2")
+            .unwrap();
+        let stale = FileStatus::Present {
+            is_changed: false,
+            is_stale: true,
+        };
+        assert_eq!(statuses(&fs), (stale.clone(), stale));
+    }
+
+    #[test]
+    pub fn multi_writer_files_are_stale_when_any_writer_is() {
+        use xfs::Xfs;
+
+        let root = PathBuf::from("project");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.create_dir_all(&root.join(".wrought").join("packages"))
+            .unwrap();
+        fs.add_r(&root.join("a.md"), b"a v2".to_vec()).unwrap();
+        fs.add_r(&root.join("b.md"), b"b".to_vec()).unwrap();
+        fs.add_r(&root.join("out.md"), b"all".to_vec()).unwrap();
+
+        let read = |path: &str, content: &[u8]| {
+            Event::from(ReadFileEvent {
+                path: PathBuf::from(path),
+                hash: Some(ContentHash::from_content(content)),
+            })
+        };
+        let run = |command: &str, events: Vec<Event>| {
+            let mut group = EventGroup::empty();
+            group.command = command.to_string();
+            group.events = events;
+            group
+        };
+        let flag = |value: Option<&str>| {
+            let mut group = EventGroup::empty();
+            group.events.push(Event::from(SetMetadataEvent {
+                path: PathBuf::from("out.md"),
+                key: MetadataKey::from(MULTI_WRITER_KEY),
+                before_value: None,
+                after_value: value.map(MetadataEntry::from),
+            }));
+            group
+        };
+        let status_of_out = |event_log: &SQLiteEventLog, fs: &xfs::mockfs::MockFS| {
+            get_project_status(event_log, fs, &root, &IgnoreRules::empty(), None, None)
+                .unwrap()
+                .file_statuses
+                .into_iter()
+                .find(|e| e.path == Path::new("out.md"))
+                .unwrap()
+                .status
+        };
+        // The header script read an older a.md than is there now; the body script, which
+        // wrote last, is up to date.
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let writes = [
+            run(
+                "run-script header.luau",
+                vec![
+                    read("a.md", b"a v1"),
+                    write_event("out.md", Some(b"header")),
+                ],
+            ),
+            run(
+                "run-script body.luau # second pass",
+                vec![read("b.md", b"b"), write_event("out.md", Some(b"all"))],
+            ),
+        ];
+        for group in &writes {
+            event_log.add_event_group(group).unwrap();
+        }
+        assert!(matches!(
+            status_of_out(&event_log, &fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+
+        event_log.add_event_group(&flag(Some("true"))).unwrap();
+        assert!(matches!(
+            status_of_out(&event_log, &fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: true
+            }
+        ));
+
+        // Re-running the header script replaces its earlier contribution.
+        event_log
+            .add_event_group(&run(
+                "run-script header.luau",
+                vec![read("a.md", b"a v2"), write_event("out.md", Some(b"all"))],
+            ))
+            .unwrap();
+        assert!(matches!(
+            status_of_out(&event_log, &fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+
+        // Clearing the flag goes back to the last run alone.
+        fs.writer(&root.join("b.md"))
+            .unwrap()
+            .write_all(b"b v2")
+            .unwrap();
+        assert!(matches!(
+            status_of_out(&event_log, &fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: true
+            }
+        ));
+        event_log.add_event_group(&flag(None)).unwrap();
+        assert!(matches!(
+            status_of_out(&event_log, &fs),
+            FileStatus::Present {
+                is_changed: false,
+                is_stale: false
+            }
+        ));
+    }
+
+    #[test]
+    pub fn pinned_files_are_ok_while_they_match_the_pin() {
+        use xfs::Xfs;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = PathBuf::from("project");
+        fs.create_dir_all(&root.join(".wrought").join("packages"))
+            .unwrap();
+        fs.add_r(&root.join("a.md"), b"generated".to_vec()).unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(write_event("a.md", Some(b"generated")));
+        event_log.add_event_group(&group).unwrap();
+
+        let status_of_a = |event_log: &SQLiteEventLog, fs: &xfs::mockfs::MockFS| {
+            let status =
+                get_project_status(event_log, fs, &root, &IgnoreRules::empty(), None, None)
+                    .unwrap();
+            let entry = status
+                .file_statuses
+                .into_iter()
+                .find(|e| e.path == Path::new("a.md"))
+                .unwrap();
+            match entry.status {
+                FileStatus::Present { is_changed, .. } => is_changed,
+                s => panic!("unexpected status {:?}", s),
+            }
+        };
+
+        assert!(!status_of_a(&event_log, &fs));
+
+        fs.writer(&root.join("a.md"))
+            .unwrap()
+            .write_all(b"hand edited")
+            .unwrap();
+        assert!(status_of_a(&event_log, &fs));
+
+        event_log
+            .set_pin(
+                &PathBuf::from("a.md"),
+                Some(ContentHash::from_content(b"hand edited")),
+            )
+            .unwrap();
+        assert!(!status_of_a(&event_log, &fs));
+
+        fs.writer(&root.join("a.md"))
+            .unwrap()
+            .write_all(b"edited again")
+            .unwrap();
+        assert!(status_of_a(&event_log, &fs));
+
+        event_log.set_pin(&PathBuf::from("a.md"), None).unwrap();
+        assert_eq!(event_log.get_pins().unwrap().len(), 0);
+    }
+}
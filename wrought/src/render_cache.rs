@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{binary16::ContentHash, bridge::Bridge};
+
+/// Hash domain for render cache keys, keeping them apart from file content hashes.
+const RENDER_CACHE_DOMAIN: &str = "template-render";
+
+/// The most renders a cache keeps in memory. Older ones are still found in the store.
+pub const MAX_RECENT_RENDERS: usize = 64;
+
+/// Reuses the output of templates already rendered with the same context. Rendering
+/// is deterministic, so a batch run that renders many items through a few templates
+/// only needs to render each distinct one once.
+///
+/// The output is kept in the content store, and which key gave which output in the
+/// project's render cache index, so later runs reuse it too. The latest renders are
+/// also kept in memory, so repeats within a run don't go back to the store.
+#[derive(Debug, Default)]
+pub struct RenderCache {
+    recent: BTreeMap<ContentHash, String>,
+    /// The keys in `recent`, oldest first.
+    order: VecDeque<ContentHash>,
+}
+
+impl RenderCache {
+    /// Covers every template's source, not just the one rendered, as that can include
+    /// or extend the others.
+    pub fn key(
+        sources: &BTreeMap<String, String>,
+        template: &str,
+        context: &serde_json::Value,
+    ) -> ContentHash {
+        // Sizes first, so no choice of sources can run one into the next.
+        let mut input = vec![];
+        for (name, source) in sources {
+            input.extend(format!("{}:{}:{}:{}\n", name.len(), name, source.len(), source).bytes());
+        }
+        input.extend(format!("{}:{}\n{}", template.len(), template, context).bytes());
+        ContentHash::from_content_tagged(RENDER_CACHE_DOMAIN, &input)
+    }
+
+    /// The cached output for `template` rendered with `context`, or else what `render`
+    /// gives, which is cached for next time.
+    pub fn render(
+        &mut self,
+        bridge: &mut dyn Bridge,
+        sources: &BTreeMap<String, String>,
+        template: &str,
+        context: &serde_json::Value,
+        render: impl FnOnce() -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let key = Self::key(sources, template, context);
+        if let Some(rendered) = self.recent.get(&key) {
+            return Ok(rendered.clone());
+        }
+        // The cache is only an optimisation. An entry that can't be read (e.g. its
+        // output was removed by a gc) just means rendering again, and one that can't be
+        // kept (e.g. in a read-only run) means rendering every time.
+        let rendered = match bridge.cached_render(&key) {
+            Ok(Some(rendered)) => rendered,
+            _ => {
+                let rendered = render()?;
+                let _ = bridge.cache_render(&key, &rendered);
+                rendered
+            }
+        };
+        self.remember(key, rendered.clone());
+        Ok(rendered)
+    }
+
+    fn remember(&mut self, key: ContentHash, rendered: String) {
+        if self.recent.insert(key.clone(), rendered).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_RECENT_RENDERS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.recent.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        cell::Cell,
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::json;
+
+    use super::{RenderCache, MAX_RECENT_RENDERS};
+    use crate::{
+        backend::test_utils::MockBackend, binary16::ContentHash, bridge::tests::test_bridge,
+    };
+
+    type Store = Arc<Mutex<BTreeMap<ContentHash, Vec<u8>>>>;
+
+    /// A backend whose content store and render cache index are `store` and `index`.
+    fn backend_with(
+        store: &Store,
+        index: &Arc<Mutex<BTreeMap<ContentHash, ContentHash>>>,
+    ) -> MockBackend {
+        let mut backend = MockBackend::default();
+        let s = store.clone();
+        backend.expect_store_content().returning(move |value| {
+            let hash = ContentHash::from_content(value);
+            s.lock().unwrap().insert(hash.clone(), value.to_vec());
+            Ok(hash)
+        });
+        let s = store.clone();
+        backend
+            .expect_read_stored_content()
+            .returning(move |hash| Ok(s.lock().unwrap().get(hash).cloned()));
+        let i = index.clone();
+        backend
+            .expect_set_render_cache_entry()
+            .returning(move |key, output| {
+                i.lock().unwrap().insert(key.clone(), output.clone());
+                Ok(())
+            });
+        let i = index.clone();
+        backend
+            .expect_get_render_cache_entry()
+            .returning(move |key| Ok(i.lock().unwrap().get(key).cloned()));
+        backend
+    }
+
+    #[test]
+    pub fn the_same_template_and_context_is_rendered_once() {
+        let stored = Store::default();
+        let index = Arc::default();
+        let sources = BTreeMap::from([("page".to_string(), "Hi {{ name }}".to_string())]);
+        let renders = Cell::new(0);
+        let render = |cache: &mut RenderCache, context: serde_json::Value| {
+            let mut bridge = test_bridge(backend_with(&stored, &index), BTreeMap::new());
+            cache
+                .render(&mut bridge, &sources, "page", &context, || {
+                    renders.set(renders.get() + 1);
+                    Ok(format!("Hi {}", context["name"].as_str().unwrap()))
+                })
+                .unwrap()
+        };
+
+        let mut cache = RenderCache::default();
+        assert_eq!(render(&mut cache, json!({"name": "Ann"})), "Hi Ann");
+        assert_eq!(render(&mut cache, json!({"name": "Ann"})), "Hi Ann");
+        assert_eq!(renders.get(), 1);
+        // A different context is a different key.
+        assert_eq!(render(&mut cache, json!({"name": "Bob"})), "Hi Bob");
+        assert_eq!(renders.get(), 2);
+        assert_eq!(stored.lock().unwrap().len(), 2);
+
+        // A later run finds them in the store.
+        let mut cache = RenderCache::default();
+        assert_eq!(render(&mut cache, json!({"name": "Bob"})), "Hi Bob");
+        assert_eq!(renders.get(), 2);
+
+        // Output gone from the store is rendered again.
+        stored.lock().unwrap().clear();
+        let mut cache = RenderCache::default();
+        assert_eq!(render(&mut cache, json!({"name": "Ann"})), "Hi Ann");
+        assert_eq!(renders.get(), 3);
+
+        // As is a change to any template.
+        let mut changed = sources.clone();
+        changed.insert("footer".to_string(), "Bye".to_string());
+        assert_ne!(
+            RenderCache::key(&sources, "page", &json!({})),
+            RenderCache::key(&changed, "page", &json!({}))
+        );
+    }
+
+    #[test]
+    pub fn only_the_latest_renders_are_kept_in_memory() {
+        // Nothing can be stored, so everything not in memory is rendered again.
+        let mut backend = MockBackend::default();
+        backend
+            .expect_get_render_cache_entry()
+            .returning(|_| Ok(None));
+        backend
+            .expect_store_content()
+            .returning(|_| Err(anyhow::anyhow!("read-only")));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        let sources = BTreeMap::new();
+        let renders = Cell::new(0);
+        let mut cache = RenderCache::default();
+        let mut render = |n: usize| {
+            cache
+                .render(&mut bridge, &sources, "page", &json!(n), || {
+                    renders.set(renders.get() + 1);
+                    Ok(n.to_string())
+                })
+                .unwrap()
+        };
+
+        for n in 0..=MAX_RECENT_RENDERS {
+            render(n);
+        }
+        assert_eq!(renders.get(), MAX_RECENT_RENDERS + 1);
+        render(MAX_RECENT_RENDERS);
+        assert_eq!(renders.get(), MAX_RECENT_RENDERS + 1);
+        // The oldest was dropped.
+        render(0);
+        assert_eq!(renders.get(), MAX_RECENT_RENDERS + 2);
+    }
+}
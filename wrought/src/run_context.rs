@@ -0,0 +1,229 @@
+use crate::{
+    clock::Clock,
+    event_log::EventLog,
+    events::{Event, EventGroup},
+};
+
+/// How much of what a run prints is kept. Past this only the number of bytes left out is.
+pub const MAX_OUTPUT: usize = 64 * 1024;
+
+/// One run of a script - the events it generates, collected into a group of their own
+/// and recorded once it has finished. Each bridge has its own, so runs in the same
+/// process, even at the same time on different threads, never see each other's events.
+#[derive(Debug)]
+pub struct RunContext {
+    pub event_group: EventGroup,
+    output: Vec<u8>,
+    output_dropped: usize,
+}
+
+impl RunContext {
+    /// `command` is what the run is recorded as, e.g. `run-script blog/build.luau`.
+    pub fn new(command: Option<&str>) -> RunContext {
+        let mut event_group = EventGroup::empty();
+        if let Some(command) = command {
+            event_group.command = command.to_string();
+        }
+        RunContext {
+            event_group,
+            output: vec![],
+            output_dropped: 0,
+        }
+    }
+
+    /// The run is timed from its first event.
+    pub fn add_event(&mut self, event: Event, clock: &dyn Clock) {
+        if self.event_group.created_at.is_none() {
+            self.event_group.created_at = Some(clock.now());
+        }
+        self.event_group.events.push(event);
+    }
+
+    /// Keeps what the script printed, up to `MAX_OUTPUT` bytes.
+    pub fn add_output(&mut self, output: &[u8]) {
+        let kept = output.len().min(MAX_OUTPUT - self.output.len());
+        self.output.extend_from_slice(&output[..kept]);
+        self.output_dropped += output.len() - kept;
+    }
+
+    /// What the script has printed since this was last called, with a note at the end
+    /// if some was left out. `None` if it printed nothing.
+    pub fn take_output(&mut self) -> Option<Vec<u8>> {
+        if self.output.is_empty() {
+            return None;
+        }
+        let mut output = std::mem::take(&mut self.output);
+        if self.output_dropped > 0 {
+            if !output.ends_with(b"\n") {
+                output.push(b'\n');
+            }
+            output.extend_from_slice(
+                format!(
+                    "[output truncated: {} more bytes]\n",
+                    std::mem::take(&mut self.output_dropped)
+                )
+                .as_bytes(),
+            );
+        }
+        Some(output)
+    }
+
+    /// What the run has done so far, or `None` if it hasn't done anything.
+    pub fn event_group(&self) -> Option<EventGroup> {
+        if self.event_group.events.is_empty() {
+            return None;
+        }
+        Some(self.event_group.clone())
+    }
+
+    /// Adds the run's events to `event_log` as a new group, returning it as recorded.
+    /// A run that did nothing isn't recorded. The events are taken, so recording again
+    /// only adds what has happened since.
+    pub fn record(&mut self, event_log: &mut dyn EventLog) -> anyhow::Result<Option<EventGroup>> {
+        let Some(group) = self.event_group() else {
+            return Ok(None);
+        };
+        let recorded = event_log.add_event_group(&group)?;
+        self.event_group.events.clear();
+        self.event_group.created_at = None;
+        self.event_group.output = None;
+        Ok(Some(recorded))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use xfs::Xfs;
+
+    use super::{RunContext, MAX_OUTPUT};
+    use crate::{
+        backend::{test_utils::MockBackend, BackendOptions, SimpleBackend},
+        bridge::{tests::test_bridge, Bridge},
+        content_store::{ContentStore, FileSystemContentStore},
+        event_log::{EventLog, SQLiteEventLog},
+        events::EventType,
+        scripting_luau,
+    };
+
+    #[test]
+    pub fn concurrent_runs_are_recorded_separately() {
+        let event_log = Arc::new(Mutex::new(SQLiteEventLog::open_in_memory().unwrap()));
+        let runs: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let event_log = event_log.clone();
+                std::thread::spawn(move || {
+                    let script = PathBuf::from(format!("project/{}.luau", name));
+                    let mut fs = xfs::mockfs::MockFS::new();
+                    fs.add_r(
+                        &script,
+                        format!(r#"for i = 1, 20 do log("{}" .. i) end"#, name).into_bytes(),
+                    )
+                    .unwrap();
+
+                    let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+                    bridge.event_log = event_log;
+                    bridge.run.event_group.command = format!("run-script {}.luau", name);
+                    let bridge = Arc::new(Mutex::new(bridge));
+                    scripting_luau::run_script(bridge.clone(), Arc::new(Mutex::new(fs)), &script)
+                        .unwrap();
+                    let mut bridge = bridge.lock().unwrap();
+                    let recorded = bridge.record_run().unwrap().unwrap();
+                    // Recording takes the events.
+                    assert!(bridge.get_event_group().is_none());
+                    assert!(bridge.record_run().unwrap().is_none());
+                    recorded.id
+                })
+            })
+            .collect();
+        let ids: Vec<u64> = runs.into_iter().map(|r| r.join().unwrap()).collect();
+        assert_ne!(ids[0], ids[1]);
+
+        let groups = event_log.lock().unwrap().all_event_groups().unwrap();
+        assert_eq!(groups.len(), 2);
+        for group in groups {
+            let name = group
+                .command
+                .strip_prefix("run-script ")
+                .and_then(|c| c.strip_suffix(".luau"))
+                .unwrap()
+                .to_string();
+            assert_eq!(group.events.len(), 20);
+            for event in &group.events {
+                let EventType::Log(log) = &event.event_type else {
+                    panic!("unexpected event {:?}", event);
+                };
+                assert!(
+                    log.message.starts_with(&name),
+                    "{} in {}",
+                    log.message,
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn printed_output_is_kept_with_the_recorded_group() {
+        let root = PathBuf::from("project");
+        let script = root.join("build.luau");
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.create_dir_all(&root.join(".wrought/content")).unwrap();
+        fs.add_r(
+            &script,
+            b"print('building', 3, nil)\nlog('done')\nprint('bye')\n".to_vec(),
+        )
+        .unwrap();
+        let fs = Arc::new(Mutex::new(fs));
+        let content_store = Arc::new(Mutex::new(FileSystemContentStore::new(
+            fs.clone(),
+            root.join(".wrought/content"),
+        )));
+        let event_log = Arc::new(Mutex::new(SQLiteEventLog::open_in_memory().unwrap()));
+
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        bridge.backend = Arc::new(Mutex::new(SimpleBackend {
+            fs: fs.clone(),
+            os_fs: false,
+            root: root.clone(),
+            content_store: content_store.clone(),
+            options: BackendOptions::default(),
+        }));
+        bridge.fs = fs.clone();
+        bridge.event_log = event_log.clone();
+        let bridge = Arc::new(Mutex::new(bridge));
+        scripting_luau::run_script(bridge.clone(), fs, &script).unwrap();
+        let id = bridge.lock().unwrap().record_run().unwrap().unwrap().id;
+
+        let group = event_log.lock().unwrap().get_event_group(id).unwrap();
+        let output = group.unwrap().output.expect("the group should have output");
+        let output = content_store.lock().unwrap().retrieve(output).unwrap();
+        assert_eq!(
+            String::from_utf8(output.unwrap()).unwrap(),
+            "building\t3\tnil\nbye\n"
+        );
+    }
+
+    #[test]
+    pub fn chatty_runs_have_their_output_truncated() {
+        let mut run = RunContext::new(None);
+        assert_eq!(run.take_output(), None);
+        run.add_output(&vec![b'x'; MAX_OUTPUT - 1]);
+        run.add_output(b"abc");
+        run.add_output(b"de");
+
+        let output = run.take_output().unwrap();
+        let (kept, note) = output.split_at(MAX_OUTPUT);
+        assert!(kept.ends_with(b"xa"));
+        assert_eq!(note, b"\n[output truncated: 4 more bytes]\n");
+        // Taking the output starts afresh.
+        run.add_output(b"more");
+        assert_eq!(run.take_output().unwrap(), b"more");
+    }
+}
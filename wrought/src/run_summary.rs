@@ -0,0 +1,209 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::PathBuf,
+};
+
+use serde::Serialize;
+
+use crate::events::{EventGroup, EventType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChange {
+    Created,
+    Modified,
+    Deleted,
+}
+
+impl FileChange {
+    fn marker(&self) -> char {
+        match self {
+            FileChange::Created => '+',
+            FileChange::Modified => '~',
+            FileChange::Deleted => '-',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct MetadataChange {
+    pub path: PathBuf,
+    pub key: String,
+}
+
+/// What a script run did, grouped by operation, built from the events it recorded.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub written: BTreeMap<PathBuf, FileChange>,
+    pub read: BTreeSet<PathBuf>,
+    pub metadata_set: BTreeSet<MetadataChange>,
+    /// What a plugin handed back with `wrought_set_result`, if anything.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+impl RunSummary {
+    pub fn from_event_group(group: &EventGroup) -> RunSummary {
+        let mut summary = RunSummary::default();
+        // Overall change per file, from its first before_hash and last after_hash.
+        let mut writes = BTreeMap::new();
+        for event in &group.events {
+            match &event.event_type {
+                EventType::WriteFile(e) => {
+                    let entry = writes
+                        .entry(e.path.clone())
+                        .or_insert((e.before_hash.is_none(), false));
+                    entry.1 = e.after_hash.is_none();
+                }
+                // The old path is deleted and the new one created, as far as a
+                // summary is concerned.
+                EventType::RenameFile(e) => {
+                    writes.entry(e.from.clone()).or_insert((false, false)).1 = true;
+                    writes.entry(e.to.clone()).or_insert((true, false)).1 = false;
+                }
+                EventType::ReadFile(e) => {
+                    summary.read.insert(e.path.clone());
+                }
+                EventType::SetMetadata(e) => {
+                    summary.metadata_set.insert(MetadataChange {
+                        path: e.path.clone(),
+                        key: e.key.as_string(),
+                    });
+                }
+                EventType::GetMetadata(_) | EventType::Log(_) | EventType::DeclareOutput(_) => {}
+            }
+        }
+        summary.written = writes
+            .into_iter()
+            .map(|(path, (created, deleted))| {
+                let change = match (created, deleted) {
+                    (true, false) => FileChange::Created,
+                    (false, true) => FileChange::Deleted,
+                    _ => FileChange::Modified,
+                };
+                (path, change)
+            })
+            .collect();
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.written.is_empty()
+            && self.read.is_empty()
+            && self.metadata_set.is_empty()
+            && self.result.is_none()
+    }
+}
+
+fn plural(n: usize, what: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, what)
+    } else {
+        format!("{} {}s", n, what)
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No files were touched");
+        }
+        if !self.written.is_empty() {
+            writeln!(f, "Wrote {}:", plural(self.written.len(), "file"))?;
+            for (path, change) in &self.written {
+                writeln!(f, "  {} {}", change.marker(), path.display())?;
+            }
+        }
+        if !self.read.is_empty() {
+            writeln!(f, "Read {}:", plural(self.read.len(), "file"))?;
+            for path in &self.read {
+                writeln!(f, "    {}", path.display())?;
+            }
+        }
+        if !self.metadata_set.is_empty() {
+            let n = self.metadata_set.len();
+            writeln!(f, "Set {}:", plural(n, "metadata value"))?;
+            for change in &self.metadata_set {
+                writeln!(f, "    {}: {}", change.path.display(), change.key)?;
+            }
+        }
+        if let Some(result) = &self.result {
+            let result = serde_json::to_string_pretty(result).map_err(|_| std::fmt::Error)?;
+            writeln!(f, "Result:")?;
+            for line in result.lines() {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use mockall::predicate;
+
+    use crate::{
+        backend::test_utils::MockBackend, binary16::ContentHash, bridge::tests::test_bridge,
+        bridge::Bridge, scripting_luau,
+    };
+
+    use super::{FileChange, RunSummary};
+
+    #[test]
+    pub fn summary_lists_scripted_reads_and_writes() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        fs.add_r(
+            &PathBuf::from("script.luau"),
+            br#"
+            local input = read_file("input.md")
+            write_file("output.md", input .. "!")
+            write_file("output.md", input .. "!!")
+            "#
+            .to_vec(),
+        )
+        .unwrap();
+
+        let mut backend = MockBackend::default();
+        backend
+            .expect_read_file()
+            .with(predicate::eq(Path::new("input.md")))
+            .returning(|_| Ok(Some((ContentHash::from_content(b"hi"), b"hi".to_vec()))));
+        let mut previous = None;
+        backend
+            .expect_write_file_with_options()
+            .returning(move |_, value, _| {
+                let hash = ContentHash::from_content(value);
+                Ok((previous.replace(hash.clone()), hash))
+            });
+
+        let bridge = Arc::new(Mutex::new(test_bridge(backend, BTreeMap::new())));
+        scripting_luau::run_script(
+            bridge.clone(),
+            Arc::new(Mutex::new(fs)),
+            Path::new("script.luau"),
+        )
+        .unwrap();
+
+        let group = bridge.lock().unwrap().get_event_group().unwrap();
+        let summary = RunSummary::from_event_group(&group);
+        assert_eq!(
+            summary.written,
+            BTreeMap::from([(PathBuf::from("output.md"), FileChange::Created)])
+        );
+        assert_eq!(
+            summary.read.iter().collect::<Vec<_>>(),
+            vec![Path::new("input.md")]
+        );
+
+        let text = summary.to_string();
+        assert!(text.contains("+ output.md"), "{}", text);
+        assert!(text.contains("input.md"), "{}", text);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -6,37 +7,78 @@ use std::sync::Mutex;
 use mlua::prelude::*;
 use mlua::Lua;
 
-use crate::bridge::Bridge;
-use crate::luau_json::lua_table_to_json;
-
-// pub fn lua_print(_lua: &Lua, vals: MultiValue) -> mlua::Result<()> {
-//     println!(
-//         "Lua: {}",
-//         vals.iter()
-//             .map(|v| v.to_string())
-//             .collect::<Result<Vec<_>, _>>()
-//             .map(|v| v.join(" "))
-//             .unwrap()
-//     );
-//     Ok(())
-// }
+use crate::backend::WriteOptions;
+use crate::binary16::ContentHash;
+use crate::bridge::{Bridge, PreviousRun, ScriptFailure};
+use crate::events::LogLevel;
+use crate::luau_json::{self, lua_table_to_json, lua_value_to_json_value};
+use crate::project_status::FileStatus;
+use crate::render_cache::RenderCache;
+
+/// Replaces Lua's `print` - the line is printed as usual, and also kept with the run.
+pub fn lua_print(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    lua: &Lua,
+    values: LuaMultiValue,
+) -> anyhow::Result<()> {
+    let tostring: LuaFunction = lua.globals().get("tostring")?;
+    let mut line = values
+        .into_iter()
+        .map(|v| {
+            Ok(tostring
+                .call::<_, LuaString>(v)?
+                .to_string_lossy()
+                .into_owned())
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .join("\t");
+    println!("{}", line);
+    line.push('\n');
+    bridge.lock().unwrap().add_output(line.as_bytes())
+}
 
 pub fn convert_error(e: anyhow::Error) -> mlua::Error {
-    mlua::Error::runtime(format!("{}", e))
+    // `{:#}` includes the context, e.g. which bridge call failed.
+    mlua::Error::runtime(format!("{:#}", e))
 }
 
 pub fn lua_write_file(
     bridge: Arc<Mutex<dyn Bridge>>,
     _lua: &Lua,
-    (file_name, value): (String, String),
+    (file_name, value, options): (String, String, Option<LuaTable>),
 ) -> anyhow::Result<()> {
-    bridge
-        .lock()
-        .unwrap()
-        .write_file(&PathBuf::from(file_name), value.as_bytes())?;
+    let path = PathBuf::from(file_name);
+    let mut bridge = bridge.lock().unwrap();
+    match options {
+        // e.g. write_file("a.md", content, { trailing_newline = true })
+        Some(options) => {
+            let options = WriteOptions {
+                ensure_trailing_newline: options.get("trailing_newline")?,
+            };
+            bridge.write_file_with_options(&path, value.as_bytes(), &options)?;
+        }
+        None => bridge.write_file(&path, value.as_bytes())?,
+    }
     Ok(())
 }
 
+/// e.g. `write_file_with_metadata("a.html", content, { content_type = "text/html" })`
+pub fn lua_write_file_with_metadata(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (file_name, value, metadata): (String, String, LuaTable),
+) -> anyhow::Result<()> {
+    let serde_json::Value::Object(metadata) = lua_table_to_json(metadata, false)? else {
+        anyhow::bail!("metadata must be a table of key = value pairs");
+    };
+    let metadata = metadata.into_iter().collect();
+    bridge.lock().unwrap().write_file_with_metadata(
+        &PathBuf::from(file_name),
+        value.as_bytes(),
+        &metadata,
+    )
+}
+
 pub fn lua_read_file(
     bridge: Arc<Mutex<dyn Bridge>>,
     _lua: &Lua,
@@ -53,6 +95,67 @@ pub fn lua_read_file(
     Ok(Some(result))
 }
 
+/// Reads without recording a dependency - see `Bridge::read_file_untracked`.
+pub fn lua_read_file_untracked(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<Option<String>> {
+    let result = bridge
+        .lock()
+        .unwrap()
+        .read_file_untracked(&PathBuf::from(file_name))?;
+    let Some(result) = result else {
+        return Ok(None);
+    };
+    let result = String::from_utf8(result)?;
+    Ok(Some(result))
+}
+
+pub fn lua_read_package_file(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<Option<String>> {
+    let result = bridge
+        .lock()
+        .unwrap()
+        .read_package_file(&PathBuf::from(file_name))?;
+    let Some(result) = result else {
+        return Ok(None);
+    };
+    let result = String::from_utf8(result)?;
+    Ok(Some(result))
+}
+
+pub fn lua_read_lines(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<Option<Vec<String>>> {
+    bridge.lock().unwrap().read_lines(&PathBuf::from(file_name))
+}
+
+/// Returns `current, previous` - see `Bridge::read_tracked_input`.
+pub fn lua_read_tracked_input(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<(Option<String>, Option<String>)> {
+    let input = bridge
+        .lock()
+        .unwrap()
+        .read_tracked_input(&PathBuf::from(file_name))?;
+    let Some(input) = input else {
+        return Ok((None, None));
+    };
+    let previous = input.previous.map(String::from_utf8).transpose()?;
+    Ok((Some(String::from_utf8(input.current)?), previous))
+}
+
+/// Always a string (or nil) - values that aren't strings come back as JSON, so
+/// `set_metadata(p, k, 42)` then `get_metadata(p, k)` gives `"42"`. Use
+/// `get_metadata_typed` to get the number back.
 pub fn lua_get_metadata(
     bridge: Arc<Mutex<dyn Bridge>>,
     _lua: &Lua,
@@ -65,16 +168,56 @@ pub fn lua_get_metadata(
     Ok(result)
 }
 
+/// A JSON value handed to Lua as the matching Lua type.
+pub struct JsonForLua(serde_json::Value);
+
+impl<'lua> IntoLua<'lua> for JsonForLua {
+    fn into_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        luau_json::json_value_to_lua_value(lua, &self.0).map_err(LuaError::external)
+    }
+}
+
+/// The value with the type it was set with - a number, boolean, table or string.
+pub fn lua_get_metadata_typed(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (file_name, key): (String, String),
+) -> anyhow::Result<Option<JsonForLua>> {
+    let result = bridge
+        .lock()
+        .unwrap()
+        .get_metadata_typed(&PathBuf::from(file_name), &key)?;
+    Ok(result.map(JsonForLua))
+}
+
+/// The value can be a string, number, boolean or table. Empty tables are stored as
+/// objects, as with `json_encode`.
 pub fn lua_set_metadata(
     bridge: Arc<Mutex<dyn Bridge>>,
     _lua: &Lua,
-    (file_name, key, value): (String, String, String),
+    (file_name, key, value): (String, String, LuaValue),
 ) -> anyhow::Result<()> {
-    bridge
+    let path = PathBuf::from(file_name);
+    let mut bridge = bridge.lock().unwrap();
+    match value {
+        LuaValue::Nil => anyhow::bail!("metadata values can't be nil"),
+        LuaValue::String(s) => bridge.set_metadata(&path, &key, s.to_str()?)?,
+        value => bridge.set_metadata_typed(&path, &key, lua_value_to_json_value(value, false)?)?,
+    }
+    Ok(())
+}
+
+/// `set_metadata_glob(pattern, key, value)` returns the paths it set the key on.
+pub fn lua_set_metadata_glob(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (pattern, key, value): (String, String, String),
+) -> anyhow::Result<Vec<String>> {
+    let paths = bridge
         .lock()
         .unwrap()
-        .set_metadata(&PathBuf::from(file_name), &key, &value)?;
-    Ok(())
+        .set_metadata_glob(&pattern, &key, &value)?;
+    Ok(paths.iter().map(|p| p.display().to_string()).collect())
 }
 
 pub fn lua_ai_query(
@@ -86,18 +229,36 @@ pub fn lua_ai_query(
 }
 
 struct LuaTemplater {
+    bridge: Arc<Mutex<dyn Bridge>>,
     tera: tera::Tera,
+    /// Every template's source, for keying the render cache.
+    sources: BTreeMap<String, String>,
+    /// Only once the script has called `enable_cache`.
+    cache: Option<RenderCache>,
 }
 
 impl LuaTemplater {
     pub fn add_template(&mut self, key: String, value: String) -> anyhow::Result<()> {
         self.tera.add_raw_template(&key, &value)?;
+        self.sources.insert(key, value);
         Ok(())
     }
-    pub fn render_template(&self, key: String, table: mlua::Table) -> anyhow::Result<String> {
+    pub fn render_template(&mut self, key: String, table: mlua::Table) -> anyhow::Result<String> {
         let value = lua_table_to_json(table, true)?;
-        let context = tera::Context::from_value(value)?;
-        Ok(self.tera.render(&key, &context)?)
+        let render = |value: serde_json::Value| -> anyhow::Result<String> {
+            let context = tera::Context::from_value(value)?;
+            Ok(self.tera.render(&key, &context)?)
+        };
+        match &mut self.cache {
+            Some(cache) => cache.render(
+                &mut *self.bridge.lock().unwrap(),
+                &self.sources,
+                &key,
+                &value,
+                || render(value.clone()),
+            ),
+            None => render(value),
+        }
     }
 }
 
@@ -106,7 +267,12 @@ impl LuaUserData for LuaTemplater {
         methods.add_method_mut("add_template", |_, this, (key, value): (String, String)| {
             this.add_template(key, value).map_err(convert_error)
         });
-        methods.add_method(
+        // Opt in to reusing output for a template and context rendered before.
+        methods.add_method_mut("enable_cache", |_, this, ()| {
+            this.cache.get_or_insert_with(RenderCache::default);
+            Ok(())
+        });
+        methods.add_method_mut(
             "render_template",
             |_, this, (key, context): (String, mlua::Table)| {
                 this.render_template(key, context).map_err(convert_error)
@@ -115,13 +281,201 @@ impl LuaUserData for LuaTemplater {
     }
 }
 
-fn lua_template(
+pub fn lua_log(bridge: Arc<Mutex<dyn Bridge>>, _lua: &Lua, message: String) -> anyhow::Result<()> {
+    bridge.lock().unwrap().log(LogLevel::Info, &message)
+}
+
+pub fn lua_warn(bridge: Arc<Mutex<dyn Bridge>>, _lua: &Lua, message: String) -> anyhow::Result<()> {
+    bridge.lock().unwrap().log(LogLevel::Warn, &message)
+}
+
+/// `fail(message, exit_code?)` - the exit code defaults to 1.
+pub fn lua_fail(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (message, exit_code): (String, Option<i32>),
+) -> anyhow::Result<()> {
+    let failure = ScriptFailure {
+        message,
+        exit_code: exit_code.unwrap_or(1),
+    };
+    bridge.lock().unwrap().fail(failure.clone())?;
+    // Unwinds the script. The failure itself is reported once the script has stopped.
+    Err(failure.into())
+}
+
+pub fn lua_set_status(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (name, content): (String, String),
+) -> anyhow::Result<()> {
+    bridge.lock().unwrap().set_status(&name, &content)
+}
+
+pub fn lua_set_run_description(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    description: String,
+) -> anyhow::Result<()> {
+    bridge.lock().unwrap().set_run_description(&description)
+}
+
+pub fn lua_move_file(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (from, to): (String, String),
+) -> anyhow::Result<()> {
+    bridge
+        .lock()
+        .unwrap()
+        .move_file(&PathBuf::from(from), &PathBuf::from(to))
+}
+
+pub fn lua_declare_output(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<()> {
+    bridge
+        .lock()
+        .unwrap()
+        .declare_output(&PathBuf::from(file_name))
+}
+
+pub fn lua_temp_dir(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    _params: (),
+) -> anyhow::Result<String> {
+    let dir = bridge.lock().unwrap().temp_dir()?;
+    Ok(dir.to_string_lossy().into_owned())
+}
+
+pub fn lua_get_config(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    key: String,
+) -> anyhow::Result<Option<String>> {
+    bridge.lock().unwrap().get_config(&key)
+}
+
+/// Returns a table like `{ tracked = true, exists = true, changed = false, stale = true }`.
+pub fn lua_file_status(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<BTreeMap<&'static str, bool>> {
+    let status = bridge
+        .lock()
+        .unwrap()
+        .file_status(&PathBuf::from(file_name))?;
+    let (tracked, exists, changed, stale) = match status {
+        FileStatus::Untracked => (false, true, false, false),
+        FileStatus::Deleted => (true, false, false, false),
+        FileStatus::Present {
+            is_changed,
+            is_stale,
+        } => (true, true, is_changed, is_stale),
+    };
+    Ok(BTreeMap::from([
+        ("tracked", tracked),
+        ("exists", exists),
+        ("changed", changed),
+        ("stale", stale),
+    ]))
+}
+
+impl<'lua> IntoLua<'lua> for PreviousRun {
+    fn into_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        let table = lua.create_table()?;
+        table.set("id", self.id)?;
+        table.set("command", self.command)?;
+        table.set(
+            "created_at",
+            self.created_at
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        )?;
+        let outputs: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        table.set("outputs", outputs)?;
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Earlier runs of this script, or of commands starting with `command_prefix` if given.
+/// Each is a table `{ id, command, created_at, outputs }`, oldest first.
+pub fn lua_previous_runs(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    command_prefix: Option<String>,
+) -> anyhow::Result<Vec<PreviousRun>> {
+    bridge
+        .lock()
+        .unwrap()
+        .previous_runs(command_prefix.as_deref())
+}
+
+/// The hash wrought last wrote to the file, or nil if it isn't tracked.
+pub fn lua_last_hash(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    file_name: String,
+) -> anyhow::Result<Option<String>> {
+    bridge.lock().unwrap().last_hash(&PathBuf::from(file_name))
+}
+
+/// The same hash wrought uses for file content. Lua strings are byte strings, so this
+/// works for binary data too.
+pub fn lua_content_hash(
     _bridge: Arc<Mutex<dyn Bridge>>,
     _lua: &Lua,
+    content: LuaString,
+) -> anyhow::Result<String> {
+    Ok(ContentHash::from_content(content.as_bytes()).to_string())
+}
+
+/// `json_encode(value, [empty_is_array])`. An empty table could be meant as either an
+/// array or an object, so it is encoded as `{}` unless `empty_is_array` is true. A
+/// table made by `empty_array()` is always `[]`.
+pub fn lua_json_encode(
+    _bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    (value, empty_is_array): (LuaValue, Option<bool>),
+) -> anyhow::Result<String> {
+    let value = lua_value_to_json_value(value, empty_is_array.unwrap_or(false))?;
+    Ok(serde_json::to_string(&value)?)
+}
+
+/// What `empty_array()` gives scripts - see `luau_json::empty_array`.
+pub struct EmptyArray;
+
+impl<'lua> IntoLua<'lua> for EmptyArray {
+    fn into_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        Ok(LuaValue::Table(luau_json::empty_array(lua)?))
+    }
+}
+
+pub fn lua_empty_array(
+    _bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
+    _params: (),
+) -> anyhow::Result<EmptyArray> {
+    Ok(EmptyArray)
+}
+
+fn lua_template(
+    bridge: Arc<Mutex<dyn Bridge>>,
+    _lua: &Lua,
     _params: (),
 ) -> anyhow::Result<LuaTemplater> {
     Ok(LuaTemplater {
+        bridge,
         tera: tera::Tera::default(),
+        sources: BTreeMap::new(),
+        cache: None,
     })
 }
 
@@ -137,13 +491,38 @@ where
     R: IntoLuaMulti<'lua>,
 {
     let globals = lua.globals();
+    let function_name = name.to_string();
     globals.set(
         name,
-        lua.create_function(move |l, v| f(bridge.clone(), l, v).map_err(convert_error))?,
+        lua.create_function(move |l, args: LuaMultiValue<'lua>| {
+            // Describe the call before the arguments are consumed, so errors can say
+            // which call failed - e.g. "write_file('out/x.md'): permission denied".
+            let call = describe_call(&function_name, &args);
+            let v = A::from_lua_multi(args, l)?;
+            f(bridge.clone(), l, v).map_err(|e| convert_error(e.context(call)))
+        })?,
     )?;
     Ok(())
 }
 
+/// `name('first argument')`. Only the first argument is shown - for bridge functions
+/// it's the path or key - and long ones are cut short, as it may be file content.
+fn describe_call(name: &str, args: &LuaMultiValue) -> String {
+    const MAX_ARG_LEN: usize = 60;
+    let first = match args.iter().next() {
+        Some(LuaValue::String(s)) => {
+            let s = s.to_string_lossy();
+            match s.char_indices().nth(MAX_ARG_LEN) {
+                Some((i, _)) => format!("'{}...'", &s[..i]),
+                None => format!("'{}'", s),
+            }
+        }
+        Some(v) => v.type_name().to_string(),
+        None => String::new(),
+    };
+    format!("{}({})", name, first)
+}
+
 pub fn run_script(
     bridge: Arc<Mutex<dyn Bridge>>,
     fs: Arc<Mutex<dyn xfs::Xfs>>,
@@ -166,16 +545,71 @@ where
 
     lua.sandbox(true)?;
 
-    // Replace print with our own function.
-    // let globals = lua.globals();
-    // let print = lua.create_function(lua_print)?;
-    // globals.set("print", print)?;
+    // Replace print with our own function, so what the script prints is kept.
+    add_bridge_function(bridge.clone(), &lua, "print", lua_print)?;
     add_bridge_function(bridge.clone(), &lua, "write_file", lua_write_file)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "write_file_with_metadata",
+        lua_write_file_with_metadata,
+    )?;
     add_bridge_function(bridge.clone(), &lua, "read_file", lua_read_file)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "read_file_untracked",
+        lua_read_file_untracked,
+    )?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "read_package_file",
+        lua_read_package_file,
+    )?;
+    add_bridge_function(bridge.clone(), &lua, "read_lines", lua_read_lines)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "read_tracked_input",
+        lua_read_tracked_input,
+    )?;
     add_bridge_function(bridge.clone(), &lua, "set_metadata", lua_set_metadata)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "set_metadata_glob",
+        lua_set_metadata_glob,
+    )?;
     add_bridge_function(bridge.clone(), &lua, "get_metadata", lua_get_metadata)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "get_metadata_typed",
+        lua_get_metadata_typed,
+    )?;
     add_bridge_function(bridge.clone(), &lua, "ai_query", lua_ai_query)?;
     add_bridge_function(bridge.clone(), &lua, "wrought_template", lua_template)?;
+    add_bridge_function(bridge.clone(), &lua, "log", lua_log)?;
+    add_bridge_function(bridge.clone(), &lua, "warn", lua_warn)?;
+    add_bridge_function(bridge.clone(), &lua, "fail", lua_fail)?;
+    add_bridge_function(bridge.clone(), &lua, "get_config", lua_get_config)?;
+    add_bridge_function(bridge.clone(), &lua, "set_status", lua_set_status)?;
+    add_bridge_function(
+        bridge.clone(),
+        &lua,
+        "set_run_description",
+        lua_set_run_description,
+    )?;
+    add_bridge_function(bridge.clone(), &lua, "temp_dir", lua_temp_dir)?;
+    add_bridge_function(bridge.clone(), &lua, "declare_output", lua_declare_output)?;
+    add_bridge_function(bridge.clone(), &lua, "move_file", lua_move_file)?;
+    add_bridge_function(bridge.clone(), &lua, "file_status", lua_file_status)?;
+    add_bridge_function(bridge.clone(), &lua, "previous_runs", lua_previous_runs)?;
+    add_bridge_function(bridge.clone(), &lua, "last_hash", lua_last_hash)?;
+    add_bridge_function(bridge.clone(), &lua, "content_hash", lua_content_hash)?;
+    add_bridge_function(bridge.clone(), &lua, "json_encode", lua_json_encode)?;
+    add_bridge_function(bridge.clone(), &lua, "empty_array", lua_empty_array)?;
 
     f(&lua)?;
 
@@ -185,13 +619,43 @@ where
         .reader(script_path)?
         .read_to_string(&mut script)?;
 
-    lua.load(script).exec()?;
+    // Naming the chunk after the script means errors read `path/to/script.luau:3: ...`
+    let chunk = lua
+        .load(script)
+        .set_name(format!("@{}", script_path.display()))
+        .into_function()?;
+    exec_with_traceback(&lua, chunk)
+}
+
+/// Runs `f`, turning any error into one that includes the Lua stack traceback.
+///
+/// By the time an error gets back to rust the Lua stack has been unwound, so we
+/// need to grab the traceback from inside an `xpcall` message handler.
+fn exec_with_traceback(lua: &Lua, f: LuaFunction) -> anyhow::Result<()> {
+    let runner: LuaFunction = lua
+        .load(
+            r#"
+            local f = ...
+            return xpcall(f, function(e) return debug.traceback(tostring(e), 2) end)
+            "#,
+        )
+        .set_name("=wrought")
+        .into_function()?;
+    let (ok, err): (bool, LuaValue) = runner.call(f)?;
+    if !ok {
+        let message = match err {
+            LuaValue::String(s) => s.to_string_lossy().into_owned(),
+            v => format!("{:?}", v),
+        };
+        anyhow::bail!("lua script failed: {}", message);
+    }
     Ok(())
 }
 
 #[cfg(test)]
-mod tests {
-    use crate::events::EventGroup;
+pub mod tests {
+    use crate::bridge::TrackedInput;
+    use crate::events::{EventGroup, LogLevel};
 
     use super::*;
     use anyhow::anyhow;
@@ -204,11 +668,38 @@ mod tests {
 
         impl Bridge for Bridge {
             fn write_file(&mut self, path: &Path, value: &[u8]) -> anyhow::Result<()>;
+            fn write_file_with_options(&mut self, path: &Path, value: &[u8], options: &WriteOptions) -> anyhow::Result<()>;
+            fn write_file_with_metadata(&mut self, path: &Path, value: &[u8], metadata: &BTreeMap<String, serde_json::Value>) -> anyhow::Result<()>;
             fn read_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+            fn read_file_untracked(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+            fn read_package_file(&mut self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+            fn move_file(&mut self, from: &Path, to: &Path) -> anyhow::Result<()>;
+            fn read_lines(&mut self, path: &Path) -> anyhow::Result<Option<Vec<String>>>;
+            fn read_tracked_input(&mut self, path: &Path) -> anyhow::Result<Option<TrackedInput>>;
+            fn file_status(&mut self, path: &Path) -> anyhow::Result<FileStatus>;
+            fn last_hash(&mut self, path: &Path) -> anyhow::Result<Option<String>>;
             fn get_metadata(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<String>>;
+            fn get_metadata_typed(&mut self, path: &Path, key: &str) -> anyhow::Result<Option<serde_json::Value>>;
             fn set_metadata(&mut self, path: &Path, key: &str, value: &str) -> anyhow::Result<()>;
+            fn set_metadata_typed(&mut self, path: &Path, key: &str, value: serde_json::Value) -> anyhow::Result<()>;
+            fn set_metadata_glob(&mut self, pattern: &str, key: &str, value: &str) -> anyhow::Result<Vec<PathBuf>>;
+            fn all_metadata(&mut self) -> anyhow::Result<BTreeMap<PathBuf, BTreeMap<String, String>>>;
             fn ai_query(&mut self, query: &str) -> anyhow::Result<String>;
+            fn log(&mut self, level: LogLevel, message: &str) -> anyhow::Result<()>;
+            fn add_output(&mut self, output: &[u8]) -> anyhow::Result<()>;
+            fn set_status(&mut self, name: &str, content: &str) -> anyhow::Result<()>;
+            fn get_config(&mut self, key: &str) -> anyhow::Result<Option<String>>;
             fn get_event_group(&self) -> Option<EventGroup>;
+            fn record_run(&mut self) -> anyhow::Result<Option<EventGroup>>;
+            fn declare_output(&mut self, path: &Path) -> anyhow::Result<()>;
+            fn temp_dir(&mut self) -> anyhow::Result<PathBuf>;
+            fn previous_runs<'a>(&mut self, command_prefix: Option<&'a str>) -> anyhow::Result<Vec<PreviousRun>>;
+            fn set_run_description(&mut self, description: &str) -> anyhow::Result<()>;
+            fn store_content(&mut self, content: &[u8]) -> anyhow::Result<ContentHash>;
+            fn cached_render(&mut self, key: &ContentHash) -> anyhow::Result<Option<String>>;
+            fn cache_render(&mut self, key: &ContentHash, rendered: &str) -> anyhow::Result<()>;
+            fn fail(&mut self, failure: ScriptFailure) -> anyhow::Result<()>;
+            fn failure(&self) -> Option<ScriptFailure>;
         }
     }
 
@@ -304,6 +795,42 @@ mod tests {
         mock_bridge.lock().unwrap().checkpoint();
     }
 
+    #[test]
+    pub fn run_script_write_file_with_options() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            br#"write_file("someplace/foo.txt", "some content", { trailing_newline = true })"#
+                .to_vec(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        mock_bridge
+            .expect_write_file_with_options()
+            .with(
+                predicate::eq(PathBuf::from("someplace/foo.txt")),
+                predicate::eq(b"some content".to_vec()),
+                predicate::eq(WriteOptions {
+                    ensure_trailing_newline: Some(true),
+                }),
+            )
+            .returning(|_, _, _| Ok(()));
+
+        let mock_bridge = Arc::new(Mutex::new(mock_bridge));
+        let fs = Arc::new(Mutex::new(fs));
+
+        run_script(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+        )
+        .unwrap();
+
+        mock_bridge.lock().unwrap().checkpoint();
+    }
+
     #[test]
     pub fn run_script_read_file() {
         let mut fs = xfs::mockfs::MockFS::new();
@@ -392,7 +919,325 @@ mod tests {
             fs,
             &PathBuf::from("somedir/script.luau"),
         );
-        assert!(result.is_err());
+        let message = format!("{:#}", result.unwrap_err());
+        assert!(
+            message.contains("read_file('someplace/foo.txt'): Read Failure"),
+            "{}",
+            message
+        );
+
+        mock_bridge.lock().unwrap().checkpoint();
+    }
+
+    #[test]
+    pub fn long_arguments_are_cut_short_in_errors() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let long_path = "x".repeat(100);
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            format!(r#"write_file("{}", "content")"#, long_path).into_bytes(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        mock_bridge
+            .expect_write_file()
+            .returning(|_, _| Err(anyhow!("permission denied")));
+
+        let result = run_script(
+            Arc::new(Mutex::new(mock_bridge)),
+            Arc::new(Mutex::new(fs)),
+            &PathBuf::from("somedir/script.luau"),
+        );
+        let message = format!("{:#}", result.unwrap_err());
+        let expected = format!("write_file('{}...'): permission denied", "x".repeat(60));
+        assert!(message.contains(&expected), "{}", message);
+    }
+
+    #[test]
+    pub fn lua_errors_include_traceback() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                "local function inner_function()",
+                "    error(\"something went wrong\")",
+                "end",
+                "local function outer_function()",
+                "    inner_function()",
+                "end",
+                "outer_function()",
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mock_bridge = Arc::new(Mutex::new(MockBridge::new()));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let e = run_script(mock_bridge, fs, &PathBuf::from("somedir/script.luau")).unwrap_err();
+        let message = e.to_string();
+        assert!(message.contains("something went wrong"), "{}", message);
+        assert!(message.contains("script.luau:2"), "{}", message);
+        assert!(message.contains("inner_function"), "{}", message);
+        assert!(message.contains("outer_function"), "{}", message);
+    }
+
+    #[test]
+    pub fn run_script_read_lines() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                r#"local lines = read_lines("someplace/foo.txt")"#,
+                r#"push_test_value(tostring(#lines))"#,
+                r#"for _, line in ipairs(lines) do push_test_value(line) end"#,
+                r#"push_test_value(tostring(read_lines("missing.txt")))"#,
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        mock_bridge
+            .expect_read_lines()
+            .with(predicate::eq(PathBuf::from("someplace/foo.txt")))
+            .returning(|_| Ok(Some(vec!["one".to_string(), "two".to_string()])));
+        mock_bridge
+            .expect_read_lines()
+            .with(predicate::eq(PathBuf::from("missing.txt")))
+            .returning(|_| Ok(None));
+
+        let mock_bridge = Arc::new(Mutex::new(mock_bridge));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let test_values = Arc::new(Mutex::new(vec![]));
+        let test_values_copy = test_values.clone();
+        run_script_ex(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+            |l| add_test_helpers(l, test_values_copy),
+        )
+        .unwrap();
+        assert_eq!(
+            test_values.lock().unwrap().clone(),
+            vec!["2", "one", "two", "nil"]
+        );
+
+        mock_bridge.lock().unwrap().checkpoint();
+    }
+
+    #[test]
+    pub fn run_script_file_status() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                r#"local s = file_status("out.md")"#,
+                r#"push_test_value(tostring(s.tracked) .. " " .. tostring(s.changed) .. " " .. tostring(s.stale))"#,
+                r#"push_test_value(tostring(file_status("notes.md").tracked))"#,
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        mock_bridge
+            .expect_file_status()
+            .with(predicate::eq(PathBuf::from("out.md")))
+            .returning(|_| {
+                Ok(FileStatus::Present {
+                    is_changed: false,
+                    is_stale: true,
+                })
+            });
+        mock_bridge
+            .expect_file_status()
+            .with(predicate::eq(PathBuf::from("notes.md")))
+            .returning(|_| Ok(FileStatus::Untracked));
+
+        let mock_bridge = Arc::new(Mutex::new(mock_bridge));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let test_values = Arc::new(Mutex::new(vec![]));
+        let test_values_copy = test_values.clone();
+        run_script_ex(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+            |l| add_test_helpers(l, test_values_copy),
+        )
+        .unwrap();
+        assert_eq!(
+            test_values.lock().unwrap().clone(),
+            vec!["true false true", "false"]
+        );
+
+        mock_bridge.lock().unwrap().checkpoint();
+    }
+
+    #[test]
+    pub fn run_script_last_hash() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                r#"push_test_value(last_hash("out.md"))"#,
+                r#"push_test_value(tostring(last_hash("notes.md")))"#,
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        mock_bridge
+            .expect_last_hash()
+            .with(predicate::eq(PathBuf::from("out.md")))
+            .returning(|_| Ok(Some("AAAAAAAAAAAAAAAAAAAAAA==".to_string())));
+        mock_bridge
+            .expect_last_hash()
+            .with(predicate::eq(PathBuf::from("notes.md")))
+            .returning(|_| Ok(None));
+
+        let mock_bridge = Arc::new(Mutex::new(mock_bridge));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let test_values = Arc::new(Mutex::new(vec![]));
+        let test_values_copy = test_values.clone();
+        run_script_ex(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+            |l| add_test_helpers(l, test_values_copy),
+        )
+        .unwrap();
+        assert_eq!(
+            test_values.lock().unwrap().clone(),
+            vec!["AAAAAAAAAAAAAAAAAAAAAA==", "nil"]
+        );
+
+        mock_bridge.lock().unwrap().checkpoint();
+    }
+
+    #[test]
+    pub fn run_script_content_hash() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                r#"push_test_value(content_hash("some content"))"#,
+                r#"push_test_value(content_hash("\0\255"))"#,
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mock_bridge = Arc::new(Mutex::new(MockBridge::new()));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let test_values = Arc::new(Mutex::new(vec![]));
+        let test_values_copy = test_values.clone();
+        run_script_ex(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+            |l| add_test_helpers(l, test_values_copy),
+        )
+        .unwrap();
+        assert_eq!(
+            test_values.lock().unwrap().clone(),
+            vec![
+                ContentHash::from_content(b"some content").to_string(),
+                ContentHash::from_content(&[0, 255]).to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn run_script_json_encode() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            [
+                r#"push_test_value(json_encode({}))"#,
+                r#"push_test_value(json_encode({}, false))"#,
+                r#"push_test_value(json_encode({}, true))"#,
+                r#"push_test_value(json_encode({ tags = empty_array(), meta = {} }))"#,
+                r#"push_test_value(json_encode(empty_array(), false))"#,
+                r#"push_test_value(json_encode({ 1, 2 }))"#,
+            ]
+            .join("\n")
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mock_bridge = Arc::new(Mutex::new(MockBridge::new()));
+        let fs = Arc::new(Mutex::new(fs));
+
+        let test_values = Arc::new(Mutex::new(vec![]));
+        let test_values_copy = test_values.clone();
+        run_script_ex(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+            |l| add_test_helpers(l, test_values_copy),
+        )
+        .unwrap();
+        assert_eq!(
+            test_values.lock().unwrap().clone(),
+            vec!["{}", "{}", "[]", r#"{"meta":{},"tags":[]}"#, "[]", "[1,2]"]
+        );
+    }
+
+    #[test]
+    pub fn run_script_log() {
+        let mut fs = xfs::mockfs::MockFS::new();
+
+        fs.add_r(
+            &PathBuf::from("somedir/script.luau"),
+            br#"log("starting") warn("no title found")"#.to_vec(),
+        )
+        .unwrap();
+
+        let mut mock_bridge = MockBridge::new();
+        let mut seq = mockall::Sequence::new();
+        mock_bridge
+            .expect_log()
+            .with(predicate::eq(LogLevel::Info), predicate::eq("starting"))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        mock_bridge
+            .expect_log()
+            .with(
+                predicate::eq(LogLevel::Warn),
+                predicate::eq("no title found"),
+            )
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+
+        let mock_bridge = Arc::new(Mutex::new(mock_bridge));
+        let fs = Arc::new(Mutex::new(fs));
+
+        run_script(
+            mock_bridge.clone(),
+            fs,
+            &PathBuf::from("somedir/script.luau"),
+        )
+        .unwrap();
 
         mock_bridge.lock().unwrap().checkpoint();
     }
@@ -1,15 +1,23 @@
 use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use bytes::Bytes;
-use wasmtime::{Caller, Config, Engine, Linker, Module, Store};
+use wasmtime::{Caller, Config, Engine, ExternType, Linker, Module, Store, ValType};
 use wasmtime_wasi::preview1::{self, WasiP1Ctx};
-use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamResult, Subscribe, WasiCtxBuilder};
-use wrought_wasm_bindings::WroughtResult;
+use wasmtime_wasi::{
+    HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe, WasiCtxBuilder,
+};
+use wrought_wasm_bindings::{
+    PluginManifest, WroughtResult, MANIFEST_SECTION, PLUGIN_ERROR, PLUGIN_OK,
+};
 
-use crate::bridge::Bridge;
+use crate::binary16::ContentHash;
+use crate::bridge::{Bridge, ScriptFailure};
+use crate::events::LogLevel;
+use crate::render_cache::RenderCache;
 
 // In your host code:
 #[derive(Debug)]
@@ -22,11 +30,209 @@ enum WasmError {
 const ERROR_TYPE_NORMAL: i32 = 1;
 const ERROR_TYPE_PANIC: i32 = 2;
 
+/// A plugin ran to completion but returned something other than `PLUGIN_OK`.
+/// This is the error inside the `anyhow::Error` returned by `run_script`, so callers
+/// can get at the code with `downcast_ref::<PluginExitError>()`.
+#[derive(Debug)]
+pub struct PluginExitError {
+    pub code: i32,
+    /// Whatever the plugin reported via `host_report_error`.
+    pub errors: Vec<String>,
+}
+
+impl std::fmt::Display for PluginExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.errors.is_empty()) {
+            (PLUGIN_ERROR, false) => write!(f, "WASM execution failed:")?,
+            (PLUGIN_ERROR, true) => write!(
+                f,
+                "WASM execution failed, but the plugin didn't report an error"
+            )?,
+            (code, _) => write!(
+                f,
+                "WASM execution failed with unexpected return code {} (expected {} or {})",
+                code, PLUGIN_OK, PLUGIN_ERROR
+            )?,
+        }
+        for e in &self.errors {
+            write!(f, "\n{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PluginExitError {}
+
+/// What the host gives every plugin: stdio (inherited WASI streams) and the
+/// wrought bridge functions. There's no WASI filesystem or network access.
+pub const GRANTED_CAPABILITIES: &[&str] = &["stdio", "bridge"];
+
+fn read_leb128_u32(bytes: &[u8], pos: &mut usize) -> anyhow::Result<u32> {
+    let mut result: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(*pos).context("truncated wasm module")?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    bail!("invalid LEB128 value in wasm module")
+}
+
+/// Returns the (name, payload) of each custom section in a wasm binary.
+fn custom_sections(bytes: &[u8]) -> anyhow::Result<Vec<(&str, &[u8])>> {
+    if bytes.len() < 8 || &bytes[0..4] != b"\0asm" {
+        bail!("not a wasm module");
+    }
+    let mut result = vec![];
+    let mut pos = 8;
+    while pos < bytes.len() {
+        let id = bytes[pos];
+        pos += 1;
+        let size = read_leb128_u32(bytes, &mut pos)? as usize;
+        let end = pos
+            .checked_add(size)
+            .filter(|end| *end <= bytes.len())
+            .context("truncated wasm section")?;
+        if id == 0 {
+            let mut p = pos;
+            let name_len = read_leb128_u32(bytes, &mut p)? as usize;
+            let name_end = p
+                .checked_add(name_len)
+                .filter(|e| *e <= end)
+                .context("truncated wasm custom section name")?;
+            let name = std::str::from_utf8(&bytes[p..name_end])?;
+            result.push((name, &bytes[name_end..end]));
+        }
+        pos = end;
+    }
+    Ok(result)
+}
+
+/// Reads the plugin's manifest, if it has one (see `MANIFEST_SECTION`).
+pub fn read_manifest(module_bytes: &[u8]) -> anyhow::Result<Option<PluginManifest>> {
+    for (name, payload) in custom_sections(module_bytes)? {
+        if name == MANIFEST_SECTION {
+            let manifest = serde_json::from_slice(payload).context("invalid plugin manifest")?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+pub fn check_capabilities(manifest: &PluginManifest) -> anyhow::Result<()> {
+    let missing: Vec<&str> = manifest
+        .capabilities
+        .iter()
+        .map(|c| c.as_str())
+        .filter(|c| !GRANTED_CAPABILITIES.contains(c))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "plugin '{}' requires capabilities that are not granted: {}",
+            manifest.name,
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Directory, within a package, whose files are made available to every template
+/// instance a plugin creates.
+pub const SHARED_TEMPLATE_DIR: &str = "templates";
+
 pub struct AppState {
     pub bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
-    pub templating: BTreeMap<i32, tera::Tera>,
-    pub next_template_id: i32,
+    /// Templates provided by the host, which every new template instance starts with.
+    pub shared_templates: TemplateInstance,
+    pub templating: TemplateInstances,
     pub call_buffer: wasmcb::CallBuffer,
+    /// Set by the plugin with `wrought_set_result`.
+    pub result: Option<serde_json::Value>,
+}
+
+/// The most template instances a plugin can have alive at once.
+pub const MAX_LIVE_TEMPLATES: usize = 1024;
+
+/// Tera doesn't keep the source of its templates, which the render cache's key is
+/// made from, so an instance keeps them alongside.
+#[derive(Default)]
+pub struct TemplateInstance {
+    pub tera: tera::Tera,
+    pub sources: BTreeMap<String, String>,
+    /// Only once the plugin has called `wrought_enable_render_cache`.
+    pub cache: Option<RenderCache>,
+}
+
+impl TemplateInstance {
+    pub fn add_templates(&mut self, templates: Vec<(String, String)>) -> anyhow::Result<()> {
+        self.tera.add_raw_templates(templates.clone())?;
+        self.sources.extend(templates);
+        Ok(())
+    }
+}
+
+/// The template instances a plugin has created, by id. Dropped ids are reused, so ids
+/// stay below `MAX_LIVE_TEMPLATES` however many instances a plugin creates over time.
+/// Unknown ids are errors rather than panics - they come from the plugin, and a buggy
+/// plugin shouldn't be able to crash the host.
+#[derive(Default)]
+pub struct TemplateInstances {
+    instances: BTreeMap<i32, TemplateInstance>,
+    free_ids: Vec<i32>,
+    next_id: i32,
+}
+
+impl TemplateInstances {
+    pub fn insert(&mut self, instance: TemplateInstance) -> WroughtResult<i32> {
+        if self.instances.len() >= MAX_LIVE_TEMPLATES {
+            return Err(format!(
+                "too many templates - a plugin can only have {} at once",
+                MAX_LIVE_TEMPLATES
+            ));
+        }
+        let id = match self.free_ids.pop() {
+            Some(id) => id,
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                id
+            }
+        };
+        self.instances.insert(id, instance);
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: i32) -> WroughtResult<()> {
+        match self.instances.remove(&id) {
+            Some(_) => {
+                self.free_ids.push(id);
+                Ok(())
+            }
+            None => Err(format!("unknown template id {}", id)),
+        }
+    }
+
+    pub fn get(&self, id: i32) -> WroughtResult<&TemplateInstance> {
+        self.instances
+            .get(&id)
+            .ok_or_else(|| format!("unknown template id {}", id))
+    }
+
+    pub fn get_mut(&mut self, id: i32) -> WroughtResult<&mut TemplateInstance> {
+        self.instances
+            .get_mut(&id)
+            .ok_or_else(|| format!("unknown template id {}", id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
 }
 
 pub struct CombinedContext(AppState, WasiP1Ctx);
@@ -41,20 +247,100 @@ impl wasmcb::ProvidesCallBuffer for CombinedContext {
     }
 }
 
+fn collect_template_files(
+    fs: &dyn xfs::Xfs,
+    dir: &Path,
+    prefix: &str,
+    templates: &mut Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    fs.on_each_entry(dir, &mut |fs, entry| {
+        let path = entry.path();
+        let name = format!(
+            "{}{}",
+            prefix,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        if entry.metadata()?.is_dir() {
+            collect_template_files(fs, &path, &format!("{}/", name), templates)
+        } else {
+            let mut content = String::new();
+            fs.reader(&path)?
+                .read_to_string(&mut content)
+                .with_context(|| format!("template {:?} is not valid UTF-8", path))?;
+            templates.push((name, content));
+            Ok(())
+        }
+    })
+}
+
+/// Loads the package's shared templates from `SHARED_TEMPLATE_DIR`. Templates are
+/// named by their path relative to that directory, e.g. `layouts/base.html`.
+pub fn load_shared_templates(
+    fs: &dyn xfs::Xfs,
+    package_dir: &Path,
+) -> anyhow::Result<TemplateInstance> {
+    let template_dir = package_dir.join(SHARED_TEMPLATE_DIR);
+    let mut shared = TemplateInstance::default();
+    if !fs.is_dir(&template_dir) {
+        return Ok(shared);
+    }
+    let mut templates = vec![];
+    collect_template_files(fs, &template_dir, "", &mut templates)?;
+    shared
+        .add_templates(templates)
+        .with_context(|| format!("invalid shared template in {:?}", template_dir))?;
+    Ok(shared)
+}
+
+/// A fresh template instance, pre-populated with the shared templates.
+fn new_template_instance(shared: &TemplateInstance) -> anyhow::Result<TemplateInstance> {
+    let mut instance = TemplateInstance::default();
+    instance.tera.extend(&shared.tera)?;
+    instance.sources = shared.sources.clone();
+    Ok(instance)
+}
+
+/// Replaces whatever template `key` the instance has with the shared one.
+fn use_shared_template(
+    instance: &mut TemplateInstance,
+    shared: &TemplateInstance,
+    key: &str,
+) -> anyhow::Result<()> {
+    let template = shared
+        .tera
+        .templates
+        .get(key)
+        .with_context(|| format!("no shared template named '{}'", key))?;
+    instance
+        .tera
+        .templates
+        .insert(key.to_string(), template.clone());
+    instance.tera.build_inheritance_chains()?;
+    instance
+        .sources
+        .insert(key.to_string(), shared.sources[key].clone());
+    Ok(())
+}
+
+/// Returns whatever the plugin set with `wrought_set_result`.
 pub fn run_script(
     bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
     fs: Arc<Mutex<dyn xfs::Xfs>>,
     script_path: &Path,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<serde_json::Value>> {
     run_script_ex(bridge, fs, script_path, |_| Ok(()))
 }
 
+/// The plugin's stdout or stderr. What it writes is passed through, and kept with the
+/// run via `Bridge::add_output`.
 struct CustomHostOutputStream {
-    buffer: Arc<Mutex<Vec<u8>>>,
+    bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
+    is_stderr: bool,
 }
 
 struct CustomStdout {
-    buffer: Arc<Mutex<Vec<u8>>>,
+    bridge: Arc<Mutex<dyn Bridge + Send + 'static>>,
+    is_stderr: bool,
 }
 
 impl Subscribe for CustomHostOutputStream {
@@ -63,12 +349,26 @@ impl Subscribe for CustomHostOutputStream {
 
 impl HostOutputStream for CustomHostOutputStream {
     fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
-        self.buffer.lock().unwrap().extend_from_slice(&bytes);
-        StreamResult::Ok(())
+        let echoed = if self.is_stderr {
+            std::io::stderr().write_all(&bytes)
+        } else {
+            std::io::stdout().write_all(&bytes)
+        };
+        echoed.map_err(|e| StreamError::LastOperationFailed(e.into()))?;
+        self.bridge
+            .lock()
+            .unwrap()
+            .add_output(&bytes)
+            .map_err(StreamError::LastOperationFailed)
     }
 
     fn flush(&mut self) -> StreamResult<()> {
-        StreamResult::Ok(())
+        let flushed = if self.is_stderr {
+            std::io::stderr().flush()
+        } else {
+            std::io::stdout().flush()
+        };
+        flushed.map_err(|e| StreamError::LastOperationFailed(e.into()))
     }
 
     fn check_write(&mut self) -> StreamResult<usize> {
@@ -79,15 +379,37 @@ impl HostOutputStream for CustomHostOutputStream {
 impl StdoutStream for CustomStdout {
     fn stream(&self) -> Box<dyn HostOutputStream> {
         Box::new(CustomHostOutputStream {
-            buffer: self.buffer.clone(),
+            bridge: self.bridge.clone(),
+            is_stderr: self.is_stderr,
         })
     }
 
     fn isatty(&self) -> bool {
-        true
+        if self.is_stderr {
+            std::io::stderr().is_terminal()
+        } else {
+            std::io::stdout().is_terminal()
+        }
     }
 }
 
+/// The bytes a plugin passed to a host function by pointer and length.
+fn guest_bytes<'a>(data: &'a [u8], ptr: i32, len: i32, what: &str) -> WroughtResult<&'a [u8]> {
+    let start = usize::try_from(ptr).map_err(|_| format!("invalid {} pointer", what))?;
+    let len = usize::try_from(len).map_err(|_| format!("invalid {} length", what))?;
+    start
+        .checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| format!("{} is outside the plugin's memory", what))
+}
+
+/// Like `guest_bytes`, for arguments that must be UTF-8. Bad input is reported back
+/// to the plugin rather than crashing the host.
+fn guest_str<'a>(data: &'a [u8], ptr: i32, len: i32, what: &str) -> WroughtResult<&'a str> {
+    let bytes = guest_bytes(data, ptr, len, what)?;
+    std::str::from_utf8(bytes).map_err(|e| format!("{} is not valid UTF-8: {}", what, e))
+}
+
 // exposed in the bindings as:
 // fn wrought_write_file(
 //     path_ptr: *const u8,
@@ -104,18 +426,20 @@ fn wasm_write_file(
 ) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let path =
-        std::str::from_utf8(&data[path_ptr as usize..(path_ptr + path_len) as usize]).unwrap();
-    let content = &data[content_ptr as usize..(content_ptr + content_len) as usize];
-    let path = PathBuf::from(path);
-    let result: wrought_wasm_bindings::WroughtResult<()> = caller
-        .data()
-        .0
-        .bridge
-        .lock()
-        .unwrap()
-        .write_file(&path, content)
-        .map_err(|e| format!("{}", e));
+    let path = guest_str(data, path_ptr, path_len, "path");
+    // The content is passed through as-is, it doesn't need to be text.
+    let content = guest_bytes(data, content_ptr, content_len, "content");
+    let result: WroughtResult<()> = path.and_then(|path| {
+        let content = content?;
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .write_file(Path::new(path), content)
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -128,17 +452,17 @@ fn wasm_write_file(
 fn wasm_read_file(mut caller: Caller<'_, CombinedContext>, path_ptr: i32, path_len: i32) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let path =
-        std::str::from_utf8(&data[path_ptr as usize..(path_ptr + path_len) as usize]).unwrap();
-    let path = PathBuf::from(path);
-    let result: wrought_wasm_bindings::WroughtResult<Option<Vec<u8>>> = caller
-        .data()
-        .0
-        .bridge
-        .lock()
-        .unwrap()
-        .read_file(&path)
-        .map_err(|e| format!("{}", e));
+    let path = guest_str(data, path_ptr, path_len, "path");
+    let result: WroughtResult<Option<Vec<u8>>> = path.and_then(|path| {
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .read_file(Path::new(path))
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -159,19 +483,20 @@ fn wasm_get_metadata(
 ) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let path =
-        std::str::from_utf8(&data[path_ptr as usize..(path_ptr + path_len) as usize]).unwrap();
-    let path = PathBuf::from(path);
-    let key = std::str::from_utf8(&data[key_ptr as usize..(key_ptr + key_len) as usize]).unwrap();
-
-    let result: wrought_wasm_bindings::WroughtResult<Option<String>> = caller
-        .data()
-        .0
-        .bridge
-        .lock()
-        .unwrap()
-        .get_metadata(&path, key)
-        .map_err(|e| format!("{}", e));
+    let path = guest_str(data, path_ptr, path_len, "path");
+    let key = guest_str(data, key_ptr, key_len, "key");
+
+    let result: WroughtResult<Option<String>> = path.and_then(|path| {
+        let key = key?;
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .get_metadata(Path::new(path), key)
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -197,22 +522,22 @@ fn wasm_set_metadata(
 ) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let path =
-        std::str::from_utf8(&data[path_ptr as usize..(path_ptr + path_len) as usize]).unwrap();
-    let path = PathBuf::from(path);
-    let key = std::str::from_utf8(&data[key_ptr as usize..(key_ptr + key_len) as usize]).unwrap();
-    let content =
-        std::str::from_utf8(&data[content_ptr as usize..(content_ptr + content_len) as usize])
-            .unwrap();
+    let path = guest_str(data, path_ptr, path_len, "path");
+    let key = guest_str(data, key_ptr, key_len, "key");
+    let content = guest_str(data, content_ptr, content_len, "metadata value");
 
-    let result: wrought_wasm_bindings::WroughtResult<()> = caller
-        .data()
-        .0
-        .bridge
-        .lock()
-        .unwrap()
-        .set_metadata(&path, key, content)
-        .map_err(|e| format!("{}", e));
+    let result: WroughtResult<()> = path.and_then(|path| {
+        let key = key?;
+        let content = content?;
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .set_metadata(Path::new(path), key, content)
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -226,34 +551,153 @@ fn wasm_set_metadata(
 fn wasm_ai_query(mut caller: Caller<'_, CombinedContext>, query_ptr: i32, query_len: i32) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let query =
-        std::str::from_utf8(&data[query_ptr as usize..(query_ptr + query_len) as usize]).unwrap();
+    let query = guest_str(data, query_ptr, query_len, "query");
 
-    let result: wrought_wasm_bindings::WroughtResult<String> = caller
-        .data()
-        .0
-        .bridge
-        .lock()
-        .unwrap()
-        .ai_query(query)
-        .map_err(|e| format!("{}", e));
+    let result: WroughtResult<String> = query.and_then(|query| {
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .ai_query(query)
+            .map_err(|e| format!("{}", e))
+    });
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
+/*
+    fn wrought_log(
+    level: i32,
+    message_ptr: *const u8,
+    message_len: usize,
+);
+*/
+fn wasm_log(
+    mut caller: Caller<'_, CombinedContext>,
+    level: i32,
+    message_ptr: i32,
+    message_len: i32,
+) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let message = guest_str(data, message_ptr, message_len, "message");
+    let level = match level {
+        wrought_wasm_bindings::LOG_LEVEL_WARN => LogLevel::Warn,
+        _ => LogLevel::Info,
+    };
+
+    let result: WroughtResult<()> = message.and_then(|message| {
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .log(level, message)
+            .map_err(|e| format!("{}", e))
+    });
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
+/*
+    fn wrought_fail(
+    exit_code: i32,
+    message_ptr: *const u8,
+    message_len: usize,
+);
+*/
+fn wasm_fail(
+    mut caller: Caller<'_, CombinedContext>,
+    exit_code: i32,
+    message_ptr: i32,
+    message_len: i32,
+) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let message = guest_str(data, message_ptr, message_len, "message");
+
+    let result: WroughtResult<()> = message.and_then(|message| {
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .fail(ScriptFailure {
+                message: message.to_string(),
+                exit_code,
+            })
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
 
+/*
+fn wrought_get_config(
+    key_ptr: *const u8,
+    key_len: usize,
+);
+*/
+fn wasm_get_config(mut caller: Caller<'_, CombinedContext>, key_ptr: i32, key_len: i32) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let key = guest_str(data, key_ptr, key_len, "key");
+
+    let result: WroughtResult<Option<String>> = key.and_then(|key| {
+        caller
+            .data()
+            .0
+            .bridge
+            .lock()
+            .unwrap()
+            .get_config(key)
+            .map_err(|e| format!("{}", e))
+    });
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
+/*
+fn wrought_content_hash(
+    content_ptr: *const u8,
+    content_len: usize,
+);
+*/
+fn wasm_content_hash(mut caller: Caller<'_, CombinedContext>, content_ptr: i32, content_len: i32) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let result: WroughtResult<String> = guest_bytes(data, content_ptr, content_len, "content")
+        .map(|content| ContentHash::from_content(content).to_string());
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
+// fn wrought_set_result(result_ptr: *const u8, result_len: usize);
+fn wasm_set_result(mut caller: Caller<'_, CombinedContext>, result_ptr: i32, result_len: i32) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let value = guest_str(data, result_ptr, result_len, "result").and_then(|json| {
+        serde_json::from_str::<serde_json::Value>(json)
+            .map_err(|e| format!("result is not valid JSON: {}", e))
+    });
+    let app_state = &mut caller.data_mut().0;
+    let result: WroughtResult<()> = value.map(|value| app_state.result = Some(value));
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    app_state.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
 // fn wrought_init_template();
 fn wasm_init_template(mut caller: Caller<'_, CombinedContext>) {
     // let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
 
     let app_state = &mut caller.data_mut().0;
-    let template_id = app_state.next_template_id;
-    assert!(!app_state.templating.contains_key(&template_id));
-    app_state.next_template_id += 1;
-    app_state
-        .templating
-        .insert(template_id, tera::Tera::default());
-
-    let result = WroughtResult::Ok(template_id);
+    let result: WroughtResult<i32> = new_template_instance(&app_state.shared_templates)
+        .map_err(|e| format!("{}", e))
+        .and_then(|instance| app_state.templating.insert(instance));
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -261,9 +705,11 @@ fn wasm_init_template(mut caller: Caller<'_, CombinedContext>) {
 // fn wrought_drop_template(id: i32);
 fn wasm_drop_template(mut caller: Caller<'_, CombinedContext>, id: i32) {
     let app_state = &mut caller.data_mut().0;
-    // TODO: This should probably not be an assert, as that allows plugins to crash the host.
-    assert!(app_state.templating.contains_key(&id));
-    app_state.templating.remove(&id);
+    // The bindings drop templates from `Drop`, so nothing reads this, but it's still an
+    // error rather than a crash.
+    let result = app_state.templating.remove(id);
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    app_state.call_buffer.call_buffer = Some(Ok(out_buf));
 }
 
 // fn wrought_add_templates(id: i32, encoded_templates_ptr: *const u8, len: usize);
@@ -275,26 +721,47 @@ fn wasm_add_templates(
 ) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let encoded_templates = std::str::from_utf8(
-        &data[encoded_templates_ptr as usize..(encoded_templates_ptr + len) as usize],
-    )
-    .unwrap();
+    let encoded_templates = match guest_str(data, encoded_templates_ptr, len, "templates") {
+        Ok(encoded_templates) => encoded_templates,
+        Err(e) => {
+            let out_buf = serde_json::to_vec(&WroughtResult::<()>::Err(e)).unwrap();
+            caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+            return;
+        }
+    };
 
-    // decode them...
-    // TODO: Not an unwrap....
-    let templates: Vec<(String, String)> = serde_json::from_str(encoded_templates).unwrap();
+    let templates = serde_json::from_str::<Vec<(String, String)>>(encoded_templates)
+        .map_err(|e| format!("templates are not valid JSON: {}", e));
 
-    // Then add them all
     let app_state = &mut caller.data_mut().0;
-    // TODO: These should probably not be an unwrap, as that allows plugins to crash the host.
-    app_state
-        .templating
-        .get_mut(&id)
-        .unwrap()
-        .add_raw_templates(templates)
-        .unwrap();
+    let result: WroughtResult<()> = templates.and_then(|templates| {
+        app_state
+            .templating
+            .get_mut(id)?
+            .add_templates(templates)
+            .map_err(|e| format!("{}", e))
+    });
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    app_state.call_buffer.call_buffer = Some(Ok(out_buf));
+}
+
+// fn wrought_use_shared_template(id: i32, key_ptr: *const u8, key_len: usize);
+fn wasm_use_shared_template(
+    mut caller: Caller<'_, CombinedContext>,
+    id: i32,
+    key_ptr: i32,
+    key_len: i32,
+) {
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let data = memory.data(&caller);
+    let key = guest_str(data, key_ptr, key_len, "key").map(|key| key.to_string());
 
-    let result = WroughtResult::Ok(());
+    let app_state = &mut caller.data_mut().0;
+    let result: WroughtResult<()> = key.and_then(|key| {
+        let instance = app_state.templating.get_mut(id)?;
+        use_shared_template(instance, &app_state.shared_templates, &key)
+            .map_err(|e| format!("{}", e))
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
     caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
 }
@@ -310,27 +777,66 @@ fn wasm_render_template(
 ) {
     let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
     let data = memory.data(&caller);
-    let key = std::str::from_utf8(&data[key_ptr as usize..(key_ptr + key_len) as usize]).unwrap();
-    let content =
-        std::str::from_utf8(&data[content_ptr as usize..(content_ptr + content_len) as usize])
-            .unwrap();
+    let args = guest_str(data, key_ptr, key_len, "key")
+        .and_then(|key| Ok((key, guest_str(data, content_ptr, content_len, "values")?)));
+    let (key, content) = match args {
+        Ok(args) => args,
+        Err(e) => {
+            let out_buf = serde_json::to_vec(&WroughtResult::<String>::Err(e)).unwrap();
+            caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+            return;
+        }
+    };
 
-    // TODO: Should not be unwrap!
-    let context: serde_json::Value = serde_json::from_str(content).unwrap();
+    let (key, content) = (key.to_string(), content.to_string());
+    let app_state = &mut caller.data_mut().0;
+    let bridge = app_state.bridge.clone();
+    let result = render_template(
+        &mut app_state.templating,
+        &mut *bridge.lock().unwrap(),
+        id,
+        &key,
+        &content,
+    );
+    let out_buf = serde_json::to_vec(&result).unwrap();
+    app_state.call_buffer.call_buffer = Some(Ok(out_buf));
+}
 
-    // Then add them all
-    let app_state = &caller.data().0;
-    // TODO: These should probably not be an unwrap, as that allows plugins to crash the host.
-    let result = app_state
-        .templating
-        .get(&id)
-        .unwrap()
-        .render(key, &tera::Context::from_value(context).unwrap())
-        .unwrap();
+/// Renders template `key` of instance `id`, with the JSON object `values` as its context.
+/// `bridge` is only used by the render cache, if the instance has one.
+fn render_template(
+    templating: &mut TemplateInstances,
+    bridge: &mut dyn Bridge,
+    id: i32,
+    key: &str,
+    values: &str,
+) -> WroughtResult<String> {
+    let TemplateInstance {
+        tera,
+        sources,
+        cache,
+    } = templating.get_mut(id)?;
+    let values: serde_json::Value =
+        serde_json::from_str(values).map_err(|e| format!("values are not valid JSON: {}", e))?;
+    let render = |values: serde_json::Value| -> anyhow::Result<String> {
+        let context = tera::Context::from_value(values)?;
+        Ok(tera.render(key, &context)?)
+    };
+    match cache {
+        Some(cache) => cache.render(bridge, sources, key, &values, || render(values.clone())),
+        None => render(values),
+    }
+    .map_err(|e| format!("{}", e))
+}
 
-    let result = WroughtResult::Ok(result);
+// fn wrought_enable_render_cache(id: i32);
+fn wasm_enable_render_cache(mut caller: Caller<'_, CombinedContext>, id: i32) {
+    let app_state = &mut caller.data_mut().0;
+    let result: WroughtResult<()> = app_state.templating.get_mut(id).map(|instance| {
+        instance.cache.get_or_insert_with(RenderCache::default);
+    });
     let out_buf = serde_json::to_vec(&result).unwrap();
-    caller.data_mut().0.call_buffer.call_buffer = Some(Ok(out_buf));
+    app_state.call_buffer.call_buffer = Some(Ok(out_buf));
 }
 
 // The additional F function is used to add hooks when testing
@@ -339,20 +845,20 @@ pub fn run_script_ex<F>(
     fs: Arc<Mutex<dyn xfs::Xfs>>,
     script_path: &Path,
     f: F,
-) -> anyhow::Result<()>
+) -> anyhow::Result<Option<serde_json::Value>>
 where
     F: FnOnce(&Linker<CombinedContext>) -> anyhow::Result<()>,
 {
     let config = Config::new();
     // config.async_support(true);
     let engine = Engine::new(&config).with_context(|| "error creating wasm context")?;
-    let stdout_buffer = Arc::new(Mutex::new(vec![]));
-    let stderr_buffer = Arc::new(Mutex::new(vec![]));
     let custom_stdout = CustomStdout {
-        buffer: stdout_buffer.clone(),
+        bridge: bridge.clone(),
+        is_stderr: false,
     };
     let custom_stderr = CustomStdout {
-        buffer: stderr_buffer.clone(),
+        bridge: bridge.clone(),
+        is_stderr: true,
     };
 
     // Add the WASI preview1 API to the linker (will be implemented in terms of
@@ -362,20 +868,21 @@ where
         .with_context(|| "error installing WASI libraries to core engine")?;
 
     // Add capabilities (e.g. filesystem access) to the WASI preview2 context
-    // here. Here only stdio is inherited, but see docs of `WasiCtxBuilder` for
+    // here. Here only stdin is inherited, but see docs of `WasiCtxBuilder` for
     // more.
     let wasi_ctx = WasiCtxBuilder::new()
         .inherit_stdin()
-        // .stdout(custom_stdout)
-        // .stderr(custom_stderr)
-        .inherit_stdout()
-        .inherit_stderr()
+        .stdout(custom_stdout)
+        .stderr(custom_stderr)
         .build_p1();
+    let package_dir = script_path.parent().unwrap_or(Path::new(""));
+    let shared_templates = load_shared_templates(&*fs.lock().unwrap(), package_dir)?;
     let app_state = AppState {
         bridge,
-        templating: BTreeMap::new(),
-        next_template_id: 0,
+        shared_templates,
+        templating: TemplateInstances::default(),
         call_buffer: wasmcb::CallBuffer::new(),
+        result: None,
     };
 
     let mut store = Store::new(&engine, CombinedContext(app_state, wasi_ctx));
@@ -401,6 +908,26 @@ where
         .func_wrap("env", "wrought_ai_query", wasm_ai_query)
         .with_context(|| "Error installing wrought_ai_query function")?;
 
+    linker
+        .func_wrap("env", "wrought_log", wasm_log)
+        .with_context(|| "Error installing wrought_log function")?;
+
+    linker
+        .func_wrap("env", "wrought_fail", wasm_fail)
+        .with_context(|| "Error installing wrought_fail function")?;
+
+    linker
+        .func_wrap("env", "wrought_get_config", wasm_get_config)
+        .with_context(|| "Error installing wrought_get_config function")?;
+
+    linker
+        .func_wrap("env", "wrought_content_hash", wasm_content_hash)
+        .with_context(|| "Error installing wrought_content_hash function")?;
+
+    linker
+        .func_wrap("env", "wrought_set_result", wasm_set_result)
+        .with_context(|| "Error installing wrought_set_result function")?;
+
     linker
         .func_wrap("env", "wrought_render_template", wasm_render_template)
         .with_context(|| "Error installing wrought_render_template function")?;
@@ -413,10 +940,26 @@ where
         .func_wrap("env", "wrought_init_template", wasm_init_template)
         .with_context(|| "Error installing wrought_drop_template function")?;
 
+    linker
+        .func_wrap(
+            "env",
+            "wrought_enable_render_cache",
+            wasm_enable_render_cache,
+        )
+        .with_context(|| "Error installing wrought_enable_render_cache function")?;
+
     linker
         .func_wrap("env", "wrought_add_templates", wasm_add_templates)
         .with_context(|| "Error installing wrought_add_templates function")?;
 
+    linker
+        .func_wrap(
+            "env",
+            "wrought_use_shared_template",
+            wasm_use_shared_template,
+        )
+        .with_context(|| "Error installing wrought_use_shared_template function")?;
+
     let errors = Arc::new(Mutex::new(Vec::new()));
     let errors_clone = errors.clone();
 
@@ -425,15 +968,21 @@ where
         "host_report_error",
         move |mut caller: Caller<'_, _>, error_type: i32, ptr: i32, len: i32| {
             let errors_clone = errors_clone.clone();
-            let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
-            let data = memory.data(&caller)[ptr as usize..(ptr + len) as usize].to_vec();
-            let error = String::from_utf8(data).unwrap();
+            let memory = caller
+                .get_export("memory")
+                .and_then(|e| e.into_memory())
+                .context("plugin reported an error but exports no memory")?;
+            // A bad pointer or length traps the plugin rather than panicking the host.
+            let data = guest_bytes(memory.data(&caller), ptr, len, "error message")
+                .map_err(anyhow::Error::msg)?;
+            let error = String::from_utf8_lossy(data).into_owned();
             let error = match error_type {
                 ERROR_TYPE_NORMAL => WasmError::Normal(error),
                 ERROR_TYPE_PANIC => WasmError::Panic(error),
                 _ => WasmError::Normal(format!("Unknown error type: {}", error)),
             };
             errors_clone.lock().unwrap().push(error);
+            anyhow::Ok(())
         },
     )?;
 
@@ -448,7 +997,28 @@ where
         .with_context(|| format!("Error reading script file {:?}", script_path))?;
     let mut content = vec![];
     reader.read_to_end(&mut content)?;
+
+    // Plugins without a manifest are still allowed, they just don't get checked.
+    if let Some(manifest) = read_manifest(&content)? {
+        check_capabilities(&manifest)?;
+        log::info!("running plugin {} {}", manifest.name, manifest.version);
+    }
+
     let module = Module::new(&engine, &content)?;
+    let has_entrypoint = match module.get_export("plugin") {
+        Some(ExternType::Func(f)) => {
+            f.params().next().is_none()
+                && f.results().len() == 1
+                && f.results().all(|r| matches!(r, ValType::I32))
+        }
+        _ => false,
+    };
+    if !has_entrypoint {
+        bail!(
+            "{:?} is not a wrought plugin - it must export a `plugin` function taking no arguments and returning an i32",
+            script_path
+        );
+    }
 
     let instance = linker
         .instantiate(&mut store, &module)
@@ -459,22 +1029,19 @@ where
     let result = func.call(&mut store, ());
 
     match result {
-        Ok(0) => {}
-        Ok(_) => {
+        Ok(PLUGIN_OK) => {}
+        Ok(code) => {
             // Handle normal error(s)
-            let errors = errors.lock().unwrap();
-            if !errors.is_empty() {
-                let error_msgs: Vec<String> = errors
-                    .iter()
-                    .map(|e| match e {
-                        WasmError::Normal(msg) => format!("Error: {}", msg),
-                        WasmError::Panic(msg) => format!("Panic: {}", msg),
-                    })
-                    .collect();
-                anyhow::bail!("WASM execution failed:\n{}", error_msgs.join("\n"));
-            } else {
-                anyhow::bail!("WASM execution failed with unknown error");
-            }
+            let errors = errors
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|e| match e {
+                    WasmError::Normal(msg) => format!("Error: {}", msg),
+                    WasmError::Panic(msg) => format!("Panic: {}", msg),
+                })
+                .collect();
+            return Err(PluginExitError { code, errors }.into());
         }
         Err(trap) => {
             // Handle trap (like panics)
@@ -494,14 +1061,548 @@ where
         }
     }
 
-    println!(
-        "WASM STDOUT\n{}",
-        String::from_utf8_lossy(stdout_buffer.lock().unwrap().as_slice())
-    );
-    println!(
-        "WASM STDERR\n{}",
-        String::from_utf8_lossy(stderr_buffer.lock().unwrap().as_slice())
-    );
+    Ok(store.into_data().0.result)
+}
 
-    Ok(())
+#[cfg(test)]
+pub mod tests {
+    use wrought_wasm_bindings::{PluginManifest, MANIFEST_SECTION};
+
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        check_capabilities, guest_str, load_shared_templates, new_template_instance, read_manifest,
+        render_template, run_script, use_shared_template, PluginExitError, TemplateInstance,
+        TemplateInstances, MAX_LIVE_TEMPLATES,
+    };
+    use crate::scripting_luau::tests::MockBridge;
+    use crate::{
+        backend::test_utils::MockBackend, binary16::ContentHash, bridge::tests::test_bridge,
+        render_cache::RenderCache,
+    };
+
+    // An empty module, with a single custom section.
+    fn module_with_custom_section(name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(payload);
+        assert!(
+            section.len() < 128,
+            "keep test sections to a single LEB128 byte"
+        );
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.push(0);
+        module.push(section.len() as u8);
+        module.extend(section);
+        module
+    }
+
+    fn section(id: u8, content: &[u8]) -> Vec<u8> {
+        assert!(content.len() < 128);
+        let mut result = vec![id, content.len() as u8];
+        result.extend_from_slice(content);
+        result
+    }
+
+    fn name(s: &str) -> Vec<u8> {
+        let mut result = vec![s.len() as u8];
+        result.extend_from_slice(s.as_bytes());
+        result
+    }
+
+    // A plugin that calls `wrought_read_file` with a non-UTF-8 path, then reports
+    // whatever ended up in the call buffer back as its error.
+    fn plugin_reading_invalid_path() -> Vec<u8> {
+        const I32: u8 = 0x7f;
+        // (i32, i32) -> (), () -> i32, (i32, i32, i32) -> ()
+        let types = [
+            &[3, 0x60, 2, I32, I32, 0][..],
+            &[0x60, 0, 1, I32],
+            &[0x60, 3, I32, I32, I32, 0],
+        ]
+        .concat();
+        let mut imports = vec![4];
+        for (f, t) in [
+            ("wrought_read_file", 0),
+            ("get_call_buffer_len", 1),
+            ("read_call_buffer", 0),
+            ("host_report_error", 2),
+        ] {
+            imports.extend(name("env"));
+            imports.extend(name(f));
+            imports.extend([0, t]);
+        }
+        let mut exports = vec![2];
+        exports.extend(name("memory"));
+        exports.extend([2, 0]);
+        exports.extend(name("plugin"));
+        exports.extend([0, 4]);
+        #[rustfmt::skip]
+        let body = [
+            1, 1, I32, // one i32 local, for the call buffer length
+            0x41, 0, 0x41, 2, 0x10, 0, // wrought_read_file(0, 2)
+            0x10, 1, 0x21, 0, // len = get_call_buffer_len()
+            0x41, 16, 0x20, 0, 0x10, 2, // read_call_buffer(16, len)
+            0x41, 1, 0x41, 16, 0x20, 0, 0x10, 3, // host_report_error(1, 16, len)
+            0x41, 0x7f, 0x0b, // return PLUGIN_ERROR (-1)
+        ];
+        let mut code = vec![1, body.len() as u8];
+        code.extend(body);
+        // Memory 0 starts with the bytes 0xff 0xfe, which aren't valid UTF-8.
+        let data = [1, 0, 0x41, 0, 0x0b, 2, 0xff, 0xfe];
+
+        [
+            &b"\0asm\x01\0\0\0"[..],
+            &section(1, &types),
+            &section(2, &imports),
+            &section(3, &[1, 1]),
+            &section(5, &[1, 0, 1]),
+            &section(7, &exports),
+            &section(10, &code),
+            &section(11, &data),
+        ]
+        .concat()
+    }
+
+    // A plugin that passes `json` to `wrought_set_result`, then returns PLUGIN_OK.
+    fn plugin_setting_result(json: &[u8]) -> Vec<u8> {
+        assert!(json.len() < 64);
+        const I32: u8 = 0x7f;
+        // (i32, i32) -> (), () -> i32
+        let types = [&[2, 0x60, 2, I32, I32, 0][..], &[0x60, 0, 1, I32]].concat();
+        let mut imports = vec![1];
+        imports.extend(name("env"));
+        imports.extend(name("wrought_set_result"));
+        imports.extend([0, 0]);
+        let mut exports = vec![2];
+        exports.extend(name("memory"));
+        exports.extend([2, 0]);
+        exports.extend(name("plugin"));
+        exports.extend([0, 1]);
+        #[rustfmt::skip]
+        let body = [
+            0, // no locals
+            0x41, 0, 0x41, json.len() as u8, 0x10, 0, // wrought_set_result(0, len)
+            0x41, 0, 0x0b, // return PLUGIN_OK
+        ];
+        let mut code = vec![1, body.len() as u8];
+        code.extend(body);
+        let mut data = vec![1, 0, 0x41, 0, 0x0b, json.len() as u8];
+        data.extend_from_slice(json);
+
+        [
+            &b"\0asm\x01\0\0\0"[..],
+            &section(1, &types),
+            &section(2, &imports),
+            &section(3, &[1, 1]),
+            &section(5, &[1, 0, 1]),
+            &section(7, &exports),
+            &section(10, &code),
+            &section(11, &data),
+        ]
+        .concat()
+    }
+
+    // A plugin that calls `host_report_error(0, ptr, len)`, where `ptr` and `len` are
+    // signed LEB128 encoded, then returns PLUGIN_ERROR.
+    fn plugin_reporting_error(ptr: &[u8], len: &[u8]) -> Vec<u8> {
+        const I32: u8 = 0x7f;
+        // (i32, i32, i32) -> (), () -> i32
+        let types = [&[2, 0x60, 3, I32, I32, I32, 0][..], &[0x60, 0, 1, I32]].concat();
+        let mut imports = vec![1];
+        imports.extend(name("env"));
+        imports.extend(name("host_report_error"));
+        imports.extend([0, 0]);
+        let mut exports = vec![2];
+        exports.extend(name("memory"));
+        exports.extend([2, 0]);
+        exports.extend(name("plugin"));
+        exports.extend([0, 1]);
+        let body = [
+            &[0, 0x41, 0, 0x41][..],
+            ptr,
+            &[0x41],
+            len,
+            &[0x10, 0, 0x41, 0x7f, 0x0b],
+        ]
+        .concat();
+        let mut code = vec![1, body.len() as u8];
+        code.extend(body);
+
+        [
+            &b"\0asm\x01\0\0\0"[..],
+            &section(1, &types),
+            &section(2, &imports),
+            &section(3, &[1, 1]),
+            &section(5, &[1, 0, 1]),
+            &section(7, &exports),
+            &section(10, &code),
+        ]
+        .concat()
+    }
+
+    // A plugin that does nothing but return `code`, which must fit in one LEB128 byte.
+    fn plugin_returning(code: i8) -> Vec<u8> {
+        assert!((-64..64).contains(&code));
+        let body = [0, 0x41, code as u8 & 0x7f, 0x0b];
+        let mut code_section = vec![1, body.len() as u8];
+        code_section.extend(body);
+        let mut exports = vec![1];
+        exports.extend(name("plugin"));
+        exports.extend([0, 0]);
+        [
+            &b"\0asm\x01\0\0\0"[..],
+            &section(1, &[1, 0x60, 0, 1, 0x7f]),
+            &section(3, &[1, 0]),
+            &section(7, &exports),
+            &section(10, &code_section),
+        ]
+        .concat()
+    }
+
+    fn run_plugin(module: Vec<u8>) -> anyhow::Result<Option<serde_json::Value>> {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let script_path = Path::new("p/plugin.wasm");
+        fs.add_r(script_path, module).unwrap();
+        let bridge = Arc::new(Mutex::new(MockBridge::new()));
+        run_script(bridge, Arc::new(Mutex::new(fs)), script_path)
+    }
+
+    #[test]
+    pub fn plugin_return_codes() {
+        run_plugin(plugin_returning(0)).unwrap();
+
+        let e = run_plugin(plugin_returning(7)).unwrap_err();
+        let exit = e.downcast_ref::<PluginExitError>().unwrap();
+        assert_eq!(exit.code, 7);
+        assert!(e.to_string().contains("unexpected return code 7"), "{}", e);
+
+        let e = run_plugin(plugin_returning(-1)).unwrap_err();
+        assert_eq!(e.downcast_ref::<PluginExitError>().unwrap().code, -1);
+        assert!(e.to_string().contains("didn't report an error"), "{}", e);
+    }
+
+    #[test]
+    pub fn bad_error_reports_trap_the_plugin() {
+        // Reporting from inside its memory is fine.
+        let e = run_plugin(plugin_reporting_error(&[0], &[4])).unwrap_err();
+        assert_eq!(e.downcast_ref::<PluginExitError>().unwrap().code, -1);
+
+        for (ptr, len) in [
+            (&[0x7f][..], &[1][..]),        // negative pointer
+            (&[0], &[0x7f]),                // negative length
+            (&[0x80, 0x80, 0x08], &[4]),    // past the end of its single page
+            (&[0xf0, 0xff, 0x03], &[0x20]), // straddling the end
+        ] {
+            let e = run_plugin(plugin_reporting_error(ptr, len)).unwrap_err();
+            assert!(e.downcast_ref::<PluginExitError>().is_none(), "{}", e);
+            assert!(e.to_string().contains("trapped"), "{}", e);
+        }
+    }
+
+    #[test]
+    pub fn plugin_can_return_a_result() {
+        assert_eq!(run_plugin(plugin_returning(0)).unwrap(), None);
+
+        let result = run_plugin(plugin_setting_result(br#"{"pages":3,"ok":true}"#)).unwrap();
+        assert_eq!(result, Some(serde_json::json!({"pages": 3, "ok": true})));
+
+        // The plugin is told about bad JSON, rather than the host failing.
+        assert_eq!(run_plugin(plugin_setting_result(b"{oops")).unwrap(), None);
+    }
+
+    #[test]
+    pub fn guest_strings_are_checked() {
+        let data = b"ok\xff";
+        assert_eq!(guest_str(data, 0, 2, "path"), Ok("ok"));
+        assert!(guest_str(data, 1, 2, "path")
+            .unwrap_err()
+            .contains("not valid UTF-8"));
+        assert!(guest_str(data, 2, 5, "path")
+            .unwrap_err()
+            .contains("outside"));
+        assert!(guest_str(data, -1, 1, "path").is_err());
+    }
+
+    #[test]
+    pub fn invalid_utf8_path_is_an_error_not_a_crash() {
+        // The mock bridge has no expectations - it must not be called.
+        let e = run_plugin(plugin_reading_invalid_path()).unwrap_err();
+        assert_eq!(e.downcast_ref::<PluginExitError>().unwrap().code, -1);
+        let message = format!("{:#}", e);
+        assert!(message.contains("path is not valid UTF-8"), "{}", message);
+    }
+
+    #[test]
+    pub fn reads_manifest_from_custom_section() {
+        let module = module_with_custom_section(
+            MANIFEST_SECTION,
+            br#"{"name":"demo","version":"1.0","capabilities":["stdio"]}"#,
+        );
+        let manifest = read_manifest(&module).unwrap().unwrap();
+        assert_eq!(
+            manifest,
+            PluginManifest {
+                name: "demo".to_string(),
+                version: "1.0".to_string(),
+                capabilities: vec!["stdio".to_string()],
+            }
+        );
+        check_capabilities(&manifest).unwrap();
+    }
+
+    #[test]
+    pub fn module_without_manifest() {
+        let module = module_with_custom_section("name", b"\x00");
+        assert_eq!(read_manifest(&module).unwrap(), None);
+    }
+
+    #[test]
+    pub fn excess_capabilities_are_refused() {
+        let manifest = PluginManifest {
+            name: "greedy".to_string(),
+            version: "1.0".to_string(),
+            capabilities: vec!["stdio".to_string(), "network".to_string()],
+        };
+        let e = check_capabilities(&manifest).unwrap_err();
+        assert!(e.to_string().contains("network"), "{}", e);
+    }
+
+    #[test]
+    pub fn truncated_module_is_an_error() {
+        let mut module = module_with_custom_section(MANIFEST_SECTION, b"{}");
+        module.truncate(module.len() - 1);
+        assert!(read_manifest(&module).is_err());
+    }
+
+    #[test]
+    pub fn template_instances_inherit_shared_templates() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let package_dir = Path::new("/project/.wrought/packages/p");
+        fs.add_r(
+            &package_dir.join("templates/layouts/base.html"),
+            b"<h1>{% block title %}{% endblock %}</h1>".to_vec(),
+        )
+        .unwrap();
+        fs.add_r(
+            &package_dir.join("templates/footer.txt"),
+            b"shared footer".to_vec(),
+        )
+        .unwrap();
+        let shared = load_shared_templates(&fs, package_dir).unwrap();
+
+        let mut instance = new_template_instance(&shared).unwrap();
+        instance
+            .add_templates(vec![
+                (
+                    "page.html".to_string(),
+                    r#"{% extends "layouts/base.html" %}{% block title %}{{ t }}{% endblock %}"#
+                        .to_string(),
+                ),
+                ("footer.txt".to_string(), "plugin footer".to_string()),
+            ])
+            .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("t", "Hi");
+        assert_eq!(
+            instance.tera.render("page.html", &context).unwrap(),
+            "<h1>Hi</h1>"
+        );
+        assert_eq!(
+            instance.tera.render("footer.txt", &context).unwrap(),
+            "plugin footer"
+        );
+        assert_eq!(instance.sources.len(), 3);
+
+        use_shared_template(&mut instance, &shared, "footer.txt").unwrap();
+        assert_eq!(
+            instance.tera.render("footer.txt", &context).unwrap(),
+            "shared footer"
+        );
+        assert_eq!(instance.sources["footer.txt"], "shared footer");
+        assert!(use_shared_template(&mut instance, &shared, "missing.txt").is_err());
+    }
+
+    #[test]
+    pub fn package_without_templates_has_no_shared_templates() {
+        let fs = xfs::mockfs::MockFS::new();
+        let shared = load_shared_templates(&fs, Path::new("/project/p")).unwrap();
+        assert_eq!(shared.tera.get_template_names().count(), 0);
+    }
+
+    #[test]
+    pub fn dropping_a_template_twice_is_an_error() {
+        let mut templating = TemplateInstances::default();
+        let id = templating.insert(TemplateInstance::default()).unwrap();
+        assert_eq!(templating.remove(id), Ok(()));
+        assert_eq!(
+            templating.remove(id),
+            Err(format!("unknown template id {}", id))
+        );
+        assert!(templating.remove(-1).is_err());
+    }
+
+    #[test]
+    pub fn rendering_an_unknown_template_id_is_an_error() {
+        let mut templating = TemplateInstances::default();
+        let mut instance = TemplateInstance::default();
+        instance
+            .add_templates(vec![("hi.txt".to_string(), "Hi {{ name }}".to_string())])
+            .unwrap();
+        let id = templating.insert(instance).unwrap();
+        // Without a render cache, the bridge isn't used.
+        let mut bridge = test_bridge(MockBackend::default(), BTreeMap::new());
+        let mut render = |id: i32, key: &str, values: &str| {
+            render_template(&mut templating, &mut bridge, id, key, values)
+        };
+
+        assert_eq!(
+            render(id, "hi.txt", r#"{"name": "Bob"}"#),
+            Ok("Hi Bob".to_string())
+        );
+        assert_eq!(
+            render(id + 1, "hi.txt", "{}"),
+            Err(format!("unknown template id {}", id + 1))
+        );
+        // Bad values and missing templates are errors too.
+        assert!(render(id, "hi.txt", "{").is_err());
+        assert!(render(id, "missing.txt", "{}").is_err());
+    }
+
+    #[test]
+    pub fn cached_instances_keep_their_renders() {
+        let mut templating = TemplateInstances::default();
+        let mut instance = TemplateInstance::default();
+        instance
+            .add_templates(vec![("hi.txt".to_string(), "Hi {{ name }}".to_string())])
+            .unwrap();
+        instance.cache = Some(RenderCache::default());
+        let id = templating.insert(instance).unwrap();
+
+        // Only the first render misses, and is stored.
+        let mut backend = MockBackend::default();
+        backend
+            .expect_get_render_cache_entry()
+            .times(1)
+            .returning(|_| Ok(None));
+        backend
+            .expect_store_content()
+            .times(1)
+            .returning(|value| Ok(ContentHash::from_content(value)));
+        backend
+            .expect_set_render_cache_entry()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut bridge = test_bridge(backend, BTreeMap::new());
+        for _ in 0..2 {
+            assert_eq!(
+                render_template(
+                    &mut templating,
+                    &mut bridge,
+                    id,
+                    "hi.txt",
+                    r#"{"name": "Bob"}"#
+                ),
+                Ok("Hi Bob".to_string())
+            );
+        }
+    }
+
+    #[test]
+    pub fn template_ids_are_reused_and_capped() {
+        let mut templating = TemplateInstances::default();
+        let ids: Vec<_> = (0..MAX_LIVE_TEMPLATES)
+            .map(|_| templating.insert(TemplateInstance::default()).unwrap())
+            .collect();
+        assert!(templating.insert(TemplateInstance::default()).is_err());
+
+        templating.remove(ids[3]).unwrap();
+        assert_eq!(templating.insert(TemplateInstance::default()), Ok(ids[3]));
+        assert_eq!(templating.len(), MAX_LIVE_TEMPLATES);
+
+        // Churning through instances never grows the ids.
+        for _ in 0..10_000 {
+            let id = templating
+                .remove(ids[0])
+                .and_then(|_| templating.insert(TemplateInstance::default()));
+            assert_eq!(id, Ok(ids[0]));
+        }
+        assert!(ids
+            .iter()
+            .all(|id| (0..MAX_LIVE_TEMPLATES as i32).contains(id)));
+    }
+
+    /// Builds the guest crate `test_plugins/<name>` for `wasm32-wasip1` and returns the
+    /// module. See test_plugins/README.md.
+    #[cfg(feature = "wasm-plugin-tests")]
+    fn build_test_plugin(name: &str) -> Vec<u8> {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let target_dir = manifest_dir.join("target/test_plugins");
+        let status = std::process::Command::new(option_env!("CARGO").unwrap_or("cargo"))
+            .args([
+                "build",
+                "--quiet",
+                "--target",
+                "wasm32-wasip1",
+                "--manifest-path",
+            ])
+            .arg(
+                manifest_dir
+                    .join("test_plugins")
+                    .join(name)
+                    .join("Cargo.toml"),
+            )
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .expect("unable to run cargo");
+        assert!(status.success(), "building test plugin '{}' failed", name);
+        let wasm = target_dir
+            .join("wasm32-wasip1/debug")
+            .join(format!("{}.wasm", name.replace('-', "_")));
+        std::fs::read(&wasm).unwrap_or_else(|e| panic!("reading {}: {}", wasm.display(), e))
+    }
+
+    #[cfg(feature = "wasm-plugin-tests")]
+    #[test]
+    pub fn compiled_plugin_runs_end_to_end() {
+        use mockall::predicate;
+        use std::path::PathBuf;
+
+        let mut fs = xfs::mockfs::MockFS::new();
+        let script_path = Path::new("p/shout.wasm");
+        fs.add_r(script_path, build_test_plugin("shout")).unwrap();
+
+        let mut bridge = MockBridge::new();
+        let mut seq = mockall::Sequence::new();
+        bridge
+            .expect_read_file()
+            .with(predicate::eq(PathBuf::from("in.txt")))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(Some(b"hello".to_vec())));
+        bridge
+            .expect_write_file()
+            .with(
+                predicate::eq(PathBuf::from("out.txt")),
+                predicate::eq(b"HELLO".to_vec()),
+            )
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        let bridge = Arc::new(Mutex::new(bridge));
+
+        let result = super::run_script_ex(
+            bridge.clone(),
+            Arc::new(Mutex::new(fs)),
+            script_path,
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(result, None);
+        bridge.lock().unwrap().checkpoint();
+    }
 }
@@ -0,0 +1,509 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{llm::LlmProvider, llm_cache::DEFAULT_LLM_CACHE_DIR};
+
+/// Settings that scripts are allowed to read via `get_config`.
+///
+/// This is deliberately an explicit list - anything not named here (in particular
+/// secrets like `openai_api_key`) can never be seen by a script.
+pub const SCRIPT_VISIBLE_SETTINGS: &[&str] = &["project_name", "openai_model"];
+
+/// Settings too sensitive for `config.toml`, which is meant to be committed.
+const SECRET_SETTINGS: &[&str] = &["openai_api_key"];
+
+/// Any setting can be given in the environment as this followed by its name in upper
+/// case, e.g. `WROUGHT_OPENAI_MODEL`.
+pub const SETTINGS_ENV_PREFIX: &str = "WROUGHT_";
+
+/// Where LLM queries go unless `openai_base_url` says otherwise.
+pub const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingType {
+    String,
+    Bool,
+    /// A non-negative integer.
+    Size,
+    /// An absolute http or https URL.
+    Url,
+}
+
+impl SettingType {
+    /// The value an environment variable gives, which is always text.
+    fn parse_env(&self, value: &str) -> toml::Value {
+        let parsed = match self {
+            SettingType::String | SettingType::Url => None,
+            SettingType::Bool => value.parse().ok().map(toml::Value::Boolean),
+            SettingType::Size => value.parse().ok().map(toml::Value::Integer),
+        };
+        // Left as a string if it doesn't parse, so `problem` reports it.
+        parsed.unwrap_or_else(|| toml::Value::String(value.to_string()))
+    }
+
+    /// What's wrong with `value`, if it isn't of this type.
+    fn problem(&self, value: &toml::Value) -> Option<&'static str> {
+        match (self, value) {
+            (SettingType::String, toml::Value::String(_)) => None,
+            (SettingType::String, _) => Some("must be a string"),
+            (SettingType::Bool, toml::Value::Boolean(_)) => None,
+            (SettingType::Bool, _) => Some("must be true or false"),
+            (SettingType::Size, toml::Value::Integer(i)) if *i >= 0 => None,
+            (SettingType::Size, _) => Some("must be a non-negative integer"),
+            (SettingType::Url, toml::Value::String(s)) if is_http_url(s) => None,
+            (SettingType::Url, _) => Some("must be an http or https URL"),
+        }
+    }
+}
+
+/// Every setting wrought understands, and the type its value must have.
+const KNOWN_SETTINGS: &[(&str, SettingType)] = &[
+    ("project_name", SettingType::String),
+    ("openai_api_key", SettingType::String),
+    ("openai_model", SettingType::String),
+    ("openai_base_url", SettingType::Url),
+    ("llm_provider", SettingType::String),
+    ("llm_cache_dir", SettingType::String),
+    ("ensure_trailing_newline", SettingType::Bool),
+    ("max_track_size", SettingType::Size),
+];
+
+/// A rough check - a scheme of http or https, then a host, and no whitespace.
+fn is_http_url(s: &str) -> bool {
+    let Some(rest) = s
+        .strip_prefix("https://")
+        .or_else(|| s.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && !s.contains(char::is_whitespace)
+}
+
+/// The project's settings. They come from, lowest precedence first:
+///
+/// * `.wrought/config.toml` - non-secret settings, meant to be committed.
+/// * `.wrought/settings.toml` - local settings and secrets like the API key.
+/// * `WROUGHT_<SETTING>` environment variables (see `SETTINGS_ENV_PREFIX`).
+///
+/// A setting given in more than one place takes its value from the last.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    table: toml::Table,
+    /// Problems that don't stop the settings being used, e.g. unknown (maybe
+    /// misspelt) keys. Each starts with the line it refers to.
+    pub warnings: Vec<String>,
+}
+
+impl Settings {
+    /// Parses and checks the whole file. Every wrongly typed setting is reported in
+    /// the one error, with its line, rather than just the first one found.
+    pub fn parse(content: &str) -> anyhow::Result<Settings> {
+        Self::parse_file(content, true)
+    }
+
+    /// Like `parse`, for `config.toml` - secrets there are warned about, as the file
+    /// is likely to be committed.
+    pub fn parse_config(content: &str) -> anyhow::Result<Settings> {
+        Self::parse_file(content, false)
+    }
+
+    fn parse_file(content: &str, allow_secrets: bool) -> anyhow::Result<Settings> {
+        let table = content
+            .parse::<toml::Table>()
+            .context("invalid settings file")?;
+        // Parsed again keeping spans, so problems can be reported by line.
+        let spanned: BTreeMap<String, toml::Spanned<toml::Value>> =
+            toml::from_str(content).context("invalid settings file")?;
+        let mut entries: Vec<_> = spanned.iter().collect();
+        entries.sort_by_key(|(_, value)| value.span().start);
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        for (key, value) in entries {
+            let line = content[..value.span().start].matches('\n').count() + 1;
+            match KNOWN_SETTINGS.iter().find(|(k, _)| k == key) {
+                None => warnings.push(format!("line {}: unknown setting '{}'", line, key)),
+                Some((_, setting_type)) => {
+                    if let Some(problem) = setting_type.problem(value.get_ref()) {
+                        errors.push(format!("line {}: {} {}", line, key, problem));
+                    }
+                }
+            }
+            if !allow_secrets && SECRET_SETTINGS.contains(&key.as_str()) {
+                warnings.push(format!(
+                    "line {}: {} is a secret - it belongs in settings.toml, not a committed file",
+                    line, key
+                ));
+            }
+        }
+        if !errors.is_empty() {
+            bail!("invalid settings file:\n  {}", errors.join("\n  "));
+        }
+        Ok(Settings { table, warnings })
+    }
+
+    /// The settings given as environment variables. `get` looks a variable up.
+    pub fn from_env(get: impl Fn(&str) -> Option<String>) -> anyhow::Result<Settings> {
+        let mut table = toml::Table::new();
+        let mut errors = vec![];
+        for (key, setting_type) in KNOWN_SETTINGS {
+            let name = format!("{}{}", SETTINGS_ENV_PREFIX, key.to_uppercase());
+            let Some(value) = get(&name) else {
+                continue;
+            };
+            let value = setting_type.parse_env(&value);
+            match setting_type.problem(&value) {
+                Some(problem) => errors.push(format!("{} {}", name, problem)),
+                None => {
+                    table.insert(key.to_string(), value);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            bail!(
+                "invalid settings in the environment:\n  {}",
+                errors.join("\n  ")
+            );
+        }
+        Ok(Settings {
+            table,
+            warnings: vec![],
+        })
+    }
+
+    /// These settings with `other`'s on top - where both have a value, `other` wins.
+    pub fn merge(mut self, other: Settings) -> Settings {
+        self.table.extend(other.table);
+        self.warnings.extend(other.warnings);
+        self
+    }
+
+    /// Loads the settings for the project at `root`, from both files and the
+    /// environment. Missing files just mean no settings from them. Warnings are
+    /// prefixed with the file they are about.
+    pub fn load(fs: &dyn xfs::Xfs, root: &Path) -> anyhow::Result<Settings> {
+        Self::load_with_env(fs, root, |name| std::env::var(name).ok())
+    }
+
+    pub fn load_with_env(
+        fs: &dyn xfs::Xfs,
+        root: &Path,
+        get_env: impl Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<Settings> {
+        let config = Self::load_file(fs, root, "config.toml", Settings::parse_config)?;
+        let settings = Self::load_file(fs, root, "settings.toml", Settings::parse)?;
+        Ok(config.merge(settings).merge(Settings::from_env(get_env)?))
+    }
+
+    fn load_file(
+        fs: &dyn xfs::Xfs,
+        root: &Path,
+        name: &str,
+        parse: fn(&str) -> anyhow::Result<Settings>,
+    ) -> anyhow::Result<Settings> {
+        let path = root.join(".wrought").join(name);
+        let Some(mut reader) = fs.reader_if_exists(&path)? else {
+            return Ok(Settings::default());
+        };
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let mut settings = parse(&content).with_context(|| format!("in {}", path.display()))?;
+        for warning in &mut settings.warnings {
+            *warning = format!("{} {}", name, warning);
+        }
+        Ok(settings)
+    }
+
+    pub fn openai_api_key(&self) -> anyhow::Result<Option<String>> {
+        self.get_string("openai_api_key")
+    }
+
+    /// The OpenAI compatible endpoint LLM queries are sent to, without a trailing `/`.
+    pub fn openai_base_url(&self) -> anyhow::Result<String> {
+        let url = self
+            .get_string("openai_base_url")?
+            .unwrap_or_else(|| DEFAULT_OPENAI_BASE_URL.to_string());
+        Ok(url.trim_end_matches('/').to_string())
+    }
+
+    /// `openai` unless set to `echo`, which answers queries without calling any API.
+    pub fn llm_provider(&self) -> anyhow::Result<LlmProvider> {
+        match self.get_string("llm_provider")?.as_deref() {
+            None | Some("openai") => Ok(LlmProvider::OpenAI),
+            Some("echo") => Ok(LlmProvider::Echo),
+            Some(other) => bail!(
+                "invalid setting: llm_provider must be \"openai\" or \"echo\", not \"{}\"",
+                other
+            ),
+        }
+    }
+
+    /// Where LLM responses are cached. Relative paths are relative to the project root.
+    pub fn llm_cache_dir(&self, root: &Path) -> anyhow::Result<PathBuf> {
+        let dir = self
+            .get_string("llm_cache_dir")?
+            .unwrap_or_else(|| DEFAULT_LLM_CACHE_DIR.to_string());
+        Ok(root.join(dir))
+    }
+
+    /// Whether text writes should be normalized to end in a single newline.
+    pub fn ensure_trailing_newline(&self) -> anyhow::Result<bool> {
+        match self.table.get("ensure_trailing_newline") {
+            Some(v) => v
+                .as_bool()
+                .context("invalid setting: ensure_trailing_newline is not a boolean"),
+            None => Ok(false),
+        }
+    }
+
+    /// Files larger than this many bytes aren't hashed by `status` unless a script
+    /// wrote them. No limit if unset.
+    pub fn max_track_size(&self) -> anyhow::Result<Option<u64>> {
+        match self.table.get("max_track_size") {
+            Some(v) => {
+                let size = v
+                    .as_integer()
+                    .context("invalid setting: max_track_size is not an integer")?;
+                Ok(Some(u64::try_from(size).context(
+                    "invalid setting: max_track_size must not be negative",
+                )?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_string(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match self.table.get(key) {
+            Some(v) => Ok(Some(
+                v.as_str()
+                    .with_context(|| format!("invalid setting: {} is not a string", key))?
+                    .to_string(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// The subset of the settings scripts may see (see `SCRIPT_VISIBLE_SETTINGS`).
+    /// Non-string values are given in their TOML form.
+    pub fn script_config(&self) -> BTreeMap<String, String> {
+        SCRIPT_VISIBLE_SETTINGS
+            .iter()
+            .filter_map(|k| {
+                let v = self.table.get(*k)?;
+                let v = match v {
+                    toml::Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                Some((k.to_string(), v))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Settings, DEFAULT_OPENAI_BASE_URL};
+    use crate::llm::LlmProvider;
+
+    #[test]
+    pub fn script_config_only_exposes_whitelisted_keys() {
+        let settings = Settings::parse(
+            r#"
+            openai_api_key = "sk-secret"
+            openai_model = "gpt-4o-mini"
+            something_else = 3
+            "#,
+        )
+        .unwrap();
+
+        let config = settings.script_config();
+        assert_eq!(config.len(), 1);
+        assert_eq!(config.get("openai_model").unwrap(), "gpt-4o-mini");
+        assert_eq!(
+            settings.openai_api_key().unwrap().as_deref(),
+            Some("sk-secret")
+        );
+    }
+
+    #[test]
+    pub fn ensure_trailing_newline_defaults_to_off() {
+        assert!(!Settings::default().ensure_trailing_newline().unwrap());
+        let settings = Settings::parse("ensure_trailing_newline = true").unwrap();
+        assert!(settings.ensure_trailing_newline().unwrap());
+    }
+
+    #[test]
+    pub fn llm_provider_defaults_to_openai() {
+        let provider = |s: &str| Settings::parse(s).unwrap().llm_provider();
+        assert_eq!(provider("").unwrap(), LlmProvider::OpenAI);
+        assert_eq!(
+            provider("llm_provider = \"echo\"").unwrap(),
+            LlmProvider::Echo
+        );
+        assert!(provider("llm_provider = \"gpt\"").is_err());
+    }
+
+    #[test]
+    pub fn max_track_size_must_be_a_size() {
+        assert_eq!(Settings::default().max_track_size().unwrap(), None);
+        let settings = Settings::parse("max_track_size = 1048576").unwrap();
+        assert_eq!(settings.max_track_size().unwrap(), Some(1048576));
+        for bad in ["max_track_size = -1", "max_track_size = \"1MB\""] {
+            assert!(Settings::parse(bad).is_err());
+        }
+    }
+
+    #[test]
+    pub fn llm_cache_dir_is_relative_to_root() {
+        let root = Path::new("/project");
+        assert_eq!(
+            Settings::default().llm_cache_dir(root).unwrap(),
+            root.join(".wrought/llm_cache")
+        );
+        let settings = Settings::parse("llm_cache_dir = \"cache/llm\"").unwrap();
+        assert_eq!(
+            settings.llm_cache_dir(root).unwrap(),
+            root.join("cache/llm")
+        );
+        let settings = Settings::parse("llm_cache_dir = \"/var/cache/llm\"").unwrap();
+        assert_eq!(
+            settings.llm_cache_dir(root).unwrap(),
+            PathBuf::from("/var/cache/llm")
+        );
+    }
+
+    #[test]
+    pub fn openai_base_url_must_be_a_url() {
+        assert_eq!(
+            Settings::default().openai_base_url().unwrap(),
+            DEFAULT_OPENAI_BASE_URL
+        );
+        let settings =
+            Settings::parse("openai_base_url = \"https://proxy.example.com/openai/v1/\"").unwrap();
+        assert_eq!(
+            settings.openai_base_url().unwrap(),
+            "https://proxy.example.com/openai/v1"
+        );
+        for bad in [
+            "openai_base_url = \"proxy.example.com\"",
+            "openai_base_url = \"ftp://proxy.example.com\"",
+            "openai_base_url = \"https:///v1\"",
+            "openai_base_url = \"https://proxy example.com\"",
+            "openai_base_url = 8080",
+        ] {
+            assert!(Settings::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    pub fn non_string_api_key_is_an_error() {
+        assert!(Settings::parse("openai_api_key = 12").is_err());
+    }
+
+    #[test]
+    pub fn all_bad_settings_are_reported() {
+        let e = Settings::parse(
+            "project_name = \"Docs\"\nopenai_api_key = 123\nensure_trailing_newline = \"yes\"\nmax_track_size = -5\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid settings file:\n  \
+             line 2: openai_api_key must be a string\n  \
+             line 3: ensure_trailing_newline must be true or false\n  \
+             line 4: max_track_size must be a non-negative integer"
+        );
+    }
+
+    #[test]
+    pub fn later_sources_take_precedence() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let root = Path::new("project");
+        fs.add_r(
+            &root.join(".wrought/config.toml"),
+            b"project_name = \"From config\"\nopenai_model = \"gpt-4o\"\nmax_track_size = 10\n"
+                .to_vec(),
+        )
+        .unwrap();
+        fs.add_r(
+            &root.join(".wrought/settings.toml"),
+            b"openai_model = \"gpt-4o-mini\"\nopenai_api_key = \"sk-local\"\nmax_track_size = 20\n"
+                .to_vec(),
+        )
+        .unwrap();
+
+        let env = |name: &str| match name {
+            "WROUGHT_MAX_TRACK_SIZE" => Some("30".to_string()),
+            _ => None,
+        };
+        let settings = Settings::load_with_env(&fs, root, env).unwrap();
+        assert_eq!(
+            settings.get_string("project_name").unwrap().as_deref(),
+            Some("From config")
+        );
+        assert_eq!(
+            settings.get_string("openai_model").unwrap().as_deref(),
+            Some("gpt-4o-mini")
+        );
+        assert_eq!(
+            settings.openai_api_key().unwrap().as_deref(),
+            Some("sk-local")
+        );
+        assert_eq!(settings.max_track_size().unwrap(), Some(30));
+        assert!(settings.warnings.is_empty(), "{:?}", settings.warnings);
+
+        let settings = Settings::load_with_env(&fs, root, |_| None).unwrap();
+        assert_eq!(settings.max_track_size().unwrap(), Some(20));
+    }
+
+    #[test]
+    pub fn bad_environment_settings_are_errors() {
+        let env = |name: &str| match name {
+            "WROUGHT_ENSURE_TRAILING_NEWLINE" => Some("true".to_string()),
+            "WROUGHT_MAX_TRACK_SIZE" => Some("big".to_string()),
+            _ => None,
+        };
+        let e = Settings::from_env(env).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid settings in the environment:\n  WROUGHT_MAX_TRACK_SIZE must be a non-negative integer"
+        );
+        let settings = Settings::from_env(|name: &str| {
+            (name == "WROUGHT_ENSURE_TRAILING_NEWLINE").then(|| "true".to_string())
+        })
+        .unwrap();
+        assert!(settings.ensure_trailing_newline().unwrap());
+    }
+
+    #[test]
+    pub fn secrets_in_config_are_warned_about() {
+        let settings = Settings::parse_config("openai_api_key = \"sk-oops\"\n").unwrap();
+        assert_eq!(
+            settings.warnings,
+            vec![
+                "line 1: openai_api_key is a secret - it belongs in settings.toml, not a committed file"
+                    .to_string()
+            ]
+        );
+        assert!(Settings::parse("openai_api_key = \"sk-fine\"\n")
+            .unwrap()
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    pub fn unknown_settings_are_warnings() {
+        let settings =
+            Settings::parse("project_name = \"Docs\"\nopenai_modle = \"gpt-4o\"\n").unwrap();
+        assert_eq!(
+            settings.warnings,
+            vec!["line 2: unknown setting 'openai_modle'".to_string()]
+        );
+    }
+}
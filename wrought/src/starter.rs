@@ -0,0 +1,79 @@
+use std::{io::Write, path::Path};
+
+/// The package `wrought init --empty` creates, so new projects have something to run.
+pub const STARTER_PACKAGE: &str = "starter";
+
+pub const STARTER_INIT_SCRIPT: &str = r##"-- A starter script, created by `wrought init --empty`. Run it with
+--
+--     wrought run-script starter/init.luau
+--
+-- then try `wrought status` and `wrought log` to see what wrought recorded.
+
+local name = get_config("project_name") or "world"
+
+write_file("hello.md", "# Hello, " .. name .. "!\n\nThis file was written by .wrought/packages/starter/init.luau.\n")
+log("wrote hello.md")
+"##;
+
+pub const STARTER_README: &str = r#"# Starter package
+
+Scripts in `.wrought/packages` are run with `wrought run-script <package>/<script>`,
+e.g. `wrought run-script starter/init.luau`.
+
+Everything a script reads and writes is recorded, so `wrought status` can tell you
+which generated files are out of date or have been edited since.
+
+Copy `init.luau` to start writing your own scripts.
+"#;
+
+/// Writes the starter package into `package_dir` (normally `.wrought/packages/starter`).
+pub fn write_starter_package(fs: &mut dyn xfs::Xfs, package_dir: &Path) -> anyhow::Result<()> {
+    fs.create_dir_all(package_dir)?;
+    fs.writer(&package_dir.join("init.luau"))?
+        .write_all(STARTER_INIT_SCRIPT.as_bytes())?;
+    fs.writer(&package_dir.join("README.md"))?
+        .write_all(STARTER_README.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use mockall::predicate;
+
+    use super::write_starter_package;
+    use crate::scripting_luau::{self, tests::MockBridge};
+
+    #[test]
+    pub fn starter_script_runs() {
+        let mut fs = xfs::mockfs::MockFS::new();
+        let package_dir = PathBuf::from("project/.wrought/packages/starter");
+        write_starter_package(&mut fs, &package_dir).unwrap();
+        assert!(fs.get(&package_dir.join("README.md")).is_some());
+
+        let mut bridge = MockBridge::new();
+        bridge
+            .expect_get_config()
+            .with(predicate::eq("project_name"))
+            .returning(|_| Ok(None));
+        bridge
+            .expect_write_file()
+            .withf(|path, value| {
+                path == Path::new("hello.md") && value.starts_with(b"# Hello, world!\n")
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        bridge.expect_log().returning(|_, _| Ok(()));
+
+        scripting_luau::run_script(
+            Arc::new(Mutex::new(bridge)),
+            Arc::new(Mutex::new(fs)),
+            &package_dir.join("init.luau"),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+/// How much of the content we look at when deciding if it is text, the same
+/// amount git checks.
+const TEXT_SNIFF_LEN: usize = 8000;
+
+/// A cheap binary/text heuristic: text is valid UTF-8 with no NUL bytes near the start.
+pub fn looks_like_text(content: &[u8]) -> bool {
+    let head = &content[..content.len().min(TEXT_SNIFF_LEN)];
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sniffed prefix may have cut a multi-byte character in half.
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Makes text content end in exactly one newline, using `\r\n` if the content already
+/// does. Binary and empty content is returned unchanged.
+pub fn ensure_single_trailing_newline(content: &[u8]) -> Vec<u8> {
+    if content.is_empty() || !looks_like_text(content) {
+        return content.to_vec();
+    }
+    let newline: &[u8] = if content.windows(2).any(|w| w == b"\r\n") {
+        b"\r\n"
+    } else {
+        b"\n"
+    };
+    let end = content
+        .iter()
+        .rposition(|c| *c != b'\n' && *c != b'\r')
+        .map_or(0, |i| i + 1);
+    let mut result = content[..end].to_vec();
+    result.extend_from_slice(newline);
+    result
+}
+
+/// A size for people to read, like `512 B` or `1.5 MiB`.
+pub fn format_byte_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffOp {
+    Same,
+    Removed,
+    Added,
+}
+
+/// The edits turning `old` into `new`. Myers' algorithm needs memory linear in the
+/// size of the files, so even large generated files can be diffed.
+fn diff_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    similar::capture_diff_slices(similar::Algorithm::Myers, old, new)
+        .iter()
+        .flat_map(|op| op.iter_changes(old, new))
+        .map(|change| {
+            let op = match change.tag() {
+                similar::ChangeTag::Equal => DiffOp::Same,
+                similar::ChangeTag::Delete => DiffOp::Removed,
+                similar::ChangeTag::Insert => DiffOp::Added,
+            };
+            (op, change.value())
+        })
+        .collect()
+}
+
+/// For each line of `new`, the index of the line of `old` it was kept from - or `None`
+/// if the line was added. Uses the same diff as `unified_diff`.
+pub fn kept_lines(old: &str, new: &str) -> Vec<Option<usize>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let mut result = vec![];
+    let mut old_line = 0;
+    for (op, _) in diff_ops(&old, &new) {
+        match op {
+            DiffOp::Same => {
+                result.push(Some(old_line));
+                old_line += 1;
+            }
+            DiffOp::Removed => old_line += 1,
+            DiffOp::Added => result.push(None),
+        }
+    }
+    result
+}
+
+/// A line diff of `old` and `new` in unified format, with `context` unchanged lines
+/// around each change. Empty if they have the same lines.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old, &new);
+
+    // Each change pulls in `context` lines either side; overlapping ranges form one hunk.
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (k, _) in ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| op.0 != DiffOp::Same)
+    {
+        let start = k.saturating_sub(context);
+        let end = (k + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let (mut old_line, mut new_line, mut k) = (0, 0, 0);
+    for (start, end) in hunks {
+        for (op, _) in &ops[k..start] {
+            old_line += (*op != DiffOp::Added) as usize;
+            new_line += (*op != DiffOp::Removed) as usize;
+        }
+        let hunk = &ops[start..end];
+        let old_len = hunk.iter().filter(|(op, _)| *op != DiffOp::Added).count();
+        let new_len = hunk.iter().filter(|(op, _)| *op != DiffOp::Removed).count();
+        // An empty range is numbered by the line before it.
+        let old_start = old_line + (old_len > 0) as usize;
+        let new_start = new_line + (new_len > 0) as usize;
+        out += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_len, new_start, new_len
+        );
+        for (op, line) in hunk {
+            let marker = match op {
+                DiffOp::Same => ' ',
+                DiffOp::Removed => '-',
+                DiffOp::Added => '+',
+            };
+            out += &format!("{}{}\n", marker, line);
+        }
+        old_line += old_len;
+        new_line += new_len;
+        k = end;
+    }
+    out
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{ensure_single_trailing_newline, format_byte_size, looks_like_text, unified_diff};
+
+    #[test]
+    pub fn byte_sizes() {
+        assert_eq!(format_byte_size(0), "0 B");
+        assert_eq!(format_byte_size(1023), "1023 B");
+        assert_eq!(format_byte_size(1024), "1.0 KiB");
+        assert_eq!(format_byte_size(1536), "1.5 KiB");
+        assert_eq!(format_byte_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[test]
+    pub fn text_detection() {
+        assert!(looks_like_text(b"hello\n"));
+        assert!(looks_like_text("caf\u{e9}".as_bytes()));
+        assert!(!looks_like_text(b"PK\x03\x04\x00\x00"));
+        assert!(!looks_like_text(&[0xff, 0xfe, 0x41]));
+    }
+
+    #[test]
+    pub fn trailing_newlines_are_normalized() {
+        assert_eq!(ensure_single_trailing_newline(b"a"), b"a\n");
+        assert_eq!(ensure_single_trailing_newline(b"a\n"), b"a\n");
+        assert_eq!(ensure_single_trailing_newline(b"a\n\n\n"), b"a\n");
+        assert_eq!(ensure_single_trailing_newline(b"a\r\nb"), b"a\r\nb\r\n");
+        assert_eq!(ensure_single_trailing_newline(b""), b"");
+    }
+
+    #[test]
+    pub fn unified_diffs() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new", 3), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(
+            unified_diff(old, new, "old", "new", 1),
+            "--- old\n+++ new\n\
+             @@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+             @@ -10,1 +10,2 @@\n 10\n+11\n"
+        );
+        assert_eq!(
+            unified_diff("", "a\n", "old", "new", 3),
+            "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+    }
+
+    #[test]
+    pub fn large_files_can_be_diffed() {
+        // A table of every pair of lines would take gigabytes.
+        let old: String = (0..50_000).map(|i| format!("line {}\n", i)).collect();
+        let new = old.replace("line 25000\n", "changed\n");
+        assert_eq!(
+            unified_diff(&old, &new, "old", "new", 0),
+            "--- old\n+++ new\n@@ -25001,1 +25001,1 @@\n-line 25000\n+changed\n"
+        );
+    }
+
+    #[test]
+    pub fn binary_content_is_untouched() {
+        let binary = b"\x89PNG\r\n\x1a\n\x00\x00\x00\n\n".to_vec();
+        assert_eq!(ensure_single_trailing_newline(&binary), binary);
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{bail, Context};
+use chrono::{DateTime, Duration, Utc};
+
+/// Parses either an RFC3339 time (`2024-05-01T12:00:00Z`) or a time relative to `now`
+/// like `90s`, `15m`, `1h`, `2d` or `1w`, meaning that long ago.
+pub fn parse_time_spec(s: &str, now: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    let Some(unit) = s.chars().last() else {
+        bail!("empty time");
+    };
+    let amount: i64 = s[..s.len() - unit.len_utf8()].parse().with_context(|| {
+        format!(
+            "invalid time '{}' - expected an RFC3339 time or something like '2d' or '1h'",
+            s
+        )
+    })?;
+    let duration = match unit {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => bail!(
+            "invalid time unit '{}' in '{}' - expected one of s, m, h, d or w",
+            unit,
+            s
+        ),
+    };
+    duration
+        .filter(|d| *d >= Duration::zero())
+        .and_then(|d| now.checked_sub_signed(d))
+        .with_context(|| format!("time '{}' is out of range", s))
+}
+
+/// The `--since`/`--until` range of a command. Both ends are inclusive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimeWindow {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeWindow {
+    pub fn parse(
+        since: Option<&str>,
+        until: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<TimeWindow> {
+        let since = since.map(|s| parse_time_spec(s, now)).transpose()?;
+        let until = until.map(|s| parse_time_spec(s, now)).transpose()?;
+        if let (Some(since), Some(until)) = (since, until) {
+            if since > until {
+                bail!("--since ({}) is after --until ({})", since, until);
+            }
+        }
+        Ok(TimeWindow { since, until })
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Runs recorded before wrought stored timestamps have no time. As we can't tell
+    /// where they fall, they're only included when the window is unbounded.
+    pub fn contains(&self, time: Option<DateTime<Utc>>) -> bool {
+        let Some(time) = time else {
+            return self.is_unbounded();
+        };
+        !matches!(self.since, Some(since) if time < since)
+            && !matches!(self.until, Some(until) if until < time)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{parse_time_spec, TimeWindow};
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    pub fn relative_times() {
+        let now = now();
+        assert_eq!(
+            parse_time_spec("90s", now).unwrap(),
+            now - Duration::seconds(90)
+        );
+        assert_eq!(
+            parse_time_spec("15m", now).unwrap(),
+            now - Duration::minutes(15)
+        );
+        assert_eq!(
+            parse_time_spec("1h", now).unwrap(),
+            now - Duration::hours(1)
+        );
+        assert_eq!(parse_time_spec("2d", now).unwrap(), now - Duration::days(2));
+        assert_eq!(
+            parse_time_spec("1w", now).unwrap(),
+            now - Duration::weeks(1)
+        );
+        assert_eq!(parse_time_spec("0d", now).unwrap(), now);
+    }
+
+    #[test]
+    pub fn rfc3339_times() {
+        assert_eq!(
+            parse_time_spec("2024-05-01T14:00:00+02:00", now()).unwrap(),
+            DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap()
+        );
+    }
+
+    #[test]
+    pub fn invalid_times() {
+        for s in ["", "d", "2", "2y", "-2d", "two days", "1.5h", "2024-05-01"] {
+            assert!(parse_time_spec(s, now()).is_err(), "{:?} should fail", s);
+        }
+        assert!(parse_time_spec("99999999999999w", now()).is_err());
+    }
+
+    #[test]
+    pub fn window_contains() {
+        let now = now();
+        let window = TimeWindow::parse(Some("2d"), Some("1d"), now).unwrap();
+        assert!(window.contains(Some(now - Duration::hours(36))));
+        assert!(window.contains(Some(now - Duration::days(2))));
+        assert!(!window.contains(Some(now - Duration::days(3))));
+        assert!(!window.contains(Some(now)));
+        // Untimed runs are only included when there is no window at all.
+        assert!(!window.contains(None));
+        assert!(TimeWindow::default().contains(None));
+
+        assert!(TimeWindow::parse(Some("1d"), Some("2d"), now).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+
+use anyhow::bail;
+
+use crate::{
+    event_log::EventLog,
+    events::{EventGroup, EventType},
+};
+
+/// The ids of groups after `group` that read a file at the content `group` wrote,
+/// i.e. the runs that used its output as their input.
+pub fn dependent_groups(group: &EventGroup, all_groups: &[EventGroup]) -> Vec<u64> {
+    let outputs: BTreeSet<_> = group
+        .events
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::WriteFile(w) => Some((w.path.clone(), w.after_hash.clone()?)),
+            EventType::DeclareOutput(d) => Some((d.path.clone(), d.hash.clone())),
+            EventType::RenameFile(r) => Some((r.to.clone(), r.hash.clone()?)),
+            _ => None,
+        })
+        .collect();
+    all_groups
+        .iter()
+        .filter(|g| g.id > group.id)
+        .filter(|g| {
+            g.events.iter().any(|e| match &e.event_type {
+                EventType::ReadFile(r) => r
+                    .hash
+                    .as_ref()
+                    .is_some_and(|h| outputs.contains(&(r.path.clone(), h.clone()))),
+                _ => false,
+            })
+        })
+        .map(|g| g.id)
+        .collect()
+}
+
+/// Removes a run from the event log. Unless `force` is set this refuses to remove a
+/// run whose output a later run has read, as that would leave the later run's input
+/// unexplained.
+pub fn undo_group(event_log: &mut dyn EventLog, group_id: u64, force: bool) -> anyhow::Result<()> {
+    let Some(group) = event_log.get_event_group(group_id)? else {
+        bail!("there is no group with id {}", group_id);
+    };
+    if !force {
+        let dependents = dependent_groups(&group, &event_log.all_event_groups()?);
+        if !dependents.is_empty() {
+            let ids: Vec<_> = dependents.iter().map(|id| id.to_string()).collect();
+            bail!(
+                "can not undo group {} - groups {} read its output (use --force to undo it anyway)",
+                group_id,
+                ids.join(", ")
+            );
+        }
+    }
+    event_log.delete_group(group_id)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+
+    use super::undo_group;
+    use crate::{
+        binary16::ContentHash,
+        event_log::{EventLog, SQLiteEventLog},
+        events::{Event, EventGroup, ReadFileEvent, WriteFileEvent},
+    };
+
+    fn write_group(event_log: &mut SQLiteEventLog, path: &str, content: &[u8]) -> u64 {
+        let mut group = EventGroup::empty();
+        group.command = format!("write {}", path);
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from(path),
+            before_hash: None,
+            after_hash: Some(ContentHash::from_content(content)),
+        }));
+        event_log.add_event_group(&group).unwrap().id
+    }
+
+    fn read_group(event_log: &mut SQLiteEventLog, path: &str, content: &[u8]) -> u64 {
+        let mut group = EventGroup::empty();
+        group.command = format!("read {}", path);
+        group.events.push(Event::from(ReadFileEvent {
+            path: PathBuf::from(path),
+            hash: Some(ContentHash::from_content(content)),
+        }));
+        event_log.add_event_group(&group).unwrap().id
+    }
+
+    fn group_ids(event_log: &SQLiteEventLog) -> Vec<u64> {
+        event_log
+            .all_event_groups()
+            .unwrap()
+            .iter()
+            .map(|g| g.id)
+            .collect()
+    }
+
+    #[test]
+    pub fn undo_removes_the_group() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let a = write_group(&mut event_log, "a.md", b"a");
+        let b = write_group(&mut event_log, "b.md", b"b");
+
+        undo_group(&mut event_log, a, false).unwrap();
+        assert_eq!(group_ids(&event_log), vec![b]);
+        assert!(event_log.get_event_group(a).unwrap().is_none());
+        assert_eq!(
+            event_log
+                .get_last_write_event(&PathBuf::from("a.md"))
+                .unwrap(),
+            None
+        );
+
+        assert!(undo_group(&mut event_log, a, false).is_err());
+    }
+
+    #[test]
+    pub fn undo_refuses_groups_with_dependents_unless_forced() {
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let a = write_group(&mut event_log, "a.md", b"a");
+        // Reading different content from the same file isn't a dependency.
+        let unrelated = read_group(&mut event_log, "a.md", b"other");
+        let reader = read_group(&mut event_log, "a.md", b"a");
+
+        let e = undo_group(&mut event_log, a, false).unwrap_err();
+        assert!(
+            e.to_string().contains(&format!("groups {}", reader)),
+            "{}",
+            e
+        );
+        assert_eq!(group_ids(&event_log), vec![a, unrelated, reader]);
+
+        undo_group(&mut event_log, a, true).unwrap();
+        assert_eq!(group_ids(&event_log), vec![unrelated, reader]);
+    }
+}
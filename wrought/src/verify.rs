@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    content_store::ContentStore,
+    event_log::{EventLog, IntegrityIssue},
+    events::EventType,
+};
+
+/// Everything `wrought verify` checks - the event log's own structure, and that the
+/// content store still has everything the log says was written.
+///
+/// Only written content is guaranteed to be in the store. Read content has only been
+/// stored since reads started being recorded with their content, so projects older
+/// than that legitimately have read hashes with nothing behind them. Before hashes,
+/// renames and declared outputs describe files wrought never copied either.
+pub fn verify_project(
+    event_log: &dyn EventLog,
+    content_store: &dyn ContentStore,
+) -> anyhow::Result<Vec<IntegrityIssue>> {
+    let mut issues = event_log.check_integrity()?;
+    let stored: BTreeSet<_> = content_store
+        .objects()?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let mut groups = event_log.all_event_groups()?;
+    groups.sort_by_key(|g| g.id);
+    for group in groups {
+        for event in group.events {
+            let hash = match event.event_type {
+                EventType::WriteFile(e) => e.after_hash,
+                _ => None,
+            };
+            if let Some(hash) = hash.filter(|h| !stored.contains(h)) {
+                issues.push(IntegrityIssue::MissingContent {
+                    group_id: group.id,
+                    event_id: event.id,
+                    hash,
+                });
+            }
+        }
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use super::verify_project;
+    use crate::{
+        binary16::ContentHash,
+        content_store::{ContentStore, FileSystemContentStore},
+        event_log::{EventLog, IntegrityIssue, SQLiteEventLog},
+        events::{Event, EventGroup, ReadFileEvent, WriteFileEvent},
+    };
+
+    #[test]
+    pub fn missing_content_is_reported() {
+        let fs = Arc::new(Mutex::new(xfs::mockfs::MockFS::new()));
+        let mut store = FileSystemContentStore::new(fs, PathBuf::from("project/.wrought/content"));
+        store.store(b"read").unwrap();
+
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(ReadFileEvent {
+            path: PathBuf::from("in.md"),
+            hash: Some(ContentHash::from_content(b"read")),
+        }));
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("out.md"),
+            before_hash: Some(ContentHash::from_content(b"never stored")),
+            after_hash: Some(ContentHash::from_content(b"lost")),
+        }));
+        let group = event_log.add_event_group(&group).unwrap();
+
+        assert_eq!(
+            verify_project(&event_log, &store).unwrap(),
+            vec![IntegrityIssue::MissingContent {
+                group_id: group.id,
+                event_id: group.events[1].id,
+                hash: ContentHash::from_content(b"lost"),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn reads_from_before_content_was_stored_are_not_missing() {
+        let fs = Arc::new(Mutex::new(xfs::mockfs::MockFS::new()));
+        let mut store = FileSystemContentStore::new(fs, PathBuf::from("project/.wrought/content"));
+        store.store(b"written").unwrap();
+
+        // Older versions recorded the hash of what a script read without storing it.
+        let mut event_log = SQLiteEventLog::open_in_memory().unwrap();
+        let mut group = EventGroup::empty();
+        group.events.push(Event::from(ReadFileEvent {
+            path: PathBuf::from("in.md"),
+            hash: Some(ContentHash::from_content(b"read but never stored")),
+        }));
+        group.events.push(Event::from(WriteFileEvent {
+            path: PathBuf::from("out.md"),
+            before_hash: None,
+            after_hash: Some(ContentHash::from_content(b"written")),
+        }));
+        event_log.add_event_group(&group).unwrap();
+
+        assert_eq!(verify_project(&event_log, &store).unwrap(), vec![]);
+    }
+}
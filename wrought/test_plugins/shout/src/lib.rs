@@ -0,0 +1,31 @@
+//! A tiny plugin for the host's tests: reads `in.txt` and writes it, uppercased, to
+//! `out.txt`. Nothing else, so the test can expect exactly those bridge calls.
+use std::path::Path;
+
+use wasmcb::{default_panic_hook, report_error};
+use wrought_wasm_bindings::{Wrought, WroughtResult, PLUGIN_ERROR, PLUGIN_OK};
+
+#[used]
+#[link_section = "wrought_manifest"]
+static MANIFEST: [u8; 60] = *br#"{"name":"shout","version":"0.1.0","capabilities":["bridge"]}"#;
+
+#[no_mangle]
+pub extern "C" fn plugin() -> i32 {
+    std::panic::set_hook(Box::new(default_panic_hook));
+
+    match plugin_impl() {
+        Ok(()) => PLUGIN_OK,
+        Err(e) => {
+            report_error(&e);
+            PLUGIN_ERROR
+        }
+    }
+}
+
+fn plugin_impl() -> WroughtResult<()> {
+    let mut wrought = Wrought {};
+    let content = wrought
+        .read_file(Path::new("in.txt"))?
+        .ok_or_else(|| "in.txt is missing".to_string())?;
+    wrought.write_file(Path::new("out.txt"), &content.to_ascii_uppercase())
+}